@@ -0,0 +1,63 @@
+//! Overhead of async introspection through an `AsyncCachingTokenInfoService`
+//! with an inner service which answers immediately.
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture, FutureExt};
+use tokkit::async_client::AsyncTokenInfoService;
+use tokkit::cache::AsyncCachingTokenInfoService;
+use tokkit::*;
+
+mod support;
+
+const CALLS: u64 = 200_000;
+
+struct ImmediateService;
+
+impl AsyncTokenInfoService for ImmediateService {
+    fn introspect<'a>(
+        &'a self,
+        _token: &'a AccessToken,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        future::ok(TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read"), Scope::new("write")],
+            expires_in_seconds: Some(3600),
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        })
+        .boxed()
+    }
+
+    fn introspect_with_retry<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        _budget: Duration,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect(token)
+    }
+}
+
+fn run<S: AsyncTokenInfoService>(name: &str, service: &S) {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .build()
+        .unwrap();
+    let token = AccessToken::new("token");
+    let start = Instant::now();
+    runtime.block_on(async {
+        for _ in 0..CALLS {
+            support::black_box(service.introspect(&token).await.unwrap());
+        }
+    });
+    support::report(name, 0, start.elapsed(), CALLS);
+}
+
+fn main() {
+    run("introspect/direct", &ImmediateService);
+    run(
+        "introspect/cached",
+        &AsyncCachingTokenInfoService::new(ImmediateService, Default::default()),
+    );
+}
@@ -0,0 +1,90 @@
+//! Introspections through the async client against a local server which
+//! answers with a growing number of scopes. The response bodies are
+//! received in one or more chunks, so the time and the allocations per
+//! introspection show the cost of buffering large bodies.
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use tokkit::async_client::*;
+use tokkit::parsers::PlanBTokenInfoParser;
+use tokkit::AccessToken;
+
+mod support;
+
+#[global_allocator]
+static ALLOCATOR: support::CountingAllocator = support::CountingAllocator;
+
+/// A PlanB response with `scopes` scopes.
+fn body(scopes: usize) -> Vec<u8> {
+    let scopes = (0..scopes)
+        .map(|i| format!("\"resource-{}.read\"", i))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(r#"{{"uid":"user","scope":[{}],"expires_in":3600}}"#, scopes).into_bytes()
+}
+
+/// Answers every request on a local port with `body` and returns the
+/// endpoint.
+fn serve(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/tokeninfo", listener.local_addr().unwrap());
+    let body = Arc::new(body);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let body = body.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.unwrap());
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    // Requests have no body, so an empty line ends them.
+                    if line.trim().is_empty() {
+                        let stream = reader.get_mut();
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                             Content-Length: {}\r\n\r\n",
+                            body.len()
+                        )
+                        .unwrap();
+                        stream.write_all(&body).unwrap();
+                    }
+                }
+            });
+        }
+    });
+
+    endpoint
+}
+
+fn main() {
+    let mut runtime = tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .enable_all()
+        .build()
+        .unwrap();
+    let token = AccessToken::new("token");
+
+    for &scopes in &[10, 1_000, 10_000] {
+        let body = body(scopes);
+        let len = body.len();
+        let client = AsyncTokenInfoServiceClientLight::new(
+            &serve(body),
+            Some("access_token"),
+            None,
+            PlanBTokenInfoParser,
+        )
+        .unwrap()
+        .with_default_client()
+        .unwrap();
+
+        support::bench(&format!("introspect/{}_scopes", scopes), len, || {
+            runtime.block_on(client.introspect(&token)).unwrap()
+        });
+    }
+}
@@ -0,0 +1,69 @@
+//! Throughput of the built-in `TokenInfoParser`s for typical payloads.
+use tokkit::parsers::*;
+
+mod support;
+
+#[global_allocator]
+static ALLOCATOR: support::CountingAllocator = support::CountingAllocator;
+
+const PLANB: &[u8] = br#"
+{
+    "access_token": "token",
+    "cn": true,
+    "expires_in": 28292,
+    "grant_type": "password",
+    "open_id": "token",
+    "realm": "/services",
+    "scope": ["cn", "uid", "resource.read", "resource.write"],
+    "token_type": "Bearer",
+    "uid": "test2"
+}
+"#;
+
+const GOOGLE_V3: &[u8] = br#"
+{
+    "aud": "8819981768.apps.googleusercontent.com",
+    "user_id": "123456789",
+    "scope": "https://www.googleapis.com/auth/drive.metadata.readonly https://www.googleapis.com/auth/userinfo.email openid",
+    "expires_in": 436,
+    "email": "robot@example.com",
+    "email_verified": "true"
+}
+"#;
+
+const KEYCLOAK: &[u8] = br#"
+{
+    "exp": 1893456000,
+    "iat": 1893452400,
+    "jti": "2b4e9d0c-5a2e-4b8f-9b3e-0c6d7e8f9a0b",
+    "iss": "https://keycloak.example.com/realms/services",
+    "aud": ["orders", "account"],
+    "sub": "f3c5a6b7-8d9e-4f0a-1b2c-3d4e5f6a7b8c",
+    "typ": "Bearer",
+    "azp": "orders-service",
+    "preferred_username": "service-account-orders",
+    "scope": "openid profile email orders.read orders.write",
+    "client_id": "orders-service",
+    "username": "service-account-orders",
+    "active": true
+}
+"#;
+
+fn main() {
+    let keycloak = CustomTokenInfoParser::new(
+        Some("active"),
+        Some("preferred_username"),
+        Some("scope"),
+        None::<String>,
+    );
+
+    support::bench("parse/planb", PLANB.len(), || {
+        PlanBTokenInfoParser.parse(PLANB).unwrap()
+    });
+    support::bench("parse/google_v3", GOOGLE_V3.len(), || {
+        GoogleV3TokenInfoParser.parse(GOOGLE_V3).unwrap()
+    });
+    support::bench("parse/keycloak", KEYCLOAK.len(), || {
+        keycloak.parse(KEYCLOAK).unwrap()
+    });
+}
@@ -0,0 +1,89 @@
+//! A minimal benchmark harness: Each benchmark is warmed up and then run in
+//! batches until a time budget is spent. The mean time per iteration is
+//! printed together with the throughput.
+//!
+//! Benchmarks installing the `CountingAllocator` as the global allocator
+//! also get the mean number of allocations per iteration.
+#![allow(dead_code)]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const MEASUREMENT: Duration = Duration::from_secs(1);
+
+/// Runs `f` repeatedly and prints the mean time per call.
+///
+/// `bytes` is the amount of data processed per call and is used to print
+/// the throughput if not zero.
+pub fn bench<F, T>(name: &str, bytes: usize, mut f: F)
+where
+    F: FnMut() -> T,
+{
+    let warm_up_until = Instant::now() + WARM_UP;
+    while Instant::now() < warm_up_until {
+        black_box(f());
+    }
+
+    let mut iterations: u64 = 0;
+    let mut batch: u64 = 1;
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    while start.elapsed() < MEASUREMENT {
+        for _ in 0..batch {
+            black_box(f());
+        }
+        iterations += batch;
+        batch *= 2;
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    report(name, bytes, elapsed, iterations);
+    if allocations > 0 {
+        println!(
+            "{:<48} {:>12.1} allocations/iter",
+            "",
+            allocations as f64 / iterations as f64
+        );
+    }
+}
+
+/// Prints the result of a benchmark which measured `iterations` calls
+/// taking `elapsed` in total.
+pub fn report(name: &str, bytes: usize, elapsed: Duration, iterations: u64) {
+    let nanos_per_iter = elapsed.as_nanos() as f64 / iterations as f64;
+    if bytes > 0 {
+        let mib_per_sec = bytes as f64 * 1e9 / nanos_per_iter / (1024.0 * 1024.0);
+        println!(
+            "{:<48} {:>12.1} ns/iter {:>10.1} MiB/s",
+            name, nanos_per_iter, mib_per_sec
+        );
+    } else {
+        println!("{:<48} {:>12.1} ns/iter", name, nanos_per_iter);
+    }
+}
+
+pub fn black_box<T>(value: T) -> T {
+    std::hint::black_box(value)
+}
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator counting the allocations made.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
@@ -0,0 +1,51 @@
+//! `get_access_token` of an `AccessTokenSource` under contention.
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Instant;
+
+use tokkit::token_manager::*;
+use tokkit::AccessToken;
+
+mod support;
+
+const CALLS_PER_THREAD: u64 = 200_000;
+
+fn main() {
+    let source = AccessTokenSource::new_detached(&[
+        ("a", AccessToken::new("token-a")),
+        ("b", AccessToken::new("token-b")),
+    ]);
+
+    support::bench("get_access_token/uncontended", 0, || {
+        source.get_access_token(&"a").unwrap()
+    });
+
+    for &threads in &[2u64, 4, 8] {
+        let source = source.synced();
+        let barrier = Arc::new(Barrier::new(threads as usize + 1));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let source = source.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..CALLS_PER_THREAD {
+                        support::black_box(source.get_access_token(&"a").unwrap());
+                    }
+                })
+            })
+            .collect();
+
+        barrier.wait();
+        let start = Instant::now();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        support::report(
+            &format!("get_access_token/contended/{}_threads", threads),
+            0,
+            start.elapsed(),
+            threads * CALLS_PER_THREAD,
+        );
+    }
+}
@@ -1,19 +1,41 @@
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use backoff_futures::BackoffExt;
+use backoff::backoff::Backoff;
+use bytes::{Bytes, BytesMut};
 use futures::*;
 use futures::future::{self, BoxFuture};
-use reqwest::{Client, Response, StatusCode, Url};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder, Method, Response, Url};
 
-use crate::client::assemble_url_prefix;
+use crate::client::DEFAULT_MAX_RESPONSE_SIZE;
+use crate::core::{
+    assemble_endpoint_url, assemble_url_prefix, authorization_header, complete_url,
+    decode_and_evaluate, introspection_form, is_retryable, next_interleaved_attempt,
+    next_retry_delay, request_headers, request_method, retry_backoff, send_error,
+    should_use_fallback, timeout_until, track_introspection, track_service_call,
+};
+use crate::endpoint_auth::{conflicting_authorization, EndpointAuth};
+use crate::fallback::SharedFallbackClassifier;
 use crate::metrics::{DevNullMetricsCollector, MetricsCollector};
 use crate::parsers::*;
-use crate::{AccessToken, InitializationError, InitializationResult, TokenInfo};
-use crate::{TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
+use crate::redirect::RedirectPolicy;
+use crate::tls::TlsPolicy;
+use crate::request_decorator::{decorate, RequestDecorators};
+use crate::request_log::{log_failure, RequestLog};
+use crate::unix_socket;
+use crate::{AccessToken, Credential, InitializationError, InitializationResult, TokenInfo};
+use crate::{authorize, BodyCapture, FallbackClassifier, FallbackStrategy, Scope};
+use crate::IntrospectionContext;
+use crate::singleflight::AsyncSingleFlight;
+use crate::{RedactionPolicy, RequestDecorator, TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
 
 pub type HttpClient = Client;
 
+/// Configures the `ClientBuilder` of HTTP clients created by
+/// `AsyncTokenInfoServiceClientLight::with_default_client`.
+pub type HttpClientConfigurator = Arc<dyn Fn(ClientBuilder) -> ClientBuilder + Send + Sync>;
+
 /// Gives a `TokenInfo` for an `AccessToken`.
 ///
 /// See [OAuth 2.0 Token Introspection](https://tools.ietf.org/html/rfc7662)
@@ -32,6 +54,71 @@ pub trait AsyncTokenInfoService {
         token: &'a AccessToken,
         budget: Duration,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>;
+    /// Gives a `TokenInfo` for an `AccessToken` introspected on behalf of
+    /// the request described by `context`.
+    ///
+    /// The default implementation ignores the `context`.
+    fn introspect_with_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        _context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect(token)
+    }
+    /// Gives a `TokenInfo` for an `AccessToken` introspected on behalf of
+    /// the request described by `context` with retries.
+    ///
+    /// The default implementation ignores the `context`.
+    fn introspect_with_retry_and_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+        _context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect_with_retry(token, budget)
+    }
+    /// Gives a `TokenInfo` for any `Credential`.
+    ///
+    /// The default implementation introspects `Credential::Bearer` via
+    /// `introspect` and rejects all other credentials.
+    fn introspect_credential<'a>(
+        &'a self,
+        credential: &'a Credential,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token),
+            _ => future::err(unsupported_credential(credential)).boxed(),
+        }
+    }
+    /// Gives a `TokenInfo` for any `Credential` with retries.
+    ///
+    /// The default implementation introspects `Credential::Bearer` via
+    /// `introspect_with_retry` and rejects all other credentials.
+    fn introspect_credential_with_retry<'a>(
+        &'a self,
+        credential: &'a Credential,
+        budget: Duration,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect_with_retry(token, budget),
+            _ => future::err(unsupported_credential(credential)).boxed(),
+        }
+    }
+    /// Gives a `TokenInfo` for an `AccessToken` if it has all of the
+    /// `required` `Scope`s.
+    ///
+    /// Fails with `TokenInfoErrorKind::NotAuthenticated` if the token is
+    /// not active and with `TokenInfoErrorKind::NotAuthorized` listing the
+    /// missing `Scope`s if it lacks any of them.
+    fn introspect_expecting<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        required: &'a [Scope],
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect(token)
+            .map(move |result| result.and_then(|token_info| authorize(token_info, required)))
+            .boxed()
+    }
 }
 
 /// Gives a `TokenInfo` for an `AccessToken`.
@@ -57,6 +144,70 @@ pub trait AsyncTokenInfoServiceLight {
         budget: Duration,
         http_client: &'a Client,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>;
+    /// Gives a `TokenInfo` for an `AccessToken` introspected on behalf of
+    /// the request described by `context`.
+    ///
+    /// The default implementation ignores the `context`.
+    fn introspect_with_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        _context: &'a IntrospectionContext,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect(token, http_client)
+    }
+    /// Gives a `TokenInfo` for an `AccessToken` introspected on behalf of
+    /// the request described by `context` with retries.
+    ///
+    /// The default implementation ignores the `context`.
+    fn introspect_with_retry_and_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+        _context: &'a IntrospectionContext,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.introspect_with_retry(token, budget, http_client)
+    }
+    /// Gives a `TokenInfo` for any `Credential`.
+    ///
+    /// The default implementation introspects `Credential::Bearer` via
+    /// `introspect` and rejects all other credentials.
+    fn introspect_credential<'a>(
+        &'a self,
+        credential: &'a Credential,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token, http_client),
+            _ => future::err(unsupported_credential(credential)).boxed(),
+        }
+    }
+    /// Gives a `TokenInfo` for any `Credential` with retries.
+    ///
+    /// The default implementation introspects `Credential::Bearer` via
+    /// `introspect_with_retry` and rejects all other credentials.
+    fn introspect_credential_with_retry<'a>(
+        &'a self,
+        credential: &'a Credential,
+        budget: Duration,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => {
+                self.introspect_with_retry(token, budget, http_client)
+            }
+            _ => future::err(unsupported_credential(credential)).boxed(),
+        }
+    }
+}
+
+fn unsupported_credential(credential: &Credential) -> TokenInfoError {
+    TokenInfoErrorKind::Client(format!(
+        "{:?} can not be introspected by this service",
+        credential
+    ))
+    .into()
 }
 
 /// A complete introspection client that owns a
@@ -71,9 +222,17 @@ pub trait AsyncTokenInfoServiceLight {
 pub struct AsyncTokenInfoServiceClient<P, M> {
     url_prefix: Arc<String>,
     fallback_url_prefix: Option<Arc<String>>,
+    endpoint_url: Arc<String>,
+    fallback_endpoint_url: Option<Arc<String>>,
     http_client: Client,
     parser: P,
     metrics_collector: M,
+    settings: RequestSettings,
+    single_flight: Option<Arc<AsyncSingleFlight>>,
+    redaction_policy: Option<Arc<RedactionPolicy>>,
+    request_decorators: RequestDecorators,
+    endpoint_auth: Option<EndpointAuth>,
+    fallback_classifier: Option<SharedFallbackClassifier>,
 }
 
 impl<P> AsyncTokenInfoServiceClient<P, DevNullMetricsCollector>
@@ -112,78 +271,218 @@ where
         metrics_collector: M,
     ) -> InitializationResult<AsyncTokenInfoServiceClient<P, M>> {
         let url_prefix = assemble_url_prefix(endpoint, &query_parameter)
-            .map_err(InitializationError)?;
+            .map_err(InitializationError::InvalidUrl)?;
 
         let fallback_url_prefix = if let Some(fallback_endpoint_address) = fallback_endpoint {
             Some(
                 assemble_url_prefix(fallback_endpoint_address, &query_parameter)
-                    .map_err(InitializationError)?,
+                    .map_err(InitializationError::InvalidUrl)?,
             )
         } else {
             None
         };
 
+        let endpoint_url =
+            assemble_endpoint_url(endpoint).map_err(InitializationError::InvalidUrl)?;
+        let fallback_endpoint_url = match fallback_endpoint {
+            Some(fallback_endpoint) => Some(
+                assemble_endpoint_url(fallback_endpoint)
+                    .map_err(InitializationError::InvalidUrl)?,
+            ),
+            None => None,
+        };
+
         Ok(AsyncTokenInfoServiceClient {
             url_prefix: Arc::new(url_prefix),
             fallback_url_prefix: fallback_url_prefix.map(Arc::new),
+            endpoint_url: Arc::new(endpoint_url),
+            fallback_endpoint_url: fallback_endpoint_url.map(Arc::new),
             parser,
             metrics_collector,
             http_client,
+            settings: RequestSettings::default(),
+            single_flight: None,
+            redaction_policy: None,
+            request_decorators: Vec::new(),
+            endpoint_auth: None,
+            fallback_classifier: None,
         })
     }
 
-    fn create(
-        http_client: Client,
-        url_prefix: Arc<String>,
-        fallback_url_prefix: Option<Arc<String>>,
-        parser: P,
-        metrics_collector: M,
-    ) -> AsyncTokenInfoServiceClient<P, M> {
-        AsyncTokenInfoServiceClient {
-            url_prefix,
-            fallback_url_prefix,
-            parser,
-            metrics_collector,
-            http_client,
-        }
+    /// Sets the maximum number of bytes accepted in a response body.
+    ///
+    /// The body is read in chunks and the request is aborted with
+    /// `TokenInfoErrorKind::ResponseTooLarge` once the limit is exceeded.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.settings.max_response_size = max_response_size;
+        self
+    }
+
+    /// Sends `Accept-Encoding: gzip, deflate` with each request if enabled.
+    ///
+    /// Compressed responses are always decoded before they are parsed.
+    pub fn with_gzip_requested(mut self, enabled: bool) -> Self {
+        self.settings.request_gzip = enabled;
+        self
+    }
+
+    /// Sends the access token as `Authorization: Bearer <token>` to the
+    /// endpoint as given if enabled. The query parameter is ignored then.
+    pub fn with_token_in_authorization_header(mut self, enabled: bool) -> Self {
+        self.settings.token_in_authorization_header = enabled;
+        self
+    }
+
+    /// Sends the access token in a form with a `POST` to the endpoint as
+    /// given if enabled. This takes precedence over
+    /// `with_token_in_authorization_header`.
+    pub fn with_post_introspection(mut self, enabled: bool) -> Self {
+        self.settings.post_introspection = enabled;
+        self
+    }
+
+    /// Lets concurrent introspections of the same access token share a
+    /// single request if enabled.
+    ///
+    /// Callers waiting for the shared request get an error of the same
+    /// kind if it fails.
+    pub fn with_request_coalescing(mut self, enabled: bool) -> Self {
+        self.single_flight = if enabled {
+            Some(Arc::new(AsyncSingleFlight::default()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Logs each request with level `debug` using the default
+    /// `RedactionPolicy` if enabled.
+    pub fn with_request_logging(mut self, enabled: bool) -> Self {
+        self.redaction_policy = if enabled {
+            Some(Arc::new(RedactionPolicy::default()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Logs each request with level `debug` with everything `policy`
+    /// considers secret redacted.
+    pub fn with_redaction_policy(mut self, policy: RedactionPolicy) -> Self {
+        self.redaction_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Adds a `RequestDecorator` applied to every request. Decorators are
+    /// applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+
+    /// Sets the `FallbackClassifier` deciding whether the fallback endpoint
+    /// is asked after the primary endpoint failed. The default is the
+    /// `DefaultFallbackClassifier`.
+    pub fn with_fallback_classifier<C>(mut self, classifier: C) -> Self
+    where
+        C: FallbackClassifier + Send + Sync + 'static,
+    {
+        self.fallback_classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Sets how the retries are spread over the primary and the fallback
+    /// endpoint. The default is `FallbackStrategy::PrimaryThenFallback`.
+    pub fn with_fallback_strategy(mut self, strategy: FallbackStrategy) -> Self {
+        self.settings.fallback_strategy = strategy;
+        self
+    }
+
+    /// Sets how much of a response body is put into the errors of failed
+    /// introspections. The default keeps the first
+    /// `DEFAULT_CAPTURED_BODY_LENGTH` bytes.
+    pub fn with_body_capture(mut self, body_capture: BodyCapture) -> Self {
+        self.settings.body_capture = body_capture;
+        self
+    }
+
+    fn prepare(
+        &self,
+        credential: &Credential,
+        context: Option<&IntrospectionContext>,
+    ) -> TokenInfoResult<PreparedRequests> {
+        let prepare = |url_prefix: &str, endpoint_url: &str| {
+            prepare_request(
+                credential,
+                url_prefix,
+                endpoint_url,
+                self.settings,
+                self.redaction_policy.as_ref(),
+                &self.request_decorators,
+                self.endpoint_auth.as_ref(),
+                context,
+            )
+        };
+        let primary = prepare(&self.url_prefix, &self.endpoint_url)?;
+        let fallback = match (&self.fallback_url_prefix, &self.fallback_endpoint_url) {
+            (Some(url_prefix), Some(endpoint_url)) => Some(prepare(url_prefix, endpoint_url)?),
+            _ => None,
+        };
+        Ok(PreparedRequests { primary, fallback })
     }
 }
 
-impl<P, M> AsyncTokenInfoService for AsyncTokenInfoServiceClient<P, M>
+impl<P, M> AsyncTokenInfoServiceClient<P, M>
 where
     P: TokenInfoParser + Send + Sync,
     M: MetricsCollector + Send + Sync,
 {
-    fn introspect<'a>(
+    /// Introspects the `credential` and lets concurrent introspections of the
+    /// same `AccessToken` without a `context` share a request if coalescing
+    /// is enabled.
+    fn run<'a>(
         &'a self,
-        token: &'a AccessToken,
+        http_client: &'a Client,
+        credential: &Credential,
+        budget: Option<Duration>,
+        context: Option<&'a IntrospectionContext>,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
-        let start = Instant::now();
-        self.metrics_collector.incoming_introspection_request();
-
-        async move {
-            let result = execute_once(
-                &self.http_client,
-                token,
-                &self.url_prefix,
+        let request = self.prepare(credential, context);
+        let introspect = move || {
+            introspect(
+                http_client,
+                request,
                 &self.parser,
                 &self.metrics_collector,
-            ).await;
-
-            match result {
-                Ok(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_success(start);
-                }
-                Err(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_failure(start);
-                }
+                self.settings.max_response_size,
+                budget,
+                context,
+                self.fallback_classifier.as_deref(),
+                self.settings.fallback_strategy,
+            )
+        };
+        match (self.single_flight.as_deref(), credential, context) {
+            (Some(single_flight), Credential::Bearer(token), None) => {
+                single_flight.run(token, budget.is_some(), introspect)
             }
-
-            result
+            _ => introspect(),
         }
-        .boxed()
+    }
+}
+
+impl<P, M> AsyncTokenInfoService for AsyncTokenInfoServiceClient<P, M>
+where
+    P: TokenInfoParser + Send + Sync,
+    M: MetricsCollector + Send + Sync,
+{
+    fn introspect<'a>(
+        &'a self,
+        token: &'a AccessToken,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(&self.http_client, &Credential::Bearer(token.clone()), None, None)
     }
 
     fn introspect_with_retry<'a>(
@@ -191,35 +490,41 @@ where
         token: &'a AccessToken,
         budget: Duration,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
-        let start = Instant::now();
-        self.metrics_collector.incoming_introspection_request();
-
-        let result = execute_with_retry(
-            &self.http_client,
-            token,
-            &self.url_prefix,
-            &self.parser,
-            budget,
-            &self.metrics_collector,
-        );
+        self.run(&self.http_client, &Credential::Bearer(token.clone()), Some(budget), None)
+    }
 
-        async move {
-            let result = result.await;
+    fn introspect_with_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        let credential = Credential::Bearer(token.clone());
+        self.run(&self.http_client, &credential, None, Some(context))
+    }
 
-            match result {
-                Ok(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_success(start)
-                }
-                Err(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_failure(start)
-                }
-            }
+    fn introspect_with_retry_and_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+        context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        let credential = Credential::Bearer(token.clone());
+        self.run(&self.http_client, &credential, Some(budget), Some(context))
+    }
 
-            result
-        }
-        .boxed()
+    fn introspect_credential<'a>(
+        &'a self,
+        credential: &'a Credential,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(&self.http_client, credential, None, None)
+    }
+
+    fn introspect_credential_with_retry<'a>(
+        &'a self,
+        credential: &'a Credential,
+        budget: Duration,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(&self.http_client, credential, Some(budget), None)
     }
 }
 
@@ -234,8 +539,19 @@ where
 pub struct AsyncTokenInfoServiceClientLight<P, M> {
     url_prefix: Arc<String>,
     fallback_url_prefix: Option<Arc<String>>,
+    endpoint_url: Arc<String>,
+    fallback_endpoint_url: Option<Arc<String>>,
     parser: P,
     metrics_collector: M,
+    settings: RequestSettings,
+    single_flight: Option<Arc<AsyncSingleFlight>>,
+    redaction_policy: Option<Arc<RedactionPolicy>>,
+    request_decorators: RequestDecorators,
+    endpoint_auth: Option<EndpointAuth>,
+    fallback_classifier: Option<SharedFallbackClassifier>,
+    redirect_policy: RedirectPolicy,
+    tls_policy: TlsPolicy,
+    http_client_configurator: Option<HttpClientConfigurator>,
 }
 
 impl<P> AsyncTokenInfoServiceClientLight<P, DevNullMetricsCollector>
@@ -271,25 +587,226 @@ where
         metrics_collector: M,
     ) -> InitializationResult<AsyncTokenInfoServiceClientLight<P, M>> {
         let url_prefix = assemble_url_prefix(endpoint, &query_parameter)
-            .map_err(InitializationError)?;
+            .map_err(InitializationError::InvalidUrl)?;
 
         let fallback_url_prefix = if let Some(fallback_endpoint_address) = fallback_endpoint {
             Some(
                 assemble_url_prefix(fallback_endpoint_address, &query_parameter)
-                    .map_err(InitializationError)?,
+                    .map_err(InitializationError::InvalidUrl)?,
             )
         } else {
             None
         };
 
+        let endpoint_url =
+            assemble_endpoint_url(endpoint).map_err(InitializationError::InvalidUrl)?;
+        let fallback_endpoint_url = match fallback_endpoint {
+            Some(fallback_endpoint) => Some(
+                assemble_endpoint_url(fallback_endpoint)
+                    .map_err(InitializationError::InvalidUrl)?,
+            ),
+            None => None,
+        };
+
         Ok(AsyncTokenInfoServiceClientLight {
             url_prefix: Arc::new(url_prefix),
             fallback_url_prefix: fallback_url_prefix.map(Arc::new),
+            endpoint_url: Arc::new(endpoint_url),
+            fallback_endpoint_url: fallback_endpoint_url.map(Arc::new),
             parser,
             metrics_collector,
+            settings: RequestSettings::default(),
+            single_flight: None,
+            redaction_policy: None,
+            request_decorators: Vec::new(),
+            endpoint_auth: None,
+            fallback_classifier: None,
+            redirect_policy: RedirectPolicy::default(),
+            tls_policy: TlsPolicy::default(),
+            http_client_configurator: None,
         })
     }
 
+    /// Sets the maximum number of bytes accepted in a response body.
+    ///
+    /// The body is read in chunks and the request is aborted with
+    /// `TokenInfoErrorKind::ResponseTooLarge` once the limit is exceeded.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.settings.max_response_size = max_response_size;
+        self
+    }
+
+    /// Sends `Accept-Encoding: gzip, deflate` with each request if enabled.
+    ///
+    /// Compressed responses are always decoded before they are parsed.
+    pub fn with_gzip_requested(mut self, enabled: bool) -> Self {
+        self.settings.request_gzip = enabled;
+        self
+    }
+
+    /// Sends the access token as `Authorization: Bearer <token>` to the
+    /// endpoint as given if enabled. The query parameter is ignored then.
+    pub fn with_token_in_authorization_header(mut self, enabled: bool) -> Self {
+        self.settings.token_in_authorization_header = enabled;
+        self
+    }
+
+    /// Sends the access token in a form with a `POST` to the endpoint as
+    /// given if enabled. This takes precedence over
+    /// `with_token_in_authorization_header`.
+    pub fn with_post_introspection(mut self, enabled: bool) -> Self {
+        self.settings.post_introspection = enabled;
+        self
+    }
+
+    /// Lets concurrent introspections of the same access token share a
+    /// single request if enabled.
+    ///
+    /// Callers waiting for the shared request get an error of the same
+    /// kind if it fails.
+    pub fn with_request_coalescing(mut self, enabled: bool) -> Self {
+        self.single_flight = if enabled {
+            Some(Arc::new(AsyncSingleFlight::default()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Logs each request with level `debug` using the default
+    /// `RedactionPolicy` if enabled.
+    pub fn with_request_logging(mut self, enabled: bool) -> Self {
+        self.redaction_policy = if enabled {
+            Some(Arc::new(RedactionPolicy::default()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Logs each request with level `debug` with everything `policy`
+    /// considers secret redacted.
+    pub fn with_redaction_policy(mut self, policy: RedactionPolicy) -> Self {
+        self.redaction_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Adds a `RequestDecorator` applied to every request. Decorators are
+    /// applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+
+    pub(crate) fn with_request_decorators(mut self, decorators: RequestDecorators) -> Self {
+        self.request_decorators.extend(decorators);
+        self
+    }
+
+    pub(crate) fn with_endpoint_auth(mut self, endpoint_auth: Option<EndpointAuth>) -> Self {
+        self.endpoint_auth = endpoint_auth;
+        self
+    }
+
+    /// Sets the `FallbackClassifier` deciding whether the fallback endpoint
+    /// is asked after the primary endpoint failed. The default is the
+    /// `DefaultFallbackClassifier`.
+    pub fn with_fallback_classifier<C>(mut self, classifier: C) -> Self
+    where
+        C: FallbackClassifier + Send + Sync + 'static,
+    {
+        self.fallback_classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Sets how the retries are spread over the primary and the fallback
+    /// endpoint. The default is `FallbackStrategy::PrimaryThenFallback`.
+    pub fn with_fallback_strategy(mut self, strategy: FallbackStrategy) -> Self {
+        self.settings.fallback_strategy = strategy;
+        self
+    }
+
+    /// Sets how much of a response body is put into the errors of failed
+    /// introspections. The default keeps the first
+    /// `DEFAULT_CAPTURED_BODY_LENGTH` bytes.
+    pub fn with_body_capture(mut self, body_capture: BodyCapture) -> Self {
+        self.settings.body_capture = body_capture;
+        self
+    }
+
+    pub(crate) fn with_fallback_classifier_opt(
+        mut self,
+        classifier: Option<SharedFallbackClassifier>,
+    ) -> Self {
+        self.fallback_classifier = classifier;
+        self
+    }
+
+    /// Sets the `RedirectPolicy` of the HTTP clients created by
+    /// `with_default_client`. The default follows `DEFAULT_MAX_REDIRECTS`
+    /// redirects within the origin of an endpoint.
+    pub fn with_redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self
+    }
+
+    /// Sets the `TlsPolicy` of the HTTP clients created by
+    /// `with_default_client`. It is applied before the configurator set
+    /// with `with_http_client_configurator`.
+    pub fn with_tls_policy(mut self, tls_policy: TlsPolicy) -> Self {
+        self.tls_policy = tls_policy;
+        self
+    }
+
+    /// Lets `configure` adjust the `ClientBuilder` of the HTTP clients
+    /// created by `with_default_client`, e.g. to set timeouts, proxies or
+    /// root certificates required to reach the introspection endpoint.
+    ///
+    /// Clients passed to `with_client` are used as they are.
+    pub fn with_http_client_configurator<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(ClientBuilder) -> ClientBuilder + Send + Sync + 'static,
+    {
+        self.http_client_configurator = Some(Arc::new(configure));
+        self
+    }
+
+    pub(crate) fn with_http_client_configurator_opt(
+        mut self,
+        configurator: Option<HttpClientConfigurator>,
+    ) -> Self {
+        self.http_client_configurator = configurator;
+        self
+    }
+
+    fn prepare(
+        &self,
+        credential: &Credential,
+        context: Option<&IntrospectionContext>,
+    ) -> TokenInfoResult<PreparedRequests> {
+        let prepare = |url_prefix: &str, endpoint_url: &str| {
+            prepare_request(
+                credential,
+                url_prefix,
+                endpoint_url,
+                self.settings,
+                self.redaction_policy.as_ref(),
+                &self.request_decorators,
+                self.endpoint_auth.as_ref(),
+                context,
+            )
+        };
+        let primary = prepare(&self.url_prefix, &self.endpoint_url)?;
+        let fallback = match (&self.fallback_url_prefix, &self.fallback_endpoint_url) {
+            (Some(url_prefix), Some(endpoint_url)) => Some(prepare(url_prefix, endpoint_url)?),
+            _ => None,
+        };
+        Ok(PreparedRequests { primary, fallback })
+    }
+
     /// Creates an `AsyncTokenInfoService` with the given HttpClient
     pub fn with_client(
         &self,
@@ -299,71 +816,101 @@ where
         P: Clone,
         M: Clone,
     {
-        AsyncTokenInfoServiceClient::create(
+        AsyncTokenInfoServiceClient {
+            url_prefix: self.url_prefix.clone(),
+            fallback_url_prefix: self.fallback_url_prefix.clone(),
+            endpoint_url: self.endpoint_url.clone(),
+            fallback_endpoint_url: self.fallback_endpoint_url.clone(),
+            parser: self.parser.clone(),
+            metrics_collector: self.metrics_collector.clone(),
             http_client,
-            self.url_prefix.clone(),
-            self.fallback_url_prefix.clone(),
-            self.parser.clone(),
-            self.metrics_collector.clone(),
-        )
+            settings: self.settings,
+            single_flight: self.single_flight.clone(),
+            redaction_policy: self.redaction_policy.clone(),
+            request_decorators: self.request_decorators.clone(),
+            endpoint_auth: self.endpoint_auth.clone(),
+            fallback_classifier: self.fallback_classifier.clone(),
+        }
     }
 
-    /// Creates an `AsyncTokenInfoService` with a default client using
-    /// the given number of threads to do DNS resolving
+    /// Creates an `AsyncTokenInfoService` with a default client adjusted
+    /// by the configurator set with `with_http_client_configurator`.
     pub fn with_default_client(&self) -> InitializationResult<AsyncTokenInfoServiceClient<P, M>>
     where
         P: Clone,
         M: Clone,
     {
-        let http_client = default_http_client()?;
+        let builder = Client::builder().redirect(self.redirect_policy.to_reqwest_policy());
+        let builder = self.tls_policy.configure(builder)?;
+        let builder = match self.http_client_configurator {
+            Some(ref configure) => configure(builder),
+            None => builder,
+        };
+        let http_client = builder
+            .build()
+            .map_err(|err| InitializationError::Other(err.to_string()))?;
 
         Ok(self.with_client(http_client))
     }
 }
 
-/// Creates a default HTTPS client
+/// Creates a default HTTPS client following the default `RedirectPolicy`
 pub fn default_http_client() -> Result<HttpClient, InitializationError> {
     Client::builder()
+        .redirect(RedirectPolicy::default().to_reqwest_policy())
         .build()
-        .map_err(|err| InitializationError(err.to_string()))
+        .map_err(|err| InitializationError::Other(err.to_string()))
 }
 
-impl<P, M> AsyncTokenInfoServiceLight for AsyncTokenInfoServiceClientLight<P, M>
+impl<P, M> AsyncTokenInfoServiceClientLight<P, M>
 where
     P: TokenInfoParser + Send + Sync,
     M: MetricsCollector + Send + Sync,
 {
-    fn introspect<'a>(
+    /// Introspects the `credential` and lets concurrent introspections of the
+    /// same `AccessToken` without a `context` share a request if coalescing
+    /// is enabled.
+    fn run<'a>(
         &'a self,
-        token: &'a AccessToken,
         http_client: &'a Client,
+        credential: &Credential,
+        budget: Option<Duration>,
+        context: Option<&'a IntrospectionContext>,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
-        let start = Instant::now();
-        self.metrics_collector.incoming_introspection_request();
-
-        async move {
-            let result = execute_once(
+        let request = self.prepare(credential, context);
+        let introspect = move || {
+            introspect(
                 http_client,
-                token,
-                &self.url_prefix,
+                request,
                 &self.parser,
                 &self.metrics_collector,
-            ).await;
-
-            match result {
-                Ok(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_success(start)
-                }
-                Err(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_failure(start)
-                }
+                self.settings.max_response_size,
+                budget,
+                context,
+                self.fallback_classifier.as_deref(),
+                self.settings.fallback_strategy,
+            )
+        };
+        match (self.single_flight.as_deref(), credential, context) {
+            (Some(single_flight), Credential::Bearer(token), None) => {
+                single_flight.run(token, budget.is_some(), introspect)
             }
-
-            result
+            _ => introspect(),
         }
-        .boxed()
+    }
+}
+
+impl<P, M> AsyncTokenInfoServiceLight for AsyncTokenInfoServiceClientLight<P, M>
+where
+    P: TokenInfoParser + Send + Sync,
+    M: MetricsCollector + Send + Sync,
+{
+    fn introspect<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(http_client, &Credential::Bearer(token.clone()), None, None)
     }
 
     fn introspect_with_retry<'a>(
@@ -372,88 +919,476 @@ where
         budget: Duration,
         http_client: &'a Client,
     ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
-        let start = Instant::now();
-        self.metrics_collector.incoming_introspection_request();
+        self.run(http_client, &Credential::Bearer(token.clone()), Some(budget), None)
+    }
 
-        async move {
-            let result = execute_with_retry(
-                http_client,
-                token,
-                &self.url_prefix,
-                &self.parser,
-                budget,
-                &self.metrics_collector,
-            ).await;
+    fn introspect_with_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        context: &'a IntrospectionContext,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        let credential = Credential::Bearer(token.clone());
+        self.run(http_client, &credential, None, Some(context))
+    }
 
-            match result {
-                Ok(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_success(start)
-                }
-                Err(_) => {
-                    self.metrics_collector.introspection_request(start);
-                    self.metrics_collector.introspection_request_failure(start)
+    fn introspect_with_retry_and_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+        context: &'a IntrospectionContext,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        let credential = Credential::Bearer(token.clone());
+        self.run(http_client, &credential, Some(budget), Some(context))
+    }
+
+    fn introspect_credential<'a>(
+        &'a self,
+        credential: &'a Credential,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(http_client, credential, None, None)
+    }
+
+    fn introspect_credential_with_retry<'a>(
+        &'a self,
+        credential: &'a Credential,
+        budget: Duration,
+        http_client: &'a Client,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.run(http_client, credential, Some(budget), None)
+    }
+}
+
+/// Executes the prepared requests once or with retries if a `budget` is
+/// given and tracks the metrics of the whole introspection.
+///
+/// The fallback request is only sent if the `fallback_classifier` lets
+/// the error of the primary one through. The retries are spread over both
+/// requests according to the `fallback_strategy`.
+#[allow(clippy::too_many_arguments)]
+fn introspect<'a, P, M>(
+    http_client: &'a Client,
+    requests: TokenInfoResult<PreparedRequests>,
+    parser: &'a P,
+    metrics_collector: &'a M,
+    max_response_size: usize,
+    budget: Option<Duration>,
+    context: Option<&'a IntrospectionContext>,
+    fallback_classifier: Option<&'a (dyn FallbackClassifier + Send + Sync)>,
+    fallback_strategy: FallbackStrategy,
+) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>
+where
+    P: TokenInfoParser + Send + Sync,
+    M: MetricsCollector + Send + Sync,
+{
+    let start = Instant::now();
+    let deadline = context.and_then(|context| context.deadline);
+    metrics_collector.incoming_introspection_request();
+
+    async move {
+        let result = match (requests, budget) {
+            (
+                Ok(PreparedRequests {
+                    primary,
+                    fallback: Some(fallback),
+                }),
+                Some(budget),
+            ) if fallback_strategy == FallbackStrategy::Interleave => {
+                execute_interleaved(
+                    http_client,
+                    [&primary, &fallback],
+                    parser,
+                    metrics_collector,
+                    max_response_size,
+                    budget,
+                    deadline,
+                    fallback_classifier,
+                )
+                .await
+            }
+            (Ok(requests), budget) => {
+                let primary = execute(
+                    http_client,
+                    &requests.primary,
+                    parser,
+                    metrics_collector,
+                    max_response_size,
+                    budget.map(|budget| fallback_strategy.primary_budget(budget)),
+                    deadline,
+                )
+                .await;
+                match (primary, &requests.fallback) {
+                    (Err(ref err), Some(fallback))
+                        if should_use_fallback(fallback_classifier, err) =>
+                    {
+                        // A spent budget still leaves the fallback one attempt.
+                        let elapsed = start.elapsed();
+                        let fallback_budget = budget
+                            .map(|budget| fallback_strategy.fallback_budget(budget, elapsed))
+                            .filter(|budget| *budget > Duration::from_secs(0));
+                        execute(
+                            http_client,
+                            fallback,
+                            parser,
+                            metrics_collector,
+                            max_response_size,
+                            fallback_budget,
+                            deadline,
+                        )
+                        .await
+                    }
+                    (result, _) => result,
                 }
             }
+            (Err(err), _) => Err(err),
+        };
+
+        track_introspection(metrics_collector, start, result.is_ok());
+        if let (Err(ref err), Some(context)) = (&result, context) {
+            metrics_collector.introspection_request_failure_with_context(start, context);
+            info!("Token introspection failed ({}): {}", context, err);
+        }
+
+        result
+    }
+    .boxed()
+}
+
+/// Alternates between the primary and the fallback request in `requests`
+/// with each attempt until one succeeds or the `budget` is spent.
+#[allow(clippy::too_many_arguments)]
+async fn execute_interleaved<'a, P, M>(
+    http_client: &'a Client,
+    requests: [&'a IntrospectionRequest; 2],
+    parser: &'a P,
+    metrics_collector: &'a M,
+    max_response_size: usize,
+    budget: Duration,
+    caller_deadline: Option<Instant>,
+    fallback_classifier: Option<&'a (dyn FallbackClassifier + Send + Sync)>,
+) -> Result<TokenInfo, TokenInfoError>
+where
+    P: TokenInfoParser + Send + Sync,
+    M: MetricsCollector + Send + Sync,
+{
+    let start = Instant::now();
+    let budget = match caller_deadline {
+        Some(deadline) => budget.min(deadline.saturating_duration_since(start)),
+        None => budget,
+    };
+    let mut backoff = retry_backoff();
+    backoff.max_elapsed_time = Some(budget);
+    let mut on_fallback = false;
+
+    loop {
+        let request = requests[on_fallback as usize];
+        let err = match execute_once(
+            http_client,
+            request,
+            parser,
+            metrics_collector,
+            max_response_size,
+            caller_deadline,
+        )
+        .await
+        {
+            Ok(token_info) => return Ok(token_info),
+            Err(err) => err,
+        };
+        let remaining = budget.checked_sub(start.elapsed()).unwrap_or_default();
+        let (next_on_fallback, min_wait) =
+            match next_interleaved_attempt(on_fallback, fallback_classifier, &err, remaining) {
+                Some(next) => next,
+                None => return Err(err),
+            };
+        match backoff.next_backoff() {
+            Some(wait) => {
+                warn!("Retry on token info service: {}", err);
+                tokio::time::delay_for(wait.max(min_wait)).await;
+            }
+            None => return Err(err),
+        }
+        on_fallback = next_on_fallback;
+    }
+}
+
+/// Executes a request once or with retries if a `budget` is given.
+///
+/// The `deadline` of the caller, if any, cuts the retries short and limits
+/// the timeout of each request.
+fn execute<'a, P, M>(
+    http_client: &'a Client,
+    request: &'a IntrospectionRequest,
+    parser: &'a P,
+    metrics_collector: &'a M,
+    max_response_size: usize,
+    budget: Option<Duration>,
+    deadline: Option<Instant>,
+) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>
+where
+    P: TokenInfoParser + Send + Sync,
+    M: MetricsCollector + Send + Sync,
+{
+    match budget {
+        Some(budget) => execute_with_retry(
+            http_client,
+            request,
+            parser,
+            budget,
+            deadline,
+            metrics_collector,
+            max_response_size,
+        )
+        .boxed(),
+        None => execute_once(
+            http_client,
+            request,
+            parser,
+            metrics_collector,
+            max_response_size,
+            deadline,
+        )
+        .boxed(),
+    }
+}
+
+/// Reads the body chunk by chunk and aborts as soon as more than
+/// `max_response_size` bytes were received.
+async fn read_body_limited(
+    mut response: Response,
+    max_response_size: usize,
+) -> Result<Bytes, TokenInfoErrorKind> {
+    if let Some(content_length) = response.content_length() {
+        if content_length > max_response_size as u64 {
+            return Err(TokenInfoErrorKind::ResponseTooLarge(max_response_size));
+        }
+    }
 
-            result
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| TokenInfoErrorKind::Io(format!("Could not get body chunks: {}", err)))?
+    {
+        if len + chunk.len() > max_response_size {
+            return Err(TokenInfoErrorKind::ResponseTooLarge(max_response_size));
         }
-        .boxed()
+        len += chunk.len();
+        chunks.push(chunk);
     }
+    Ok(join_chunks(chunks, len))
 }
 
-fn process_response<P>(
+/// Joins the `len` bytes of the `chunks` of a body. A body received in a
+/// single chunk is passed on without being copied.
+fn join_chunks(mut chunks: Vec<Bytes>, len: usize) -> Bytes {
+    if chunks.len() <= 1 {
+        return chunks.pop().unwrap_or_default();
+    }
+    let mut body = BytesMut::with_capacity(len);
+    for chunk in chunks {
+        body.extend_from_slice(&chunk);
+    }
+    body.freeze()
+}
+
+fn process_response<'a, P>(
     response: Response,
-    parser: &'_ P,
-) -> BoxFuture<'_, Result<TokenInfo, TokenInfoError>>
+    parser: &'a P,
+    max_response_size: usize,
+    body_capture: BodyCapture,
+    request_log: Option<(&'a RequestLog, Instant)>,
+) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>
 where
     P: TokenInfoParser + Send + Sync,
 {
     let status = response.status();
+    let headers = response.headers().clone();
 
     async move {
-        let body = response.bytes().await
-            .map_err(|err| TokenInfoErrorKind::Io(format!("Could not get body chunks: {}", err)))?;
-
-        if status == StatusCode::OK {
-            match parser.parse(&body) {
-                Ok(info) => Ok(info),
-                Err(err) => {
-                    let msg: String = String::from_utf8_lossy(&body).into();
-                    Err(TokenInfoErrorKind::InvalidResponseContent(format!(
-                        "{}: {}",
-                        err, msg
-                    )))
-                }
-            }
-        } else if status == StatusCode::UNAUTHORIZED {
-            let msg = String::from_utf8_lossy(&body);
-            Err(TokenInfoErrorKind::NotAuthenticated(format!(
-                "The server refused the token: {}",
-                msg
-            )))
-        } else if status.is_client_error() {
-            let msg = String::from_utf8_lossy(&body).into();
-            Err(TokenInfoErrorKind::Client(msg))
-        } else if status.is_server_error() {
-            let msg = String::from_utf8_lossy(&body).into();
-            Err(TokenInfoErrorKind::Server(msg))
-        } else {
-            let msg = String::from_utf8_lossy(&body).into();
-            Err(TokenInfoErrorKind::Other(msg))
-        }
-        .map_err(Into::into)
+        let body = read_body_limited(response, max_response_size)
+            .await
+            .map_err(|err| log_failure(request_log, Some(status), err.into()))?;
+        decode_and_evaluate(
+            status,
+            &headers,
+            body,
+            parser,
+            max_response_size,
+            body_capture,
+            request_log,
+        )
     }
     .boxed()
 }
 
+/// Settings applied to every introspection request
+#[derive(Debug, Clone, Copy)]
+struct RequestSettings {
+    max_response_size: usize,
+    request_gzip: bool,
+    token_in_authorization_header: bool,
+    post_introspection: bool,
+    fallback_strategy: FallbackStrategy,
+    body_capture: BodyCapture,
+}
+
+impl Default for RequestSettings {
+    fn default() -> Self {
+        RequestSettings {
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            request_gzip: false,
+            token_in_authorization_header: false,
+            post_introspection: false,
+            fallback_strategy: FallbackStrategy::default(),
+            body_capture: BodyCapture::default(),
+        }
+    }
+}
+
+/// The requests to the primary and the fallback endpoint
+struct PreparedRequests {
+    primary: IntrospectionRequest,
+    fallback: Option<IntrospectionRequest>,
+}
+
+/// A request to the introspection service ready to be sent
+#[derive(Clone)]
+struct IntrospectionRequest {
+    url: Url,
+    headers: HeaderMap,
+    /// The form sent with a `POST` instead of a `GET`, if any. Shared by
+    /// all attempts.
+    body: Option<Bytes>,
+    log: Option<RequestLog>,
+    decorators: RequestDecorators,
+    body_capture: BodyCapture,
+}
+
+impl IntrospectionRequest {
+    fn method(&self) -> Method {
+        request_method(self.body.is_some())
+    }
+}
+
+/// Puts a `Credential::Bearer` into the URL unless it has to be sent in
+/// a form or in the `Authorization` header. All other credentials are
+/// always sent in the header.
+///
+/// The request is logged if a `redaction_policy` is given. The `decorators`
+/// are applied to each attempt. The `endpoint_auth` is sent in the
+/// `Authorization` header and the `context` is sent along if given.
+#[allow(clippy::too_many_arguments)]
+fn prepare_request(
+    credential: &Credential,
+    url_prefix: &str,
+    endpoint_url: &str,
+    settings: RequestSettings,
+    redaction_policy: Option<&Arc<RedactionPolicy>>,
+    decorators: &RequestDecorators,
+    endpoint_auth: Option<&EndpointAuth>,
+    context: Option<&IntrospectionContext>,
+) -> TokenInfoResult<IntrospectionRequest> {
+    let mut authorization = None;
+    let mut body = None;
+    let (url, token_in_url) = match *credential {
+        Credential::Bearer(ref token) if settings.post_introspection => {
+            body = Some(Bytes::from(introspection_form(token)));
+            (endpoint_url.parse()?, None)
+        }
+        Credential::Bearer(ref token) if !settings.token_in_authorization_header => {
+            (complete_url(url_prefix, token)?, Some(token))
+        }
+        _ if endpoint_auth.is_some() => return Err(conflicting_authorization()),
+        _ => {
+            authorization = Some(authorization_header(credential)?);
+            (endpoint_url.parse()?, None)
+        }
+    };
+    if let Some(endpoint_auth) = endpoint_auth {
+        authorization = Some(endpoint_auth.authorization()?);
+    }
+    let headers = request_headers(
+        authorization,
+        settings.request_gzip,
+        context,
+        body.is_some(),
+    );
+    let method = request_method(body.is_some());
+    let log = redaction_policy
+        .map(|policy| RequestLog::new(policy, &method, &url, token_in_url).with_context(context));
+    Ok(IntrospectionRequest {
+        url,
+        headers,
+        body,
+        log,
+        decorators: decorators.clone(),
+        body_capture: settings.body_capture,
+    })
+}
+
+/// Requests a `TokenInfo` via a Unix domain socket.
+///
+/// The socket is used in a blocking fashion on the blocking thread pool of
+/// the runtime so that the executor is not blocked.
+#[allow(clippy::too_many_arguments)]
+async fn execute_on_unix_socket<P>(
+    method: Method,
+    uri: Url,
+    headers: HeaderMap,
+    body: Option<Bytes>,
+    parser: &P,
+    max_response_size: usize,
+    body_capture: BodyCapture,
+    request_log: Option<(&RequestLog, Instant)>,
+    timeout: Duration,
+) -> Result<TokenInfo, TokenInfoError>
+where
+    P: TokenInfoParser + Send + Sync,
+{
+    let response = match tokio::task::spawn_blocking(move || {
+        unix_socket::send(
+            method,
+            &uri,
+            &headers,
+            body.as_deref(),
+            max_response_size,
+            timeout,
+        )
+    })
+    .await
+    {
+        Ok(response) => response.map_err(TokenInfoError::from),
+        Err(err) => Err(TokenInfoErrorKind::Connection(format!(
+            "Using the socket failed: {}",
+            err
+        ))
+        .into()),
+    }
+    .map_err(|err| log_failure(request_log, None, err))?;
+
+    decode_and_evaluate(
+        response.status,
+        &response.headers,
+        Bytes::from(response.body),
+        parser,
+        max_response_size,
+        body_capture,
+        request_log,
+    )
+}
+
 fn execute_with_retry<'a, M, P>(
     http_client: &'a Client,
-    token: &'a AccessToken,
-    url_prefix: &'a str,
+    request: &'a IntrospectionRequest,
     parser: &'a P,
     budget: Duration,
+    caller_deadline: Option<Instant>,
     metrics_collector: &'a M,
+    max_response_size: usize,
 ) -> impl Future<Output = Result<TokenInfo, TokenInfoError>> + Send + 'a
 where
     P: TokenInfoParser + Send + Sync,
@@ -465,97 +1400,306 @@ where
         ).boxed();
     }
 
-    let deadline = Instant::now() + budget;
-
-    let mut backoff = backoff::ExponentialBackoff::default();
-    backoff.max_elapsed_time = Some(Duration::from_millis(200));
-    backoff.initial_interval = Duration::from_millis(10);
-    backoff.multiplier = 1.5;
-
-    let mut attempt = 1;
+    let deadline = match caller_deadline {
+        Some(caller_deadline) => caller_deadline.min(Instant::now() + budget),
+        None => Instant::now() + budget,
+    };
 
-    let action = move || {
-        let execution_result = execute_once(
-            http_client,
-            token,
-            url_prefix,
-            parser,
-            metrics_collector,
-        );
+    let mut backoff = retry_backoff();
+    backoff.max_elapsed_time = Some(budget);
 
-        async move {
+    async move {
+        let mut attempt = 1;
+        loop {
             let result = if Instant::now() <= deadline {
-                execution_result.await
+                execute_once(
+                    http_client,
+                    request,
+                    parser,
+                    metrics_collector,
+                    max_response_size,
+                    caller_deadline,
+                )
+                .await
             } else {
                 Err(TokenInfoErrorKind::BudgetExceeded.into())
             };
 
-            result.map_err(|err| {
-                warn!(
-                    "Attempt({}) on token introspection service. Reason: {}",
-                    attempt, err
-                );
-                attempt += 1;
-
-                if Instant::now() <= deadline && err.is_retry_suggested() {
-                    backoff::Error::Transient(err)
-                } else {
-                    backoff::Error::Permanent(err)
-                }
-            })
-        }
-    };
+            let err = match result {
+                Ok(token_info) => return Ok(token_info),
+                Err(err) => err,
+            };
+            warn!(
+                "Attempt({}) on token introspection service. Reason: {}",
+                attempt, err
+            );
+            attempt += 1;
 
-    async move {
-        action.with_backoff(&mut backoff).await.map_err(|err| match err {
-            backoff::Error::Transient(err) => err,
-            backoff::Error::Permanent(err) => err,
-        })
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if is_retryable(&err) => remaining,
+                _ => return Err(err),
+            };
+            match next_retry_delay(&mut backoff, &err, remaining) {
+                Some(delay) => tokio::time::delay_for(delay).await,
+                None => return Err(err),
+            }
+        }
     }
     .boxed()
 }
 
 fn execute_once<'a, P, M>(
     client: &'a Client,
-    token: &'a AccessToken,
-    url_prefix: &str,
+    request: &IntrospectionRequest,
     parser: &'a P,
     metrics_collector: &'a M,
+    max_response_size: usize,
+    deadline: Option<Instant>,
 ) -> impl Future<Output = Result<TokenInfo, TokenInfoError>> + Send + 'a
 where
     P: TokenInfoParser + Send + Sync,
     M: MetricsCollector + Send + Sync,
 {
     let start = Instant::now();
-    let uri = complete_url(url_prefix, &token);
+    let method = request.method();
+    let IntrospectionRequest {
+        url,
+        mut headers,
+        body,
+        log,
+        decorators,
+        body_capture,
+    } = request.clone();
+    decorate(&decorators, &method, &url, &mut headers, body.as_deref());
 
     async move {
-        let uri = uri?;
+        let timeout = timeout_until(deadline)?;
+        let request_log = log.as_ref().map(|log| (log, start));
+        if url.scheme() == unix_socket::UNIX_SCHEME {
+            let result = execute_on_unix_socket(
+                method,
+                url,
+                headers,
+                body,
+                parser,
+                max_response_size,
+                body_capture,
+                request_log,
+                timeout.unwrap_or(unix_socket::SOCKET_TIMEOUT),
+            )
+            .await;
+            track_service_call(metrics_collector, start, result.is_ok());
+            return result;
+        }
 
-        match client.get(uri).send().await {
+        let request = client.request(method, url).headers(headers);
+        let request = match body {
+            Some(body) => request.body(body),
+            None => request,
+        };
+        let request = match timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        };
+        match request.send().await {
             Ok(response) => {
-                metrics_collector.introspection_service_call(start);
-                metrics_collector.introspection_service_call_success(start);
-                process_response(response, parser).await
+                track_service_call(metrics_collector, start, true);
+                process_response(
+                    response,
+                    parser,
+                    max_response_size,
+                    body_capture,
+                    request_log,
+                )
+                .await
             }
             Err(err) => {
-                metrics_collector.introspection_service_call(start);
-                metrics_collector.introspection_service_call_failure(start);
-                Err(err.into())
+                track_service_call(metrics_collector, start, false);
+                Err(log_failure(request_log, None, send_error(&err)))
             }
         }
     }
 }
 
-fn complete_url(url_prefix: &str, token: &AccessToken) -> TokenInfoResult<Url> {
-    let mut url_str = url_prefix.to_string();
-    url_str.push_str(token.0.as_ref());
-    let url = url_str.parse()?;
-    Ok(url)
-}
-
 impl From<reqwest::Error> for TokenInfoError {
     fn from(err: reqwest::Error) -> Self {
         TokenInfoErrorKind::Other(err.to_string()).into()
     }
 }
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::client::TokenInfoServiceClient;
+    use crate::TokenInfoService;
+
+    const VALID_BODY: &str = r#"{"uid":"user","scope":[],"expires_in":10}"#;
+
+    /// Answers every request on a Unix domain socket with `status` and
+    /// `body`.
+    fn serve(name: &str, status: &'static str, body: &'static [u8]) -> PathBuf {
+        let socket_path = std::env::temp_dir().join(format!(
+            "tokkit-conformance-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let stream = reader.get_mut();
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+
+        socket_path
+    }
+
+    fn endpoint(socket_path: &Path) -> String {
+        format!("unix://{}:/introspect", socket_path.display())
+    }
+
+    /// Introspects with the blocking and the async client and describes
+    /// the outcome so that both can be compared.
+    fn introspect_with_both(
+        endpoint: &str,
+        fallback_endpoint: Option<&str>,
+    ) -> (String, String) {
+        let describe = |result: TokenInfoResult<TokenInfo>| match result {
+            Ok(token_info) => format!("{:?}", token_info),
+            Err(err) => format!("{:?}", err.kind()),
+        };
+        let token = AccessToken::new("token");
+
+        let client = TokenInfoServiceClient::new(
+            endpoint,
+            Some("access_token"),
+            fallback_endpoint,
+            PlanBTokenInfoParser,
+        )
+        .unwrap();
+        let sync_result = describe(client.introspect(&token));
+
+        let client = AsyncTokenInfoServiceClientLight::new(
+            endpoint,
+            Some("access_token"),
+            fallback_endpoint,
+            PlanBTokenInfoParser,
+        )
+        .unwrap();
+        let http_client = Client::new();
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let async_result = describe(runtime.block_on(AsyncTokenInfoServiceLight::introspect(
+            &client,
+            &token,
+            &http_client,
+        )));
+
+        (sync_result, async_result)
+    }
+
+    #[test]
+    fn both_clients_interpret_responses_alike() {
+        let cases: &[(&str, &str, &[u8])] = &[
+            ("ok", "200 OK", VALID_BODY.as_bytes()),
+            ("invalid", "200 OK", b"{"),
+            ("unauthorized", "401 Unauthorized", b"nope"),
+            ("forbidden", "403 Forbidden", b"forbidden"),
+            ("server", "500 Internal Server Error", b"down"),
+            ("latin1", "500 Internal Server Error", &[0x64, 0xf6, 0x77, 0x6e]),
+            ("redirect", "302 Found", b""),
+        ];
+
+        for &(name, status, body) in cases {
+            let socket_path = serve(name, status, body);
+            let (sync_result, async_result) = introspect_with_both(&endpoint(&socket_path), None);
+            let _ = std::fs::remove_file(&socket_path);
+            assert_eq!(sync_result, async_result, "case '{}'", name);
+        }
+    }
+
+    #[test]
+    fn both_clients_ask_the_fallback_after_server_errors() {
+        let primary = serve("primary", "503 Service Unavailable", b"down");
+        let fallback = serve("fallback", "200 OK", VALID_BODY.as_bytes());
+
+        let (sync_result, async_result) =
+            introspect_with_both(&endpoint(&primary), Some(&endpoint(&fallback)));
+        let _ = std::fs::remove_file(&primary);
+        let _ = std::fs::remove_file(&fallback);
+
+        assert!(sync_result.contains("user"), "{}", sync_result);
+        assert_eq!(sync_result, async_result);
+    }
+
+    #[test]
+    fn both_clients_do_not_ask_the_fallback_after_client_errors() {
+        let primary = serve("refusing", "400 Bad Request", b"bad");
+        let fallback = serve("unused", "200 OK", VALID_BODY.as_bytes());
+
+        let (sync_result, async_result) =
+            introspect_with_both(&endpoint(&primary), Some(&endpoint(&fallback)));
+        let _ = std::fs::remove_file(&primary);
+        let _ = std::fs::remove_file(&fallback);
+
+        assert_eq!(sync_result, "Client(\"bad\")");
+        assert_eq!(sync_result, async_result);
+    }
+    #[test]
+    fn default_clients_are_adjusted_by_the_configurator() {
+        let configured = Arc::new(AtomicUsize::new(0));
+        let counter = configured.clone();
+        let light = AsyncTokenInfoServiceClientLight::new(
+            "https://example.com/tokeninfo",
+            None,
+            None,
+            PlanBTokenInfoParser,
+        )
+        .unwrap()
+        .with_http_client_configurator(move |builder| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            builder.timeout(Duration::from_secs(1))
+        });
+
+        light.with_default_client().unwrap();
+        light.with_default_client().unwrap();
+        assert_eq!(configured.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn single_chunks_are_not_copied() {
+        let chunk = Bytes::from_static(VALID_BODY.as_bytes());
+        let body = join_chunks(vec![chunk.clone()], chunk.len());
+        assert_eq!(body.as_ptr(), chunk.as_ptr());
+
+        let chunks = vec![chunk.slice(..10), chunk.slice(10..)];
+        assert_eq!(join_chunks(chunks, chunk.len()), chunk);
+        assert!(join_chunks(Vec::new(), 0).is_empty());
+    }
+}
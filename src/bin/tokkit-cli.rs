@@ -0,0 +1,245 @@
+//! A command line tool to debug the configuration of tokkit.
+//!
+//! Everything is configured with the same environment variables a service
+//! would use. Logs are written according to `RUST_LOG`.
+//!
+//! ```text
+//! tokkit-cli fetch <provider> [--reveal] [scope...]
+//! tokkit-cli introspect <parser> <token>
+//! tokkit-cli watch <provider> [--interval <secs>] [scope...]
+//! ```
+//!
+//! Providers: `ropc`, `cognito`, `azure`, `gce`, `kubernetes` and with the
+//! respective features `google` and `spiffe`.
+//!
+//! Parsers: `plan-b`, `google-v3`, `amazon` and `cognito`.
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use tokkit::client::TokenInfoServiceClientBuilder;
+use tokkit::parsers::*;
+use tokkit::token_manager::token_provider::credentials::SplitFileCredentialsProvider;
+use tokkit::token_manager::token_provider::*;
+use tokkit::token_manager::*;
+use tokkit::*;
+
+const USAGE: &str = "\
+Usage:
+    tokkit-cli fetch <provider> [--reveal] [scope...]
+    tokkit-cli introspect <parser> <token>
+    tokkit-cli watch <provider> [--interval <secs>] [scope...]
+
+Providers: ropc, cognito, azure, gce, kubernetes, google, spiffe
+Parsers:   plan-b, google-v3, amazon, cognito";
+
+/// The id of the token managed by `watch`.
+const WATCHED_TOKEN: &str = "tokkit-cli";
+
+fn main() {
+    let _ = env_logger::try_init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("fetch") => fetch(&args[1..]),
+        Some("introspect") => introspect(&args[1..]),
+        Some("watch") => watch(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn fetch(args: &[String]) -> Result<(), String> {
+    let (provider, rest) = split_provider(args)?;
+    let mut reveal = false;
+    let mut scopes = Vec::new();
+    for arg in rest {
+        match arg.as_str() {
+            "--reveal" => reveal = true,
+            scope => scopes.push(Scope::new(scope)),
+        }
+    }
+
+    let rsp = provider
+        .request_access_token(&scopes)
+        .map_err(|err| format!("Could not fetch a token: {}", err))?;
+
+    println!("access_token:  {}", show_token(&rsp.access_token, reveal));
+    println!("expires_in:    {}s", rsp.expires_in.as_secs());
+    println!(
+        "token_type:    {}",
+        rsp.token_type.as_deref().unwrap_or("<not sent>")
+    );
+    match rsp.scopes {
+        Some(scopes) => println!("scope:         {}", ScopeList::from(scopes)),
+        None => println!("scope:         <not sent>"),
+    }
+    println!(
+        "refresh_token: {}",
+        if rsp.refresh_token.is_some() {
+            "<present>"
+        } else {
+            "<not sent>"
+        }
+    );
+    Ok(())
+}
+
+fn introspect(args: &[String]) -> Result<(), String> {
+    let token = match args {
+        [_, token] => AccessToken::new(token.as_str()),
+        _ => return Err(USAGE.to_string()),
+    };
+    let token_info = match args[0].as_str() {
+        "plan-b" => introspect_with(PlanBTokenInfoParser, &token)?,
+        "google-v3" => introspect_with(GoogleV3TokenInfoParser, &token)?,
+        "amazon" => introspect_with(AmazonTokenInfoParser, &token)?,
+        "cognito" => introspect_with(CognitoTokenInfoParser, &token)?,
+        other => return Err(format!("Unknown parser '{}'\n\n{}", other, USAGE)),
+    };
+    println!("{:#?}", token_info);
+    Ok(())
+}
+
+fn introspect_with<P>(parser: P, token: &AccessToken) -> Result<TokenInfo, String>
+where
+    P: TokenInfoParser + Clone + Sync + Send + 'static,
+{
+    let service = TokenInfoServiceClientBuilder::from_env(parser)
+        .and_then(|builder| builder.build())
+        .map_err(|err| format!("Could not create the client: {}", err))?;
+    service
+        .introspect(token)
+        .map_err(|err| format!("Could not introspect the token: {}", err))
+}
+
+fn watch(args: &[String]) -> Result<(), String> {
+    let (provider, rest) = split_provider(args)?;
+    let mut interval = Duration::from_secs(10);
+    let mut scopes = Vec::new();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--interval" => {
+                interval = rest
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| "'--interval' needs a number of seconds".to_string())?
+            }
+            scope => scopes.push(Scope::new(scope)),
+        }
+    }
+
+    let group = ManagedTokenGroupBuilder::single_token(WATCHED_TOKEN, scopes, provider)
+        .build()
+        .map_err(|err| err.to_string())?;
+    let source = AccessTokenManager::start_with_listener(vec![group], Arc::new(PrintingListener))
+        .map_err(|err| err.to_string())?
+        .synced();
+
+    let mut last = None;
+    loop {
+        match source.get_access_token(&WATCHED_TOKEN) {
+            Ok(token) => {
+                if last.as_ref().map(AccessToken::reveal) != Some(token.reveal()) {
+                    println!("token rotated: {}", show_token(&token, false));
+                }
+                last = Some(token);
+            }
+            Err(err) => println!("no token: {}", err),
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Prints the notifications of the `AccessTokenManager`.
+struct PrintingListener;
+
+impl TokenLifecycleListener<&'static str> for PrintingListener {
+    fn on_notification(&self, notification: &TokenNotification<&'static str>) {
+        println!(
+            "{:?}({:?}) for {}",
+            notification.kind, notification.severity, notification.token_id
+        );
+    }
+
+    fn on_scopes_downgraded(&self, downgrade: &ScopesDowngraded<&'static str>) {
+        println!(
+            "scopes missing from the token: {}",
+            ScopeList::from(downgrade.missing.clone())
+        );
+    }
+}
+
+/// Any of the `AccessTokenProvider`s which can be configured from the
+/// environment.
+struct CliProvider(Box<dyn AccessTokenProvider + Send + Sync>);
+
+impl AccessTokenProvider for CliProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.0.request_access_token(scopes)
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        self.0.request_access_token_with_params(scopes, params)
+    }
+}
+
+fn split_provider(args: &[String]) -> Result<(CliProvider, &[String]), String> {
+    match args.split_first() {
+        Some((name, rest)) => Ok((provider_from_env(name)?, rest)),
+        None => Err(USAGE.to_string()),
+    }
+}
+
+fn provider_from_env(name: &str) -> Result<CliProvider, String> {
+    let provider: InitializationResult<Box<dyn AccessTokenProvider + Send + Sync>> = match name {
+        "ropc" => SplitFileCredentialsProvider::with_default_parsers_from_env()
+            .and_then(
+                ResourceOwnerPasswordCredentialsGrantProvider::from_env_with_credentials_provider,
+            )
+            .map(|p| Box::new(p) as Box<_>),
+        "cognito" => SplitFileCredentialsProvider::with_default_parsers_from_env()
+            .and_then(CognitoAccessTokenProvider::from_env_with_credentials_provider)
+            .map(|p| Box::new(p) as Box<_>),
+        "azure" => AzureManagedIdentityProvider::from_env().map(|p| Box::new(p) as Box<_>),
+        "gce" => GceMetadataAccessTokenProvider::from_env().map(|p| Box::new(p) as Box<_>),
+        "kubernetes" => {
+            KubernetesServiceAccountTokenProvider::from_env().map(|p| Box::new(p) as Box<_>)
+        }
+        #[cfg(feature = "gcp")]
+        "google" => GoogleServiceAccountProvider::from_env().map(|p| Box::new(p) as Box<_>),
+        #[cfg(feature = "spiffe")]
+        "spiffe" => SpiffeAccessTokenProvider::from_env().map(|p| Box::new(p) as Box<_>),
+        other => return Err(format!("Unknown provider '{}'\n\n{}", other, USAGE)),
+    };
+    provider
+        .map(CliProvider)
+        .map_err(|err| format!("Could not configure the provider '{}': {}", name, err))
+}
+
+fn show_token(token: &AccessToken, reveal: bool) -> String {
+    if reveal {
+        token.reveal().to_string()
+    } else {
+        format!(
+            "<{} characters, use --reveal to print>",
+            token.reveal().chars().count()
+        )
+    }
+}
@@ -0,0 +1,123 @@
+use crate::sha256::{hex, sha256};
+
+/// The number of bytes of a response body kept by the default
+/// `BodyCapture`.
+pub const DEFAULT_CAPTURED_BODY_LENGTH: usize = 512;
+
+/// Decides how much of a response body ends up in the message of a
+/// `TokenInfoError`.
+///
+/// The bodies of failed introspections are put into the errors to help
+/// finding the cause. They may contain claims about the user or echo the
+/// token, so errors which are logged or passed on may leak them. This
+/// applies to the bodies of responses with an error status as well as to
+/// the bodies a `TokenInfoParser` failed on.
+///
+/// ```rust
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+/// use tokkit::BodyCapture;
+///
+/// let mut builder = TokenInfoServiceClientBuilder::google_v3();
+/// builder.with_body_capture(BodyCapture::HashOnly);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyCapture {
+    /// The whole body.
+    Full,
+    /// At most the given number of bytes of the body followed by the
+    /// length of the whole body.
+    Truncated(usize),
+    /// Only the length and the hex encoded SHA-256 hash of the body. The
+    /// hash allows to correlate errors with the responses logged by the
+    /// introspection service.
+    HashOnly,
+    /// Only the length of the body.
+    Omitted,
+}
+
+impl Default for BodyCapture {
+    fn default() -> Self {
+        BodyCapture::Truncated(DEFAULT_CAPTURED_BODY_LENGTH)
+    }
+}
+
+impl BodyCapture {
+    /// The part of `body` which may be put into an error.
+    pub(crate) fn capture(self, body: &[u8]) -> String {
+        match self {
+            BodyCapture::Full => String::from_utf8_lossy(body).into(),
+            BodyCapture::Truncated(max_length) => {
+                let text = String::from_utf8_lossy(body);
+                if text.len() <= max_length {
+                    return text.into();
+                }
+                let mut end = max_length;
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                format!("{}... ({} bytes)", &text[..end], body.len())
+            }
+            BodyCapture::HashOnly => {
+                format!("<{} bytes with SHA-256 {}>", body.len(), hex(&sha256(body)))
+            }
+            BodyCapture::Omitted => format!("<{} bytes omitted>", body.len()),
+        }
+    }
+
+    /// Like `capture` but keeps the `error` of an OAuth 2.0 error response
+    /// body like `{"error": "invalid_token"}` since it tells why a request
+    /// was refused. Nothing else of such a body is kept unless the whole
+    /// body is captured.
+    pub(crate) fn capture_refusal(self, body: &[u8]) -> String {
+        if self == BodyCapture::Full {
+            return self.capture(body);
+        }
+        let error = std::str::from_utf8(body)
+            .ok()
+            .and_then(|body| json::parse(body).ok())
+            .and_then(|body| body["error"].as_str().map(str::to_owned));
+        match error {
+            Some(error) => json::stringify(json::object! { "error" => error }),
+            None => self.capture(body),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn captured_bodies_are_cut_on_character_boundaries() {
+        let body = "äöü".repeat(100);
+        let captured = BodyCapture::Truncated(5).capture(body.as_bytes());
+        assert_eq!(captured, "äö... (600 bytes)");
+        assert_eq!(BodyCapture::Truncated(600).capture(body.as_bytes()), body);
+        assert_eq!(BodyCapture::Full.capture(body.as_bytes()), body);
+        assert_eq!(
+            BodyCapture::Omitted.capture(body.as_bytes()),
+            "<600 bytes omitted>"
+        );
+
+        let hashed = BodyCapture::HashOnly.capture(b"secret");
+        assert!(hashed.starts_with("<6 bytes with SHA-256 "), "{}", hashed);
+        assert!(!hashed.contains("secret"));
+    }
+
+    #[test]
+    fn refusals_keep_only_the_oauth_error() {
+        let body = br#"{"error":"invalid_token","user":"alice"}"#;
+        assert_eq!(
+            BodyCapture::Omitted.capture_refusal(body),
+            r#"{"error":"invalid_token"}"#
+        );
+        assert_eq!(
+            BodyCapture::Full.capture_refusal(body),
+            String::from_utf8_lossy(body)
+        );
+        assert_eq!(
+            BodyCapture::Omitted.capture_refusal(b"alice"),
+            "<5 bytes omitted>"
+        );
+    }
+}
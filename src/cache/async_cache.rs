@@ -0,0 +1,421 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{self, StreamExt};
+
+use super::prefetch::{HotTokens, PrefetchConfig};
+use super::{lookup, stale_fallback, store};
+use super::{CacheBackend, CacheConfig, TokenHasher, TokenInfoCache};
+use crate::async_client::AsyncTokenInfoService;
+use crate::metrics::{DevNullMetricsCollector, MetricsCollector};
+use crate::{AccessToken, Credential, IntrospectionContext, TokenInfo, TokenInfoError};
+
+/// The number of introspections `AsyncCachingTokenInfoService::warm_up`
+/// runs concurrently.
+pub const DEFAULT_WARM_UP_CONCURRENCY: usize = 8;
+
+/// The outcome of `AsyncCachingTokenInfoService::warm_up`.
+#[derive(Debug, Default)]
+pub struct WarmUpReport {
+    /// The number of tokens whose `TokenInfo` is cached now.
+    pub cached: usize,
+    /// The errors of the failed introspections.
+    pub failures: Vec<TokenInfoError>,
+}
+
+/// An `AsyncTokenInfoService` that caches the results of another
+/// `AsyncTokenInfoService`.
+///
+/// Only `Credential::Bearer` is cached. Errors are never cached.
+///
+/// The `CacheBackend` is called directly on the executor. Backends
+/// doing I/O should answer quickly or time out early.
+///
+/// Frequently used tokens can be refreshed in the background before their
+/// entries expire. See `prefetch_task`.
+///
+/// With `with_stale_fallback` a previous result for a token is returned
+/// while the introspection fails.
+#[derive(Clone)]
+pub struct AsyncCachingTokenInfoService<S, M = DevNullMetricsCollector, C = TokenInfoCache> {
+    service: S,
+    cache: Arc<C>,
+    metrics: M,
+    hasher: TokenHasher,
+    stale: Option<Arc<TokenInfoCache>>,
+    hot_tokens: Option<Arc<HotTokens>>,
+}
+
+impl<S> AsyncCachingTokenInfoService<S, DevNullMetricsCollector, TokenInfoCache>
+where
+    S: AsyncTokenInfoService + Sync,
+{
+    /// Wraps `service` with a new cache.
+    pub fn new(service: S, config: CacheConfig) -> Self {
+        Self::with_metrics(service, config, DevNullMetricsCollector)
+    }
+}
+
+impl<S, M> AsyncCachingTokenInfoService<S, M, TokenInfoCache>
+where
+    S: AsyncTokenInfoService + Sync,
+    M: MetricsCollector + Sync,
+{
+    /// Wraps `service` with a new cache that reports to the
+    /// given `MetricsCollector`.
+    pub fn with_metrics(service: S, config: CacheConfig, metrics: M) -> Self {
+        Self::with_cache(service, Arc::new(TokenInfoCache::new(config)), metrics)
+    }
+}
+
+impl<S, M, C> AsyncCachingTokenInfoService<S, M, C>
+where
+    S: AsyncTokenInfoService + Sync,
+    M: MetricsCollector + Sync,
+    C: CacheBackend + Send + Sync,
+{
+    /// Wraps `service` with an existing cache.
+    ///
+    /// The entries are keyed with a random secret.
+    pub fn with_cache(service: S, cache: Arc<C>, metrics: M) -> Self {
+        AsyncCachingTokenInfoService {
+            service,
+            cache,
+            metrics,
+            hasher: TokenHasher::random(),
+            stale: None,
+            hot_tokens: None,
+        }
+    }
+
+    /// Sets the `TokenHasher` deriving the keys of the entries.
+    ///
+    /// Instances sharing a `DistributedCacheBackend` must use the same
+    /// secret.
+    pub fn with_token_hasher(mut self, hasher: TokenHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Keeps the results of successful introspections in a second cache
+    /// bounded by `config` until the tokens expire but no longer than
+    /// `config.max_ttl`.
+    ///
+    /// See `CachingTokenInfoService::with_stale_fallback`.
+    pub fn with_stale_fallback(mut self, config: CacheConfig) -> Self {
+        self.stale = Some(Arc::new(TokenInfoCache::new(config)));
+        self
+    }
+
+    /// The cache used.
+    pub fn cache(&self) -> &Arc<C> {
+        &self.cache
+    }
+
+    /// Introspects `tokens` with up to `DEFAULT_WARM_UP_CONCURRENCY`
+    /// concurrent requests so that their results are cached, e.g. after a
+    /// deploy before traffic is routed to the instance.
+    ///
+    /// Failed introspections do not stop the warm-up but are reported.
+    /// Tokens which are already cached are not introspected again.
+    pub fn warm_up<'a>(&'a self, tokens: &'a [AccessToken]) -> BoxFuture<'a, WarmUpReport> {
+        self.warm_up_with_concurrency(tokens, DEFAULT_WARM_UP_CONCURRENCY)
+    }
+
+    /// Like `warm_up` but with up to `concurrency` concurrent requests.
+    pub fn warm_up_with_concurrency<'a>(
+        &'a self,
+        tokens: &'a [AccessToken],
+        concurrency: usize,
+    ) -> BoxFuture<'a, WarmUpReport> {
+        stream::iter(tokens)
+            .map(move |token| self.introspect(token))
+            .buffer_unordered(concurrency.max(1))
+            .fold(WarmUpReport::default(), |mut report, result| {
+                match result {
+                    Ok(_) => report.cached += 1,
+                    Err(err) => {
+                        debug!("Warming up the cache failed: {}", err);
+                        report.failures.push(err);
+                    }
+                }
+                future::ready(report)
+            })
+            .boxed()
+    }
+
+    fn cached<'a, F>(
+        &'a self,
+        token: &'a AccessToken,
+        introspect: F,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>
+    where
+        F: FnOnce() -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>,
+    {
+        let key = self.hasher.key(token);
+        if let Some(token_info) = lookup(&*self.cache, &key, &self.metrics) {
+            if let Some(ref hot_tokens) = self.hot_tokens {
+                hot_tokens.hit(token);
+            }
+            return futures::future::ok(token_info).boxed();
+        }
+
+        let introspect = introspect();
+        async move {
+            let token_info = match introspect.await {
+                Ok(token_info) => token_info,
+                Err(err) => return stale_fallback(self.stale.as_deref(), &key, err),
+            };
+            store(&*self.cache, &key, &token_info, &self.metrics);
+            if let Some(ref stale) = self.stale {
+                stale.put(&key, &token_info);
+            }
+            if let Some(ref hot_tokens) = self.hot_tokens {
+                hot_tokens.introspected(token, self.cache.ttl(&token_info));
+            }
+            Ok(token_info)
+        }
+        .boxed()
+    }
+}
+
+impl<S, M, C> AsyncCachingTokenInfoService<S, M, C>
+where
+    S: AsyncTokenInfoService + Clone + Send + Sync + 'static,
+    M: MetricsCollector + Clone + Send + Sync + 'static,
+    C: CacheBackend + Send + Sync + 'static,
+{
+    /// Starts tracking which tokens are served from the cache and returns
+    /// the task that introspects hot tokens again shortly before their
+    /// entries expire.
+    ///
+    /// The task has to be spawned on a `tokio` runtime. It finishes once
+    /// this service and all of its clones have been dropped.
+    /// Clones made before calling this do not track their tokens.
+    pub fn prefetch_task(&mut self, config: PrefetchConfig) -> BoxFuture<'static, ()> {
+        let hot_tokens = Arc::new(HotTokens::new(config));
+        let tracked = Arc::downgrade(&hot_tokens);
+        self.hot_tokens = Some(hot_tokens);
+
+        let service = self.service.clone();
+        let cache = Arc::clone(&self.cache);
+        let metrics = self.metrics.clone();
+        let hasher = self.hasher.clone();
+        let stale = self.stale.clone();
+
+        async move {
+            loop {
+                let interval = match tracked.upgrade() {
+                    Some(hot_tokens) => hot_tokens.config().interval,
+                    None => return,
+                };
+                tokio::time::delay_for(interval).await;
+
+                let hot_tokens = match tracked.upgrade() {
+                    Some(hot_tokens) => hot_tokens,
+                    None => return,
+                };
+                for token in hot_tokens.due(Instant::now()) {
+                    match service.introspect(&token).await {
+                        Ok(token_info) => {
+                            let key = hasher.key(&token);
+                            store(&*cache, &key, &token_info, &metrics);
+                            if let Some(ref stale) = stale {
+                                stale.put(&key, &token_info);
+                            }
+                            hot_tokens.introspected(&token, cache.ttl(&token_info));
+                        }
+                        Err(err) => {
+                            debug!("Prefetching a token info failed: {}", err);
+                            hot_tokens.forget(&token);
+                        }
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<S, M, C> AsyncTokenInfoService for AsyncCachingTokenInfoService<S, M, C>
+where
+    S: AsyncTokenInfoService + Sync,
+    M: MetricsCollector + Sync,
+    C: CacheBackend + Send + Sync,
+{
+    fn introspect<'a>(
+        &'a self,
+        token: &'a AccessToken,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.cached(token, || self.service.introspect(token))
+    }
+
+    fn introspect_with_retry<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.cached(token, || self.service.introspect_with_retry(token, budget))
+    }
+
+    fn introspect_with_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.cached(token, || self.service.introspect_with_context(token, context))
+    }
+
+    fn introspect_with_retry_and_context<'a>(
+        &'a self,
+        token: &'a AccessToken,
+        budget: Duration,
+        context: &'a IntrospectionContext,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        self.cached(token, || {
+            self.service
+                .introspect_with_retry_and_context(token, budget, context)
+        })
+    }
+
+    fn introspect_credential<'a>(
+        &'a self,
+        credential: &'a Credential,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token),
+            _ => self.service.introspect_credential(credential),
+        }
+    }
+
+    fn introspect_credential_with_retry<'a>(
+        &'a self,
+        credential: &'a Credential,
+        budget: Duration,
+    ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect_with_retry(token, budget),
+            _ => self
+                .service
+                .introspect_credential_with_retry(credential, budget),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::TokenInfoErrorKind;
+
+    #[derive(Clone)]
+    struct CountingService(Arc<AtomicUsize>);
+
+    impl AsyncTokenInfoService for CountingService {
+        fn introspect<'a>(
+            &'a self,
+            _token: &'a AccessToken,
+        ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::ok(TokenInfo {
+                active: true,
+                user_id: None,
+                scope: Vec::new(),
+                expires_in_seconds: Some(2),
+                certificate_thumbprint: None,
+                from_stale_cache: false,
+                extra: Default::default(),
+            })
+            .boxed()
+        }
+
+        fn introspect_with_retry<'a>(
+            &'a self,
+            token: &'a AccessToken,
+            _budget: Duration,
+        ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+            self.introspect(token)
+        }
+    }
+
+    #[test]
+    fn hot_tokens_are_refreshed_in_the_background() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut service =
+            AsyncCachingTokenInfoService::new(CountingService(calls.clone()), Default::default());
+        let task = service.prefetch_task(
+            PrefetchConfig::default()
+                .with_interval(Duration::from_millis(50))
+                .with_lead_time(Duration::from_millis(1_700))
+                .with_min_hits(1),
+        );
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let token = AccessToken::new("token");
+            service.introspect(&token).await.unwrap();
+            service.introspect(&token).await.unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+            let task = tokio::spawn(task);
+            tokio::time::delay_for(Duration::from_millis(700)).await;
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+            drop(service);
+            task.await.unwrap();
+        });
+    }
+
+    #[derive(Clone)]
+    struct RejectingService;
+
+    impl AsyncTokenInfoService for RejectingService {
+        fn introspect<'a>(
+            &'a self,
+            _token: &'a AccessToken,
+        ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+            future::err(TokenInfoErrorKind::Other("down".to_string()).into()).boxed()
+        }
+
+        fn introspect_with_retry<'a>(
+            &'a self,
+            token: &'a AccessToken,
+            _budget: Duration,
+        ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> {
+            self.introspect(token)
+        }
+    }
+
+    #[test]
+    fn warm_up_caches_all_tokens() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let service =
+            AsyncCachingTokenInfoService::new(CountingService(calls.clone()), Default::default());
+        let tokens: Vec<_> = (0..5)
+            .map(|i| AccessToken::new(format!("token-{}", i)))
+            .collect();
+
+        let report = futures::executor::block_on(service.warm_up_with_concurrency(&tokens, 2));
+        assert_eq!(report.cached, 5);
+        assert!(report.failures.is_empty());
+
+        futures::executor::block_on(service.warm_up(&tokens));
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn warm_up_reports_failures() {
+        let service = AsyncCachingTokenInfoService::new(RejectingService, Default::default());
+        let tokens = vec![AccessToken::new("a"), AccessToken::new("b")];
+
+        let report = futures::executor::block_on(service.warm_up(&tokens));
+        assert_eq!(report.cached, 0);
+        assert_eq!(report.failures.len(), 2);
+    }
+}
@@ -0,0 +1,106 @@
+//! Encoding of cache entries for stores outside of the process.
+//!
+//! Tokens are never stored. Entries are keyed by the `TokenKey` of the
+//! token and `expires_in_seconds` is stored as an absolute timestamp so
+//! that it can be adjusted when read later on.
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+
+use crate::{Claims, Scope, TokenInfo, UserId};
+use crate::{TokenInfoErrorKind, TokenInfoResult};
+
+/// Seconds since the epoch.
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub(crate) fn encode(token_info: &TokenInfo, now: u64) -> String {
+    let mut data = json::object! {
+        "active" => token_info.active,
+        "scope" => token_info
+            .scope
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>(),
+    };
+    if let Some(ref user_id) = token_info.user_id {
+        data["user_id"] = user_id.0.as_str().into();
+    }
+    if let Some(expires_in) = token_info.expires_in_seconds {
+        data["expires_at"] = now.saturating_add(expires_in).into();
+    }
+    if let Some(ref thumbprint) = token_info.certificate_thumbprint {
+        data["x5t#S256"] = thumbprint.as_str().into();
+    }
+    if !token_info.extra.is_empty() {
+        data["extra"] = token_info.extra.as_json().clone();
+    }
+    data.dump()
+}
+
+pub(crate) fn decode(bytes: &[u8], now: u64) -> TokenInfoResult<TokenInfo> {
+    let data = str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| json::parse(s).ok())
+        .filter(JsonValue::is_object)
+        .ok_or_else(|| invalid("not a JSON object"))?;
+
+    let active = data["active"]
+        .as_bool()
+        .ok_or_else(|| invalid("'active' is missing"))?;
+    let scope = data["scope"]
+        .members()
+        .map(|scope| scope.as_str().map(Scope::new))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("'scope' must only contain strings"))?;
+
+    Ok(TokenInfo {
+        active,
+        user_id: data["user_id"].as_str().map(UserId::new),
+        scope,
+        expires_in_seconds: data["expires_at"]
+            .as_u64()
+            .map(|expires_at| expires_at.saturating_sub(now)),
+        certificate_thumbprint: data["x5t#S256"].as_str().map(str::to_owned),
+        from_stale_cache: false,
+        extra: Claims::from_json(data["extra"].clone()),
+    })
+}
+
+fn invalid(msg: &str) -> TokenInfoErrorKind {
+    TokenInfoErrorKind::InvalidResponseContent(format!("Invalid cache entry: {}", msg))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip_adjusts_expires_in() {
+        let token_info = TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read"), Scope::new("write")],
+            expires_in_seconds: Some(60),
+            certificate_thumbprint: Some("thumbprint".to_string()),
+            from_stale_cache: false,
+            extra: Claims::from_json(json::object! { "tenant" => "acme" }),
+        };
+
+        let encoded = encode(&token_info, 1_000);
+        let decoded = decode(encoded.as_bytes(), 1_020).unwrap();
+
+        assert_eq!(
+            decoded,
+            TokenInfo {
+                expires_in_seconds: Some(40),
+                ..token_info
+            }
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::codec;
+use super::{CacheBackend, TokenKey, DEFAULT_MAX_TTL};
+use crate::{TokenInfo, TokenInfoErrorKind, TokenInfoResult};
+
+/// A key value store shared by multiple processes like memcached or Redis.
+///
+/// Every `DistributedCacheBackend` is a `CacheBackend`: Keys are the
+/// `TokenKey`s of the tokens and values are encoded `TokenInfo`s. An entry
+/// lives as long as the token or `max_ttl`, whichever is shorter. Tokens
+/// themselves are never passed to the store.
+///
+/// The instances sharing the store must derive their `TokenKey`s with the
+/// same secret. See `TokenHasher::with_secret`.
+pub trait DistributedCacheBackend {
+    /// Returns the value for `key` if it has not expired.
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<Vec<u8>>>;
+    /// Stores `value` for `key` for `ttl`.
+    fn set(&self, key: &TokenKey, value: &[u8], ttl: Duration) -> TokenInfoResult<()>;
+    /// The maximum time an entry is kept. The default is 60 seconds.
+    fn max_ttl(&self) -> Duration {
+        DEFAULT_MAX_TTL
+    }
+}
+
+impl<B> CacheBackend for B
+where
+    B: DistributedCacheBackend,
+{
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<TokenInfo>> {
+        match DistributedCacheBackend::get(self, key)? {
+            Some(value) => codec::decode(&value, codec::unix_now()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, key: &TokenKey, token_info: &TokenInfo) -> TokenInfoResult<usize> {
+        let ttl = CacheBackend::ttl(self, token_info);
+        if ttl < Duration::from_secs(1) {
+            return Ok(0);
+        }
+
+        let value = codec::encode(token_info, codec::unix_now());
+        self.set(key, value.as_bytes(), ttl)?;
+        Ok(0)
+    }
+
+    fn ttl(&self, token_info: &TokenInfo) -> Duration {
+        super::ttl(token_info, self.max_ttl())
+    }
+}
+
+/// A `DistributedCacheBackend` keeping all entries in memory.
+///
+/// This is a reference implementation mostly useful for tests. Expired
+/// entries are removed when they are read.
+pub struct InMemoryDistributedCache {
+    entries: Mutex<HashMap<TokenKey, (Vec<u8>, Instant)>>,
+    max_ttl: Duration,
+}
+
+impl InMemoryDistributedCache {
+    /// Creates a new empty cache.
+    pub fn new() -> InMemoryDistributedCache {
+        InMemoryDistributedCache {
+            entries: Mutex::new(HashMap::new()),
+            max_ttl: DEFAULT_MAX_TTL,
+        }
+    }
+
+    /// Sets the maximum time an entry is kept. The default is 60 seconds.
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// The number of stored entries including expired ones not read yet.
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map(|entries| entries.len())
+            .unwrap_or(0)
+    }
+
+    /// Returns `true` if nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for InMemoryDistributedCache {
+    fn default() -> InMemoryDistributedCache {
+        InMemoryDistributedCache::new()
+    }
+}
+
+impl DistributedCacheBackend for InMemoryDistributedCache {
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<Vec<u8>>> {
+        let mut entries = self
+            .entries
+            .lock()
+            .map_err(|err| TokenInfoErrorKind::Other(err.to_string()))?;
+        let expires_at = match entries.get(key) {
+            Some(&(_, expires_at)) => expires_at,
+            None => return Ok(None),
+        };
+        if expires_at <= Instant::now() {
+            entries.remove(key);
+            return Ok(None);
+        }
+        Ok(entries.get(key).map(|(value, _)| value.clone()))
+    }
+
+    fn set(&self, key: &TokenKey, value: &[u8], ttl: Duration) -> TokenInfoResult<()> {
+        let mut entries = self
+            .entries
+            .lock()
+            .map_err(|err| TokenInfoErrorKind::Other(err.to_string()))?;
+        entries.insert(key.clone(), (value.to_vec(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn max_ttl(&self) -> Duration {
+        self.max_ttl
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::TokenHasher;
+    use crate::{AccessToken, Scope, UserId};
+
+    #[test]
+    fn token_infos_are_shared_by_instances_with_the_same_secret() {
+        let cache = InMemoryDistributedCache::new();
+        let hasher = TokenHasher::with_secret("shared");
+        let token = AccessToken::new("secret-token");
+        let token_info = TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read")],
+            expires_in_seconds: Some(30),
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        };
+
+        CacheBackend::put(&cache, &hasher.key(&token), &token_info).unwrap();
+
+        let key = TokenHasher::with_secret("shared").key(&token);
+        let cached = CacheBackend::get(&cache, &key).unwrap().unwrap();
+        assert_eq!(cached.user_id, token_info.user_id);
+        let key = TokenHasher::random().key(&token);
+        assert!(CacheBackend::get(&cache, &key).unwrap().is_none());
+        let key = hasher.key(&AccessToken::new("other"));
+        assert!(CacheBackend::get(&cache, &key).unwrap().is_none());
+    }
+}
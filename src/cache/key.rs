@@ -0,0 +1,109 @@
+//! Keys of cache entries which do not reveal the tokens.
+use std::fmt;
+
+use openssl::rand::rand_bytes;
+
+use crate::sha256::{hex, hmac_sha256, sha256};
+use crate::AccessToken;
+
+/// Identifies the cached `TokenInfo` of an `AccessToken`.
+///
+/// A `TokenKey` is the HMAC-SHA256 of the token and can only be created
+/// by a `TokenHasher`. `CacheBackend`s only ever get to see `TokenKey`s so
+/// they can not store or log the tokens by accident.
+///
+/// It is displayed as a hex string which may be used in logs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct TokenKey([u8; 32]);
+
+impl TokenKey {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex(&self.0))
+    }
+}
+
+impl fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TokenKey({})", self)
+    }
+}
+
+/// Derives `TokenKey`s from `AccessToken`s with a secret.
+///
+/// By default the secret is generated randomly when the caching service
+/// is created so keys can not be linked to tokens after a restart.
+/// Instances sharing a `DistributedCacheBackend` have to use the same
+/// secret which can be set with `TokenHasher::with_secret`.
+#[derive(Clone)]
+pub struct TokenHasher {
+    secret: [u8; 32],
+}
+
+impl TokenHasher {
+    /// Creates a `TokenHasher` with a random secret.
+    pub fn random() -> TokenHasher {
+        TokenHasher {
+            secret: random_secret(),
+        }
+    }
+
+    /// Creates a `TokenHasher` with the given secret.
+    pub fn with_secret<T: AsRef<[u8]>>(secret: T) -> TokenHasher {
+        TokenHasher {
+            secret: sha256(secret.as_ref()),
+        }
+    }
+
+    /// The `TokenKey` of the token.
+    pub fn key(&self, token: &AccessToken) -> TokenKey {
+        TokenKey(hmac_sha256(&self.secret, token.reveal().as_bytes()))
+    }
+}
+
+impl Default for TokenHasher {
+    fn default() -> TokenHasher {
+        TokenHasher::random()
+    }
+}
+
+impl fmt::Debug for TokenHasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TokenHasher { .. }")
+    }
+}
+
+/// Draws a secret from the cryptographically secure generator of OpenSSL.
+fn random_secret() -> [u8; 32] {
+    let mut secret = [0; 32];
+    rand_bytes(&mut secret).expect("OpenSSL failed to generate random bytes");
+    secret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_depend_on_the_secret_and_do_not_reveal_the_token() {
+        let token = AccessToken::new("secret-token");
+        let hasher = TokenHasher::with_secret("shared");
+
+        let key = hasher.key(&token);
+        assert_eq!(key, TokenHasher::with_secret("shared").key(&token));
+        assert_ne!(key, hasher.key(&AccessToken::new("other")));
+        assert_ne!(key, TokenHasher::random().key(&token));
+        assert_ne!(
+            TokenHasher::random().key(&token),
+            TokenHasher::random().key(&token)
+        );
+
+        assert_eq!(key.to_string().len(), 64);
+        assert!(!format!("{:?}", key).contains("secret-token"));
+    }
+}
@@ -0,0 +1,578 @@
+//! Caching of introspection results
+//!
+//! `CachingTokenInfoService` wraps a `TokenInfoService` and
+//! `AsyncCachingTokenInfoService` wraps an `AsyncTokenInfoService`
+//! (feature `async`). Both keep successful introspection results in a
+//! `TokenInfoCache` until the token expires or the configured maximum
+//! TTL is reached.
+//!
+//! The cache is bounded by a maximum number of entries and optionally by
+//! an estimated number of bytes. Which entries get evicted once a bound is
+//! reached is determined by the `EvictionStrategy`.
+//!
+//! Hits, misses and evictions are reported to a `MetricsCollector`.
+//!
+//! Entries are keyed by `TokenKey`s which are derived from the tokens by
+//! a `TokenHasher` with a secret generated at startup. Neither the caches
+//! nor the logs ever contain the tokens.
+//!
+//! Other stores can be used by implementing `CacheBackend`. Stores shared
+//! by multiple instances like memcached only need to implement the simpler
+//! `DistributedCacheBackend`. Instances sharing a store need to share the
+//! secret of their `TokenHasher`.
+//! With the feature `redis` a `RedisTokenInfoCache` is available.
+//!
+//! ```rust,no_run
+//! use tokkit::cache::{CacheConfig, CachingTokenInfoService, EvictionStrategy};
+//! use tokkit::client::TokenInfoServiceClientBuilder;
+//!
+//! let service = TokenInfoServiceClientBuilder::google_v3().build().unwrap();
+//!
+//! let config = CacheConfig::default()
+//!     .with_max_entries(100_000)
+//!     .with_max_bytes(Some(64 * 1024 * 1024))
+//!     .with_eviction(EvictionStrategy::TinyLfu);
+//!
+//! let service = CachingTokenInfoService::new(service, config);
+//! ```
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::metrics::{DevNullMetricsCollector, MetricsCollector};
+use crate::{AccessToken, Credential, InitializationError, InitializationResult};
+use crate::{IntrospectionContext, TokenInfo, TokenInfoError, TokenInfoResult, TokenInfoService};
+
+#[cfg(feature = "async")]
+mod async_cache;
+mod codec;
+mod distributed;
+mod key;
+#[cfg(feature = "async")]
+mod prefetch;
+#[cfg(feature = "redis")]
+mod redis;
+mod store;
+
+#[cfg(feature = "async")]
+pub use self::async_cache::{
+    AsyncCachingTokenInfoService, WarmUpReport, DEFAULT_WARM_UP_CONCURRENCY,
+};
+pub use self::distributed::{DistributedCacheBackend, InMemoryDistributedCache};
+pub use self::key::{TokenHasher, TokenKey};
+#[cfg(feature = "async")]
+pub use self::prefetch::PrefetchConfig;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisTokenInfoCache;
+
+use self::store::Store;
+
+/// The default for the maximum time an entry is cached.
+const DEFAULT_MAX_TTL: Duration = Duration::from_secs(60);
+
+/// Determines which entries are evicted once a `TokenInfoCache`
+/// reached its bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionStrategy {
+    /// Evict the least recently used entry.
+    #[default]
+    Lru,
+    /// Evict the entry that expires next. Reading an entry does not
+    /// change its position.
+    TtlOnly,
+    /// Least recently used with a TinyLFU style admission filter:
+    /// A new entry only replaces the least recently used entry if it
+    /// has been requested more frequently recently. This keeps bursts
+    /// of one-off tokens from flushing the hot ones.
+    TinyLfu,
+}
+
+impl EvictionStrategy {
+    fn parse(s: &str) -> InitializationResult<EvictionStrategy> {
+        match s.to_lowercase().as_ref() {
+            "lru" => Ok(EvictionStrategy::Lru),
+            "ttl" | "ttl_only" => Ok(EvictionStrategy::TtlOnly),
+            "tinylfu" | "tiny_lfu" => Ok(EvictionStrategy::TinyLfu),
+            _ => Err(InitializationError::Other(format!(
+                "'{}' is not a valid eviction strategy",
+                s
+            ))),
+        }
+    }
+}
+
+/// Configures the bounds of a `TokenInfoCache`.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// The maximum number of cached entries. A value of 0 disables
+    /// caching.
+    pub max_entries: usize,
+    /// The maximum number of bytes the cached entries may occupy.
+    /// The size of an entry is estimated.
+    pub max_bytes: Option<usize>,
+    /// The maximum time an entry is kept. Entries are never kept longer
+    /// than the `expires_in_seconds` of the `TokenInfo`.
+    pub max_ttl: Duration,
+    /// Determines which entries are evicted
+    pub eviction: EvictionStrategy,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            max_entries: 10_000,
+            max_bytes: None,
+            max_ttl: DEFAULT_MAX_TTL,
+            eviction: EvictionStrategy::default(),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Sets the maximum number of cached entries.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the maximum number of bytes the cached entries may occupy.
+    pub fn with_max_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Sets the maximum time an entry is kept.
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Sets the `EvictionStrategy`.
+    pub fn with_eviction(mut self, eviction: EvictionStrategy) -> Self {
+        self.eviction = eviction;
+        self
+    }
+
+    /// Creates a `CacheConfig` from environment variables.
+    /// Variables that are not set keep their default values.
+    ///
+    /// * `TOKKIT_CACHE_MAX_ENTRIES`: The maximum number of entries
+    /// * `TOKKIT_CACHE_MAX_BYTES`: The maximum number of bytes
+    /// * `TOKKIT_CACHE_MAX_TTL_SECONDS`: The maximum TTL in seconds
+    /// * `TOKKIT_CACHE_EVICTION`: One of `lru`, `ttl_only` or `tiny_lfu`
+    pub fn from_env() -> InitializationResult<CacheConfig> {
+        let mut config = CacheConfig::default();
+        if let Some(max_entries) = env_parsed("TOKKIT_CACHE_MAX_ENTRIES")? {
+            config.max_entries = max_entries;
+        }
+        if let Some(max_bytes) = env_parsed("TOKKIT_CACHE_MAX_BYTES")? {
+            config.max_bytes = Some(max_bytes);
+        }
+        if let Some(max_ttl) = env_parsed("TOKKIT_CACHE_MAX_TTL_SECONDS")? {
+            config.max_ttl = Duration::from_secs(max_ttl);
+        }
+        if let Ok(eviction) = env::var("TOKKIT_CACHE_EVICTION") {
+            config.eviction = EvictionStrategy::parse(&eviction)?;
+        }
+        Ok(config)
+    }
+}
+
+fn env_parsed<T: std::str::FromStr>(var: &str) -> InitializationResult<Option<T>> {
+    match env::var(var) {
+        Ok(value) => value.parse().map(Some).map_err(|_| {
+            InitializationError::env_var(var, format!("'{}' is not a valid value", value))
+        }),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(InitializationError::env_var(var, err)),
+    }
+}
+
+/// A bounded, thread safe cache for `TokenInfo`s keyed by `TokenKey`.
+pub struct TokenInfoCache {
+    store: Mutex<Store>,
+    max_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl TokenInfoCache {
+    /// Creates a new empty cache.
+    pub fn new(config: CacheConfig) -> TokenInfoCache {
+        TokenInfoCache {
+            max_ttl: config.max_ttl,
+            store: Mutex::new(Store::new(config)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached `TokenInfo` for the `TokenKey` if it
+    /// has not expired.
+    ///
+    /// `expires_in_seconds` is reduced by the time the
+    /// `TokenInfo` has been cached.
+    pub fn get(&self, key: &TokenKey) -> Option<TokenInfo> {
+        let token_info = self.store.lock().ok()?.get(key, Instant::now());
+        let counter = if token_info.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        token_info
+    }
+
+    /// Caches the `TokenInfo` for the `TokenKey`.
+    ///
+    /// Returns the number of entries that were evicted to make room.
+    pub fn put(&self, key: &TokenKey, token_info: &TokenInfo) -> usize {
+        match self.store.lock() {
+            Ok(mut store) => store.put(key, token_info, Instant::now()),
+            Err(_) => 0,
+        }
+    }
+
+    /// The number of cached entries.
+    pub fn len(&self) -> usize {
+        self.store.lock().map(|store| store.len()).unwrap_or(0)
+    }
+
+    /// Returns `true` if nothing is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The estimated number of bytes occupied by the cached entries.
+    pub fn bytes(&self) -> usize {
+        self.store.lock().map(|store| store.bytes()).unwrap_or(0)
+    }
+
+    /// The number of lookups which found an entry since the cache was
+    /// created.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups which found no entry since the cache was
+    /// created.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) {
+        if let Ok(mut store) = self.store.lock() {
+            store.clear();
+        }
+    }
+}
+
+/// A store for `TokenInfo`s used by the caching decorators.
+///
+/// Failing backends do not fail the introspection. The decorators log
+/// the error and introspect the token as if nothing was cached.
+///
+/// Backends never see the tokens, only their `TokenKey`s.
+pub trait CacheBackend {
+    /// Returns the cached `TokenInfo` for the `TokenKey` if there is one.
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<TokenInfo>>;
+    /// Caches the `TokenInfo` for the `TokenKey`.
+    ///
+    /// Returns the number of entries that were evicted to make room.
+    fn put(&self, key: &TokenKey, token_info: &TokenInfo) -> TokenInfoResult<usize>;
+    /// The time the `TokenInfo` will be cached for when it is put now.
+    ///
+    /// The default is the time until the token expires but no longer
+    /// than 60 seconds.
+    fn ttl(&self, token_info: &TokenInfo) -> Duration {
+        ttl(token_info, DEFAULT_MAX_TTL)
+    }
+}
+
+impl CacheBackend for TokenInfoCache {
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<TokenInfo>> {
+        Ok(TokenInfoCache::get(self, key))
+    }
+
+    fn put(&self, key: &TokenKey, token_info: &TokenInfo) -> TokenInfoResult<usize> {
+        Ok(TokenInfoCache::put(self, key, token_info))
+    }
+
+    fn ttl(&self, token_info: &TokenInfo) -> Duration {
+        ttl(token_info, self.max_ttl)
+    }
+}
+
+/// The time a `TokenInfo` may be cached: Until the token expires but
+/// no longer than `max_ttl`.
+fn ttl(token_info: &TokenInfo, max_ttl: Duration) -> Duration {
+    token_info
+        .expires_in_seconds
+        .map(Duration::from_secs)
+        .unwrap_or(max_ttl)
+        .min(max_ttl)
+}
+
+/// Looks up a `TokenInfo` in the cache and reports a hit or miss.
+fn lookup<C, M>(cache: &C, key: &TokenKey, metrics: &M) -> Option<TokenInfo>
+where
+    C: CacheBackend + ?Sized,
+    M: MetricsCollector,
+{
+    let cached = cache.get(key).unwrap_or_else(|err| {
+        warn!("Could not read entry {} from cache: {}", key, err);
+        None
+    });
+    if cached.is_some() {
+        metrics.cache_hit();
+    } else {
+        metrics.cache_miss();
+    }
+    cached
+}
+
+/// Caches the `TokenInfo` and reports evictions.
+fn store<C, M>(cache: &C, key: &TokenKey, token_info: &TokenInfo, metrics: &M)
+where
+    C: CacheBackend + ?Sized,
+    M: MetricsCollector,
+{
+    match cache.put(key, token_info) {
+        Ok(evicted) => {
+            for _ in 0..evicted {
+                metrics.cache_eviction();
+            }
+        }
+        Err(err) => warn!("Could not write entry {} to cache: {}", key, err),
+    }
+}
+
+/// Returns the stale `TokenInfo` of the token if the introspection failed
+/// with an error suggesting a retry.
+fn stale_fallback(
+    stale: Option<&TokenInfoCache>,
+    key: &TokenKey,
+    err: TokenInfoError,
+) -> TokenInfoResult<TokenInfo> {
+    if !err.is_retry_suggested() {
+        return Err(err);
+    }
+    match stale.and_then(|stale| stale.get(key)) {
+        Some(mut token_info) => {
+            warn!(
+                "Introspection failed. Serving stale entry {} instead: {}",
+                key, err
+            );
+            token_info.from_stale_cache = true;
+            Ok(token_info)
+        }
+        None => Err(err),
+    }
+}
+
+/// A `TokenInfoService` that caches the results of another
+/// `TokenInfoService`.
+///
+/// Only `Credential::Bearer` is cached. Errors are never cached.
+///
+/// With `with_stale_fallback` a previous result for a token is returned
+/// while the introspection fails.
+#[derive(Clone)]
+pub struct CachingTokenInfoService<S, M = DevNullMetricsCollector, C = TokenInfoCache> {
+    service: S,
+    cache: Arc<C>,
+    metrics: M,
+    hasher: TokenHasher,
+    stale: Option<Arc<TokenInfoCache>>,
+}
+
+impl<S> CachingTokenInfoService<S, DevNullMetricsCollector, TokenInfoCache>
+where
+    S: TokenInfoService,
+{
+    /// Wraps `service` with a new cache.
+    pub fn new(service: S, config: CacheConfig) -> Self {
+        Self::with_metrics(service, config, DevNullMetricsCollector)
+    }
+}
+
+impl<S, M> CachingTokenInfoService<S, M, TokenInfoCache>
+where
+    S: TokenInfoService,
+    M: MetricsCollector,
+{
+    /// Wraps `service` with a new cache that reports to the
+    /// given `MetricsCollector`.
+    pub fn with_metrics(service: S, config: CacheConfig, metrics: M) -> Self {
+        Self::with_cache(service, Arc::new(TokenInfoCache::new(config)), metrics)
+    }
+}
+
+impl<S, M, C> CachingTokenInfoService<S, M, C>
+where
+    S: TokenInfoService,
+    M: MetricsCollector,
+    C: CacheBackend,
+{
+    /// Wraps `service` with an existing cache.
+    ///
+    /// The entries are keyed with a random secret.
+    pub fn with_cache(service: S, cache: Arc<C>, metrics: M) -> Self {
+        CachingTokenInfoService {
+            service,
+            cache,
+            metrics,
+            hasher: TokenHasher::random(),
+            stale: None,
+        }
+    }
+
+    /// Sets the `TokenHasher` deriving the keys of the entries.
+    ///
+    /// Instances sharing a `DistributedCacheBackend` must use the same
+    /// secret.
+    pub fn with_token_hasher(mut self, hasher: TokenHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Keeps the results of successful introspections in a second cache
+    /// bounded by `config` until the tokens expire but no longer than
+    /// `config.max_ttl`.
+    ///
+    /// If an introspection fails with an error suggesting a retry the
+    /// result is taken from there with `TokenInfo::from_stale_cache` set.
+    /// This favours availability over freshness: Revoked tokens may be
+    /// accepted during an outage of the introspection service.
+    pub fn with_stale_fallback(mut self, config: CacheConfig) -> Self {
+        self.stale = Some(Arc::new(TokenInfoCache::new(config)));
+        self
+    }
+
+    /// The cache used.
+    pub fn cache(&self) -> &Arc<C> {
+        &self.cache
+    }
+
+    fn cached<F>(&self, token: &AccessToken, introspect: F) -> TokenInfoResult<TokenInfo>
+    where
+        F: FnOnce() -> TokenInfoResult<TokenInfo>,
+    {
+        let key = self.hasher.key(token);
+        if let Some(token_info) = lookup(&*self.cache, &key, &self.metrics) {
+            return Ok(token_info);
+        }
+        let token_info = match introspect() {
+            Ok(token_info) => token_info,
+            Err(err) => return stale_fallback(self.stale.as_deref(), &key, err),
+        };
+        store(&*self.cache, &key, &token_info, &self.metrics);
+        if let Some(ref stale) = self.stale {
+            stale.put(&key, &token_info);
+        }
+        Ok(token_info)
+    }
+}
+
+impl<S, M, C> TokenInfoService for CachingTokenInfoService<S, M, C>
+where
+    S: TokenInfoService,
+    M: MetricsCollector,
+    C: CacheBackend,
+{
+    fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+        self.cached(token, || self.service.introspect(token))
+    }
+
+    fn introspect_credential(&self, credential: &Credential) -> TokenInfoResult<TokenInfo> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token),
+            _ => self.service.introspect_credential(credential),
+        }
+    }
+
+    fn introspect_with_context(
+        &self,
+        token: &AccessToken,
+        context: &IntrospectionContext,
+    ) -> TokenInfoResult<TokenInfo> {
+        self.cached(token, || {
+            self.service.introspect_with_context(token, context)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::TokenInfoErrorKind;
+
+    struct CountingService(AtomicUsize);
+
+    impl TokenInfoService for CountingService {
+        fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if token.reveal() == "invalid" {
+                return Err(TokenInfoErrorKind::not_authenticated("invalid").into());
+            }
+            Ok(TokenInfo {
+                active: true,
+                user_id: None,
+                scope: Vec::new(),
+                expires_in_seconds: Some(60),
+                certificate_thumbprint: None,
+                from_stale_cache: false,
+                extra: Default::default(),
+            })
+        }
+    }
+
+    #[test]
+    fn only_successful_results_are_cached() {
+        let service =
+            CachingTokenInfoService::new(CountingService(AtomicUsize::new(0)), Default::default());
+
+        let token = AccessToken::new("token");
+        service.introspect(&token).unwrap();
+        service.introspect(&token).unwrap();
+        assert_eq!(service.service.0.load(Ordering::SeqCst), 1);
+
+        let invalid = AccessToken::new("invalid");
+        assert!(service.introspect(&invalid).is_err());
+        assert!(service.introspect(&invalid).is_err());
+        assert_eq!(service.service.0.load(Ordering::SeqCst), 3);
+        assert_eq!(service.cache().len(), 1);
+    }
+
+    struct FailingAfterFirstCall(AtomicUsize);
+
+    impl TokenInfoService for FailingAfterFirstCall {
+        fn introspect(&self, _token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+            if self.0.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Err(TokenInfoErrorKind::Server("unavailable".to_string()).into());
+            }
+            Ok(TokenInfo::new(true).with_expires_in_seconds(60))
+        }
+    }
+
+    #[test]
+    fn stale_results_are_served_while_the_introspection_fails() {
+        let service = CachingTokenInfoService::new(
+            FailingAfterFirstCall(AtomicUsize::new(0)),
+            CacheConfig::default().with_max_entries(0),
+        )
+        .with_stale_fallback(CacheConfig::default().with_max_ttl(Duration::from_secs(3600)));
+
+        let token = AccessToken::new("token");
+        assert!(!service.introspect(&token).unwrap().from_stale_cache);
+        let token_info = service.introspect(&token).unwrap();
+        assert!(token_info.from_stale_cache);
+        assert!(token_info.active);
+
+        assert!(service.introspect(&AccessToken::new("other")).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::AccessToken;
+
+/// Configures the background prefetching of an
+/// `AsyncCachingTokenInfoService`.
+#[derive(Debug, Clone)]
+pub struct PrefetchConfig {
+    /// How often the task checks for entries to refresh.
+    pub interval: Duration,
+    /// How long before the cached entry expires it is refreshed.
+    pub lead_time: Duration,
+    /// How often a token must have been served from the cache since it
+    /// was last introspected to be considered hot.
+    pub min_hits: u32,
+    /// The maximum number of tokens tracked.
+    pub max_tracked: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> PrefetchConfig {
+        PrefetchConfig {
+            interval: Duration::from_secs(1),
+            lead_time: Duration::from_secs(5),
+            min_hits: 2,
+            max_tracked: 1_000,
+        }
+    }
+}
+
+impl PrefetchConfig {
+    /// Sets how often the task checks for entries to refresh.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets how long before the cached entry expires it is refreshed.
+    pub fn with_lead_time(mut self, lead_time: Duration) -> Self {
+        self.lead_time = lead_time;
+        self
+    }
+
+    /// Sets how often a token must have been served from the cache to be
+    /// considered hot.
+    pub fn with_min_hits(mut self, min_hits: u32) -> Self {
+        self.min_hits = min_hits;
+        self
+    }
+
+    /// Sets the maximum number of tokens tracked.
+    pub fn with_max_tracked(mut self, max_tracked: usize) -> Self {
+        self.max_tracked = max_tracked;
+        self
+    }
+}
+
+struct Tracked {
+    token: AccessToken,
+    hits: u32,
+    expires_at: Instant,
+}
+
+/// Remembers recently introspected tokens and how often they were
+/// served from the cache.
+pub(crate) struct HotTokens {
+    config: PrefetchConfig,
+    tracked: Mutex<HashMap<String, Tracked>>,
+}
+
+impl HotTokens {
+    pub fn new(config: PrefetchConfig) -> HotTokens {
+        HotTokens {
+            config,
+            tracked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &PrefetchConfig {
+        &self.config
+    }
+
+    /// The token was introspected and cached for `ttl`.
+    pub fn introspected(&self, token: &AccessToken, ttl: Duration) {
+        let mut tracked = match self.tracked.lock() {
+            Ok(tracked) => tracked,
+            Err(_) => return,
+        };
+        let expires_at = Instant::now() + ttl;
+        if let Some(entry) = tracked.get_mut(token.reveal()) {
+            entry.hits = 0;
+            entry.expires_at = expires_at;
+        } else if tracked.len() < self.config.max_tracked {
+            tracked.insert(
+                token.reveal().to_owned(),
+                Tracked {
+                    token: token.clone(),
+                    hits: 0,
+                    expires_at,
+                },
+            );
+        }
+    }
+
+    /// The token was served from the cache.
+    pub fn hit(&self, token: &AccessToken) {
+        if let Ok(mut tracked) = self.tracked.lock() {
+            if let Some(entry) = tracked.get_mut(token.reveal()) {
+                entry.hits = entry.hits.saturating_add(1);
+            }
+        }
+    }
+
+    /// The token could not be refreshed.
+    pub fn forget(&self, token: &AccessToken) {
+        if let Ok(mut tracked) = self.tracked.lock() {
+            tracked.remove(token.reveal());
+        }
+    }
+
+    /// Returns the hot tokens whose entries expire within the lead time.
+    /// Tokens whose entries already expired are forgotten.
+    pub fn due(&self, now: Instant) -> Vec<AccessToken> {
+        let mut tracked = match self.tracked.lock() {
+            Ok(tracked) => tracked,
+            Err(_) => return Vec::new(),
+        };
+        let config = &self.config;
+        tracked.retain(|_, entry| entry.expires_at > now);
+        tracked
+            .values()
+            .filter(|entry| entry.expires_at <= now + config.lead_time)
+            .filter(|entry| entry.hits >= config.min_hits)
+            .map(|entry| entry.token.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_hot_tokens_are_due_before_they_expire() {
+        let hot_tokens = HotTokens::new(
+            PrefetchConfig::default()
+                .with_lead_time(Duration::from_secs(5))
+                .with_min_hits(2),
+        );
+        let hot = AccessToken::new("hot");
+        let cold = AccessToken::new("cold");
+        hot_tokens.introspected(&hot, Duration::from_secs(10));
+        hot_tokens.introspected(&cold, Duration::from_secs(10));
+        hot_tokens.hit(&hot);
+        hot_tokens.hit(&hot);
+        hot_tokens.hit(&cold);
+
+        let now = Instant::now();
+        assert!(hot_tokens.due(now).is_empty());
+
+        let due = hot_tokens.due(now + Duration::from_secs(6));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].reveal(), "hot");
+
+        assert!(hot_tokens.due(now + Duration::from_secs(11)).is_empty());
+        assert!(hot_tokens.tracked.lock().unwrap().is_empty());
+    }
+}
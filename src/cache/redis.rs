@@ -0,0 +1,474 @@
+//! A `DistributedCacheBackend` storing `TokenInfo`s in Redis.
+//!
+//! Speaks just enough of the
+//! [Redis protocol](https://redis.io/topics/protocol) to authenticate,
+//! select a database and `GET`/`SET` entries so that no Redis client
+//! library is needed.
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use super::{DistributedCacheBackend, TokenKey, DEFAULT_MAX_TTL};
+use crate::{InitializationError, InitializationResult};
+use crate::{TokenInfoErrorKind, TokenInfoResult};
+
+const DEFAULT_PORT: u16 = 6379;
+const DEFAULT_KEY_PREFIX: &str = "tokkit:tokeninfo:";
+const DEFAULT_MAX_IDLE_CONNECTIONS: usize = 8;
+
+/// A `DistributedCacheBackend` that shares introspection results between
+/// processes via Redis.
+///
+/// Each request takes an idle connection or opens a new one, so
+/// concurrent requests do not wait for each other. At most
+/// `max_idle_connections` connections are kept open between requests.
+///
+/// A request failing on a connection which was idle, e.g. because Redis
+/// closed it, is retried once on a new connection. If Redis can not be
+/// reached the cache reports nothing cached for `retry_after` before it
+/// tries to reconnect, so introspection continues without it.
+pub struct RedisTokenInfoCache {
+    host: String,
+    port: u16,
+    username: Option<Vec<u8>>,
+    password: Option<Vec<u8>>,
+    database: Option<u32>,
+    key_prefix: String,
+    max_ttl: Duration,
+    timeout: Duration,
+    retry_after: Duration,
+    max_idle_connections: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    idle: Vec<Connection>,
+    down_until: Option<Instant>,
+}
+
+impl RedisTokenInfoCache {
+    /// Creates a new `RedisTokenInfoCache`.
+    ///
+    /// `url` has the form
+    /// `redis://[[username]:password@]host[:port][/database]` where the
+    /// username and the password are percent-encoded. With a username the
+    /// connections authenticate as that ACL user of Redis 6.
+    pub fn new<T: AsRef<str>>(url: T) -> InitializationResult<RedisTokenInfoCache> {
+        let url = Url::parse(url.as_ref())
+            .map_err(|err| InitializationError::InvalidUrl(err.to_string()))?;
+        if url.scheme() != "redis" {
+            return Err(InitializationError::InvalidUrl(format!(
+                "Expected scheme 'redis' but found '{}'",
+                url.scheme()
+            )));
+        }
+        let host = match url.host_str() {
+            Some(host) => host.to_string(),
+            None => {
+                return Err(InitializationError::InvalidUrl(
+                    "The Redis URL has no host".into(),
+                ))
+            }
+        };
+        let database = match url.path().trim_start_matches('/') {
+            "" => None,
+            db => Some(db.parse().map_err(|_| {
+                InitializationError::InvalidUrl(format!("'{}' is not a valid Redis database", db))
+            })?),
+        };
+
+        Ok(RedisTokenInfoCache {
+            host,
+            port: url.port().unwrap_or(DEFAULT_PORT),
+            username: Some(url.username())
+                .filter(|username| !username.is_empty())
+                .map(|username| percent_decode_str(username).collect()),
+            password: url
+                .password()
+                .map(|password| percent_decode_str(password).collect()),
+            database,
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+            max_ttl: DEFAULT_MAX_TTL,
+            timeout: Duration::from_millis(100),
+            retry_after: Duration::from_secs(5),
+            max_idle_connections: DEFAULT_MAX_IDLE_CONNECTIONS,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Creates a new `RedisTokenInfoCache` from environment variables:
+    ///
+    /// * `TOKKIT_REDIS_URL`: The URL of the Redis server (mandatory)
+    /// * `TOKKIT_REDIS_KEY_PREFIX`: The prefix for all keys (optional)
+    pub fn from_env() -> InitializationResult<RedisTokenInfoCache> {
+        let url = env::var("TOKKIT_REDIS_URL")
+            .map_err(|err| InitializationError::env_var("TOKKIT_REDIS_URL", err))?;
+        let mut cache = RedisTokenInfoCache::new(url)?;
+        if let Ok(key_prefix) = env::var("TOKKIT_REDIS_KEY_PREFIX") {
+            cache.key_prefix = key_prefix;
+        }
+        Ok(cache)
+    }
+
+    /// Sets the prefix for all keys. The default is `tokkit:tokeninfo:`.
+    pub fn with_key_prefix<T: Into<String>>(mut self, key_prefix: T) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets the maximum time an entry is kept. The default is 60 seconds.
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Sets the timeout for connecting, reading and writing.
+    /// The default is 100ms.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how long Redis is not used after it failed.
+    /// The default is 5 seconds.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Sets the maximum number of connections kept open between requests.
+    /// The default is 8.
+    pub fn with_max_idle_connections(mut self, max_idle_connections: usize) -> Self {
+        self.max_idle_connections = max_idle_connections;
+        self
+    }
+
+    /// Runs `f` on an idle or a new connection. Returns `Ok(None)` while
+    /// Redis is considered down.
+    fn with_connection<T, F>(&self, f: F) -> TokenInfoResult<Option<T>>
+    where
+        F: Fn(&mut Connection) -> io::Result<T>,
+    {
+        let idle = {
+            let mut state = self.lock_state()?;
+            if let Some(down_until) = state.down_until {
+                if down_until > Instant::now() {
+                    return Ok(None);
+                }
+                state.down_until = None;
+            }
+            state.idle.pop()
+        };
+
+        if let Some(mut connection) = idle {
+            if let Ok(result) = f(&mut connection) {
+                self.release(connection)?;
+                return Ok(Some(result));
+            }
+            // The connection is dropped and the request is retried once
+            // since Redis may have closed the connection while it was idle.
+        }
+
+        match self
+            .connect()
+            .and_then(|mut connection| f(&mut connection).map(|result| (connection, result)))
+        {
+            Ok((connection, result)) => {
+                self.release(connection)?;
+                Ok(Some(result))
+            }
+            Err(err) => {
+                let mut state = self.lock_state()?;
+                state.idle.clear();
+                state.down_until = Some(Instant::now() + self.retry_after);
+                Err(TokenInfoErrorKind::Connection(format!(
+                    "Redis at {}:{} failed: {}",
+                    self.host, self.port, err
+                ))
+                .into())
+            }
+        }
+    }
+
+    /// Keeps `connection` for later requests unless enough are idle.
+    fn release(&self, connection: Connection) -> TokenInfoResult<()> {
+        let mut state = self.lock_state()?;
+        if state.idle.len() < self.max_idle_connections {
+            state.idle.push(connection);
+        }
+        Ok(())
+    }
+
+    fn lock_state(&self) -> TokenInfoResult<MutexGuard<'_, State>> {
+        self.state
+            .lock()
+            .map_err(|err| TokenInfoErrorKind::Other(err.to_string()).into())
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address resolved");
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    let mut connection = Connection {
+                        stream: BufReader::new(stream),
+                    };
+                    match (&self.username, &self.password) {
+                        (Some(username), Some(password)) => {
+                            connection.command(&[b"AUTH", username, password])?;
+                        }
+                        (None, Some(password)) => {
+                            connection.command(&[b"AUTH", password])?;
+                        }
+                        _ => {}
+                    }
+                    if let Some(database) = self.database {
+                        connection.command(&[b"SELECT", database.to_string().as_bytes()])?;
+                    }
+                    return Ok(connection);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl DistributedCacheBackend for RedisTokenInfoCache {
+    fn get(&self, key: &TokenKey) -> TokenInfoResult<Option<Vec<u8>>> {
+        let key = format!("{}{}", self.key_prefix, key);
+        match self.with_connection(|c| c.command(&[b"GET", key.as_bytes()]))? {
+            Some(Reply::Bulk(value)) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    fn set(&self, key: &TokenKey, value: &[u8], ttl: Duration) -> TokenInfoResult<()> {
+        let key = format!("{}{}", self.key_prefix, key);
+        let ttl = ttl.as_secs().max(1).to_string();
+        self.with_connection(|c| {
+            c.command(&[b"SET", key.as_bytes(), value, b"EX", ttl.as_bytes()])
+        })?;
+        Ok(())
+    }
+
+    fn max_ttl(&self) -> Duration {
+        self.max_ttl
+    }
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Nil,
+    Status(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+}
+
+impl Connection {
+    fn command(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        self.stream.get_mut().write_all(&request)?;
+        self.read_reply()
+    }
+
+    fn read_reply(&mut self) -> io::Result<Reply> {
+        let line = self.read_line()?;
+        let (kind, content) = match line.chars().next() {
+            Some(kind) => (kind, &line[1..]),
+            None => return Err(protocol_error("empty reply")),
+        };
+        match kind {
+            '+' => Ok(Reply::Status(content.to_string())),
+            '-' => Err(io::Error::other(content.to_string())),
+            ':' => content
+                .parse()
+                .map(Reply::Integer)
+                .map_err(|_| protocol_error("invalid integer")),
+            '$' => {
+                let len: i64 = content
+                    .parse()
+                    .map_err(|_| protocol_error("invalid length"))?;
+                if len < 0 {
+                    return Ok(Reply::Nil);
+                }
+                let mut value = vec![0; len as usize + 2];
+                self.stream.read_exact(&mut value)?;
+                value.truncate(len as usize);
+                Ok(Reply::Bulk(value))
+            }
+            _ => Err(protocol_error("unsupported reply")),
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::cache::{CacheBackend, TokenHasher};
+    use crate::{AccessToken, Scope, TokenInfo, UserId};
+
+    /// Serves `GET` and `SET`, closing each connection after
+    /// `commands_per_connection` commands. The arguments of `AUTH` are
+    /// stored under `AUTH`.
+    fn fake_redis(commands_per_connection: usize) -> (u16, Arc<Mutex<HashMap<String, String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let data: Arc<Mutex<HashMap<String, String>>> = Default::default();
+        let shared = data.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let data = data.clone();
+                thread::spawn(move || serve(stream.unwrap(), &data, commands_per_connection));
+            }
+        });
+        (port, shared)
+    }
+
+    fn serve(stream: TcpStream, data: &Mutex<HashMap<String, String>>, commands: usize) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        for _ in 0..commands {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                return;
+            }
+            let n: usize = line.trim()[1..].parse().unwrap();
+            let mut args = Vec::new();
+            for _ in 0..n {
+                let mut len = String::new();
+                reader.read_line(&mut len).unwrap();
+                let mut arg = String::new();
+                reader.read_line(&mut arg).unwrap();
+                args.push(arg.trim_end().to_string());
+            }
+            let mut data = data.lock().unwrap();
+            let reply = match args[0].as_str() {
+                "SET" => {
+                    assert_eq!(args[3], "EX");
+                    data.insert(args[1].clone(), args[2].clone());
+                    "+OK\r\n".to_string()
+                }
+                "AUTH" => {
+                    data.insert("AUTH".to_string(), args[1..].join(" "));
+                    "+OK\r\n".to_string()
+                }
+                "GET" => match data.get(&args[1]) {
+                    Some(v) => format!("${}\r\n{}\r\n", v.len(), v),
+                    None => "$-1\r\n".to_string(),
+                },
+                _ => "-ERR unknown command\r\n".to_string(),
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn entries_are_shared_via_redis() {
+        let (port, _) = fake_redis(usize::MAX);
+        let cache = RedisTokenInfoCache::new(format!("redis://127.0.0.1:{}", port))
+            .unwrap()
+            .with_timeout(Duration::from_secs(5));
+        let key = TokenHasher::random().key(&AccessToken::new("token"));
+        let token_info = TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read")],
+            expires_in_seconds: Some(30),
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        };
+
+        assert_eq!(CacheBackend::get(&cache, &key).unwrap(), None);
+        CacheBackend::put(&cache, &key, &token_info).unwrap();
+        let cached = CacheBackend::get(&cache, &key).unwrap().unwrap();
+
+        assert_eq!(cached.user_id, token_info.user_id);
+        assert!(cached.expires_in_seconds.unwrap() <= 30);
+    }
+
+    #[test]
+    fn closed_connections_are_replaced() {
+        let (port, _) = fake_redis(1);
+        let cache = RedisTokenInfoCache::new(format!("redis://127.0.0.1:{}", port))
+            .unwrap()
+            .with_timeout(Duration::from_secs(5));
+        let key = TokenHasher::random().key(&AccessToken::new("token"));
+
+        for _ in 0..3 {
+            assert_eq!(CacheBackend::get(&cache, &key).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn credentials_are_percent_decoded() {
+        let key = TokenHasher::random().key(&AccessToken::new("token"));
+        for &(credentials, auth) in &[
+            (":p%40ss%3Aw%25rd", "p@ss:w%rd"),
+            ("ci%40user:p%40ss", "ci@user p@ss"),
+        ] {
+            let (port, data) = fake_redis(usize::MAX);
+            let cache =
+                RedisTokenInfoCache::new(format!("redis://{}@127.0.0.1:{}", credentials, port))
+                    .unwrap()
+                    .with_timeout(Duration::from_secs(5));
+
+            assert_eq!(CacheBackend::get(&cache, &key).unwrap(), None);
+            assert_eq!(data.lock().unwrap()["AUTH"], auth);
+        }
+    }
+
+    #[test]
+    fn unreachable_redis_is_skipped_until_retry() {
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let cache = RedisTokenInfoCache::new(format!("redis://127.0.0.1:{}", port))
+            .unwrap()
+            .with_retry_after(Duration::from_secs(60));
+        let key = TokenHasher::random().key(&AccessToken::new("token"));
+
+        assert!(CacheBackend::get(&cache, &key).is_err());
+        assert_eq!(CacheBackend::get(&cache, &key).unwrap(), None);
+    }
+}
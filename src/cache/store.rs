@@ -0,0 +1,356 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use super::{CacheConfig, EvictionStrategy, TokenKey};
+use crate::TokenInfo;
+
+/// Position of an entry in the eviction order.
+///
+/// Entries with the lowest rank get evicted first. The sequence number
+/// makes the rank unique.
+type Rank = (u64, u64);
+
+struct Entry {
+    token_info: TokenInfo,
+    inserted: Instant,
+    expires_at: Instant,
+    size: usize,
+    rank: Rank,
+}
+
+/// The state of a `TokenInfoCache`. Not thread safe.
+pub(crate) struct Store {
+    config: CacheConfig,
+    entries: HashMap<TokenKey, Entry>,
+    order: BTreeMap<Rank, TokenKey>,
+    bytes: usize,
+    seq: u64,
+    created: Instant,
+    sketch: Option<FrequencySketch>,
+}
+
+impl Store {
+    pub fn new(config: CacheConfig) -> Store {
+        let sketch = if config.eviction == EvictionStrategy::TinyLfu {
+            Some(FrequencySketch::new(config.max_entries))
+        } else {
+            None
+        };
+        Store {
+            config,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            bytes: 0,
+            seq: 0,
+            created: Instant::now(),
+            sketch,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
+
+    /// Returns the cached `TokenInfo` with `expires_in_seconds` adjusted
+    /// to the time already spent in the cache.
+    pub fn get(&mut self, key: &TokenKey, now: Instant) -> Option<TokenInfo> {
+        if let Some(ref mut sketch) = self.sketch {
+            sketch.increment(key);
+        }
+
+        let expires_at = self.entries.get(key)?.expires_at;
+        if expires_at <= now {
+            self.remove(key);
+            return None;
+        }
+
+        if self.config.eviction != EvictionStrategy::TtlOnly {
+            let rank = self.next_rank(expires_at);
+            let entry = self.entries.get_mut(key)?;
+            let old_rank = mem::replace(&mut entry.rank, rank);
+            if let Some(key) = self.order.remove(&old_rank) {
+                self.order.insert(rank, key);
+            }
+        }
+
+        let entry = self.entries.get(key)?;
+        let mut token_info = entry.token_info.clone();
+        let elapsed = now.duration_since(entry.inserted).as_secs();
+        token_info.expires_in_seconds = token_info
+            .expires_in_seconds
+            .map(|expires_in| expires_in.saturating_sub(elapsed));
+        Some(token_info)
+    }
+
+    /// Inserts the `TokenInfo` and returns the number of entries
+    /// that had to be evicted.
+    pub fn put(&mut self, key: &TokenKey, token_info: &TokenInfo, now: Instant) -> usize {
+        let ttl = super::ttl(token_info, self.config.max_ttl);
+        if ttl == Duration::from_secs(0) || self.config.max_entries == 0 {
+            return 0;
+        }
+
+        let size = estimate_size(token_info);
+        if let Some(max_bytes) = self.config.max_bytes {
+            if size > max_bytes {
+                return 0;
+            }
+        }
+
+        self.remove(key);
+
+        if self.is_over_bounds(size) && !self.admit(key) {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.is_over_bounds(size) {
+            let victim = match self.order.values().next() {
+                Some(victim) => victim.clone(),
+                None => break,
+            };
+            self.remove(&victim);
+            evicted += 1;
+        }
+
+        let expires_at = now + ttl;
+        let rank = self.next_rank(expires_at);
+        self.order.insert(rank, key.clone());
+        self.entries.insert(
+            key.clone(),
+            Entry {
+                token_info: token_info.clone(),
+                inserted: now,
+                expires_at,
+                size,
+                rank,
+            },
+        );
+        self.bytes += size;
+
+        evicted
+    }
+
+    fn remove(&mut self, key: &TokenKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.rank);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn is_over_bounds(&self, additional_bytes: usize) -> bool {
+        if self.entries.len() >= self.config.max_entries {
+            return true;
+        }
+        match self.config.max_bytes {
+            Some(max_bytes) => self.bytes + additional_bytes > max_bytes,
+            None => false,
+        }
+    }
+
+    /// TinyLFU admission: A new entry may only replace the next victim
+    /// if it has been requested more frequently.
+    fn admit(&self, key: &TokenKey) -> bool {
+        let sketch = match self.sketch {
+            Some(ref sketch) => sketch,
+            None => return true,
+        };
+        match self.order.values().next() {
+            Some(victim) => sketch.estimate(key) > sketch.estimate(victim),
+            None => true,
+        }
+    }
+
+    fn next_rank(&mut self, expires_at: Instant) -> Rank {
+        self.seq += 1;
+        match self.config.eviction {
+            EvictionStrategy::TtlOnly => {
+                let expires_in = expires_at.duration_since(self.created);
+                (expires_in.as_millis() as u64, self.seq)
+            }
+            EvictionStrategy::Lru | EvictionStrategy::TinyLfu => (0, self.seq),
+        }
+    }
+}
+
+fn estimate_size(token_info: &TokenInfo) -> usize {
+    mem::size_of::<Entry>()
+        + mem::size_of::<TokenKey>()
+        + token_info.user_id.as_ref().map_or(0, |uid| uid.0.len())
+        + token_info
+            .scope
+            .iter()
+            .map(|scope| mem::size_of_val(scope) + scope.0.len())
+            .sum::<usize>()
+        + token_info
+            .certificate_thumbprint
+            .as_ref()
+            .map_or(0, String::len)
+}
+
+const SKETCH_DEPTH: usize = 4;
+const MAX_COUNT: u8 = 15;
+
+/// A count-min sketch with 4 bit saturating counters that halves all
+/// counters once a sample period is over so that old popularity fades.
+struct FrequencySketch {
+    table: Vec<u8>,
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> FrequencySketch {
+        let width = capacity.max(16).next_power_of_two();
+        FrequencySketch {
+            table: vec![0; width * SKETCH_DEPTH],
+            mask: width - 1,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn increment(&mut self, key: &TokenKey) {
+        let width = self.mask + 1;
+        for row in 0..SKETCH_DEPTH {
+            let idx = row * width + self.index(key, row);
+            if self.table[idx] < MAX_COUNT {
+                self.table[idx] += 1;
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.table.iter_mut().for_each(|count| *count /= 2);
+            self.additions /= 2;
+        }
+    }
+
+    fn estimate(&self, key: &TokenKey) -> u8 {
+        let width = self.mask + 1;
+        (0..SKETCH_DEPTH)
+            .map(|row| self.table[row * width + self.index(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn index(&self, key: &TokenKey, row: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as usize & self.mask
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::TokenHasher;
+    use crate::{AccessToken, Scope, UserId};
+
+    fn key(token: &str) -> TokenKey {
+        TokenHasher::with_secret("test").key(&AccessToken::new(token))
+    }
+
+    fn token_info(expires_in_seconds: Option<u64>) -> TokenInfo {
+        TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read")],
+            expires_in_seconds,
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        }
+    }
+
+    fn config(eviction: EvictionStrategy) -> CacheConfig {
+        CacheConfig::default()
+            .with_max_entries(2)
+            .with_eviction(eviction)
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let now = Instant::now();
+        let mut store = Store::new(config(EvictionStrategy::Lru));
+
+        store.put(&key("a"), &token_info(Some(60)), now);
+        store.put(&key("b"), &token_info(Some(60)), now);
+        assert!(store.get(&key("a"), now).is_some());
+        assert_eq!(store.put(&key("c"), &token_info(Some(60)), now), 1);
+
+        assert!(store.get(&key("a"), now).is_some());
+        assert!(store.get(&key("b"), now).is_none());
+        assert!(store.get(&key("c"), now).is_some());
+    }
+
+    #[test]
+    fn ttl_only_evicts_soonest_expiring_and_expires() {
+        let now = Instant::now();
+        let mut store = Store::new(config(EvictionStrategy::TtlOnly));
+
+        store.put(&key("a"), &token_info(Some(60)), now);
+        store.put(&key("b"), &token_info(Some(10)), now);
+        assert_eq!(store.put(&key("c"), &token_info(Some(30)), now), 1);
+        assert!(store.get(&key("b"), now).is_none());
+
+        let later = now + Duration::from_secs(31);
+        assert!(store.get(&key("c"), later).is_none());
+        assert_eq!(
+            store.get(&key("a"), later).unwrap().expires_in_seconds,
+            Some(29)
+        );
+    }
+
+    #[test]
+    fn tiny_lfu_rejects_rarely_requested_entries() {
+        let now = Instant::now();
+        let mut store = Store::new(config(EvictionStrategy::TinyLfu));
+
+        store.put(&key("a"), &token_info(Some(60)), now);
+        store.put(&key("b"), &token_info(Some(60)), now);
+        for _ in 0..3 {
+            store.get(&key("a"), now);
+            store.get(&key("b"), now);
+        }
+
+        store.get(&key("c"), now);
+        assert_eq!(store.put(&key("c"), &token_info(Some(60)), now), 0);
+        assert!(store.get(&key("c"), now).is_none());
+
+        for _ in 0..5 {
+            store.get(&key("c"), now);
+        }
+        assert_eq!(store.put(&key("c"), &token_info(Some(60)), now), 1);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn byte_cap_is_enforced() {
+        let now = Instant::now();
+        let size = estimate_size(&token_info(Some(60)));
+        let mut store =
+            Store::new(CacheConfig::default().with_max_bytes(Some(size * 2 + size / 2)));
+
+        store.put(&key("a"), &token_info(Some(60)), now);
+        store.put(&key("b"), &token_info(Some(60)), now);
+        assert_eq!(store.put(&key("c"), &token_info(Some(60)), now), 1);
+        assert_eq!(store.len(), 2);
+        assert!(store.bytes() <= size * 2 + size / 2);
+    }
+}
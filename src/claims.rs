@@ -0,0 +1,246 @@
+//! Extractors for the members of JSON responses shared by all parsers.
+//!
+//! The parsers of introspection responses, of token endpoint responses
+//! and of credentials files read the same kinds of members. They are
+//! extracted here so that all parsers accept the same encodings, e.g. an
+//! `expires_in` sent as a string, and reject the same values.
+//!
+//! Errors are plain messages which the parsers wrap into their own error
+//! types. They never contain the values of the members since these may
+//! be tokens or secrets.
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+
+use crate::parsers::MAX_EXPIRES_IN_SECONDS;
+use crate::{Scope, ScopeInterner, ScopeList};
+
+/// Parses `bytes` as a JSON object.
+pub(crate) fn object(bytes: &[u8]) -> Result<JsonValue, String> {
+    let json = str::from_utf8(bytes).map_err(|err| format!("String was not UTF-8: {}", err))?;
+    let data = json::parse(json).map_err(|err| err.to_string())?;
+    if data.is_object() {
+        Ok(data)
+    } else {
+        Err(
+            "Expected a JSON object but found something else which i won't show \
+             since it might contain a token."
+                .to_string(),
+        )
+    }
+}
+
+/// The string member `field` of `data` or `None` if it is missing or
+/// `null`.
+pub(crate) fn string<'a>(data: &'a JsonValue, field: &str) -> Result<Option<&'a str>, String> {
+    match data[field] {
+        JsonValue::Null => Ok(None),
+        ref value => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("Expected a string in field '{}'", field)),
+    }
+}
+
+/// The string member `field` of `data` which must be present.
+pub(crate) fn required_string<'a>(data: &'a JsonValue, field: &str) -> Result<&'a str, String> {
+    string(data, field)?.ok_or_else(|| format!("No field '{}' found", field))
+}
+
+/// A number of seconds like `expires_in` or `None` if the member `field`
+/// is missing or `null`.
+///
+/// Some servers (e.g. Azure) send the number as a string which is
+/// accepted as well.
+pub(crate) fn seconds(data: &JsonValue, field: &str) -> Result<Option<u64>, String> {
+    seconds_of(&data[field], field)
+}
+
+/// Like `seconds` for the `value` of the member `field`.
+///
+/// Fractions are rounded to whole seconds.
+pub(crate) fn seconds_of(value: &JsonValue, field: &str) -> Result<Option<u64>, String> {
+    let seconds = match *value {
+        JsonValue::Null => return Ok(None),
+        JsonValue::Number(number) => {
+            let seconds = f64::from(number).round();
+            if seconds.is_nan() || seconds < 0.0 {
+                return Err(format!("Field '{}' must not be negative", field));
+            }
+            // Saturates for huge numbers which are then out of bounds.
+            seconds as u64
+        }
+        ref value => match value.as_str() {
+            Some(seconds) => parse_seconds(seconds, field)?,
+            None => return Err(format!("Expected a number in field '{}'", field)),
+        },
+    };
+    bounded(seconds, field).map(Some)
+}
+
+/// Parses a number of seconds sent as a string, e.g. in a form.
+pub(crate) fn parse_seconds(seconds: &str, field: &str) -> Result<u64, String> {
+    let seconds = seconds
+        .trim()
+        .parse()
+        .map_err(|_| format!("Expected a number in field '{}'", field))?;
+    bounded(seconds, field)
+}
+
+/// The seconds left until the absolute time in seconds since the epoch
+/// of the member `field`, e.g. `exp`, or `None` if it is missing or `null`.
+///
+/// Times in the past are zero seconds away.
+pub(crate) fn seconds_until(data: &JsonValue, field: &str) -> Result<Option<u64>, String> {
+    let timestamp = match data[field] {
+        JsonValue::Null => return Ok(None),
+        ref value => value
+            .as_u64()
+            .ok_or_else(|| format!("Expected a number in field '{}'", field))?,
+    };
+    bounded(timestamp.saturating_sub(unix_time()?), field).map(Some)
+}
+
+/// The current time in seconds since the epoch.
+pub(crate) fn unix_time() -> Result<u64, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .map_err(|err| err.to_string())
+}
+
+fn bounded(seconds: u64, field: &str) -> Result<u64, String> {
+    if seconds > MAX_EXPIRES_IN_SECONDS {
+        Err(format!(
+            "Field '{}' must not be more than {} seconds.",
+            field, MAX_EXPIRES_IN_SECONDS
+        ))
+    } else {
+        Ok(seconds)
+    }
+}
+
+/// Splits space-separated scopes into a canonical list of interned scopes.
+///
+/// Invalid scopes are skipped as described for `server_scope`.
+pub(crate) fn split_scopes(scope: &str) -> Vec<Scope> {
+    let scopes: Vec<_> = scope.split_whitespace().filter_map(server_scope).collect();
+    ScopeList::from(scopes).into_vec()
+}
+
+/// Interns a scope sent by a server.
+///
+/// A scope which is not valid according to RFC 6749 is logged and
+/// skipped instead of failing the whole response, since servers are not
+/// always that strict. Such a scope is never granted.
+pub(crate) fn server_scope(scope: &str) -> Option<Scope> {
+    match ScopeInterner::global().parse(scope) {
+        Ok(scope) => Some(scope),
+        Err(err) => {
+            warn!("Skipping a scope sent by the server: {}", err);
+            None
+        }
+    }
+}
+
+/// Extracts the `x5t#S256` member of a confirmation claim
+/// as defined in [RFC 8705](https://tools.ietf.org/html/rfc8705#section-3.1).
+pub(crate) fn certificate_thumbprint(cnf: &JsonValue) -> Option<String> {
+    cnf["x5t#S256"].as_str().map(str::to_owned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Zero, common lifetimes and the values next to the bound.
+    const SECONDS: &[u64] = &[
+        0,
+        1,
+        59,
+        3599,
+        86_400,
+        123_456_789,
+        MAX_EXPIRES_IN_SECONDS - 1,
+        MAX_EXPIRES_IN_SECONDS,
+    ];
+
+    #[test]
+    fn seconds_are_read_from_numbers_and_strings_alike() {
+        for &n in SECONDS {
+            let encodings = [
+                json::object! { "expires_in" => n },
+                json::object! { "expires_in" => n.to_string() },
+                json::object! { "expires_in" => format!(" {} ", n) },
+            ];
+            for data in &encodings {
+                assert_eq!(seconds(data, "expires_in"), Ok(Some(n)), "{}", data);
+            }
+        }
+        for invalid in &[
+            json::object! { "expires_in" => -1 },
+            json::object! { "expires_in" => -0.6 },
+            json::object! { "expires_in" => "soon" },
+            json::object! { "expires_in" => true },
+        ] {
+            assert!(seconds(invalid, "expires_in").is_err(), "{}", invalid);
+        }
+        assert_eq!(seconds(&json::object! {}, "expires_in"), Ok(None));
+    }
+
+    #[test]
+    fn seconds_until_never_exceed_the_bound() {
+        let now = unix_time().unwrap();
+        for &n in SECONDS {
+            let data = json::object! { "exp" => now.saturating_add(n) };
+            match seconds_until(&data, "exp") {
+                Ok(Some(left)) => assert!(left <= n && left + 5 >= n, "{} {}", n, left),
+                other => panic!("unexpected result for {}: {:?}", n, other),
+            }
+            let past = json::object! { "exp" => now.saturating_sub(n) };
+            assert_eq!(seconds_until(&past, "exp"), Ok(Some(0)));
+        }
+        assert_eq!(seconds_until(&json::object! {}, "exp"), Ok(None));
+    }
+
+    #[test]
+    fn seconds_out_of_range_are_rejected() {
+        let now = unix_time().unwrap();
+        for &n in &[MAX_EXPIRES_IN_SECONDS + 1, 123_456_789_012, u64::MAX] {
+            assert!(seconds(&json::object! { "expires_in" => n }, "expires_in").is_err());
+            assert!(parse_seconds(&n.to_string(), "expires_in").is_err());
+            let huge = json::parse(&format!(r#"{{"expires_in": {}e300}}"#, n)).unwrap();
+            assert!(seconds(&huge, "expires_in").is_err(), "{}", huge);
+            let data = json::object! { "exp" => now.saturating_add(n) };
+            assert!(seconds_until(&data, "exp").is_err(), "{}", n);
+        }
+    }
+
+    #[test]
+    fn split_scopes_are_canonical() {
+        let samples = ["", "  ", "read", "write read", "b a b  a\tc", "c\nb a"];
+        for sample in &samples {
+            let scopes = split_scopes(sample);
+            assert!(scopes.windows(2).all(|w| w[0] < w[1]), "{:?}", scopes);
+            let joined: Vec<_> = scopes.iter().map(Scope::as_str).collect();
+            assert_eq!(split_scopes(&joined.join(" ")), scopes);
+            for scope in sample.split_whitespace() {
+                assert!(scopes.contains(&Scope::new(scope)));
+            }
+        }
+    }
+
+    #[test]
+    fn strings_are_optional_unless_required() {
+        let data = json::object! { "short" => "a", "long" => "a".repeat(64), "n" => 1 };
+        assert_eq!(string(&data, "short"), Ok(Some("a")));
+        assert_eq!(required_string(&data, "long").map(str::len), Ok(64));
+        assert_eq!(string(&data, "missing"), Ok(None));
+        assert!(required_string(&data, "missing").is_err());
+        assert!(string(&data, "n").is_err());
+        assert!(object(b"[]").is_err());
+        assert!(object(b"\xff").is_err());
+        assert!(object(b"{}").is_ok());
+    }
+}
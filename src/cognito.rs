@@ -0,0 +1,68 @@
+//! Shared settings for AWS Cognito user pools.
+
+/// Assembles the base URL of a Cognito user pool domain.
+///
+/// `domain` is either the prefix of a Cognito hosted domain which is then
+/// located in `region` or a custom domain like `auth.example.com` for which
+/// `region` is ignored.
+pub(crate) fn domain_url(domain: &str, region: &str) -> Result<String, String> {
+    let domain = domain.trim().trim_end_matches('/');
+    if domain.is_empty() {
+        return Err("The Cognito domain must not be empty".to_string());
+    }
+
+    if let Some(custom_domain) = domain.strip_prefix("https://") {
+        return Ok(format!("https://{}", custom_domain));
+    }
+    if domain.contains('.') {
+        return Ok(format!("https://{}", domain));
+    }
+
+    if !is_valid_region(region) {
+        return Err(format!("'{}' is not a valid AWS region", region));
+    }
+
+    Ok(format!(
+        "https://{}.auth.{}.amazoncognito.com",
+        domain, region
+    ))
+}
+
+/// Regions look like `eu-central-1` or `us-gov-west-1`.
+fn is_valid_region(region: &str) -> bool {
+    let parts: Vec<&str> = region.split('-').collect();
+    parts.len() >= 3
+        && parts.iter().all(|p| {
+            !p.is_empty()
+                && p.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        })
+        && parts[parts.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hosted_domain_is_located_in_region() {
+        assert_eq!(
+            domain_url("my-pool", "eu-central-1").unwrap(),
+            "https://my-pool.auth.eu-central-1.amazoncognito.com"
+        );
+        assert!(domain_url("my-pool", "EU-central").is_err());
+        assert!(domain_url("my-pool", "").is_err());
+    }
+
+    #[test]
+    fn custom_domain_ignores_region() {
+        assert_eq!(
+            domain_url("auth.example.com/", "").unwrap(),
+            "https://auth.example.com"
+        );
+        assert_eq!(
+            domain_url("https://auth.example.com", "us-east-1").unwrap(),
+            "https://auth.example.com"
+        );
+    }
+}
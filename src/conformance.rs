@@ -0,0 +1,446 @@
+//! A test-kit for `TokenInfoParser`s.
+//!
+//! The corpus contains introspection responses as returned by common
+//! authorization servers together with the `TokenInfo` a parser is
+//! expected to create from them. `check_parser` runs a parser against the
+//! samples of a `Provider` and reports every mismatch which is useful when
+//! writing a custom parser.
+//!
+//! ```rust
+//! use tokkit::conformance::{check_parser, Provider};
+//! use tokkit::parsers::PlanBTokenInfoParser;
+//!
+//! let report = check_parser(&PlanBTokenInfoParser, Provider::PlanB);
+//! assert!(report.is_conformant(), "{}", report);
+//! ```
+//!
+//! Providers following [RFC 7662](https://tools.ietf.org/html/rfc7662)
+//! send the expiry as an absolute timestamp in `exp`. A parser for them
+//! has to convert it to the seconds left.
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::parsers::TokenInfoParser;
+use crate::TokenInfo;
+
+/// An authorization server the corpus contains responses of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Google,
+    Amazon,
+    PlanB,
+    Keycloak,
+    Auth0,
+    Okta,
+}
+
+impl Provider {
+    /// All providers contained in the corpus.
+    pub fn all() -> &'static [Provider] {
+        &[
+            Provider::Google,
+            Provider::Amazon,
+            Provider::PlanB,
+            Provider::Keycloak,
+            Provider::Auth0,
+            Provider::Okta,
+        ]
+    }
+}
+
+/// The expected `expires_in_seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedExpiry {
+    /// No expiry.
+    Absent,
+    /// The number of seconds sent by the provider.
+    Seconds(u64),
+    /// The seconds left until the given timestamp in seconds since the
+    /// epoch.
+    Until(u64),
+}
+
+/// A response of an authorization server and the expected result.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub name: &'static str,
+    pub provider: Provider,
+    pub body: &'static str,
+    pub active: bool,
+    pub user_id: Option<&'static str>,
+    /// The expected scopes in any order.
+    pub scope: &'static [&'static str],
+    pub expires_in: ExpectedExpiry,
+}
+
+/// A timestamp far in the future used in the samples sending `exp`.
+const FAR_FUTURE: u64 = 4_102_444_800;
+
+/// Seconds an absolute expiry may be off to compensate the time passed
+/// since it was parsed.
+const TOLERANCE_SECONDS: u64 = 60;
+
+static CORPUS: &[Sample] = &[
+    Sample {
+        name: "google-v3",
+        provider: Provider::Google,
+        body: r#"{
+            "aud": "8819981768.apps.googleusercontent.com",
+            "user_id": "123456789",
+            "scope": "https://www.googleapis.com/auth/drive.metadata.readonly",
+            "expires_in": 436
+        }"#,
+        active: true,
+        user_id: Some("123456789"),
+        scope: &["https://www.googleapis.com/auth/drive.metadata.readonly"],
+        expires_in: ExpectedExpiry::Seconds(436),
+    },
+    Sample {
+        name: "google-v3-multiple-scopes",
+        provider: Provider::Google,
+        body: r#"{
+            "azp": "8819981768.apps.googleusercontent.com",
+            "aud": "8819981768.apps.googleusercontent.com",
+            "user_id": "110169484474386276334",
+            "scope": "openid https://www.googleapis.com/auth/userinfo.email",
+            "expires_in": 3599,
+            "email_verified": "true",
+            "access_type": "online"
+        }"#,
+        active: true,
+        user_id: Some("110169484474386276334"),
+        scope: &["openid", "https://www.googleapis.com/auth/userinfo.email"],
+        expires_in: ExpectedExpiry::Seconds(3599),
+    },
+    Sample {
+        name: "amazon",
+        provider: Provider::Amazon,
+        body: r#"{
+            "iss": "https://www.amazon.com",
+            "user_id": "amznl.account.K2LI23KL2LK2",
+            "aud": "amznl.oa2-client.ASFWDFBRN",
+            "app_id": "amznl.application.436457DFHDH",
+            "exp": 3597,
+            "iat": 1311280970
+        }"#,
+        active: true,
+        user_id: Some("amznl.account.K2LI23KL2LK2"),
+        scope: &[],
+        expires_in: ExpectedExpiry::Seconds(3597),
+    },
+    Sample {
+        name: "planb",
+        provider: Provider::PlanB,
+        body: r#"{
+            "access_token": "token",
+            "cn": true,
+            "expires_in": 28292,
+            "grant_type": "password",
+            "open_id": "token",
+            "realm": "/services",
+            "scope": ["cn"],
+            "token_type": "Bearer",
+            "uid": "test2"
+        }"#,
+        active: true,
+        user_id: Some("test2"),
+        scope: &["cn"],
+        expires_in: ExpectedExpiry::Seconds(28292),
+    },
+    Sample {
+        name: "planb-multiple-scopes",
+        provider: Provider::PlanB,
+        body: r#"{
+            "expires_in": 3600,
+            "realm": "/employees",
+            "scope": ["uid", "cn", "sales_order.read"],
+            "token_type": "Bearer",
+            "uid": "jdoe"
+        }"#,
+        active: true,
+        user_id: Some("jdoe"),
+        scope: &["uid", "cn", "sales_order.read"],
+        expires_in: ExpectedExpiry::Seconds(3600),
+    },
+    Sample {
+        name: "keycloak",
+        provider: Provider::Keycloak,
+        body: r#"{
+            "exp": 4102444800,
+            "iat": 1600000000,
+            "jti": "d2f0c6a6-4c59-4b4b-9ad3-5c1c1e6f9c3e",
+            "iss": "https://sso.example.com/auth/realms/services",
+            "aud": "account",
+            "sub": "f6b3c8d2-2c55-4d6c-9a3e-77a1f0c1e2b9",
+            "typ": "Bearer",
+            "azp": "orders",
+            "scope": "openid email profile",
+            "client_id": "orders",
+            "username": "service-account-orders",
+            "active": true
+        }"#,
+        active: true,
+        user_id: Some("f6b3c8d2-2c55-4d6c-9a3e-77a1f0c1e2b9"),
+        scope: &["openid", "email", "profile"],
+        expires_in: ExpectedExpiry::Until(FAR_FUTURE),
+    },
+    Sample {
+        name: "keycloak-inactive",
+        provider: Provider::Keycloak,
+        body: r#"{"active": false}"#,
+        active: false,
+        user_id: None,
+        scope: &[],
+        expires_in: ExpectedExpiry::Absent,
+    },
+    Sample {
+        name: "auth0",
+        provider: Provider::Auth0,
+        body: r#"{
+            "active": true,
+            "iss": "https://example.eu.auth0.com/",
+            "sub": "auth0|5f7c8ec7c33c6c004bbafe82",
+            "aud": ["https://api.example.com", "https://example.eu.auth0.com/userinfo"],
+            "iat": 1600000000,
+            "exp": 4102444800,
+            "azp": "my5BHuRqfqTK0o4nU2MmTWHvUlcFsGmq",
+            "scope": "openid profile read:orders"
+        }"#,
+        active: true,
+        user_id: Some("auth0|5f7c8ec7c33c6c004bbafe82"),
+        scope: &["openid", "profile", "read:orders"],
+        expires_in: ExpectedExpiry::Until(FAR_FUTURE),
+    },
+    Sample {
+        name: "okta",
+        provider: Provider::Okta,
+        body: r#"{
+            "active": true,
+            "token_type": "Bearer",
+            "scope": "openid profile",
+            "client_id": "0oabskvc6442nkvQO0h7",
+            "username": "john.doe@example.com",
+            "exp": 4102444800,
+            "iat": 1600000000,
+            "sub": "john.doe@example.com",
+            "aud": "https://example.okta.com",
+            "iss": "https://example.okta.com/oauth2/default",
+            "jti": "AT.7P4KlczBYVcWLkxduEuKeZfeiNYkZIC9uGJI1KlYhDY",
+            "uid": "00uid4BxXw6I6TV4m0g3"
+        }"#,
+        active: true,
+        user_id: Some("john.doe@example.com"),
+        scope: &["openid", "profile"],
+        expires_in: ExpectedExpiry::Until(FAR_FUTURE),
+    },
+    Sample {
+        name: "okta-inactive",
+        provider: Provider::Okta,
+        body: r#"{"active": false}"#,
+        active: false,
+        user_id: None,
+        scope: &[],
+        expires_in: ExpectedExpiry::Absent,
+    },
+];
+
+/// Returns all samples of the corpus.
+pub fn corpus() -> &'static [Sample] {
+    CORPUS
+}
+
+/// Returns the samples of the given `Provider`.
+pub fn samples_of(provider: Provider) -> impl Iterator<Item = &'static Sample> {
+    CORPUS
+        .iter()
+        .filter(move |sample| sample.provider == provider)
+}
+
+/// A field of a `TokenInfo` that did not match the expectation or a
+/// sample that could not be parsed at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub sample: &'static str,
+    /// The name of the field or `parse` if parsing failed.
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: '{}' expected {} but was {}",
+            self.sample, self.field, self.expected, self.actual
+        )
+    }
+}
+
+/// The result of running a parser against samples of the corpus.
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    /// The number of samples the parser was run against.
+    pub samples: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ConformanceReport {
+    /// Returns `true` if all samples were parsed as expected.
+    pub fn is_conformant(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} mismatches in {} samples",
+            self.mismatches.len(),
+            self.samples
+        )?;
+        for mismatch in &self.mismatches {
+            write!(f, "\n  {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the `parser` against all samples of the `provider`.
+pub fn check_parser<P>(parser: &P, provider: Provider) -> ConformanceReport
+where
+    P: TokenInfoParser + ?Sized,
+{
+    check_samples(parser, samples_of(provider))
+}
+
+/// Runs the `parser` against the given samples.
+pub fn check_samples<'a, P, I>(parser: &P, samples: I) -> ConformanceReport
+where
+    P: TokenInfoParser + ?Sized,
+    I: IntoIterator<Item = &'a Sample>,
+{
+    let mut report = ConformanceReport::default();
+    for sample in samples {
+        report.samples += 1;
+        match parser.parse(sample.body.as_bytes()) {
+            Ok(token_info) => compare(sample, &token_info, &mut report.mismatches),
+            Err(err) => report.mismatches.push(Mismatch {
+                sample: sample.name,
+                field: "parse",
+                expected: "a TokenInfo".to_string(),
+                actual: format!("error '{}'", err),
+            }),
+        }
+    }
+    report
+}
+
+fn compare(sample: &Sample, token_info: &TokenInfo, mismatches: &mut Vec<Mismatch>) {
+    let mut mismatch = |field, expected: String, actual: String| {
+        mismatches.push(Mismatch {
+            sample: sample.name,
+            field,
+            expected,
+            actual,
+        })
+    };
+
+    if token_info.active != sample.active {
+        mismatch(
+            "active",
+            sample.active.to_string(),
+            token_info.active.to_string(),
+        );
+    }
+
+    let user_id = token_info.user_id.as_ref().map(|user_id| user_id.0.as_str());
+    if user_id != sample.user_id {
+        mismatch(
+            "user_id",
+            format!("{:?}", sample.user_id),
+            format!("{:?}", user_id),
+        );
+    }
+
+    let mut expected_scope = sample.scope.to_vec();
+    expected_scope.sort_unstable();
+    let mut scope: Vec<&str> = token_info.scope.iter().map(|s| s.as_str()).collect();
+    scope.sort_unstable();
+    if scope != expected_scope {
+        mismatch(
+            "scope",
+            format!("{:?}", expected_scope),
+            format!("{:?}", scope),
+        );
+    }
+
+    let expires_in = token_info.expires_in_seconds;
+    let expiry_matches = match sample.expires_in {
+        ExpectedExpiry::Absent => expires_in.is_none(),
+        ExpectedExpiry::Seconds(seconds) => expires_in == Some(seconds),
+        ExpectedExpiry::Until(timestamp) => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let left = timestamp.saturating_sub(now);
+            expires_in.is_some_and(|e| e <= left && e + TOLERANCE_SECONDS >= left)
+        }
+    };
+    if !expiry_matches {
+        mismatch(
+            "expires_in_seconds",
+            format!("{:?}", sample.expires_in),
+            format!("{:?}", expires_in),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsers::*;
+
+    #[test]
+    fn the_built_in_parsers_conform() {
+        let report = check_parser(&GoogleV3TokenInfoParser, Provider::Google);
+        assert!(report.is_conformant(), "{}", report);
+        let report = check_parser(&AmazonTokenInfoParser, Provider::Amazon);
+        assert!(report.is_conformant(), "{}", report);
+        let report = check_parser(&PlanBTokenInfoParser, Provider::PlanB);
+        assert!(report.is_conformant(), "{}", report);
+    }
+
+    #[test]
+    fn every_provider_has_samples() {
+        for &provider in Provider::all() {
+            assert!(samples_of(provider).next().is_some(), "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn a_relative_expiry_does_not_conform_to_rfc_7662() {
+        let parser = CustomTokenInfoParser::new(
+            Some("active"),
+            Some("sub"),
+            Some("scope"),
+            Some("exp"),
+        );
+        let report = check_parser(&parser, Provider::Keycloak);
+        assert_eq!(report.samples, 2);
+        let fields: Vec<_> = report
+            .mismatches
+            .iter()
+            .map(|m| (m.sample, m.field))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("keycloak", "expires_in_seconds"),
+                ("keycloak-inactive", "parse"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Decoding of compressed response bodies.
+//!
+//! The decoding is done here explicitly so that parsers always receive
+//! plain bytes regardless of the features `reqwest` was built with.
+//! Bodies which are not compressed are passed on without being copied.
+use bytes::Bytes;
+use miniz_oxide::inflate::{self, DecompressError, TINFLStatus};
+
+use crate::{TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
+
+/// The value sent with `Accept-Encoding` if compressed responses are
+/// requested.
+pub(crate) const ACCEPT_ENCODING_VALUE: &str = "gzip, deflate";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const GZIP_HEADER_LEN: usize = 10;
+const GZIP_TRAILER_LEN: usize = 8;
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+/// Decodes `body` according to the value of the `Content-Encoding` header.
+///
+/// The decoded body may not exceed `max_response_size` bytes.
+pub(crate) fn decode_body(
+    content_encoding: Option<&str>,
+    body: Bytes,
+    max_response_size: usize,
+) -> TokenInfoResult<Bytes> {
+    let content_encoding = match content_encoding {
+        Some(content_encoding) => content_encoding.trim().to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+
+    match content_encoding.as_str() {
+        "" | "identity" => Ok(body),
+        "gzip" | "x-gzip" => decode_gzip(&body, max_response_size).map(Bytes::from),
+        "deflate" => decode_deflate(&body, max_response_size).map(Bytes::from),
+        unsupported => Err(TokenInfoErrorKind::InvalidResponseContent(format!(
+            "Unsupported content encoding '{}'",
+            unsupported
+        ))
+        .into()),
+    }
+}
+
+fn decode_gzip(body: &[u8], max_response_size: usize) -> TokenInfoResult<Vec<u8>> {
+    if body.len() < GZIP_HEADER_LEN + GZIP_TRAILER_LEN || body[0..2] != GZIP_MAGIC {
+        return Err(invalid("Not a gzip stream"));
+    }
+    if body[2] != 8 {
+        return Err(invalid("Unsupported gzip compression method"));
+    }
+
+    let flags = body[3];
+    let mut pos = GZIP_HEADER_LEN;
+
+    if flags & FLAG_EXTRA != 0 {
+        let len = body
+            .get(pos..pos + 2)
+            .map(|b| usize::from(b[0]) | usize::from(b[1]) << 8)
+            .ok_or_else(|| invalid("Truncated gzip header"))?;
+        pos += 2 + len;
+    }
+    if flags & FLAG_NAME != 0 {
+        pos = skip_zero_terminated(body, pos)?;
+    }
+    if flags & FLAG_COMMENT != 0 {
+        pos = skip_zero_terminated(body, pos)?;
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+
+    if pos > body.len() - GZIP_TRAILER_LEN {
+        return Err(invalid("Truncated gzip header"));
+    }
+
+    let trailer = &body[body.len() - GZIP_TRAILER_LEN..];
+    let compressed = &body[pos..body.len() - GZIP_TRAILER_LEN];
+    let decoded = inflate::decompress_to_vec_with_limit(compressed, max_response_size)
+        .map_err(|err| decompress_error(err, max_response_size))?;
+
+    // The trailer holds the CRC32 and the length modulo 2^32 of the
+    // decoded data, both little endian.
+    if read_u32_le(&trailer[0..4]) != crc32(&decoded) {
+        return Err(invalid("gzip checksum mismatch"));
+    }
+    if read_u32_le(&trailer[4..8]) != decoded.len() as u32 {
+        return Err(invalid("gzip length mismatch"));
+    }
+
+    Ok(decoded)
+}
+
+/// "deflate" should be zlib wrapped but some servers send raw deflate
+/// streams, so both are accepted.
+fn decode_deflate(body: &[u8], max_response_size: usize) -> TokenInfoResult<Vec<u8>> {
+    match inflate::decompress_to_vec_zlib_with_limit(body, max_response_size) {
+        Ok(decoded) => Ok(decoded),
+        Err(ref err) if err.status == TINFLStatus::HasMoreOutput => {
+            Err(TokenInfoErrorKind::ResponseTooLarge(max_response_size).into())
+        }
+        Err(_) => inflate::decompress_to_vec_with_limit(body, max_response_size)
+            .map_err(|err| decompress_error(err, max_response_size)),
+    }
+}
+
+fn skip_zero_terminated(body: &[u8], pos: usize) -> TokenInfoResult<usize> {
+    body.get(pos..)
+        .and_then(|rest| rest.iter().position(|&b| b == 0))
+        .map(|terminator| pos + terminator + 1)
+        .ok_or_else(|| invalid("Truncated gzip header"))
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0])
+        | u32::from(bytes[1]) << 8
+        | u32::from(bytes[2]) << 16
+        | u32::from(bytes[3]) << 24
+}
+
+/// The CRC32 (IEEE 802.3, reflected) gzip uses to check the decoded data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn decompress_error(err: DecompressError, max_response_size: usize) -> TokenInfoError {
+    if err.status == TINFLStatus::HasMoreOutput {
+        TokenInfoErrorKind::ResponseTooLarge(max_response_size).into()
+    } else {
+        invalid(&format!("Could not decompress response body: {}", err))
+    }
+}
+
+fn invalid(msg: &str) -> TokenInfoError {
+    TokenInfoErrorKind::InvalidResponseContent(msg.to_string()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miniz_oxide::deflate;
+
+    const SAMPLE: &[u8] = br#"{"uid":"test2","scope":["cn"],"expires_in":28292}"#;
+
+    fn gzip(data: &[u8], flags: u8, extra: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, flags, 0, 0, 0, 0, 0, 255];
+        out.extend_from_slice(extra);
+        out.extend(deflate::compress_to_vec(data, 6));
+        out.extend_from_slice(&crc32(data).to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn computes_the_crc32_of_gzip() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn passes_through_identity() {
+        let decoded = decode_body(Some("identity"), Bytes::from_static(SAMPLE), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+        assert_eq!(SAMPLE.as_ptr(), decoded.as_ptr());
+        let decoded = decode_body(None, Bytes::from_static(SAMPLE), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+    }
+
+    #[test]
+    fn decodes_gzip() {
+        let decoded = decode_body(Some("gzip"), gzip(SAMPLE, 0, &[]).into(), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+    }
+
+    #[test]
+    fn decodes_gzip_with_file_name() {
+        let body = gzip(SAMPLE, FLAG_NAME, b"tokeninfo.json\0");
+        let decoded = decode_body(Some("GZIP"), body.into(), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+    }
+
+    #[test]
+    fn rejects_gzip_with_corrupted_trailer() {
+        let mut body = gzip(SAMPLE, 0, &[]);
+        let len = body.len();
+        body[len - 8] ^= 0xff;
+        let err = decode_body(Some("gzip"), body.into(), 1024).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{}", err);
+
+        let mut body = gzip(SAMPLE, 0, &[]);
+        let len = body.len();
+        body[len - 1] ^= 0xff;
+        let err = decode_body(Some("gzip"), body.into(), 1024).unwrap_err();
+        assert!(err.to_string().contains("length"), "{}", err);
+    }
+
+    #[test]
+    fn decodes_zlib_and_raw_deflate() {
+        let zlib = deflate::compress_to_vec_zlib(SAMPLE, 6);
+        let decoded = decode_body(Some("deflate"), zlib.into(), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+
+        let raw = deflate::compress_to_vec(SAMPLE, 6);
+        let decoded = decode_body(Some("deflate"), raw.into(), 1024).unwrap();
+        assert_eq!(SAMPLE, &decoded[..]);
+    }
+
+    #[test]
+    fn decoded_body_is_limited() {
+        let err = decode_body(Some("gzip"), gzip(SAMPLE, 0, &[]).into(), 10).unwrap_err();
+        match *err.kind() {
+            TokenInfoErrorKind::ResponseTooLarge(10) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_encoding() {
+        assert!(decode_body(Some("br"), Bytes::from_static(SAMPLE), 1024).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// The header an `IntrospectionContext` sends its correlation id in.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// The header an `IntrospectionContext` sends its peer in.
+pub const PEER_HEADER: &str = "x-forwarded-for";
+
+/// Describes the request an introspection is done for.
+///
+/// The context is sent to the introspection service as
+/// `X-Correlation-Id` and `X-Forwarded-For` headers and becomes part of the
+/// log messages and failure metrics of the introspection so that a
+/// rejected token can be traced back to the request that presented it.
+///
+/// A `deadline`, e.g. derived from the time the originating request may
+/// still take, caps the time spent on retries and the timeout of each HTTP
+/// request of the introspection. Introspections whose deadline passed fail
+/// with `TokenInfoErrorKind::BudgetExceeded` without sending a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntrospectionContext {
+    /// An id identifying the originating request across services.
+    pub correlation_id: Option<String>,
+    /// The address of the client which presented the token.
+    pub peer: Option<String>,
+    /// The time by which the introspection must be done.
+    pub deadline: Option<Instant>,
+}
+
+impl IntrospectionContext {
+    /// Creates an empty `IntrospectionContext`.
+    pub fn new() -> Self {
+        IntrospectionContext::default()
+    }
+
+    /// Sets the id identifying the originating request.
+    pub fn with_correlation_id<T: Into<String>>(mut self, correlation_id: T) -> Self {
+        self.correlation_id = Some(correlation_id.into());
+        self
+    }
+
+    /// Sets the address of the client which presented the token.
+    pub fn with_peer<T: Into<String>>(mut self, peer: T) -> Self {
+        self.peer = Some(peer.into());
+        self
+    }
+
+    /// Sets the time by which the introspection must be done.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the deadline to `remaining` from now, e.g. the remaining
+    /// budget of the originating request.
+    pub fn with_remaining_time(self, remaining: Duration) -> Self {
+        self.with_deadline(Instant::now() + remaining)
+    }
+
+    /// The time left until the deadline, if there is one.
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Adds the context to `headers`. Values not allowed in a header are
+    /// skipped.
+    pub fn inject_into(&self, headers: &mut HeaderMap) {
+        let fields = [
+            (CORRELATION_ID_HEADER, &self.correlation_id),
+            (PEER_HEADER, &self.peer),
+        ];
+        for (name, value) in fields.iter() {
+            if let Some(value) = value.as_ref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        }
+    }
+}
+
+impl fmt::Display for IntrospectionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "correlation id: {}, peer: {}",
+            self.correlation_id.as_deref().unwrap_or("-"),
+            self.peer.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn context_is_sent_as_headers() {
+        let mut headers = HeaderMap::new();
+        IntrospectionContext::new()
+            .with_correlation_id("abc-123")
+            .with_peer("10.0.0.1")
+            .inject_into(&mut headers);
+        assert_eq!(headers[CORRELATION_ID_HEADER], "abc-123");
+        assert_eq!(headers[PEER_HEADER], "10.0.0.1");
+
+        let mut headers = HeaderMap::new();
+        IntrospectionContext::new()
+            .with_correlation_id("line\nbreak")
+            .inject_into(&mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn remaining_time_never_is_negative() {
+        assert_eq!(IntrospectionContext::new().remaining_time(), None);
+        let context = IntrospectionContext::new().with_remaining_time(Duration::from_secs(60));
+        assert!(context.remaining_time().unwrap() > Duration::from_secs(50));
+        let context = IntrospectionContext::new().with_deadline(Instant::now());
+        assert_eq!(context.remaining_time(), Some(Duration::from_secs(0)));
+    }
+}
@@ -0,0 +1,789 @@
+//! The parts of a token introspection shared by the blocking client in
+//! `client` and the async clients in `async_client`.
+//!
+//! Both clients assemble their URLs and requests, decode and interpret
+//! the responses of the introspection service and decide on retries and
+//! the fallback here so that they behave the same for the same inputs.
+//! Nothing in here sends a request, so the clients only differ in how
+//! they do that.
+use std::time::{Duration, Instant, SystemTime};
+
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::header::{ACCEPT_ENCODING, AUTHORIZATION, WWW_AUTHENTICATE};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::content_encoding::{decode_body, ACCEPT_ENCODING_VALUE};
+use crate::parsers::TokenInfoParser;
+use crate::request_log::{log_failure, RequestLog};
+use crate::unix_socket;
+use crate::IntrospectionContext;
+use crate::{AccessToken, BearerChallenge, BearerErrorCode, Credential, Scope, TokenInfo};
+use crate::{BodyCapture, DefaultFallbackClassifier, FallbackClassifier};
+use crate::{TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
+
+#[cfg(feature = "async")]
+use crate::metrics::MetricsCollector;
+
+/// Creates the prefix the access token is appended to when it is sent as
+/// part of the URL.
+pub(crate) fn assemble_url_prefix(
+    endpoint: &str,
+    query_parameter: &Option<&str>,
+) -> ::std::result::Result<String, String> {
+    let mut url_prefix = if unix_socket::is_unix_endpoint(endpoint) {
+        unix_socket::normalize_endpoint(endpoint)
+    } else {
+        String::from(endpoint)
+    };
+
+    if let Some(query_parameter) = query_parameter {
+        if url_prefix.ends_with('/') {
+            url_prefix.pop();
+        }
+        url_prefix.push_str(&format!("?{}=", query_parameter));
+    } else if !url_prefix.ends_with('/') {
+        url_prefix.push('/');
+    }
+
+    let test_url = format!("{}test_token", url_prefix);
+    let _ = test_url
+        .parse::<Url>()
+        .map_err(|err| format!("Invalid URL: {}", err))?;
+
+    Ok(url_prefix)
+}
+
+/// Validates the endpoint for requests which send the access token in the
+/// `Authorization` header.
+pub(crate) fn assemble_endpoint_url(endpoint: &str) -> ::std::result::Result<String, String> {
+    let endpoint_url = if unix_socket::is_unix_endpoint(endpoint) {
+        unix_socket::normalize_endpoint(endpoint)
+    } else {
+        String::from(endpoint)
+    };
+
+    let _ = endpoint_url
+        .parse::<Url>()
+        .map_err(|err| format!("Invalid URL: {}", err))?;
+
+    Ok(endpoint_url)
+}
+
+/// Appends the `token` to a prefix created by `assemble_url_prefix`.
+pub(crate) fn complete_url(url_prefix: &str, token: &AccessToken) -> TokenInfoResult<Url> {
+    let mut url_str = url_prefix.to_string();
+    url_str.push_str(token.reveal());
+    let url = url_str.parse()?;
+    Ok(url)
+}
+
+/// Creates the value of the `Authorization` header carrying `credential`.
+pub(crate) fn authorization_header(credential: &Credential) -> TokenInfoResult<HeaderValue> {
+    credential.authorization_header_value().map_err(|_| {
+        TokenInfoErrorKind::Client(
+            "The credential contains characters not allowed in a header".to_string(),
+        )
+        .into()
+    })
+}
+
+pub(crate) fn content_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Maps a request which could not be sent to the introspection service.
+pub(crate) fn send_error(err: &reqwest::Error) -> TokenInfoError {
+    TokenInfoErrorKind::Connection(err.to_string()).into()
+}
+
+/// The content type of the form sent with `introspection_form`.
+pub(crate) const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// The form body of an introspection request as defined in
+/// [RFC 7662 Sec. 2.1](https://tools.ietf.org/html/rfc7662#section-2.1).
+pub(crate) fn introspection_form(token: &AccessToken) -> Vec<u8> {
+    url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("token", token.reveal())
+        .append_pair("token_type_hint", "access_token")
+        .finish()
+        .into_bytes()
+}
+
+/// The method of an introspection request which is a `POST` if the token
+/// is sent in a form.
+pub(crate) fn request_method(has_form: bool) -> Method {
+    if has_form {
+        Method::POST
+    } else {
+        Method::GET
+    }
+}
+
+/// The headers of an introspection request before the `RequestDecorator`s
+/// are applied.
+///
+/// `authorization` is sent in the `Authorization` header and the
+/// `context` is sent along if given.
+pub(crate) fn request_headers(
+    authorization: Option<HeaderValue>,
+    request_gzip: bool,
+    context: Option<&IntrospectionContext>,
+    has_form: bool,
+) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(authorization) = authorization {
+        headers.insert(AUTHORIZATION, authorization);
+    }
+    if request_gzip {
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_static(ACCEPT_ENCODING_VALUE),
+        );
+    }
+    if let Some(context) = context {
+        context.inject_into(&mut headers);
+    }
+    if has_form {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(FORM_CONTENT_TYPE));
+    }
+    headers
+}
+
+/// Precedes the body of a `401` in the message of the
+/// `TokenInfoErrorKind::NotAuthenticated`.
+pub(crate) const REFUSED_TOKEN_PREFIX: &str = "The server refused the token: ";
+
+/// Decodes the `body` of a response according to its `Content-Encoding`,
+/// logs the response and evaluates it with the `parser`.
+pub(crate) fn decode_and_evaluate<P>(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: Bytes,
+    parser: &P,
+    max_response_size: usize,
+    body_capture: BodyCapture,
+    request_log: Option<(&RequestLog, Instant)>,
+) -> TokenInfoResult<TokenInfo>
+where
+    P: TokenInfoParser + ?Sized,
+{
+    let body = decode_body(content_encoding(headers), body, max_response_size)
+        .map_err(|err| log_failure(request_log, Some(status), err))?;
+    if let Some((request_log, start)) = request_log {
+        request_log.response(start, status, &body);
+    }
+    evaluate_response(status, headers, &body, parser, body_capture)
+}
+
+/// Turns the decoded response of the introspection service into a
+/// `TokenInfo` or the error matching the `status`.
+///
+/// The `body_capture` decides how much of the `body` ends up in an error.
+pub(crate) fn evaluate_response<P>(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    parser: &P,
+    body_capture: BodyCapture,
+) -> TokenInfoResult<TokenInfo>
+where
+    P: TokenInfoParser + ?Sized,
+{
+    let result = if status == StatusCode::OK {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        parser
+            .parse_with_content_type(body, content_type)
+            .map_err(|err| {
+                TokenInfoErrorKind::InvalidResponseContent(format!(
+                    "{} (response body: {})",
+                    err,
+                    body_capture.capture(body)
+                ))
+            })
+    } else if is_rate_limited(status, headers) {
+        Err(TokenInfoErrorKind::RateLimited {
+            retry_after: parse_retry_after(headers),
+        })
+    } else if status == StatusCode::UNAUTHORIZED {
+        let msg = body_capture.capture_refusal(body);
+        Err(TokenInfoErrorKind::NotAuthenticated {
+            message: format!("{}{}", REFUSED_TOKEN_PREFIX, msg),
+            challenge: parse_bearer_challenge(headers).map(Box::new),
+        })
+    } else if status.is_client_error() {
+        let msg = body_capture.capture_refusal(body);
+        Err(TokenInfoErrorKind::Client(msg))
+    } else if status.is_server_error() {
+        Err(TokenInfoErrorKind::Server(body_capture.capture(body)))
+    } else {
+        Err(TokenInfoErrorKind::Other(body_capture.capture(body)))
+    };
+    result.map_err(Into::into)
+}
+
+/// Parses the `Bearer` challenge of the `WWW-Authenticate` headers.
+///
+/// Challenges of other schemes are skipped.
+pub(crate) fn parse_bearer_challenge(headers: &HeaderMap) -> Option<BearerChallenge> {
+    headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(|value| {
+            parse_challenges(value)
+                .into_iter()
+                .find(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        })
+        .map(|(_, params)| {
+            let mut challenge = BearerChallenge::default();
+            for (name, value) in params {
+                match name.to_ascii_lowercase().as_str() {
+                    "realm" => challenge.realm = Some(value),
+                    "error" => challenge.error = Some(BearerErrorCode::parse(&value)),
+                    "error_description" => challenge.error_description = Some(value),
+                    "scope" => challenge.scope = value.split_whitespace().map(Scope::new).collect(),
+                    _ => {}
+                }
+            }
+            challenge
+        })
+}
+
+/// Splits a `WWW-Authenticate` value into its challenges as defined in
+/// [RFC 7235 Sec. 4.1](https://tools.ietf.org/html/rfc7235#section-4.1).
+///
+/// A `token68` of a challenge is skipped.
+fn parse_challenges(value: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut chars = value.chars().peekable();
+    loop {
+        while let Some(&c) = chars.peek() {
+            if c == ',' || c.is_whitespace() {
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let name: String = take_token(&mut chars);
+        if name.is_empty() {
+            match chars.next() {
+                // A stray character or the `=` padding of a `token68`
+                Some(_) => continue,
+                None => break,
+            }
+        }
+        while chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
+        if chars.peek() != Some(&'=') {
+            challenges.push((name, Vec::new()));
+            continue;
+        }
+        chars.next();
+        while chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            chars.next();
+        }
+        let value = if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut value = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            value
+        } else {
+            take_token(&mut chars)
+        };
+        if let Some((_, params)) = challenges.last_mut() {
+            params.push((name, value));
+        }
+    }
+    challenges
+}
+
+fn take_token<I: Iterator<Item = char>>(chars: &mut std::iter::Peekable<I>) -> String {
+    let mut token = String::new();
+    while let Some(&c) = chars.peek() {
+        if c == ',' || c == '=' || c == '"' || c.is_whitespace() {
+            break;
+        }
+        token.push(c);
+        chars.next();
+    }
+    token
+}
+
+/// Returns `true` if a failed request may succeed when sent again.
+pub(crate) fn is_retryable(err: &TokenInfoError) -> bool {
+    err.is_retry_suggested()
+}
+
+/// Returns `true` for a `429` and for a `503` which says when to retry.
+pub(crate) fn is_rate_limited(status: StatusCode, headers: &HeaderMap) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::SERVICE_UNAVAILABLE && headers.contains_key(RETRY_AFTER))
+}
+
+/// The time to wait before the next request as given by the `Retry-After`
+/// header in seconds or as an HTTP date.
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or_else(|_| Duration::from_secs(0)),
+    )
+}
+
+/// How a retry is affected by the `Retry-After` of a rate limited request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RetryAfter {
+    /// The server did not say when to retry.
+    Unspecified,
+    /// Wait this long before retrying.
+    Wait(Duration),
+    /// The server asked to wait longer than the remaining budget so that
+    /// a retry is pointless.
+    ExceedsBudget,
+}
+
+impl RetryAfter {
+    /// Clamps `retry_after` to the `remaining` budget of the retries.
+    pub fn within(retry_after: Option<Duration>, remaining: Duration) -> RetryAfter {
+        match retry_after {
+            None => RetryAfter::Unspecified,
+            Some(retry_after) if retry_after > remaining => RetryAfter::ExceedsBudget,
+            Some(retry_after) => RetryAfter::Wait(retry_after),
+        }
+    }
+
+    /// Clamps the `Retry-After` of a `TokenInfoErrorKind::RateLimited`.
+    pub fn of_error(err: &TokenInfoError, remaining: Duration) -> RetryAfter {
+        match *err.kind() {
+            TokenInfoErrorKind::RateLimited { retry_after } => {
+                RetryAfter::within(retry_after, remaining)
+            }
+            _ => RetryAfter::Unspecified,
+        }
+    }
+}
+
+/// The timeout of a request for a caller with a `deadline`, if any.
+///
+/// Fails with `TokenInfoErrorKind::BudgetExceeded` once the deadline
+/// passed so that no request is sent.
+pub(crate) fn timeout_until(deadline: Option<Instant>) -> TokenInfoResult<Option<Duration>> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(timeout) if timeout > Duration::from_secs(0) => Ok(Some(timeout)),
+            _ => Err(TokenInfoErrorKind::BudgetExceeded.into()),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns `true` if the fallback endpoint should be asked after the
+/// primary endpoint failed with `err` according to `classifier` or the
+/// `DefaultFallbackClassifier` if there is none.
+pub(crate) fn should_use_fallback(
+    classifier: Option<&(dyn FallbackClassifier + Send + Sync)>,
+    err: &TokenInfoError,
+) -> bool {
+    match classifier {
+        Some(classifier) => classifier.use_fallback(err),
+        None => DefaultFallbackClassifier.use_fallback(err),
+    }
+}
+
+/// The next attempt of an introspection alternating between the primary
+/// and the fallback endpoint after an attempt on the fallback endpoint
+/// (`on_fallback`) or on the primary endpoint failed with `err`.
+///
+/// Returns whether the next attempt goes to the fallback endpoint and the
+/// time to wait at least before it or `None` if there is no next attempt.
+pub(crate) fn next_interleaved_attempt(
+    on_fallback: bool,
+    classifier: Option<&(dyn FallbackClassifier + Send + Sync)>,
+    err: &TokenInfoError,
+    remaining: Duration,
+) -> Option<(bool, Duration)> {
+    if !on_fallback && should_use_fallback(classifier, err) {
+        return Some((true, Duration::from_secs(0)));
+    }
+    if !is_retryable(err) {
+        return None;
+    }
+    if on_fallback {
+        return Some((false, Duration::from_secs(0)));
+    }
+    match RetryAfter::of_error(err, remaining) {
+        RetryAfter::Unspecified => Some((false, Duration::from_secs(0))),
+        RetryAfter::Wait(retry_after) => Some((false, retry_after)),
+        RetryAfter::ExceedsBudget => None,
+    }
+}
+
+/// The time to wait before retrying on the same endpoint after `err` or
+/// `None` if there is no retry within the `remaining` budget.
+///
+/// A `Retry-After` of the server replaces the next interval of the
+/// `backoff` instead of adding to it. Without one the interval is
+/// shortened to the `remaining` budget.
+pub(crate) fn next_retry_delay<B: Backoff>(
+    backoff: &mut B,
+    err: &TokenInfoError,
+    remaining: Duration,
+) -> Option<Duration> {
+    let interval = backoff.next_backoff()?;
+    match RetryAfter::of_error(err, remaining) {
+        RetryAfter::Unspecified => Some(interval.min(remaining)),
+        RetryAfter::Wait(retry_after) => Some(retry_after),
+        RetryAfter::ExceedsBudget => None,
+    }
+}
+
+/// The backoff between retries on the same endpoint.
+pub(crate) fn retry_backoff() -> ExponentialBackoff {
+    let mut backoff = ExponentialBackoff::default();
+    backoff.max_elapsed_time = Some(Duration::from_millis(200));
+    backoff.initial_interval = Duration::from_millis(10);
+    backoff.multiplier = 1.5;
+    backoff
+}
+
+/// Tracks a single call of the introspection service started at `start`.
+#[cfg(feature = "async")]
+pub(crate) fn track_service_call<M>(metrics_collector: &M, start: Instant, success: bool)
+where
+    M: MetricsCollector + ?Sized,
+{
+    metrics_collector.introspection_service_call(start);
+    if success {
+        metrics_collector.introspection_service_call_success(start);
+    } else {
+        metrics_collector.introspection_service_call_failure(start);
+    }
+}
+
+/// Tracks a complete introspection started at `start` including all
+/// retries and the fallback.
+#[cfg(feature = "async")]
+pub(crate) fn track_introspection<M>(metrics_collector: &M, start: Instant, success: bool)
+where
+    M: MetricsCollector + ?Sized,
+{
+    metrics_collector.introspection_request(start);
+    if success {
+        metrics_collector.introspection_request_success(start);
+    } else {
+        metrics_collector.introspection_request_failure(start);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsers::PlanBTokenInfoParser;
+
+    const VALID_BODY: &[u8] = br#"{"uid":"user","scope":["read"],"expires_in":10}"#;
+
+    fn evaluate(status: u16, body: &[u8]) -> TokenInfoResult<TokenInfo> {
+        evaluate_response(
+            StatusCode::from_u16(status).unwrap(),
+            &HeaderMap::new(),
+            body,
+            &PlanBTokenInfoParser,
+            BodyCapture::Full,
+        )
+    }
+
+    #[test]
+    fn forms_are_posted_with_their_content_type() {
+        let context = IntrospectionContext::new().with_correlation_id("abc");
+        let headers = request_headers(
+            Some(HeaderValue::from_static("Bearer endpoint")),
+            true,
+            Some(&context),
+            true,
+        );
+        assert_eq!(request_method(true), Method::POST);
+        assert_eq!(headers[AUTHORIZATION], "Bearer endpoint");
+        assert_eq!(headers[ACCEPT_ENCODING], ACCEPT_ENCODING_VALUE);
+        assert_eq!(headers[CONTENT_TYPE], FORM_CONTENT_TYPE);
+        assert_eq!(headers["x-correlation-id"], "abc");
+
+        assert_eq!(request_method(false), Method::GET);
+        assert!(request_headers(None, false, None, false).is_empty());
+    }
+
+    #[test]
+    fn ok_responses_are_parsed() {
+        let token_info = evaluate(200, VALID_BODY).unwrap();
+        assert_eq!(token_info.user_id.unwrap().0, "user");
+    }
+
+    #[test]
+    fn unparsable_ok_responses_are_invalid_content() {
+        match *evaluate(200, b"{").unwrap_err().kind() {
+            TokenInfoErrorKind::InvalidResponseContent(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_contain_only_the_captured_body() {
+        let body = br#"{"uid":"alice","#;
+        let err = evaluate_response(
+            StatusCode::OK,
+            &HeaderMap::new(),
+            body,
+            &PlanBTokenInfoParser,
+            BodyCapture::Truncated(8),
+        )
+        .unwrap_err();
+        let expected = format!(r#"(response body: {{"uid":"... ({} bytes))"#, body.len());
+        assert!(err.to_string().ends_with(&expected), "{}", err);
+
+        for &status in &[
+            StatusCode::OK,
+            StatusCode::FORBIDDEN,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            let err = evaluate_response(
+                status,
+                &HeaderMap::new(),
+                body,
+                &PlanBTokenInfoParser,
+                BodyCapture::HashOnly,
+            )
+            .unwrap_err();
+            assert!(!err.to_string().contains("alice"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn status_codes_are_mapped_to_error_kinds() {
+        match *evaluate(401, b"nope").unwrap_err().kind() {
+            TokenInfoErrorKind::NotAuthenticated {
+                ref message,
+                ref challenge,
+            } => {
+                assert_eq!(message, "The server refused the token: nope");
+                assert_eq!(*challenge, None);
+            }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        match *evaluate(403, b"forbidden").unwrap_err().kind() {
+            TokenInfoErrorKind::Client(ref msg) => assert_eq!(msg, "forbidden"),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        match *evaluate(503, b"down").unwrap_err().kind() {
+            TokenInfoErrorKind::Server(ref msg) => assert_eq!(msg, "down"),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        match *evaluate(302, b"").unwrap_err().kind() {
+            TokenInfoErrorKind::Other(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bearer_challenges_are_parsed_from_refusals() {
+        let mut headers = HeaderMap::new();
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic abc=="));
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static(
+                "Basic realm=\"other\", Bearer realm=\"api\", error=\"insufficient_scope\", \
+                 error_description=\"needs \\\"write\\\"\", scope=\"read write\"",
+            ),
+        );
+        let err = evaluate_response(
+            StatusCode::UNAUTHORIZED,
+            &headers,
+            b"",
+            &PlanBTokenInfoParser,
+            BodyCapture::Full,
+        )
+        .unwrap_err();
+        match *err.kind() {
+            TokenInfoErrorKind::NotAuthenticated {
+                challenge: Some(ref challenge),
+                ..
+            } => {
+                assert_eq!(challenge.realm.as_deref(), Some("api"));
+                assert_eq!(challenge.error, Some(BearerErrorCode::InsufficientScope));
+                assert_eq!(
+                    challenge.error_description.as_deref(),
+                    Some("needs \"write\"")
+                );
+                assert_eq!(
+                    challenge.scope,
+                    vec![Scope::new("read"), Scope::new("write")]
+                );
+            }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bodies_of_errors_need_not_be_utf8() {
+        match *evaluate(500, &[0xff, 0xfe]).unwrap_err().kind() {
+            TokenInfoErrorKind::Server(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_transient_errors_are_retried() {
+        let err = |kind: TokenInfoErrorKind| TokenInfoError::from(kind);
+        assert!(is_retryable(&err(TokenInfoErrorKind::Server(String::new()))));
+        assert!(is_retryable(&err(TokenInfoErrorKind::Connection(String::new()))));
+        assert!(!is_retryable(&err(TokenInfoErrorKind::Client(String::new()))));
+        assert!(!is_retryable(&err(TokenInfoErrorKind::not_authenticated(""))));
+        assert!(!is_retryable(&err(TokenInfoErrorKind::ResponseTooLarge(1))));
+    }
+
+    #[test]
+    fn interleaved_attempts_alternate_between_the_endpoints() {
+        let remaining = Duration::from_secs(1);
+        let next = |on_fallback, kind| {
+            next_interleaved_attempt(on_fallback, None, &TokenInfoError::from(kind), remaining)
+        };
+        let none = Duration::from_secs(0);
+
+        assert_eq!(
+            next(false, TokenInfoErrorKind::Server(String::new())),
+            Some((true, none))
+        );
+        assert_eq!(
+            next(true, TokenInfoErrorKind::Server(String::new())),
+            Some((false, none))
+        );
+        assert_eq!(
+            next(
+                true,
+                TokenInfoErrorKind::InvalidResponseContent(String::new())
+            ),
+            None
+        );
+        assert_eq!(next(false, TokenInfoErrorKind::Client(String::new())), None);
+
+        let never = |_: &TokenInfoError| false;
+        let rate_limited = TokenInfoError::from(TokenInfoErrorKind::RateLimited {
+            retry_after: Some(Duration::from_millis(10)),
+        });
+        assert_eq!(
+            next_interleaved_attempt(false, Some(&never), &rate_limited, remaining),
+            Some((false, Duration::from_millis(10)))
+        );
+    }
+
+    #[test]
+    fn retry_after_replaces_the_backoff_within_the_budget() {
+        let mut backoff = ExponentialBackoff::default();
+        backoff.initial_interval = Duration::from_millis(500);
+        backoff.randomization_factor = 0.0;
+        let rate_limited = TokenInfoError::from(TokenInfoErrorKind::RateLimited {
+            retry_after: Some(Duration::from_secs(1)),
+        });
+        let server_error = TokenInfoError::from(TokenInfoErrorKind::Server(String::new()));
+
+        // The budget is less than the Retry-After and the backoff combined.
+        let remaining = Duration::from_millis(1200);
+        assert_eq!(
+            next_retry_delay(&mut backoff, &rate_limited, remaining),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            next_retry_delay(&mut backoff, &rate_limited, Duration::from_millis(800)),
+            None
+        );
+        assert_eq!(
+            next_retry_delay(&mut backoff, &server_error, Duration::from_millis(300)),
+            Some(Duration::from_millis(300))
+        );
+    }
+
+    #[test]
+    fn the_fallback_is_not_asked_for_refused_requests() {
+        let err = |kind: TokenInfoErrorKind| TokenInfoError::from(kind);
+        let by_default = |kind: TokenInfoErrorKind| should_use_fallback(None, &err(kind));
+        assert!(by_default(TokenInfoErrorKind::Server(String::new())));
+        assert!(by_default(TokenInfoErrorKind::not_authenticated("")));
+        assert!(!by_default(TokenInfoErrorKind::Client(String::new())));
+
+        let always = |_: &TokenInfoError| true;
+        let client_error = err(TokenInfoErrorKind::Client(String::new()));
+        assert!(should_use_fallback(Some(&always), &client_error));
+    }
+
+    #[test]
+    fn the_token_is_appended_to_the_url_prefix() {
+        let prefix = assemble_url_prefix("http://localhost/info/", &Some("access_token")).unwrap();
+        let url = complete_url(&prefix, &AccessToken::new("abc")).unwrap();
+        assert_eq!(url.as_str(), "http://localhost/info?access_token=abc");
+
+        let prefix = assemble_url_prefix("http://localhost/info", &None).unwrap();
+        let url = complete_url(&prefix, &AccessToken::new("abc")).unwrap();
+        assert_eq!(url.as_str(), "http://localhost/info/abc");
+    }
+
+    #[test]
+    fn rate_limits_carry_the_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        let err = evaluate_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            &headers,
+            b"",
+            &PlanBTokenInfoParser,
+            BodyCapture::Full,
+        )
+        .unwrap_err();
+        match *err.kind() {
+            TokenInfoErrorKind::RateLimited { retry_after } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(3)))
+            }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(err.is_retry_suggested());
+        assert_eq!(
+            RetryAfter::of_error(&err, Duration::from_secs(5)),
+            RetryAfter::Wait(Duration::from_secs(3))
+        );
+        assert_eq!(
+            RetryAfter::of_error(&err, Duration::from_secs(2)),
+            RetryAfter::ExceedsBudget
+        );
+        match *evaluate(429, b"").unwrap_err().kind() {
+            TokenInfoErrorKind::RateLimited { retry_after: None } => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn retry_after_may_be_a_date() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(0)));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(parse_retry_after(&headers), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! Snapshots of the state of tokkit for an internal debug endpoint.
+//!
+//! The snapshots are JSON values which can be served as they are, e.g. on
+//! `/debug/tokkit`. They never contain tokens. Error messages are cut to
+//! `MAX_ERROR_LENGTH` bytes since they may contain the responses of an
+//! authorization server.
+//!
+//! ```rust
+//! use tokkit::cache::{CacheConfig, TokenInfoCache};
+//! use tokkit::diagnostics;
+//! use tokkit::token_manager::AccessTokenSource;
+//! use tokkit::AccessToken;
+//!
+//! let source = AccessTokenSource::new_detached(&[("api", AccessToken::new("secret"))]);
+//! let cache = TokenInfoCache::new(CacheConfig::default());
+//!
+//! let state = json::object! {
+//!     "tokens" => diagnostics::render_state(&source),
+//!     "cache" => diagnostics::render_cache_state(&cache),
+//! };
+//! assert!(!state.dump().contains("secret"));
+//! ```
+use std::fmt::Debug;
+use std::time::SystemTime;
+
+use json::JsonValue;
+
+use crate::cache::TokenInfoCache;
+use crate::token_manager::{AccessTokenSource, TokenIdRepr};
+use crate::BodyCapture;
+
+/// The maximum number of bytes of an error message in a snapshot.
+pub const MAX_ERROR_LENGTH: usize = 256;
+
+/// Renders the state of the tokens of `source` ordered by their ids.
+///
+/// ```json
+/// {
+///   "tokens": [
+///     {
+///       "token_id": "\"api\"",
+///       "available": true,
+///       "paused": false,
+///       "expires_in_seconds": 3540,
+///       "error": null
+///     }
+///   ],
+///   "dropped_commands": 0
+/// }
+/// ```
+///
+/// `expires_in_seconds` is `null` if no token was received yet and `error`
+/// is the error a request for the token would fail with.
+pub fn render_state<T: Eq + Ord + Clone + Debug>(source: &AccessTokenSource<T>) -> JsonValue {
+    let now = SystemTime::now();
+    let tokens: Vec<JsonValue> = source
+        .snapshot()
+        .into_iter()
+        .map(|(token_id, token)| {
+            let status = source.token_status(&token_id).ok();
+            let expires_at = status.as_ref().and_then(|status| status.expires_at);
+            let expires_in_seconds = expires_at.map(|expires_at| seconds_between(now, expires_at));
+            json::object! {
+                "token_id" => TokenIdRepr::of(&token_id).to_string(),
+                "available" => token.is_ok(),
+                "paused" => status.map(|status| status.paused).unwrap_or(false),
+                "expires_in_seconds" => expires_in_seconds,
+                "error" => token.err().map(|err| shortened(&err.to_string())),
+            }
+        })
+        .collect();
+    json::object! {
+        "tokens" => tokens,
+        "dropped_commands" => source.dropped_commands(),
+    }
+}
+
+/// Renders the size and the lookups of `cache`.
+///
+/// ```json
+/// {
+///   "entries": 10,
+///   "bytes": 2048,
+///   "hits": 90,
+///   "misses": 10,
+///   "hit_rate": 0.9
+/// }
+/// ```
+///
+/// `hit_rate` is `null` as long as there were no lookups.
+pub fn render_cache_state(cache: &TokenInfoCache) -> JsonValue {
+    let (hits, misses) = (cache.hits(), cache.misses());
+    let lookups = hits + misses;
+    let hit_rate = if lookups > 0 {
+        Some(hits as f64 / lookups as f64)
+    } else {
+        None
+    };
+    json::object! {
+        "entries" => cache.len(),
+        "bytes" => cache.bytes(),
+        "hits" => hits,
+        "misses" => misses,
+        "hit_rate" => hit_rate,
+    }
+}
+
+/// The seconds from `now` until `then` which are zero if `then` has passed.
+fn seconds_between(now: SystemTime, then: SystemTime) -> u64 {
+    then.duration_since(now)
+        .map(|left| left.as_secs())
+        .unwrap_or(0)
+}
+
+fn shortened(message: &str) -> String {
+    BodyCapture::Truncated(MAX_ERROR_LENGTH).capture(message.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::{CacheConfig, TokenHasher};
+    use crate::{AccessToken, TokenInfo};
+
+    #[test]
+    fn token_states_are_rendered_without_the_tokens() {
+        let source = AccessTokenSource::new_detached(&[
+            ("b", AccessToken::new("second secret")),
+            ("a", AccessToken::new("first secret")),
+        ]);
+        let state = render_state(&source);
+
+        assert_eq!(state["tokens"].len(), 2);
+        assert_eq!(state["tokens"][0]["token_id"], "\"a\"");
+        assert_eq!(state["tokens"][1]["available"], true);
+        assert!(state["tokens"][1]["expires_in_seconds"].is_null());
+        assert!(state["tokens"][1]["error"].is_null());
+        assert!(!state.dump().contains("secret"), "{}", state.dump());
+    }
+
+    #[test]
+    fn cache_states_contain_the_hit_rate() {
+        let cache = TokenInfoCache::new(CacheConfig::default());
+        assert!(render_cache_state(&cache)["hit_rate"].is_null());
+
+        let key = TokenHasher::random().key(&AccessToken::new("token"));
+        assert!(cache.get(&key).is_none());
+        cache.put(&key, &TokenInfo::new(true));
+        for _ in 0..3 {
+            assert!(cache.get(&key).is_some());
+        }
+
+        let state = render_cache_state(&cache);
+        assert_eq!(state["entries"], 1);
+        assert_eq!(state["hits"], 3);
+        assert_eq!(state["misses"], 1);
+        assert_eq!(state["hit_rate"], 0.75);
+    }
+}
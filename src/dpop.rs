@@ -0,0 +1,327 @@
+//! Proofs of possession for outbound requests
+//!
+//! Generates DPoP proofs as defined in
+//! [RFC 9449](https://datatracker.ietf.org/doc/html/rfc9449) so that
+//! sender-constrained access tokens can be presented to resource servers.
+//!
+//! ```rust,no_run
+//! use reqwest::header::HeaderMap;
+//! use reqwest::Method;
+//! use tokkit::dpop::DpopKey;
+//! use tokkit::AccessToken;
+//!
+//! let key = DpopKey::generate_es256().unwrap();
+//! let url = "https://api.example.com/resource".parse().unwrap();
+//!
+//! let mut headers = HeaderMap::new();
+//! key.inject_into(&mut headers, &Method::GET, &url, &AccessToken::new("<token>"))
+//!     .unwrap();
+//! ```
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::{self, JsonValue};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Method, Url};
+
+use crate::AccessToken;
+
+/// The name of the header carrying the proof.
+pub const DPOP_HEADER: &str = "dpop";
+
+/// The size of a P-256 coordinate and of `r` and `s` of a signature
+const P256_FIELD_SIZE: i32 = 32;
+
+/// An error that occurred while creating a key or a proof
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct DpopError(pub String);
+
+impl From<openssl::error::ErrorStack> for DpopError {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        DpopError(err.to_string())
+    }
+}
+
+/// The algorithm used to sign proofs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DpopAlgorithm {
+    /// ECDSA using P-256 and SHA-256
+    ES256,
+    /// RSASSA-PKCS1-v1_5 using SHA-256
+    RS256,
+}
+
+impl DpopAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            DpopAlgorithm::ES256 => "ES256",
+            DpopAlgorithm::RS256 => "RS256",
+        }
+    }
+}
+
+/// A key pair proving possession of sender-constrained tokens
+pub struct DpopKey {
+    algorithm: DpopAlgorithm,
+    key: PKey<Private>,
+    jwk: JsonValue,
+}
+
+impl DpopKey {
+    /// Generates a new P-256 key pair.
+    pub fn generate_es256() -> Result<Self, DpopError> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        DpopKey::from_pkey(PKey::from_ec_key(EcKey::generate(&group)?)?)
+    }
+
+    /// Loads a P-256 or RSA private key from PEM.
+    pub fn from_pem(pem: &[u8]) -> Result<Self, DpopError> {
+        DpopKey::from_pkey(PKey::private_key_from_pem(pem)?)
+    }
+
+    fn from_pkey(key: PKey<Private>) -> Result<Self, DpopError> {
+        let (algorithm, jwk) = match key.id() {
+            Id::EC => {
+                let ec_key = key.ec_key()?;
+                if ec_key.group().curve_name() != Some(Nid::X9_62_PRIME256V1) {
+                    return Err(DpopError("Only P-256 keys are supported".to_string()));
+                }
+                let mut x = BigNum::new()?;
+                let mut y = BigNum::new()?;
+                let mut ctx = BigNumContext::new()?;
+                ec_key
+                    .public_key()
+                    .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)?;
+                // Members in lexicographic order as required for thumbprints
+                let jwk = json::object! {
+                    "crv" => "P-256",
+                    "kty" => "EC",
+                    "x" => encode(&x.to_vec_padded(P256_FIELD_SIZE)?),
+                    "y" => encode(&y.to_vec_padded(P256_FIELD_SIZE)?)
+                };
+                (DpopAlgorithm::ES256, jwk)
+            }
+            Id::RSA => {
+                let rsa = key.rsa()?;
+                let jwk = json::object! {
+                    "e" => encode(&rsa.e().to_vec()),
+                    "kty" => "RSA",
+                    "n" => encode(&rsa.n().to_vec())
+                };
+                (DpopAlgorithm::RS256, jwk)
+            }
+            _ => return Err(DpopError("Only EC and RSA keys are supported".to_string())),
+        };
+
+        Ok(DpopKey {
+            algorithm,
+            key,
+            jwk,
+        })
+    }
+
+    /// The algorithm proofs are signed with
+    pub fn algorithm(&self) -> DpopAlgorithm {
+        self.algorithm
+    }
+
+    /// The public key as a JSON Web Key
+    pub fn jwk(&self) -> String {
+        self.jwk.dump()
+    }
+
+    /// The JWK SHA-256 thumbprint of the public key as defined in
+    /// [RFC 7638](https://tools.ietf.org/html/rfc7638).
+    ///
+    /// This is the value of `cnf.jkt` in tokens bound to this key.
+    pub fn thumbprint(&self) -> Result<String, DpopError> {
+        Ok(encode(&hash(
+            MessageDigest::sha256(),
+            self.jwk.dump().as_bytes(),
+        )?))
+    }
+
+    /// Creates a proof for a request with `method` to `url`.
+    ///
+    /// If `access_token` is given the proof is bound to it via the `ath`
+    /// claim as required when presenting the token to a resource server.
+    pub fn proof(
+        &self,
+        method: &Method,
+        url: &Url,
+        access_token: Option<&AccessToken>,
+    ) -> Result<String, DpopError> {
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| DpopError(err.to_string()))?
+            .as_secs();
+
+        let mut jti = [0; 16];
+        rand_bytes(&mut jti)?;
+
+        let mut htu = url.clone();
+        htu.set_query(None);
+        htu.set_fragment(None);
+
+        let header = json::object! {
+            "typ" => "dpop+jwt",
+            "alg" => self.algorithm.name(),
+            "jwk" => self.jwk.clone()
+        };
+        let mut claims = json::object! {
+            "jti" => encode(&jti),
+            "htm" => method.as_str(),
+            "htu" => htu.as_str(),
+            "iat" => iat
+        };
+        if let Some(access_token) = access_token {
+            claims["ath"] =
+                encode(&hash(MessageDigest::sha256(), access_token.reveal().as_bytes())?).into();
+        }
+
+        let signing_input = format!(
+            "{}.{}",
+            encode(header.dump().as_bytes()),
+            encode(claims.dump().as_bytes())
+        );
+        let signature = self.sign(signing_input.as_bytes())?;
+
+        Ok(format!("{}.{}", signing_input, encode(&signature)))
+    }
+
+    /// Sets `Authorization: DPoP <token>` and the `DPoP` header carrying a
+    /// fresh proof bound to `access_token` on `headers`.
+    pub fn inject_into(
+        &self,
+        headers: &mut HeaderMap,
+        method: &Method,
+        url: &Url,
+        access_token: &AccessToken,
+    ) -> Result<(), DpopError> {
+        let proof = self.proof(method, url, Some(access_token))?;
+        let mut authorization = HeaderValue::from_str(&format!("DPoP {}", access_token.reveal()))
+            .map_err(|err| DpopError(err.to_string()))?;
+        authorization.set_sensitive(true);
+        headers.insert(AUTHORIZATION, authorization);
+        headers.insert(
+            HeaderName::from_static(DPOP_HEADER),
+            HeaderValue::from_str(&proof).map_err(|err| DpopError(err.to_string()))?,
+        );
+        Ok(())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, DpopError> {
+        match self.algorithm {
+            DpopAlgorithm::ES256 => {
+                // JWS requires the raw concatenation of r and s instead of DER
+                let digest = hash(MessageDigest::sha256(), data)?;
+                let signature = EcdsaSig::sign(&digest, &*self.key.ec_key()?)?;
+                let mut raw = signature.r().to_vec_padded(P256_FIELD_SIZE)?;
+                raw.extend(signature.s().to_vec_padded(P256_FIELD_SIZE)?);
+                Ok(raw)
+            }
+            DpopAlgorithm::RS256 => {
+                let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+                signer.update(data)?;
+                Ok(signer.sign_to_vec()?)
+            }
+        }
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod test {
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    use super::*;
+
+    fn decode_json(segment: &str) -> JsonValue {
+        let bytes = base64::decode_config(segment, base64::URL_SAFE_NO_PAD).unwrap();
+        json::parse(std::str::from_utf8(&bytes).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn es256_proof_is_verifiable() {
+        let key = DpopKey::generate_es256().unwrap();
+        let url: Url = "https://api.example.com/r?x=1#f".parse().unwrap();
+        let token = AccessToken::new("token");
+
+        let proof = key.proof(&Method::POST, &url, Some(&token)).unwrap();
+        let segments: Vec<&str> = proof.split('.').collect();
+
+        let header = decode_json(segments[0]);
+        assert_eq!(header["typ"], "dpop+jwt");
+        assert_eq!(header["alg"], "ES256");
+        assert_eq!(header["jwk"]["kty"], "EC");
+
+        let claims = decode_json(segments[1]);
+        assert_eq!(claims["htm"], "POST");
+        assert_eq!(claims["htu"], "https://api.example.com/r");
+        assert!(claims["jti"].is_string());
+        assert_eq!(
+            claims["ath"],
+            encode(&hash(MessageDigest::sha256(), b"token").unwrap()).as_str()
+        );
+
+        let raw = base64::decode_config(segments[2], base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(raw.len(), 64);
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&raw[..32]).unwrap(),
+            BigNum::from_slice(&raw[32..]).unwrap(),
+        )
+        .unwrap();
+        let digest = hash(
+            MessageDigest::sha256(),
+            format!("{}.{}", segments[0], segments[1]).as_bytes(),
+        )
+        .unwrap();
+        assert!(signature
+            .verify(&digest, &*key.key.ec_key().unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn rs256_proof_is_verifiable() {
+        let pem = Rsa::generate(2048).unwrap().private_key_to_pem().unwrap();
+        let key = DpopKey::from_pem(&pem).unwrap();
+        assert_eq!(key.algorithm(), DpopAlgorithm::RS256);
+
+        let url: Url = "https://api.example.com/r".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        key.inject_into(&mut headers, &Method::GET, &url, &AccessToken::new("t"))
+            .unwrap();
+        assert_eq!(headers[AUTHORIZATION], "DPoP t");
+
+        let proof = headers[DPOP_HEADER].to_str().unwrap();
+        let idx = proof.rfind('.').unwrap();
+        let signature = base64::decode_config(&proof[idx + 1..], base64::URL_SAFE_NO_PAD).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key.key).unwrap();
+        verifier.update(&proof.as_bytes()[..idx]).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+    }
+
+    #[test]
+    fn thumbprint_is_hash_of_canonical_jwk() {
+        let key = DpopKey::generate_es256().unwrap();
+        let jwk = key.jwk();
+        assert!(jwk.starts_with(r#"{"crv":"P-256","kty":"EC","x":""#));
+        assert!(!jwk.contains(' '));
+
+        let thumbprint = key.thumbprint().unwrap();
+        assert_eq!(thumbprint.len(), 43);
+        assert_eq!(thumbprint, key.thumbprint().unwrap());
+    }
+}
@@ -0,0 +1,217 @@
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use reqwest::header::HeaderValue;
+
+use crate::core::authorization_header;
+use crate::token_manager::{
+    is_refreshing_on_current_thread, GivesFixedAccessToken, TokenErrorKind, TokenResult,
+};
+use crate::{AccessToken, Credential, TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
+
+thread_local! {
+    static FETCHING_TOKEN: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The credentials a client presents to the introspection endpoint itself.
+///
+/// They are sent in the `Authorization` header of every request so the
+/// introspected access token has to be sent in the URL.
+#[derive(Clone)]
+pub(crate) enum EndpointAuth {
+    Basic(Credential),
+    /// Takes the current `AccessToken` for every introspection.
+    ///
+    /// The `bootstrap` credential is used as long as there is no
+    /// `AccessToken` yet and while a managed token is refreshed on the
+    /// current thread.
+    Bearer {
+        token: Arc<dyn Fn() -> TokenResult<AccessToken> + Send + Sync + 'static>,
+        bootstrap: Option<Credential>,
+    },
+}
+
+impl EndpointAuth {
+    pub fn basic<U: Into<String>, W: Into<String>>(user: U, pass: W) -> EndpointAuth {
+        EndpointAuth::Basic(Credential::basic(user, pass))
+    }
+
+    pub fn bearer_from<S, T>(source: S) -> EndpointAuth
+    where
+        S: GivesFixedAccessToken<T> + Send + Sync + 'static,
+        T: Eq + Ord + Clone + Debug,
+    {
+        EndpointAuth::Bearer {
+            token: Arc::new(move || source.get_access_token()),
+            bootstrap: None,
+        }
+    }
+
+    /// Sets the bootstrap credential of a `Bearer`.
+    pub fn with_bootstrap(self, bootstrap: Option<Credential>) -> EndpointAuth {
+        match self {
+            EndpointAuth::Bearer { token, .. } => EndpointAuth::Bearer { token, bootstrap },
+            basic => basic,
+        }
+    }
+
+    /// Creates the value of the `Authorization` header.
+    ///
+    /// Fails if there is no `AccessToken` for the endpoint and with
+    /// `TokenInfoErrorKind::SelfIntrospection` if getting it would
+    /// wait for the current thread or recurse.
+    pub fn authorization(&self) -> TokenInfoResult<HeaderValue> {
+        let (token, bootstrap) = match *self {
+            EndpointAuth::Basic(ref credential) => return authorization_header(credential),
+            EndpointAuth::Bearer {
+                ref token,
+                ref bootstrap,
+            } => (token, bootstrap.as_ref()),
+        };
+
+        if is_refreshing_on_current_thread() {
+            return match bootstrap {
+                Some(bootstrap) => authorization_header(bootstrap),
+                None => Err(TokenInfoErrorKind::SelfIntrospection(
+                    "The introspection endpoint is authenticated with a managed token \
+                     which can not be used while refreshing a managed token"
+                        .to_string(),
+                )
+                .into()),
+            };
+        }
+
+        let token = match FetchingGuard::enter() {
+            Some(_fetching) => token(),
+            None => {
+                return Err(TokenInfoErrorKind::SelfIntrospection(
+                    "Getting the access token for the introspection endpoint \
+                     introspected recursively"
+                        .to_string(),
+                )
+                .into())
+            }
+        };
+
+        match (token, bootstrap) {
+            (Ok(token), _) => authorization_header(&Credential::Bearer(token)),
+            (Err(ref err), Some(bootstrap)) if is_not_initialized(err.kind()) => {
+                authorization_header(bootstrap)
+            }
+            (Err(err), _) => Err(TokenInfoError::from(TokenInfoErrorKind::Client(format!(
+                "No access token to authenticate at the introspection endpoint: {}",
+                err
+            )))),
+        }
+    }
+}
+
+fn is_not_initialized(kind: &TokenErrorKind) -> bool {
+    matches!(*kind, TokenErrorKind::NotInitialized(_))
+}
+
+/// Marks the current thread as getting the token for the endpoint.
+struct FetchingGuard;
+
+impl FetchingGuard {
+    /// Returns `None` if the current thread is already getting the token.
+    fn enter() -> Option<FetchingGuard> {
+        if FETCHING_TOKEN.with(|fetching| fetching.replace(true)) {
+            None
+        } else {
+            Some(FetchingGuard)
+        }
+    }
+}
+
+impl Drop for FetchingGuard {
+    fn drop(&mut self) {
+        FETCHING_TOKEN.with(|fetching| fetching.set(false));
+    }
+}
+
+/// The error for introspecting a credential which has to be sent in the
+/// `Authorization` header already used by the `EndpointAuth`.
+pub(crate) fn conflicting_authorization() -> TokenInfoError {
+    TokenInfoErrorKind::Client(
+        "The credential can not be introspected because the Authorization header \
+         authenticates at the introspection endpoint"
+            .to_string(),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token_manager::{FixedAccessTokenSourceSync, RefreshingGuard, TokenRefreshError};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    fn not_initialized() -> EndpointAuth {
+        EndpointAuth::Bearer {
+            token: Arc::new(|| Err(TokenErrorKind::not_initialized("id", Instant::now()).into())),
+            bootstrap: None,
+        }
+    }
+
+    #[test]
+    fn bearer_takes_the_token_from_the_source() {
+        let source = FixedAccessTokenSourceSync::new_detached("id", AccessToken::new("secret"));
+        let auth = EndpointAuth::bearer_from(source);
+        assert_eq!(auth.authorization().unwrap(), "Bearer secret");
+    }
+
+    #[test]
+    fn basic_is_encoded() {
+        let auth = EndpointAuth::basic("Aladdin", "open sesame");
+        assert_eq!(
+            auth.authorization().unwrap(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn the_bootstrap_is_used_until_there_is_a_token() {
+        assert!(not_initialized().authorization().is_err());
+        let auth = not_initialized().with_bootstrap(Some(Credential::basic("boot", "strap")));
+        assert_eq!(auth.authorization().unwrap(), "Basic Ym9vdDpzdHJhcA==");
+    }
+
+    #[test]
+    fn the_managed_token_is_not_used_while_refreshing() {
+        let source = FixedAccessTokenSourceSync::new_detached("id", AccessToken::new("secret"));
+        let auth = EndpointAuth::bearer_from(source);
+        let _refreshing = RefreshingGuard::enter();
+        match *auth.authorization().unwrap_err().kind() {
+            TokenInfoErrorKind::SelfIntrospection(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        let auth = auth.with_bootstrap(Some(Credential::basic("boot", "strap")));
+        assert_eq!(auth.authorization().unwrap(), "Basic Ym9vdDpzdHJhcA==");
+    }
+
+    #[test]
+    fn recursion_is_detected() {
+        let inner: Arc<Mutex<Option<EndpointAuth>>> = Arc::new(Mutex::new(None));
+        let recursing = inner.clone();
+        let auth = EndpointAuth::Bearer {
+            token: Arc::new(move || {
+                let auth = recursing.lock().unwrap().clone().unwrap();
+                match auth.authorization() {
+                    Err(err) => Err(TokenErrorKind::AccessTokenProvider(TokenRefreshError::new(
+                        err.to_string(),
+                    ))
+                    .into()),
+                    Ok(_) => Ok(AccessToken::new("unreachable")),
+                }
+            }),
+            bootstrap: None,
+        };
+        *inner.lock().unwrap() = Some(auth.clone());
+
+        let err = auth.authorization().unwrap_err();
+        assert!(err.to_string().contains("recursively"), "{}", err);
+    }
+}
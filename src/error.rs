@@ -1,7 +1,12 @@
+use std::error::Error as StdError;
 use std::fmt;
+use std::io;
+use std::time::Duration;
 
 use failure::*;
 
+use crate::{Scope, ScopeList};
+
 pub type TokenInfoResult<T> = ::std::result::Result<T, TokenInfoError>;
 
 #[derive(Debug)]
@@ -19,13 +24,18 @@ impl TokenInfoError {
         match *self.kind() {
             InvalidResponseContent(_) => false,
             UrlError(_) => false,
-            NotAuthenticated(_) => false,
+            NotAuthenticated { .. } => false,
             Connection(_) => true,
             Io(_) => true,
             Client(_) => false,
             Server(_) => true,
             Other(_) => true,
             BudgetExceeded => false,
+            ResponseTooLarge(_) => false,
+            SelfIntrospection(_) => false,
+            RateLimited { .. } => true,
+            NotAuthorized { .. } => false,
+            TooManyRequests { .. } => false,
         }
     }
 }
@@ -61,13 +71,19 @@ impl fmt::Display for TokenInfoError {
 }
 
 #[derive(Debug, Clone, Fail)]
+#[non_exhaustive]
 pub enum TokenInfoErrorKind {
     #[fail(display = "{}", _0)]
     InvalidResponseContent(String),
     #[fail(display = "{}", _0)]
     UrlError(String),
-    #[fail(display = "{}", _0)]
-    NotAuthenticated(String),
+    /// The introspection service refused the token. `challenge` is the
+    /// `Bearer` challenge of its `WWW-Authenticate` header if it sent one.
+    #[fail(display = "{}", message)]
+    NotAuthenticated {
+        message: String,
+        challenge: Option<Box<BearerChallenge>>,
+    },
     #[fail(display = "{}", _0)]
     Connection(String),
     #[fail(display = "{}", _0)]
@@ -80,4 +96,175 @@ pub enum TokenInfoErrorKind {
     Other(String),
     #[fail(display = "Request budget on tokenintrospection service exceeded")]
     BudgetExceeded,
+    #[fail(display = "The response exceeded the maximum size of {} bytes", _0)]
+    ResponseTooLarge(usize),
+    /// The introspection service refused the request because of too many
+    /// requests. `retry_after` is the time it asked to wait.
+    #[fail(display = "The introspection service is rate limited")]
+    RateLimited { retry_after: Option<Duration> },
+    /// The introspection would need the `AccessToken` it authenticates
+    /// with while that token is being obtained.
+    #[fail(display = "{}", _0)]
+    SelfIntrospection(String),
+    /// The token is active but lacks the `missing_scopes` required for
+    /// the request.
+    #[fail(display = "Required scopes not present: {}", missing_scopes)]
+    NotAuthorized { missing_scopes: ScopeList },
+    /// Too many introspections of the token failed recently. It will not
+    /// be introspected again before `retry_after` elapsed.
+    #[fail(display = "Too many failed introspections of the token")]
+    TooManyRequests { retry_after: Duration },
+}
+
+impl TokenInfoErrorKind {
+    /// A `NotAuthenticated` without a challenge.
+    pub fn not_authenticated<T: Into<String>>(message: T) -> TokenInfoErrorKind {
+        TokenInfoErrorKind::NotAuthenticated {
+            message: message.into(),
+            challenge: None,
+        }
+    }
+
+    /// A `NotAuthorized` lacking the `missing_scopes`.
+    pub fn not_authorized(missing_scopes: Vec<Scope>) -> TokenInfoErrorKind {
+        TokenInfoErrorKind::NotAuthorized {
+            missing_scopes: ScopeList::from(missing_scopes),
+        }
+    }
+}
+
+/// The parameters of a `Bearer` challenge in a `WWW-Authenticate` header as
+/// defined in [RFC 6750 Sec. 3](https://tools.ietf.org/html/rfc6750#section-3).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BearerChallenge {
+    pub realm: Option<String>,
+    pub error: Option<BearerErrorCode>,
+    pub error_description: Option<String>,
+    /// The `Scope`s required to access the resource.
+    pub scope: Vec<Scope>,
+}
+
+/// The `error` of a `BearerChallenge`.
+///
+/// See [RFC 6750 Sec. 3.1](https://tools.ietf.org/html/rfc6750#section-3.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BearerErrorCode {
+    /// The request is missing a required parameter or is otherwise
+    /// malformed.
+    InvalidRequest,
+    /// The access token is expired, revoked, malformed, or invalid for
+    /// other reasons.
+    InvalidToken,
+    /// The request requires higher privileges than provided by the access
+    /// token.
+    InsufficientScope,
+    /// An error code not defined by RFC 6750.
+    Other(String),
+}
+
+impl BearerErrorCode {
+    pub fn parse(error: &str) -> BearerErrorCode {
+        match error {
+            "invalid_request" => BearerErrorCode::InvalidRequest,
+            "invalid_token" => BearerErrorCode::InvalidToken,
+            "insufficient_scope" => BearerErrorCode::InsufficientScope,
+            other => BearerErrorCode::Other(other.to_string()),
+        }
+    }
+}
+
+/// A `Result` where the failure is always an `InitializationError`
+pub type InitializationResult<T> = ::std::result::Result<T, InitializationError>;
+
+/// An error to be returned if the initialization of a component
+/// or else fails.
+#[derive(Debug)]
+pub enum InitializationError {
+    /// A mandatory value was not configured, e.g. the name of a token
+    MissingField(String),
+    /// A URL is malformed or can not be used
+    InvalidUrl(String),
+    /// An environment variable is missing, not unicode or has an invalid
+    /// value
+    EnvVar { name: String, message: String },
+    /// A file could not be read
+    Io { message: String, source: io::Error },
+    /// Credentials or keys are malformed
+    Credentials(String),
+    /// Anything else, e.g. settings which contradict each other
+    Other(String),
+}
+
+impl InitializationError {
+    /// Creates an `InitializationError::EnvVar` for the variable `name`.
+    pub fn env_var<N: Into<String>, M: fmt::Display>(name: N, message: M) -> Self {
+        InitializationError::EnvVar {
+            name: name.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for InitializationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InitializationError::MissingField(ref field) => write!(f, "{} is mandatory", field),
+            InitializationError::InvalidUrl(ref msg) => write!(f, "Invalid URL: {}", msg),
+            InitializationError::EnvVar {
+                ref name,
+                ref message,
+            } => write!(f, "'{}': {}", name, message),
+            InitializationError::Io {
+                ref message,
+                ref source,
+            } => write!(f, "{}: {}", message, source),
+            InitializationError::Credentials(ref msg) => write!(f, "Invalid credentials: {}", msg),
+            InitializationError::Other(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl StdError for InitializationError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            InitializationError::Io { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for InitializationError {
+    fn from(msg: String) -> Self {
+        InitializationError::Other(msg)
+    }
+}
+
+impl From<&str> for InitializationError {
+    fn from(msg: &str) -> Self {
+        InitializationError::Other(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn initialization_errors_chain_their_sources() {
+        let err = InitializationError::Io {
+            message: "Could not read 'key.json'".to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, "not found"),
+        };
+        assert_eq!(err.to_string(), "Could not read 'key.json': not found");
+        assert_eq!(err.source().unwrap().to_string(), "not found");
+
+        let err = InitializationError::env_var("TOKKIT_RDS_PORT", "not a number");
+        assert_eq!(err.to_string(), "'TOKKIT_RDS_PORT': not a number");
+        assert!(err.source().is_none());
+
+        match InitializationError::from("legacy".to_string()) {
+            InitializationError::Other(ref msg) if msg == "legacy" => {}
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,207 @@
+//! Claims of introspection responses which are not mapped to a field of
+//! `TokenInfo`.
+use std::fmt;
+
+use json::JsonValue;
+
+/// The claims of an introspection response which are not mapped to a
+/// field of `TokenInfo`, e.g. a tenant id or a plan.
+///
+/// They are only captured by parsers configured to do so, see
+/// `CustomTokenInfoParser::with_extra_claims`. Read them with
+/// `TokenInfo::claim`.
+///
+/// `Debug` only shows the names of the claims since their values may
+/// contain personal data.
+#[derive(Clone, PartialEq)]
+pub struct Claims(JsonValue);
+
+impl Claims {
+    /// Creates `Claims` from a JSON object. Other JSON values are
+    /// ignored.
+    pub fn from_json(object: JsonValue) -> Claims {
+        if object.is_object() {
+            Claims(object)
+        } else {
+            Claims::default()
+        }
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, value: JsonValue) {
+        if !self.0.is_object() {
+            self.0 = JsonValue::new_object();
+        }
+        self.0[name] = value;
+    }
+
+    /// The claims as a JSON object or `null` if there are none.
+    pub fn as_json(&self) -> &JsonValue {
+        &self.0
+    }
+
+    /// The names of the top level claims.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.entries().map(|(name, _)| name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The value at the dotted `path`, e.g. `tenant.id`. A segment which
+    /// is a number selects an element of an array.
+    pub fn get(&self, path: &str) -> Option<&JsonValue> {
+        path.split('.')
+            .try_fold(&self.0, |value, segment| match value {
+                JsonValue::Object(object) => object.get(segment),
+                JsonValue::Array(elements) => segment
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|idx| elements.get(idx)),
+                _ => None,
+            })
+    }
+
+    /// The value at the dotted `path` converted to `T`.
+    pub fn claim<T: FromJson>(&self, path: &str) -> Result<T, ClaimError> {
+        let value = self
+            .get(path)
+            .filter(|value| !value.is_null())
+            .ok_or_else(|| ClaimError::Missing(path.to_string()))?;
+        T::from_json(value).ok_or_else(|| ClaimError::Unexpected {
+            path: path.to_string(),
+            expected: T::EXPECTED,
+        })
+    }
+}
+
+impl Default for Claims {
+    fn default() -> Claims {
+        Claims(JsonValue::Null)
+    }
+}
+
+impl fmt::Debug for Claims {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+/// A claim could not be read.
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+pub enum ClaimError {
+    /// There is no claim at the path or it is `null`.
+    #[fail(display = "The claim '{}' is missing.", _0)]
+    Missing(String),
+    /// The claim is not of the requested type.
+    #[fail(display = "The claim '{}' is not {}.", path, expected)]
+    Unexpected {
+        path: String,
+        expected: &'static str,
+    },
+}
+
+/// Types a claim can be converted to.
+pub trait FromJson: Sized {
+    /// Describes the expected JSON value, e.g. "a string".
+    const EXPECTED: &'static str;
+
+    /// Converts the value or returns `None` if it is of another type.
+    fn from_json(value: &JsonValue) -> Option<Self>;
+}
+
+impl FromJson for String {
+    const EXPECTED: &'static str = "a string";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        value.as_str().map(str::to_owned)
+    }
+}
+
+impl FromJson for bool {
+    const EXPECTED: &'static str = "a boolean";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl FromJson for u64 {
+    const EXPECTED: &'static str = "a non-negative integer";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        value.as_u64()
+    }
+}
+
+impl FromJson for i64 {
+    const EXPECTED: &'static str = "an integer";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        value.as_i64()
+    }
+}
+
+impl FromJson for f64 {
+    const EXPECTED: &'static str = "a number";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        value.as_f64()
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    const EXPECTED: &'static str = "an array of matching elements";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        if !value.is_array() {
+            return None;
+        }
+        value.members().map(T::from_json).collect()
+    }
+}
+
+impl FromJson for JsonValue {
+    const EXPECTED: &'static str = "a JSON value";
+
+    fn from_json(value: &JsonValue) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn claims_are_read_by_dotted_paths() {
+        let claims = Claims::from_json(json::object! {
+            "tenant" => json::object! { "id" => "acme", "regions" => json::array!["eu", "us"] },
+            "plan" => "gold",
+            "seats" => 12,
+        });
+
+        assert_eq!(claims.claim::<String>("tenant.id"), Ok("acme".to_string()));
+        assert_eq!(
+            claims.claim::<String>("tenant.regions.1"),
+            Ok("us".to_string())
+        );
+        assert_eq!(
+            claims.claim::<Vec<String>>("tenant.regions"),
+            Ok(vec!["eu".to_string(), "us".to_string()])
+        );
+        assert_eq!(claims.claim::<u64>("seats"), Ok(12));
+        assert_eq!(
+            claims.claim::<u64>("plan"),
+            Err(ClaimError::Unexpected {
+                path: "plan".to_string(),
+                expected: "a non-negative integer",
+            })
+        );
+        assert_eq!(
+            claims.claim::<String>("tenant.name"),
+            Err(ClaimError::Missing("tenant.name".to_string()))
+        );
+        assert_eq!(format!("{:?}", claims), r#"{"tenant", "plan", "seats"}"#);
+    }
+}
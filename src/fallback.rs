@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::core::REFUSED_TOKEN_PREFIX;
+use crate::{BearerErrorCode, TokenInfoError, TokenInfoErrorKind};
+
+/// Decides whether the fallback endpoint is asked after the primary
+/// endpoint failed.
+///
+/// Failures which the fallback would answer alike, e.g. a token the
+/// authorization server declared invalid, should not be sent to the
+/// fallback. They only delay the response and double the load.
+///
+/// Any `Fn(&TokenInfoError) -> bool` is a `FallbackClassifier`:
+///
+/// ```rust
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+/// use tokkit::{DefaultFallbackClassifier, FallbackClassifier, TokenInfoError, TokenInfoErrorKind};
+///
+/// let mut builder = TokenInfoServiceClientBuilder::google_v3();
+/// builder.with_fallback_classifier(|err: &TokenInfoError| match *err.kind() {
+///     TokenInfoErrorKind::NotAuthenticated { .. } => false,
+///     _ => DefaultFallbackClassifier.use_fallback(err),
+/// });
+/// ```
+pub trait FallbackClassifier {
+    /// Returns `true` if the fallback endpoint should be asked after the
+    /// primary endpoint failed with `err`.
+    fn use_fallback(&self, err: &TokenInfoError) -> bool;
+}
+
+impl<F> FallbackClassifier for F
+where
+    F: Fn(&TokenInfoError) -> bool,
+{
+    fn use_fallback(&self, err: &TokenInfoError) -> bool {
+        self(err)
+    }
+}
+
+/// The `FallbackClassifier` of a client, if any.
+pub(crate) type SharedFallbackClassifier = Arc<dyn FallbackClassifier + Send + Sync + 'static>;
+
+/// The `FallbackClassifier` used unless another one is configured.
+///
+/// The fallback is asked after server errors, invalid responses and
+/// connection failures. It is not asked if the request itself was
+/// refused with a `4xx` other than `401` or `429`, nor if a `401` says the
+/// token is invalid. A `401` says so with the error `invalid_token` in its
+/// `Bearer` challenge or in an OAuth 2.0 error response body like
+/// `{"error": "invalid_token"}`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultFallbackClassifier;
+
+impl FallbackClassifier for DefaultFallbackClassifier {
+    fn use_fallback(&self, err: &TokenInfoError) -> bool {
+        match *err.kind() {
+            TokenInfoErrorKind::Client(_)
+            | TokenInfoErrorKind::SelfIntrospection(_)
+            | TokenInfoErrorKind::NotAuthorized { .. }
+            | TokenInfoErrorKind::TooManyRequests { .. } => false,
+            TokenInfoErrorKind::NotAuthenticated {
+                ref message,
+                ref challenge,
+            } => {
+                let error = challenge
+                    .as_ref()
+                    .and_then(|challenge| challenge.error.clone())
+                    .or_else(|| {
+                        message
+                            .strip_prefix(REFUSED_TOKEN_PREFIX)
+                            .and_then(oauth_error)
+                    });
+                error != Some(BearerErrorCode::InvalidToken)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// How the retries of an introspection are spread over the primary and the
+/// fallback endpoint.
+///
+/// The fallback endpoint is only asked for errors the `FallbackClassifier`
+/// lets through with every strategy. Introspections without retries ask
+/// the primary endpoint once and then the fallback endpoint once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FallbackStrategy {
+    /// Retries the primary endpoint for the whole budget and then the
+    /// fallback endpoint for another whole budget. This is the default.
+    #[default]
+    PrimaryThenFallback,
+    /// Retries the primary endpoint for half of the budget and then the
+    /// fallback endpoint for the rest of it.
+    SplitBudget,
+    /// Alternates between the primary and the fallback endpoint with each
+    /// attempt within one budget.
+    Interleave,
+}
+
+impl FallbackStrategy {
+    /// The part of the `budget` the primary endpoint may be retried for.
+    pub(crate) fn primary_budget(self, budget: Duration) -> Duration {
+        match self {
+            FallbackStrategy::SplitBudget => budget / 2,
+            _ => budget,
+        }
+    }
+
+    /// The budget of the fallback endpoint once the primary endpoint
+    /// failed after `elapsed`.
+    pub(crate) fn fallback_budget(self, budget: Duration, elapsed: Duration) -> Duration {
+        match self {
+            FallbackStrategy::SplitBudget => budget.checked_sub(elapsed).unwrap_or_default(),
+            _ => budget,
+        }
+    }
+}
+
+/// The `error` of an OAuth 2.0 error response body.
+fn oauth_error(body: &str) -> Option<BearerErrorCode> {
+    let body = json::parse(body).ok()?;
+    body["error"].as_str().map(BearerErrorCode::parse)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BearerChallenge;
+
+    fn use_fallback(kind: TokenInfoErrorKind) -> bool {
+        DefaultFallbackClassifier.use_fallback(&TokenInfoError::from(kind))
+    }
+
+    #[test]
+    fn invalid_tokens_are_not_sent_to_the_fallback() {
+        assert!(use_fallback(TokenInfoErrorKind::Server(String::new())));
+        assert!(use_fallback(TokenInfoErrorKind::InvalidResponseContent(
+            String::new()
+        )));
+        assert!(!use_fallback(TokenInfoErrorKind::Client(
+            r#"{"error":"invalid_request"}"#.to_string()
+        )));
+
+        assert!(use_fallback(TokenInfoErrorKind::not_authenticated(
+            format!("{}nope", REFUSED_TOKEN_PREFIX)
+        )));
+        assert!(!use_fallback(TokenInfoErrorKind::not_authenticated(
+            format!("{}{{\"error\":\"invalid_token\"}}", REFUSED_TOKEN_PREFIX)
+        )));
+        assert!(!use_fallback(TokenInfoErrorKind::NotAuthenticated {
+            message: String::new(),
+            challenge: Some(Box::new(BearerChallenge {
+                error: Some(BearerErrorCode::InvalidToken),
+                ..Default::default()
+            })),
+        }));
+    }
+
+    #[test]
+    fn split_budgets_never_exceed_the_budget() {
+        let budget = Duration::from_millis(200);
+        let strategy = FallbackStrategy::SplitBudget;
+        assert_eq!(strategy.primary_budget(budget), Duration::from_millis(100));
+        assert_eq!(
+            strategy.fallback_budget(budget, Duration::from_millis(120)),
+            Duration::from_millis(80)
+        );
+        assert_eq!(
+            strategy.fallback_budget(budget, Duration::from_millis(300)),
+            Duration::from_millis(0)
+        );
+
+        let strategy = FallbackStrategy::default();
+        assert_eq!(strategy.primary_budget(budget), budget);
+        assert_eq!(strategy.fallback_budget(budget, budget), budget);
+    }
+}
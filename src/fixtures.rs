@@ -0,0 +1,100 @@
+//! Deterministic values for unit tests.
+//!
+//! The fixtures are built with the constructors of the respective types so
+//! tests using them do not break when fields are added. They always return
+//! the same values.
+//!
+//! ```rust
+//! use tokkit::fixtures;
+//!
+//! let token_info = fixtures::token_info();
+//! assert!(token_info.active);
+//! assert!(token_info.has_scope(&fixtures::scope()));
+//! ```
+use std::time::Duration;
+
+use crate::token_manager::token_provider::{
+    AccessTokenProviderError, AuthorizationRequestError, AuthorizationServerErrorCode,
+    AuthorizationServerResponse,
+};
+use crate::{AccessToken, Scope, TokenInfo, TokenInfoError, TokenInfoErrorKind};
+
+/// The secret of `access_token`.
+pub const ACCESS_TOKEN: &str = "fixture-access-token";
+/// The user of `token_info`.
+pub const USER_ID: &str = "fixture-user";
+/// The `Scope` of `token_info` and `authorization_server_response`.
+pub const SCOPE: &str = "fixture.read";
+/// The lifetime of `token_info` and `authorization_server_response`.
+pub const EXPIRES_IN: Duration = Duration::from_secs(3600);
+
+pub fn access_token() -> AccessToken {
+    AccessToken::new(ACCESS_TOKEN)
+}
+
+/// The `n`th of a sequence of distinct `AccessToken`s.
+pub fn nth_access_token(n: usize) -> AccessToken {
+    AccessToken::new(format!("{}-{}", ACCESS_TOKEN, n))
+}
+
+pub fn scope() -> Scope {
+    Scope::new(SCOPE)
+}
+
+/// An active `TokenInfo` of `USER_ID` with `SCOPE` expiring in
+/// `EXPIRES_IN`.
+pub fn token_info() -> TokenInfo {
+    TokenInfo::new(true)
+        .with_user_id(USER_ID)
+        .with_scopes(vec![scope()])
+        .with_expires_in_seconds(EXPIRES_IN.as_secs())
+}
+
+/// A `TokenInfo` of a revoked or expired token.
+pub fn inactive_token_info() -> TokenInfo {
+    TokenInfo::new(false)
+}
+
+/// A `Bearer` token with `SCOPE` expiring in `EXPIRES_IN`.
+pub fn authorization_server_response() -> AuthorizationServerResponse {
+    AuthorizationServerResponse::new(access_token(), EXPIRES_IN)
+        .with_scopes(vec![scope()])
+        .with_token_type("Bearer")
+}
+
+/// A `TokenInfoError` which suggests a retry.
+pub fn server_error() -> TokenInfoError {
+    TokenInfoErrorKind::Server("fixture server error".to_string()).into()
+}
+
+/// A `TokenInfoError` for a token which was refused.
+pub fn not_authenticated() -> TokenInfoError {
+    TokenInfoErrorKind::not_authenticated("fixture token refused").into()
+}
+
+/// An `AccessTokenProviderError` which should be retried.
+pub fn provider_server_error() -> AccessTokenProviderError {
+    AccessTokenProviderError::Server("fixture server error".to_string())
+}
+
+/// An `AccessTokenProviderError` for a request with an unknown `Scope`.
+pub fn invalid_scope() -> AccessTokenProviderError {
+    AccessTokenProviderError::BadAuthorizationRequest(
+        AuthorizationRequestError::new(AuthorizationServerErrorCode::InvalidScope)
+            .with_description(format!("unknown scope {}", SCOPE)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_deterministic() {
+        assert_eq!(token_info(), token_info());
+        assert_eq!(nth_access_token(1).reveal(), nth_access_token(1).reveal());
+        assert_ne!(nth_access_token(1).reveal(), nth_access_token(2).reveal());
+        assert!(server_error().is_retry_suggested());
+        assert!(!not_authenticated().is_retry_suggested());
+    }
+}
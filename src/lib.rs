@@ -21,14 +21,36 @@
 //! ## Features
 //!
 //! * `async`: Adds a `reqwest` based async client.
-//! See also `TokenInfoServiceClientBuilder`
+//! See also `TokenInfoServiceClientBuilder`. Managed tokens can be watched
+//! for rotations with `AccessTokenSource::watch`.
+//! * `cli`: Builds the `tokkit-cli` binary to fetch and introspect tokens
+//!   and to watch a managed token with the configuration from the
+//!   environment
 //! * `metrix`: Add support for the [metrix](https://crates.io/crates/metrix)
 //! crate(async client only)
 //! See also `TokenInfoServiceClientBuilder`
+//! * `dpop`: Adds the `dpop` module to create DPoP proofs for outbound
+//!   requests
+//! * `encrypted-credentials`: Adds an `EncryptedFileCredentialsProvider`
+//!   reading AES-256-GCM encrypted credentials files
+//! * `gcp`: Adds a `GoogleServiceAccountProvider` for Google service
+//!   account keys
+//! * `kafka`: Adds an `OAuthBearerContext` to authenticate `rdkafka`
+//!   clients with managed tokens
+//! * `mtls`: Adds `TokenInfo::must_match_client_cert` to enforce
+//!   certificate-bound tokens
+//! * `redis`: Adds a `RedisTokenInfoCache` to share cached introspection
+//!   results between processes
+//! * `spiffe`: Adds a `SpiffeAccessTokenProvider` fetching JWT-SVIDs from
+//!   the SPIFFE Workload API
+//! * `testing`: Adds the `fixtures` module with deterministic values for
+//!   unit tests
+//! * `serde`: Implements `Serialize` and `Deserialize` for `TokenInfo`,
+//!   `Scope` and `UserId`
 //!
 //! ### Verify Access Tokens
 //!
-//! `tokkit` contains a module `token_info` for protected resources to verify
+//! `tokkit` contains a module `client` for protected resources to verify
 //! access tokens.
 //!
 //! ```rust,no_run
@@ -80,28 +102,115 @@ extern crate log;
 extern crate failure;
 
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[cfg(feature = "async")]
 pub mod async_client;
+mod body_capture;
+pub mod cache;
+mod claims;
 pub mod client;
+mod cognito;
+pub mod conformance;
+mod content_encoding;
+mod context;
+mod core;
+mod credential;
+pub mod diagnostics;
+#[cfg(feature = "dpop")]
+pub mod dpop;
+mod endpoint_auth;
 mod error;
+mod extra_claims;
+mod fallback;
+#[cfg(any(test, feature = "testing"))]
+pub mod fixtures;
+pub mod metadata;
 pub mod metrics;
+pub mod offline;
 pub mod parsers;
+pub mod rate_limit;
+mod redirect;
+mod request_decorator;
+mod request_log;
+mod scope_interner;
+mod scope_matcher;
+#[cfg(feature = "serde")]
+mod serde_support;
+mod sha256;
+mod singleflight;
+mod tls;
 pub mod token_manager;
+mod unix_socket;
+pub mod validation;
 
-pub use error::{TokenInfoError, TokenInfoErrorKind, TokenInfoResult};
+pub use body_capture::{BodyCapture, DEFAULT_CAPTURED_BODY_LENGTH};
+pub use context::{IntrospectionContext, CORRELATION_ID_HEADER, PEER_HEADER};
+pub use credential::Credential;
+pub use error::{
+    BearerChallenge, BearerErrorCode, InitializationError, InitializationResult, TokenInfoError,
+    TokenInfoErrorKind, TokenInfoResult,
+};
+pub use extra_claims::{ClaimError, Claims, FromJson};
+pub use fallback::{DefaultFallbackClassifier, FallbackClassifier, FallbackStrategy};
+pub use redirect::{RedirectPolicy, DEFAULT_MAX_REDIRECTS};
+pub use request_decorator::RequestDecorator;
+pub use request_log::RedactionPolicy;
+pub use scope_interner::{ScopeInterner, DEFAULT_INTERNER_CAPACITY};
+pub use scope_matcher::ScopeMatcher;
+pub use tls::TlsPolicy;
 
 /// An access token
 ///
+/// The secret can only be read with `reveal` or `into_inner` so that it
+/// does not end up in logs by accident. `Display` and `Debug` redact it
+/// and it is overwritten with zeros when dropped.
+///
+/// ```rust
+/// use tokkit::AccessToken;
+///
+/// let token = AccessToken::new("secret");
+/// assert_eq!(token.to_string(), "<secret-access-token>");
+/// assert_eq!(token.reveal(), "secret");
+/// ```
+///
 /// See [RFC6749](https://tools.ietf.org/html/rfc6749#section-1.4)
 #[derive(Clone)]
-pub struct AccessToken(pub String);
+pub struct AccessToken(String);
 
 impl AccessToken {
     /// Creates a new `AccessToken`
     pub fn new<T: Into<String>>(token: T) -> Self {
         AccessToken(token.into())
     }
+
+    /// Returns the secret token, e.g. to put it into a header.
+    pub fn reveal(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the secret token. It is not zeroed when the
+    /// returned `String` is dropped.
+    pub fn into_inner(mut self) -> String {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl Drop for AccessToken {
+    fn drop(&mut self) {
+        // SAFETY: Zero bytes are valid UTF-8 (each one is a NUL character),
+        // so the `String` stays valid while and after it is overwritten.
+        let bytes = unsafe { self.0.as_mut_vec() };
+        for byte in bytes.iter_mut() {
+            // Volatile writes are not optimized away for memory about to be freed.
+            // SAFETY: `byte` is a valid, aligned and exclusive reference.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
 }
 
 impl fmt::Display for AccessToken {
@@ -118,14 +227,64 @@ impl fmt::Debug for AccessToken {
 
 /// An access token scope
 ///
+/// The string is shared between clones. Scopes parsed from introspection
+/// responses are shared through a `ScopeInterner` so that equal scopes are
+/// usually compared by pointer.
+///
 /// See [RFC6749](https://tools.ietf.org/html/rfc6749#page-23)
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-pub struct Scope(pub String);
+#[derive(PartialOrd, Ord, Debug, Clone)]
+pub struct Scope(pub Arc<str>);
 
 impl Scope {
     /// Creates a new `Scope`
-    pub fn new<T: Into<String>>(scope: T) -> Scope {
-        Scope(scope.into())
+    pub fn new<T: AsRef<str>>(scope: T) -> Scope {
+        Scope(Arc::from(scope.as_ref()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Creates a new `Scope` if `scope` is a valid scope token.
+    ///
+    /// A scope token must not be empty and may only contain the printable
+    /// ASCII characters except space, `"` and `\`.
+    /// See [RFC6749](https://tools.ietf.org/html/rfc6749#section-3.3)
+    pub fn parse<T: AsRef<str>>(scope: T) -> Result<Scope, InvalidScope> {
+        Scope::validate(scope.as_ref())?;
+        Ok(Scope::new(scope))
+    }
+
+    pub(crate) fn validate(s: &str) -> Result<(), InvalidScope> {
+        if s.is_empty() {
+            return Err(InvalidScope::new("A scope must not be empty."));
+        }
+        if s.bytes().all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\') {
+            return Ok(());
+        }
+        let c = s
+            .chars()
+            .find(|&c| !c.is_ascii_graphic() || c == '"' || c == '\\')
+            .unwrap_or_default();
+        Err(InvalidScope(format!(
+            "Scope '{}' contains the invalid character {:?}.",
+            s.escape_debug(),
+            c
+        )))
+    }
+}
+
+impl PartialEq for Scope {
+    fn eq(&self, other: &Scope) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Scope {}
+
+impl Hash for Scope {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
     }
 }
 
@@ -135,22 +294,214 @@ impl fmt::Display for Scope {
     }
 }
 
+impl FromStr for Scope {
+    type Err = InvalidScope;
+
+    fn from_str(s: &str) -> Result<Scope, InvalidScope> {
+        Scope::parse(s)
+    }
+}
+
+/// A set of `Scope`s in canonical form: Sorted and without duplicates.
+///
+/// It is displayed as the space-separated list used in token requests and
+/// introspection responses and can be parsed from that list.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Default)]
+pub struct ScopeList(Vec<Scope>);
+
+impl ScopeList {
+    /// Creates an empty `ScopeList`.
+    pub fn new() -> ScopeList {
+        ScopeList::default()
+    }
+
+    /// Adds a `Scope` unless it is already contained.
+    pub fn insert(&mut self, scope: Scope) {
+        if let Err(idx) = self.0.binary_search(&scope) {
+            self.0.insert(idx, scope);
+        }
+    }
+
+    /// Checks whether the given `Scope` is contained.
+    pub fn contains(&self, scope: &Scope) -> bool {
+        self.0.binary_search(scope).is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Scope] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<Scope> {
+        self.0
+    }
+}
+
+impl From<Vec<Scope>> for ScopeList {
+    fn from(mut scopes: Vec<Scope>) -> ScopeList {
+        scopes.sort();
+        scopes.dedup();
+        ScopeList(scopes)
+    }
+}
+
+impl<'a> From<&'a [Scope]> for ScopeList {
+    fn from(scopes: &'a [Scope]) -> ScopeList {
+        ScopeList::from(scopes.to_vec())
+    }
+}
+
+impl FromIterator<Scope> for ScopeList {
+    fn from_iter<I: IntoIterator<Item = Scope>>(iter: I) -> ScopeList {
+        ScopeList::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl IntoIterator for ScopeList {
+    type Item = Scope;
+    type IntoIter = ::std::vec::IntoIter<Scope>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for ScopeList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, scope) in self.0.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(&scope.0)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ScopeList {
+    type Err = InvalidScope;
+
+    /// Parses `Scope`s separated by any whitespace.
+    fn from_str(s: &str) -> Result<ScopeList, InvalidScope> {
+        s.split_whitespace().map(Scope::parse).collect()
+    }
+}
+
+/// A `Scope` violates the grammar of a scope token.
+#[derive(Debug, Fail)]
+pub struct InvalidScope(pub String);
+
+impl InvalidScope {
+    pub fn new<T: Into<String>>(msg: T) -> InvalidScope {
+        InvalidScope(msg.into())
+    }
+}
+
+impl fmt::Display for InvalidScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid scope: {}", self.0)
+    }
+}
+
 /// Gives a `TokenInfo` for an `AccessToken`.
 ///
 /// See [OAuth 2.0 Token Introspection](https://tools.ietf.org/html/rfc7662)
 pub trait TokenInfoService {
     /// Gives a `TokenInfo` for an `AccessToken`.
     fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo>;
+
+    /// Gives a `TokenInfo` for any `Credential`.
+    ///
+    /// The default implementation introspects `Credential::Bearer` via
+    /// `introspect` and rejects all other credentials.
+    fn introspect_credential(&self, credential: &Credential) -> TokenInfoResult<TokenInfo> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token),
+            _ => Err(TokenInfoErrorKind::Client(format!(
+                "{:?} can not be introspected by this service",
+                credential
+            ))
+            .into()),
+        }
+    }
+
+    /// Gives a `TokenInfo` for an `AccessToken` introspected on behalf of
+    /// the request described by `context`.
+    ///
+    /// The default implementation ignores the `context`.
+    fn introspect_with_context(
+        &self,
+        token: &AccessToken,
+        _context: &IntrospectionContext,
+    ) -> TokenInfoResult<TokenInfo> {
+        self.introspect(token)
+    }
+
+    /// Gives a `TokenInfo` for an `AccessToken` if it has all of the
+    /// `required` `Scope`s.
+    ///
+    /// Fails with `TokenInfoErrorKind::NotAuthenticated` if the token is
+    /// not active and with `TokenInfoErrorKind::NotAuthorized` listing the
+    /// missing `Scope`s if it lacks any of them.
+    fn introspect_expecting(
+        &self,
+        token: &AccessToken,
+        required: &[Scope],
+    ) -> TokenInfoResult<TokenInfo> {
+        self.introspect(token)
+            .and_then(|token_info| authorize(token_info, required))
+    }
 }
 
-/// A `Result` where the failure is always an `InitializationError`
-pub type InitializationResult<T> = ::std::result::Result<T, InitializationError>;
+impl<S> TokenInfoService for Arc<S>
+where
+    S: TokenInfoService + ?Sized,
+{
+    fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+        (**self).introspect(token)
+    }
 
-/// An error to be returned if the initialization of a component
-/// or else fails.
-#[derive(Debug, Fail)]
-#[fail(display = "{}", _0)]
-pub struct InitializationError(pub String);
+    fn introspect_credential(&self, credential: &Credential) -> TokenInfoResult<TokenInfo> {
+        (**self).introspect_credential(credential)
+    }
+
+    fn introspect_with_context(
+        &self,
+        token: &AccessToken,
+        context: &IntrospectionContext,
+    ) -> TokenInfoResult<TokenInfo> {
+        (**self).introspect_with_context(token, context)
+    }
+
+    fn introspect_expecting(
+        &self,
+        token: &AccessToken,
+        required: &[Scope],
+    ) -> TokenInfoResult<TokenInfo> {
+        (**self).introspect_expecting(token, required)
+    }
+}
+
+/// Checks that the `TokenInfo` is active and has all of the `required`
+/// `Scope`s.
+pub(crate) fn authorize(token_info: TokenInfo, required: &[Scope]) -> TokenInfoResult<TokenInfo> {
+    if !token_info.active {
+        return Err(TokenInfoErrorKind::not_authenticated("The token is not active").into());
+    }
+    let missing_scopes = ScopeMatcher::exact().missing_scopes(&token_info, required);
+    if missing_scopes.is_empty() {
+        Ok(token_info)
+    } else {
+        Err(TokenInfoErrorKind::not_authorized(missing_scopes).into())
+    }
+}
 
 /// An id that uniquely identifies the owner of a protected resource
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
@@ -171,7 +522,7 @@ impl fmt::Display for UserId {
 /// Information on an `AccessToken` returned by a `TokenInfoService`.
 ///
 /// See [OAuth 2.0 Token Introspection](https://tools.ietf.org/html/rfc7662)
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenInfo {
     /// REQUIRED.  Boolean indicator of whether or not the presented token
     /// is currently active.  The specifics of a token's "active" state
@@ -195,6 +546,9 @@ pub struct TokenInfo {
     /// scopes associated with this token, in the format described in
     /// [Section 3.3](https://tools.ietf.org/html/rfc7662#section-5.1)
     /// of OAuth 2.0 [RFC6749](https://tools.ietf.org/html/rfc6749).
+    ///
+    /// Scopes which are not valid according to RFC 6749 are skipped by
+    /// the parsers.
     pub scope: Vec<Scope>,
     /// OPTIONAL.  Integer timestamp, measured in the number of seconds
     /// since January 1 1970 UTC, indicating when this token will expire,
@@ -203,11 +557,81 @@ pub struct TokenInfo {
     /// Remark: Contains the number of seconds until the token expires.
     /// This seems to be used by most introspection services.
     pub expires_in_seconds: Option<u64>,
+    /// OPTIONAL.  The base64url encoded SHA-256 thumbprint of the client
+    /// certificate the token is bound to. Taken from the `x5t#S256`
+    /// member of the `cnf` claim as defined in
+    /// [RFC 8705](https://tools.ietf.org/html/rfc8705#section-3.1).
+    pub certificate_thumbprint: Option<String>,
+    /// Not part of the introspection response. `true` if the introspection
+    /// failed and this is a previous result of the token served by a
+    /// `CachingTokenInfoService` with a stale fallback.
+    pub from_stale_cache: bool,
+    /// The claims of the response not mapped to one of the fields above.
+    /// Empty unless the parser was configured to capture them.
+    pub extra: Claims,
 }
 
 impl TokenInfo {
+    /// Creates a `TokenInfo` without a user, `Scope`s, expiry or
+    /// certificate binding.
+    pub fn new(active: bool) -> TokenInfo {
+        TokenInfo {
+            active,
+            user_id: None,
+            scope: Vec::new(),
+            expires_in_seconds: None,
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Claims::default(),
+        }
+    }
+
+    pub fn with_user_id<T: Into<String>>(mut self, user_id: T) -> Self {
+        self.user_id = Some(UserId::new(user_id));
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<Scope>) -> Self {
+        self.scope = scopes;
+        self
+    }
+
+    pub fn with_expires_in_seconds(mut self, expires_in_seconds: u64) -> Self {
+        self.expires_in_seconds = Some(expires_in_seconds);
+        self
+    }
+
+    pub fn with_certificate_thumbprint<T: Into<String>>(mut self, thumbprint: T) -> Self {
+        self.certificate_thumbprint = Some(thumbprint.into());
+        self
+    }
+
+    /// Reads the extra claim at the dotted `path`, e.g. `tenant.id`.
+    ///
+    /// ```rust
+    /// use tokkit::parsers::{CustomTokenInfoParser, TokenInfoParser};
+    ///
+    /// let parser = CustomTokenInfoParser::new(
+    ///     Some("active"),
+    ///     Some("sub"),
+    ///     None::<&str>,
+    ///     None::<&str>,
+    /// )
+    /// .with_extra_claims(true);
+    /// let sample = br#"{"active": true, "sub": "user", "tenant": {"id": "acme"}}"#;
+    /// let token_info = parser.parse(sample).unwrap();
+    ///
+    /// assert_eq!(token_info.claim::<String>("tenant.id").unwrap(), "acme");
+    /// assert!(token_info.claim::<String>("sub").is_err());
+    /// ```
+    pub fn claim<T: FromJson>(&self, path: &str) -> ::std::result::Result<T, ClaimError> {
+        self.extra.claim(path)
+    }
+
     /// Use for authorization. Checks whether this `TokenInfo` has the given
     /// `Scope`.
+    ///
+    /// The `Scope`s must be equal. Use a `ScopeMatcher` to ignore their case.
     pub fn has_scope(&self, scope: &Scope) -> bool {
         self.scope.iter().any(|s| s == scope)
     }
@@ -220,13 +644,34 @@ impl TokenInfo {
 
     /// If the `TokenInfo` does not have the scope this method will fail.
     pub fn must_have_scope(&self, scope: &Scope) -> ::std::result::Result<(), NotAuthorized> {
-        if self.has_scope(scope) {
+        ScopeMatcher::exact().must_have_scope(self, scope)
+    }
+
+    /// Checks whether this `TokenInfo` is bound to a client certificate.
+    pub fn is_certificate_bound(&self) -> bool {
+        self.certificate_thumbprint.is_some()
+    }
+
+    /// Enforces a certificate-bound token: Fails if the `TokenInfo` is
+    /// not bound to a certificate or if the thumbprint of the given DER
+    /// encoded client certificate does not match the bound one.
+    #[cfg(feature = "mtls")]
+    pub fn must_match_client_cert(
+        &self,
+        cert_der: &[u8],
+    ) -> ::std::result::Result<(), NotAuthorized> {
+        let expected = match self.certificate_thumbprint {
+            Some(ref thumbprint) => thumbprint,
+            None => return Err(NotAuthorized::new("Token is not certificate-bound.")),
+        };
+        let digest = openssl::sha::sha256(cert_der);
+        let thumbprint = base64::encode_config(digest, base64::URL_SAFE_NO_PAD);
+        if &thumbprint == expected {
             Ok(())
         } else {
-            Err(NotAuthorized(format!(
-                "Required scope '{}' not present.",
-                scope
-            )))
+            Err(NotAuthorized::new(
+                "Client certificate does not match the certificate the token is bound to.",
+            ))
         }
     }
 }
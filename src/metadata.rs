@@ -0,0 +1,472 @@
+//! Discovering the endpoints of an authorization server
+//!
+//! Authorization servers publish their endpoints as
+//! [OAuth 2.0 Authorization Server Metadata](https://tools.ietf.org/html/rfc8414)
+//! or as [OpenID Connect Discovery](https://openid.net/specs/openid-connect-discovery-1_0.html)
+//! document. `fetch_metadata` asks for the former and falls back to the
+//! latter.
+//!
+//! A `MetadataResolver` keeps the metadata of an issuer and refreshes it
+//! periodically. `Resolved` rebuilds a client or provider from the metadata
+//! whenever it changed so that moved endpoints are picked up without a
+//! restart:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use tokkit::client::TokenInfoServiceClientBuilder;
+//! use tokkit::metadata::{MetadataResolver, Resolved};
+//! use tokkit::InitializationError;
+//!
+//! let resolver = MetadataResolver::new("https://auth.example.com").unwrap();
+//! resolver.refresh_every(Duration::from_secs(3600));
+//!
+//! let service = Resolved::new(resolver, |metadata| {
+//!     let endpoint = metadata.introspection_endpoint.clone().ok_or_else(|| {
+//!         InitializationError::Other("No introspection endpoint published".to_string())
+//!     })?;
+//!     TokenInfoServiceClientBuilder::rfc7662(endpoint, "client", "secret").build()
+//! })
+//! .unwrap();
+//! ```
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::blocking::Client;
+use reqwest::header::ACCEPT;
+use reqwest::{StatusCode, Url};
+
+use crate::claims;
+use crate::client::{read_body_limited, DEFAULT_MAX_RESPONSE_SIZE};
+use crate::token_manager::token_provider::{
+    AccessTokenProvider, AccessTokenProviderResult, TokenRequestParams,
+};
+use crate::validation::ValidationReport;
+use crate::{
+    AccessToken, Credential, InitializationError, InitializationResult, IntrospectionContext,
+    RedirectPolicy, Scope, TokenInfo, TokenInfoResult, TokenInfoService,
+};
+
+/// The well-known path of the metadata as defined in RFC 8414.
+pub const OAUTH_METADATA_PATH: &str = "/.well-known/oauth-authorization-server";
+/// The well-known path of the OpenID Connect provider configuration.
+pub const OPENID_CONFIGURATION_PATH: &str = "/.well-known/openid-configuration";
+
+/// The metadata of an authorization server.
+///
+/// Only the members of interest for clients and resource servers are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationServerMetadata {
+    pub issuer: String,
+    pub token_endpoint: Option<String>,
+    pub introspection_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    pub jwks_uri: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub scopes_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+}
+
+impl AuthorizationServerMetadata {
+    /// Parses a metadata document.
+    pub fn parse(bytes: &[u8]) -> Result<AuthorizationServerMetadata, MetadataError> {
+        let data = claims::object(bytes).map_err(MetadataError::InvalidDocument)?;
+        let string = |field: &str| -> Result<Option<String>, MetadataError> {
+            claims::string(&data, field)
+                .map(|value| value.map(str::to_owned))
+                .map_err(MetadataError::InvalidDocument)
+        };
+        let strings = |field: &str| -> Result<Vec<String>, MetadataError> {
+            if data[field].is_null() {
+                return Ok(Vec::new());
+            }
+            data[field]
+                .members()
+                .map(|value| value.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .filter(|_| data[field].is_array())
+                .ok_or_else(|| {
+                    MetadataError::InvalidDocument(format!(
+                        "Expected an array of strings in field '{}'",
+                        field
+                    ))
+                })
+        };
+        Ok(AuthorizationServerMetadata {
+            issuer: claims::required_string(&data, "issuer")
+                .map_err(MetadataError::InvalidDocument)?
+                .to_string(),
+            token_endpoint: string("token_endpoint")?,
+            introspection_endpoint: string("introspection_endpoint")?,
+            revocation_endpoint: string("revocation_endpoint")?,
+            jwks_uri: string("jwks_uri")?,
+            userinfo_endpoint: string("userinfo_endpoint")?,
+            scopes_supported: strings("scopes_supported")?,
+            grant_types_supported: strings("grant_types_supported")?,
+        })
+    }
+}
+
+/// The metadata of an authorization server could not be fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+pub enum MetadataError {
+    /// The issuer is not a valid URL.
+    #[fail(display = "Invalid issuer '{}': {}", _0, _1)]
+    InvalidIssuer(String, String),
+    /// The authorization server could not be reached.
+    #[fail(display = "Could not fetch the metadata: {}", _0)]
+    Connection(String),
+    /// The authorization server answered with an unexpected status.
+    #[fail(display = "The metadata request failed with status {}", _0)]
+    Status(u16),
+    /// The document is not valid metadata.
+    #[fail(display = "Invalid metadata: {}", _0)]
+    InvalidDocument(String),
+    /// The document is the metadata of another issuer.
+    #[fail(
+        display = "The metadata of issuer '{}' was returned for issuer '{}'",
+        found, expected
+    )]
+    IssuerMismatch { expected: String, found: String },
+}
+
+impl From<MetadataError> for InitializationError {
+    fn from(err: MetadataError) -> Self {
+        match err {
+            MetadataError::InvalidIssuer(..) => InitializationError::InvalidUrl(err.to_string()),
+            _ => InitializationError::Other(err.to_string()),
+        }
+    }
+}
+
+/// The URLs of the metadata of `issuer` in the order they are tried.
+///
+/// The well-known path is inserted between the host and the path of the
+/// issuer for RFC 8414 and appended to the issuer for OpenID Connect.
+pub fn metadata_urls(issuer: &str) -> Result<Vec<Url>, MetadataError> {
+    let invalid = |message: String| MetadataError::InvalidIssuer(issuer.to_string(), message);
+    let url: Url = issuer
+        .parse()
+        .map_err(|err: url::ParseError| invalid(err.to_string()))?;
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid(
+            "It must neither have a query nor a fragment".to_string(),
+        ));
+    }
+    let path = url.path().trim_end_matches('/');
+
+    let mut oauth = url.clone();
+    oauth.set_path(&format!("{}{}", OAUTH_METADATA_PATH, path));
+    let mut openid = url.clone();
+    openid.set_path(&format!("{}{}", path, OPENID_CONFIGURATION_PATH));
+    Ok(vec![oauth, openid])
+}
+
+/// Fetches the metadata of `issuer` from the RFC 8414 location and from
+/// the OpenID Connect location if the former does not exist.
+///
+/// Fails if the document is about another issuer.
+pub fn fetch_metadata(
+    client: &Client,
+    issuer: &str,
+) -> Result<AuthorizationServerMetadata, MetadataError> {
+    let mut last_status = StatusCode::NOT_FOUND;
+    for url in metadata_urls(issuer)? {
+        let rsp = client
+            .get(url)
+            .header(ACCEPT, "application/json")
+            .send()
+            .map_err(|err| MetadataError::Connection(err.to_string()))?;
+        let status = rsp.status();
+        if status == StatusCode::NOT_FOUND {
+            continue;
+        }
+        if !status.is_success() {
+            last_status = status;
+            break;
+        }
+        let body = read_body_limited(rsp, DEFAULT_MAX_RESPONSE_SIZE)
+            .map_err(|err| MetadataError::Connection(err.to_string()))?;
+        let metadata = AuthorizationServerMetadata::parse(&body)?;
+        return check_issuer(issuer, metadata);
+    }
+    Err(MetadataError::Status(last_status.as_u16()))
+}
+
+fn check_issuer(
+    issuer: &str,
+    metadata: AuthorizationServerMetadata,
+) -> Result<AuthorizationServerMetadata, MetadataError> {
+    if metadata.issuer.trim_end_matches('/') == issuer.trim_end_matches('/') {
+        Ok(metadata)
+    } else {
+        Err(MetadataError::IssuerMismatch {
+            expected: issuer.to_string(),
+            found: metadata.issuer,
+        })
+    }
+}
+
+type FetchMetadata =
+    dyn Fn() -> Result<AuthorizationServerMetadata, MetadataError> + Send + Sync + 'static;
+
+struct ResolverState {
+    fetch: Box<FetchMetadata>,
+    /// The metadata and the number of times it changed.
+    current: Mutex<(u64, Arc<AuthorizationServerMetadata>)>,
+}
+
+/// Keeps the metadata of an authorization server.
+///
+/// Clones share the metadata. The metadata is only fetched again on
+/// `refresh` or periodically once `refresh_every` was called.
+#[derive(Clone)]
+pub struct MetadataResolver {
+    state: Arc<ResolverState>,
+}
+
+impl MetadataResolver {
+    /// Creates a resolver for `issuer` and fetches its metadata.
+    ///
+    /// Fails if the metadata can not be fetched.
+    pub fn new<T: Into<String>>(issuer: T) -> InitializationResult<MetadataResolver> {
+        MetadataResolver::with_client(issuer, RedirectPolicy::default().blocking_client()?)
+    }
+
+    /// Creates a resolver like `new` which sends its requests with `client`.
+    pub fn with_client<T: Into<String>>(
+        issuer: T,
+        client: Client,
+    ) -> InitializationResult<MetadataResolver> {
+        let issuer = issuer.into();
+        MetadataResolver::from_fn(move || fetch_metadata(&client, &issuer))
+    }
+
+    /// Creates a resolver which gets the metadata from `fetch`, e.g. from a
+    /// file or from a configuration service.
+    pub fn from_fn<F>(fetch: F) -> InitializationResult<MetadataResolver>
+    where
+        F: Fn() -> Result<AuthorizationServerMetadata, MetadataError> + Send + Sync + 'static,
+    {
+        let metadata = fetch()?;
+        Ok(MetadataResolver {
+            state: Arc::new(ResolverState {
+                fetch: Box::new(fetch),
+                current: Mutex::new((0, Arc::new(metadata))),
+            }),
+        })
+    }
+
+    /// The current metadata.
+    pub fn metadata(&self) -> Arc<AuthorizationServerMetadata> {
+        self.state.current.lock().unwrap().1.clone()
+    }
+
+    /// The number of times the metadata changed.
+    pub fn version(&self) -> u64 {
+        self.state.current.lock().unwrap().0
+    }
+
+    /// Fetches the metadata and returns `true` if it changed.
+    ///
+    /// The current metadata is kept if fetching it fails.
+    pub fn refresh(&self) -> Result<bool, MetadataError> {
+        let metadata = (self.state.fetch)()?;
+        let mut current = self.state.current.lock().unwrap();
+        if *current.1 == metadata {
+            return Ok(false);
+        }
+        info!("The metadata of issuer '{}' changed.", metadata.issuer);
+        *current = (current.0 + 1, Arc::new(metadata));
+        Ok(true)
+    }
+
+    /// Refreshes the metadata every `interval` on a background thread
+    /// until all clones of the resolver were dropped.
+    pub fn refresh_every(&self, interval: Duration) {
+        let state: Weak<ResolverState> = Arc::downgrade(&self.state);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let resolver = match state.upgrade() {
+                Some(state) => MetadataResolver { state },
+                None => break,
+            };
+            if let Err(err) = resolver.refresh() {
+                warn!("Could not refresh the metadata: {}", err);
+            }
+        });
+    }
+}
+
+type Build<S> = dyn Fn(&AuthorizationServerMetadata) -> InitializationResult<S> + Send + Sync;
+
+/// A client or provider built from the metadata of a `MetadataResolver`
+/// and rebuilt once the metadata changed.
+///
+/// `Resolved` is a `TokenInfoService` or an `AccessTokenProvider` if what
+/// it builds is one. If rebuilding fails the previous instance is kept.
+pub struct Resolved<S> {
+    resolver: MetadataResolver,
+    build: Box<Build<S>>,
+    current: Mutex<(u64, Arc<S>)>,
+}
+
+impl<S> Resolved<S> {
+    /// Builds the initial instance from the current metadata of `resolver`.
+    pub fn new<F>(resolver: MetadataResolver, build: F) -> InitializationResult<Resolved<S>>
+    where
+        F: Fn(&AuthorizationServerMetadata) -> InitializationResult<S> + Send + Sync + 'static,
+    {
+        let version = resolver.version();
+        let initial = build(&resolver.metadata())?;
+        Ok(Resolved {
+            resolver,
+            build: Box::new(build),
+            current: Mutex::new((version, Arc::new(initial))),
+        })
+    }
+
+    /// The instance built from the latest metadata.
+    pub fn current(&self) -> Arc<S> {
+        let version = self.resolver.version();
+        let mut current = self.current.lock().unwrap();
+        if current.0 != version {
+            match (self.build)(&self.resolver.metadata()) {
+                Ok(rebuilt) => *current = (version, Arc::new(rebuilt)),
+                Err(err) => {
+                    warn!(
+                        "Keeping the previous instance since rebuilding failed: {}",
+                        err
+                    );
+                    // Do not retry with every call but with the next change.
+                    current.0 = version;
+                }
+            }
+        }
+        current.1.clone()
+    }
+}
+
+impl<S: TokenInfoService> TokenInfoService for Resolved<S> {
+    fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+        self.current().introspect(token)
+    }
+
+    fn introspect_credential(&self, credential: &Credential) -> TokenInfoResult<TokenInfo> {
+        self.current().introspect_credential(credential)
+    }
+
+    fn introspect_with_context(
+        &self,
+        token: &AccessToken,
+        context: &IntrospectionContext,
+    ) -> TokenInfoResult<TokenInfo> {
+        self.current().introspect_with_context(token, context)
+    }
+
+    fn introspect_expecting(
+        &self,
+        token: &AccessToken,
+        required: &[Scope],
+    ) -> TokenInfoResult<TokenInfo> {
+        self.current().introspect_expecting(token, required)
+    }
+}
+
+impl<S: AccessTokenProvider> AccessTokenProvider for Resolved<S> {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.current().request_access_token(scopes)
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        self.current()
+            .request_access_token_with_params(scopes, params)
+    }
+
+    fn validate(&self, live_probe: bool) -> ValidationReport {
+        self.current().validate(live_probe)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata(token_endpoint: &str) -> AuthorizationServerMetadata {
+        let document = json::object! {
+            "issuer" => "https://auth.example.com",
+            "token_endpoint" => token_endpoint,
+            "scopes_supported" => vec!["read", "write"],
+        };
+        AuthorizationServerMetadata::parse(document.dump().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn well_known_paths_are_inserted_and_appended() {
+        let urls = metadata_urls("https://auth.example.com").unwrap();
+        assert_eq!(
+            urls[0].as_str(),
+            "https://auth.example.com/.well-known/oauth-authorization-server"
+        );
+        assert_eq!(
+            urls[1].as_str(),
+            "https://auth.example.com/.well-known/openid-configuration"
+        );
+
+        let urls = metadata_urls("https://auth.example.com/tenant/").unwrap();
+        assert_eq!(
+            urls[0].as_str(),
+            "https://auth.example.com/.well-known/oauth-authorization-server/tenant"
+        );
+        assert_eq!(
+            urls[1].as_str(),
+            "https://auth.example.com/tenant/.well-known/openid-configuration"
+        );
+
+        assert!(metadata_urls("https://auth.example.com?tenant=a").is_err());
+        assert!(metadata_urls("auth.example.com").is_err());
+    }
+
+    #[test]
+    fn documents_of_other_issuers_are_rejected() {
+        let parsed = metadata("https://auth.example.com/token");
+        assert_eq!(parsed.scopes_supported, vec!["read", "write"]);
+        assert!(parsed.introspection_endpoint.is_none());
+        assert!(check_issuer("https://auth.example.com/", parsed.clone()).is_ok());
+        match check_issuer("https://evil.example.com", parsed) {
+            Err(MetadataError::IssuerMismatch { found, .. }) => {
+                assert_eq!(found, "https://auth.example.com")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(AuthorizationServerMetadata::parse(b"{}").is_err());
+        let invalid = br#"{"issuer":"https://a","scopes_supported":"read"}"#;
+        assert!(AuthorizationServerMetadata::parse(invalid).is_err());
+    }
+
+    #[test]
+    fn instances_are_rebuilt_once_the_metadata_changed() {
+        let endpoint = Arc::new(Mutex::new("https://auth.example.com/token".to_string()));
+        let published = endpoint.clone();
+        let resolver =
+            MetadataResolver::from_fn(move || Ok(metadata(&published.lock().unwrap()))).unwrap();
+        let resolved = Resolved::new(resolver.clone(), |metadata| {
+            Ok(metadata.token_endpoint.clone().unwrap())
+        })
+        .unwrap();
+
+        assert_eq!(resolver.refresh(), Ok(false));
+        assert_eq!(*resolved.current(), "https://auth.example.com/token");
+
+        *endpoint.lock().unwrap() = "https://auth.example.com/v2/token".to_string();
+        assert_eq!(resolver.refresh(), Ok(true));
+        assert_eq!(resolver.version(), 1);
+        assert_eq!(*resolved.current(), "https://auth.example.com/v2/token");
+    }
+}
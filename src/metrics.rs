@@ -1,5 +1,7 @@
 use std::time::Instant;
 
+use crate::IntrospectionContext;
+
 /// Collects metrics for token introspection
 pub trait MetricsCollector {
     /// An incoming request for token introspection
@@ -11,6 +13,15 @@ pub trait MetricsCollector {
     fn introspection_request_success(&self, request_started: Instant);
     /// The complete introspection workflow was finished and failed
     fn introspection_request_failure(&self, request_started: Instant);
+    /// The complete introspection workflow on behalf of the request
+    /// described by `context` was finished and failed. Called in addition
+    /// to `introspection_request_failure`.
+    fn introspection_request_failure_with_context(
+        &self,
+        _request_started: Instant,
+        _context: &IntrospectionContext,
+    ) {
+    }
 
     /// The token introspections was called regardless of the result.
     fn introspection_service_call(&self, request_started: Instant);
@@ -18,6 +29,13 @@ pub trait MetricsCollector {
     fn introspection_service_call_failure(&self, request_started: Instant);
     /// The token introspections was called and the call was a success.
     fn introspection_service_call_success(&self, request_started: Instant);
+
+    /// A `TokenInfo` was found in a cache.
+    fn cache_hit(&self) {}
+    /// A `TokenInfo` was not found in a cache.
+    fn cache_miss(&self) {}
+    /// An entry was evicted from a cache to stay within its bounds.
+    fn cache_eviction(&self) {}
 }
 
 #[derive(Clone)]
@@ -28,10 +46,20 @@ impl MetricsCollector for DevNullMetricsCollector {
     fn introspection_request(&self, _request_started: Instant) {}
     fn introspection_request_success(&self, _request_started: Instant) {}
     fn introspection_request_failure(&self, _request_started: Instant) {}
+    fn introspection_request_failure_with_context(
+        &self,
+        _request_started: Instant,
+        _context: &IntrospectionContext,
+    ) {
+    }
 
     fn introspection_service_call(&self, _request_started: Instant) {}
     fn introspection_service_call_failure(&self, _request_started: Instant) {}
     fn introspection_service_call_success(&self, _request_started: Instant) {}
+
+    fn cache_hit(&self) {}
+    fn cache_miss(&self) {}
+    fn cache_eviction(&self) {}
 }
 
 #[cfg(feature = "metrix")]
@@ -59,12 +87,20 @@ pub mod metrix {
         IntrospectionServiceCallFailure,
     }
 
+    #[derive(Clone, PartialEq, Eq)]
+    enum MetricsCache {
+        Hit,
+        Miss,
+        Eviction,
+    }
+
     /// A `MetricsCollector` that works with the [`metrix`](https://crates.io/crates/metrix)
     ///  library
     #[derive(Clone)]
     pub struct MetrixCollector {
         introspection_transmitter: TelemetryTransmitter<MetricsIntrospectionRequest>,
         service_transmitter: TelemetryTransmitter<MetricsIntrospectionService>,
+        cache_transmitter: TelemetryTransmitter<MetricsCache>,
     }
 
     impl MetrixCollector {
@@ -76,13 +112,16 @@ pub mod metrix {
         {
             let (introspection_tx, introspection_rx) = create_introspection_metrics();
             let (service_tx, service_rx) = create_introspection_service_metrics();
+            let (cache_tx, cache_rx) = create_cache_metrics();
 
             add_metrics_to.add_processor(introspection_rx);
             add_metrics_to.add_processor(service_rx);
+            add_metrics_to.add_processor(cache_rx);
 
             MetrixCollector {
                 introspection_transmitter: introspection_tx,
                 service_transmitter: service_tx,
+                cache_transmitter: cache_tx,
             }
         }
     }
@@ -129,6 +168,16 @@ pub mod metrix {
                 request_started,
             );
         }
+
+        fn cache_hit(&self) {
+            self.cache_transmitter.observed_one_now(MetricsCache::Hit);
+        }
+        fn cache_miss(&self) {
+            self.cache_transmitter.observed_one_now(MetricsCache::Miss);
+        }
+        fn cache_eviction(&self) {
+            self.cache_transmitter.observed_one_now(MetricsCache::Eviction);
+        }
     }
 
     fn create_introspection_metrics() -> (
@@ -193,6 +242,28 @@ pub mod metrix {
         (tx, rx)
     }
 
+    fn create_cache_metrics() -> (
+        TelemetryTransmitter<MetricsCache>,
+        TelemetryProcessor<MetricsCache>,
+    ) {
+        let mut cockpit: Cockpit<MetricsCache> = Cockpit::without_name();
+
+        let panel = Panel::named(MetricsCache::Hit, "hits");
+        add_counting_instruments_to_cockpit(&mut cockpit, panel);
+
+        let panel = Panel::named(MetricsCache::Miss, "misses");
+        add_counting_instruments_to_cockpit(&mut cockpit, panel);
+
+        let panel = Panel::named(MetricsCache::Eviction, "evictions");
+        add_counting_instruments_to_cockpit(&mut cockpit, panel);
+
+        let (tx, rx) = TelemetryProcessor::new_pair("cache");
+
+        tx.add_cockpit(cockpit);
+
+        (tx, rx)
+    }
+
     fn add_counting_instruments_to_cockpit<L>(cockpit: &mut Cockpit<L>, mut panel: Panel<L>)
     where
         L: Clone + Eq + Send + 'static,
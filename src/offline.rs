@@ -0,0 +1,271 @@
+//! Introspection from a signed snapshot for environments without an
+//! introspection service.
+//!
+//! A snapshot maps access tokens to the responses an introspection service
+//! would give for them. It is a JSON object
+//!
+//! ```json
+//! {
+//!   "payload": "<base64url encoded JSON object of tokens to responses>",
+//!   "signature": "<hex encoded HMAC-SHA256 of the payload string>"
+//! }
+//! ```
+//!
+//! and can be created with `sign_snapshot`:
+//!
+//! ```rust
+//! use tokkit::offline::{sign_snapshot, OfflineTokenInfoService};
+//! use tokkit::parsers::PlanBTokenInfoParser;
+//! use tokkit::*;
+//!
+//! let responses = json::parse(
+//!     r#"{"secret": {"uid": "alice", "scope": ["read"], "expires_in": 3600}}"#,
+//! )
+//! .unwrap();
+//! let snapshot = sign_snapshot(&responses, b"key");
+//!
+//! let service =
+//!     OfflineTokenInfoService::from_bytes(snapshot.as_bytes(), b"key", PlanBTokenInfoParser)
+//!         .unwrap();
+//!
+//! let token_info = service.introspect(&AccessToken::new("secret")).unwrap();
+//! assert_eq!(token_info.user_id, Some(UserId::new("alice")));
+//! ```
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str;
+
+use json::{self, JsonValue};
+
+use crate::parsers::TokenInfoParser;
+use crate::sha256::{hex, hmac_sha256, unhex};
+use crate::{
+    AccessToken, TokenInfo, TokenInfoError, TokenInfoErrorKind, TokenInfoResult, TokenInfoService,
+};
+
+/// A `TokenInfoService` which serves introspections from a signed
+/// snapshot loaded once. It never connects to anything.
+///
+/// Tokens which are not in the snapshot are not authenticated.
+#[derive(Clone)]
+pub struct OfflineTokenInfoService {
+    token_infos: HashMap<String, TokenInfo>,
+}
+
+impl OfflineTokenInfoService {
+    /// Loads the snapshot from the file at `path`.
+    ///
+    /// Fails if the signature does not match the `key` or if any of the
+    /// responses can not be parsed by `parser`.
+    pub fn from_file<P, K, T>(path: P, key: K, parser: T) -> TokenInfoResult<Self>
+    where
+        P: AsRef<Path>,
+        K: AsRef<[u8]>,
+        T: TokenInfoParser,
+    {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| {
+            TokenInfoErrorKind::Io(format!(
+                "Could not read snapshot '{}': {}",
+                path.display(),
+                err
+            ))
+        })?;
+        OfflineTokenInfoService::from_bytes(&bytes, key, parser)
+    }
+
+    /// Loads the snapshot from `bytes`.
+    ///
+    /// Fails if the signature does not match the `key` or if any of the
+    /// responses can not be parsed by `parser`.
+    pub fn from_bytes<K, T>(bytes: &[u8], key: K, parser: T) -> TokenInfoResult<Self>
+    where
+        K: AsRef<[u8]>,
+        T: TokenInfoParser,
+    {
+        let payload = verified_payload(bytes, key.as_ref())?;
+        let responses = match payload {
+            JsonValue::Object(responses) => responses,
+            _ => return Err(invalid_snapshot("The payload is not a JSON object")),
+        };
+
+        let mut token_infos = HashMap::with_capacity(responses.len());
+        for (token, response) in responses.iter() {
+            let token_info = parser.parse(response.dump().as_bytes()).map_err(|err| {
+                invalid_snapshot(format!("Invalid response for a token: {}", err))
+            })?;
+            token_infos.insert(token.to_string(), token_info);
+        }
+
+        Ok(OfflineTokenInfoService { token_infos })
+    }
+
+    /// The number of tokens in the snapshot.
+    pub fn len(&self) -> usize {
+        self.token_infos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.token_infos.is_empty()
+    }
+}
+
+impl TokenInfoService for OfflineTokenInfoService {
+    fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+        match self.token_infos.get(token.reveal()) {
+            Some(token_info) => Ok(token_info.clone()),
+            None => Err(TokenInfoErrorKind::not_authenticated(
+                "The token is not contained in the offline snapshot",
+            )
+            .into()),
+        }
+    }
+}
+
+/// Creates a snapshot of `responses` which maps tokens to the responses of
+/// an introspection service signed with `key`.
+pub fn sign_snapshot(responses: &JsonValue, key: &[u8]) -> String {
+    let payload = base64::encode_config(responses.dump(), base64::URL_SAFE_NO_PAD);
+    let signature = hex(&hmac_sha256(key, payload.as_bytes()));
+    json::stringify(json::object! {
+        "payload" => payload,
+        "signature" => signature,
+    })
+}
+
+fn verified_payload(bytes: &[u8], key: &[u8]) -> TokenInfoResult<JsonValue> {
+    let snapshot = str::from_utf8(bytes)
+        .map_err(|err| invalid_snapshot(err.to_string()))
+        .and_then(|s| json::parse(s).map_err(|err| invalid_snapshot(err.to_string())))?;
+
+    let (payload, signature) = match (snapshot["payload"].as_str(), snapshot["signature"].as_str())
+    {
+        (Some(payload), Some(signature)) => (payload, signature),
+        _ => {
+            return Err(invalid_snapshot(
+                "The snapshot must contain the strings 'payload' and 'signature'",
+            ))
+        }
+    };
+
+    let expected = hmac_sha256(key, payload.as_bytes());
+    let signature = unhex(signature).unwrap_or_default();
+    if !constant_time_eq(&expected, &signature) {
+        return Err(invalid_snapshot("The signature of the snapshot is invalid"));
+    }
+
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| invalid_snapshot(format!("Invalid payload: {}", err)))?;
+    str::from_utf8(&payload)
+        .map_err(|err| invalid_snapshot(err.to_string()))
+        .and_then(|s| json::parse(s).map_err(|err| invalid_snapshot(err.to_string())))
+}
+
+fn invalid_snapshot<T: Into<String>>(msg: T) -> TokenInfoError {
+    TokenInfoErrorKind::InvalidResponseContent(format!("Invalid offline snapshot: {}", msg.into()))
+        .into()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsers::PlanBTokenInfoParser;
+    use crate::Scope;
+
+    fn responses() -> JsonValue {
+        json::object! {
+            "secret" => json::object! {
+                "uid" => "alice",
+                "scope" => json::array!["read"],
+                "expires_in" => 3600
+            },
+        }
+    }
+
+    #[test]
+    fn introspects_tokens_of_the_snapshot() {
+        let snapshot = sign_snapshot(&responses(), b"key");
+        let service =
+            OfflineTokenInfoService::from_bytes(snapshot.as_bytes(), b"key", PlanBTokenInfoParser)
+                .unwrap();
+
+        assert_eq!(service.len(), 1);
+        let token_info = service.introspect(&AccessToken::new("secret")).unwrap();
+        assert!(token_info.has_scope(&crate::Scope::new("read")));
+        match *service
+            .introspect(&AccessToken::new("unknown"))
+            .unwrap_err()
+            .kind()
+        {
+            TokenInfoErrorKind::NotAuthenticated { .. } => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn introspection_can_expect_scopes() {
+        let snapshot = sign_snapshot(&responses(), b"key");
+        let service =
+            OfflineTokenInfoService::from_bytes(snapshot.as_bytes(), b"key", PlanBTokenInfoParser)
+                .unwrap();
+        let token = AccessToken::new("secret");
+
+        assert!(service
+            .introspect_expecting(&token, &[Scope::new("read")])
+            .is_ok());
+        let required = [Scope::new("write"), Scope::new("read"), Scope::new("admin")];
+        let err = service.introspect_expecting(&token, &required).unwrap_err();
+        match *err.kind() {
+            TokenInfoErrorKind::NotAuthorized {
+                ref missing_scopes, ..
+            } => assert_eq!(
+                missing_scopes.as_slice(),
+                &[Scope::new("admin"), Scope::new("write")]
+            ),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(err.to_string(), "Required scopes not present: admin write");
+    }
+
+    #[test]
+    fn snapshots_with_another_key_are_rejected() {
+        let snapshot = sign_snapshot(&responses(), b"other key");
+        let err =
+            OfflineTokenInfoService::from_bytes(snapshot.as_bytes(), b"key", PlanBTokenInfoParser)
+                .err()
+                .unwrap();
+        assert!(err.to_string().contains("signature"), "{}", err);
+    }
+
+    #[test]
+    fn upper_case_signatures_are_accepted() {
+        let mut snapshot = json::parse(&sign_snapshot(&responses(), b"key")).unwrap();
+        let signature = snapshot["signature"].as_str().unwrap().to_uppercase();
+        snapshot["signature"] = signature.into();
+        assert!(OfflineTokenInfoService::from_bytes(
+            snapshot.dump().as_bytes(),
+            b"key",
+            PlanBTokenInfoParser
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn tampered_payloads_are_rejected() {
+        let mut snapshot = json::parse(&sign_snapshot(&responses(), b"key")).unwrap();
+        let tampered = json::object! { "forged" => json::object! { "uid" => "mallory" } };
+        snapshot["payload"] =
+            base64::encode_config(tampered.dump(), base64::URL_SAFE_NO_PAD).into();
+        assert!(OfflineTokenInfoService::from_bytes(
+            snapshot.dump().as_bytes(),
+            b"key",
+            PlanBTokenInfoParser
+        )
+        .is_err());
+    }
+}
@@ -1,14 +1,90 @@
 //! Various parsers for the responses of a token info service.
 use std::env;
 use std::str;
+use std::sync::Arc;
 
 use failure::*;
 
-use crate::{Scope, TokenInfo, UserId};
+use crate::claims;
+use crate::client::DEFAULT_MAX_RESPONSE_SIZE;
+#[cfg(test)]
+use crate::Scope;
+use crate::{Claims, ScopeList, TokenInfo, UserId};
+
+mod scanner;
+
+/// The largest `expires_in_seconds` accepted from a response. Larger values
+/// are rejected so that computing an expiry can not overflow.
+pub const MAX_EXPIRES_IN_SECONDS: u64 = u32::MAX as u64;
 
 /// A parser that can parse a slice of bytes to a `TokenInfo`
 pub trait TokenInfoParser: Send + 'static {
     fn parse(&self, bytes: &[u8]) -> Result<TokenInfo, Error>;
+
+    /// Parses a response which was sent with the given `Content-Type`.
+    ///
+    /// The clients call this instead of `parse`. The default ignores the
+    /// `content_type`.
+    fn parse_with_content_type(
+        &self,
+        bytes: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<TokenInfo, Error> {
+        let _ = content_type;
+        self.parse(bytes)
+    }
+}
+
+/// A `TokenInfoParser` chosen at runtime, e.g. from a configuration.
+///
+/// It can be given to a `TokenInfoServiceClientBuilder` like any concrete
+/// parser so that the type of the parser does not show up in the type of
+/// the client. A `Box<dyn TokenInfoParser + Send + Sync>` can be given to
+/// `TokenInfoServiceClientBuilder::boxed`.
+///
+/// ```rust
+/// use std::sync::Arc;
+///
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+/// use tokkit::parsers::*;
+///
+/// fn parser(name: &str) -> SharedTokenInfoParser {
+///     match name {
+///         "plan_b" => Arc::new(PlanBTokenInfoParser),
+///         _ => Arc::new(Rfc7662TokenInfoParser),
+///     }
+/// }
+///
+/// let builder = TokenInfoServiceClientBuilder::new(parser("plan_b"), "https://example.com");
+/// ```
+pub type SharedTokenInfoParser = Arc<dyn TokenInfoParser + Send + Sync>;
+
+impl TokenInfoParser for Arc<dyn TokenInfoParser + Send + Sync> {
+    fn parse(&self, bytes: &[u8]) -> Result<TokenInfo, Error> {
+        (**self).parse(bytes)
+    }
+
+    fn parse_with_content_type(
+        &self,
+        bytes: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<TokenInfo, Error> {
+        (**self).parse_with_content_type(bytes, content_type)
+    }
+}
+
+impl TokenInfoParser for Box<dyn TokenInfoParser + Send + Sync> {
+    fn parse(&self, bytes: &[u8]) -> Result<TokenInfo, Error> {
+        (**self).parse(bytes)
+    }
+
+    fn parse_with_content_type(
+        &self,
+        bytes: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<TokenInfo, Error> {
+        (**self).parse_with_content_type(bytes, content_type)
+    }
 }
 
 /// A configurable `TokenInfoParser` that parses a `TokenInfo` from JSON
@@ -36,6 +112,13 @@ pub struct CustomTokenInfoParser {
     /// for the `TokenInfo`. If None the field will not be looked up
     /// and set to `None` in the `TokenInfo` right away.
     pub expires_in_field: Option<String>,
+    /// If set only a boolean is accepted as the `active` field. Otherwise
+    /// the strings `"true"` and `"false"` and the numbers `1` and `0` are
+    /// accepted as well since some introspection services send them.
+    pub strict_active: bool,
+    /// If set all other members of the response are captured as the
+    /// `extra` claims of the `TokenInfo`.
+    pub extra_claims: bool,
 }
 
 impl CustomTokenInfoParser {
@@ -56,9 +139,27 @@ impl CustomTokenInfoParser {
             user_id_field: user_id_field.map(Into::into),
             scope_field: scope_field.map(Into::into),
             expires_in_field: expires_in_field.map(Into::into),
+            strict_active: false,
+            extra_claims: false,
         }
     }
 
+    /// Rejects responses whose `active` field is not a boolean, e.g. to
+    /// detect sloppy introspection services. By default strings and
+    /// numbers are accepted as well.
+    pub fn with_strict_active(mut self, strict_active: bool) -> Self {
+        self.strict_active = strict_active;
+        self
+    }
+
+    /// Captures the members of the response which are not mapped to a
+    /// field of the `TokenInfo` as its `extra` claims. They can be read
+    /// with `TokenInfo::claim`.
+    pub fn with_extra_claims(mut self, extra_claims: bool) -> Self {
+        self.extra_claims = extra_claims;
+        self
+    }
+
     /// Create a new parser from environment variables.
     ///
     /// The following variables used to identify the field in a token info
@@ -104,12 +205,14 @@ impl CustomTokenInfoParser {
 
 impl TokenInfoParser for CustomTokenInfoParser {
     fn parse(&self, json: &[u8]) -> Result<TokenInfo, Error> {
-        parse(
+        parse_fields(
             json,
             self.active_field.as_ref().map(|s| &**s),
             self.user_id_field.as_ref().map(|s| &**s),
             self.scope_field.as_ref().map(|s| &**s),
             self.expires_in_field.as_ref().map(|s| &**s),
+            self.strict_active,
+            self.extra_claims,
         )
     }
 }
@@ -143,6 +246,9 @@ impl TokenInfoParser for CustomTokenInfoParser {
 ///     user_id: Some(UserId::new("test2")),
 ///     scope: vec![Scope::new("cn")],
 ///     expires_in_seconds: Some(28292),
+///     certificate_thumbprint: None,
+///     from_stale_cache: false,
+///     extra: Default::default(),
 /// };
 ///
 /// let token_info = PlanBTokenInfoParser.parse(sample).unwrap();
@@ -184,6 +290,9 @@ impl TokenInfoParser for PlanBTokenInfoParser {
 ///             "https://www.googleapis.com/auth/drive.metadata.readonly",
 ///     )],
 ///     expires_in_seconds: Some(436),
+///     certificate_thumbprint: None,
+///     from_stale_cache: false,
+///     extra: Default::default(),
 /// };
 ///
 /// let token_info = GoogleV3TokenInfoParser.parse(sample).unwrap();
@@ -234,6 +343,9 @@ impl TokenInfoParser for GoogleV3TokenInfoParser {
 ///         user_id: Some(UserId::new("amznl.account.K2LI23KL2LK2")),
 ///         scope: Vec::new(),
 ///         expires_in_seconds: Some(3597),
+///         certificate_thumbprint: None,
+///         from_stale_cache: false,
+///         extra: Default::default(),
 ///     };
 ///
 ///     let token_info = AmazonTokenInfoParser.parse(sample).unwrap();
@@ -249,6 +361,302 @@ impl TokenInfoParser for AmazonTokenInfoParser {
     }
 }
 
+/// Parses a `TokenInfo` from the JSON returned by AWS Cognito user pools
+///
+/// Both the response of the `/oauth2/userInfo` endpoint and the claims of a
+/// Cognito access token (e.g. as returned by an introspection proxy) are
+/// understood:
+///
+/// * The user id is taken from `username` and falls back to `sub`
+/// * `active` is optional and defaults to `true`
+/// * `scope` is optional and space separated
+/// * `exp` is an absolute timestamp which is converted to the seconds left
+///
+/// [Description](https://docs.aws.amazon.com/cognito/latest/developerguide/userinfo-endpoint.html)
+///
+/// ##Example
+///
+/// ```rust
+/// use tokkit::parsers::{CognitoTokenInfoParser, TokenInfoParser};
+/// use tokkit::*;
+///
+/// let sample = br#"
+/// {
+/// "sub":"248289761001",
+/// "email_verified":"true",
+/// "email":"janedoe@example.com",
+/// "username":"janedoe"
+/// }
+/// "#;
+///
+///     let expected = TokenInfo {
+///         active: true,
+///         user_id: Some(UserId::new("janedoe")),
+///         scope: Vec::new(),
+///         expires_in_seconds: None,
+///         certificate_thumbprint: None,
+///         from_stale_cache: false,
+///         extra: Default::default(),
+///     };
+///
+///     let token_info = CognitoTokenInfoParser.parse(sample).unwrap();
+///
+///     assert_eq!(expected, token_info);
+/// ```
+#[derive(Clone)]
+pub struct CognitoTokenInfoParser;
+
+impl TokenInfoParser for CognitoTokenInfoParser {
+    fn parse(&self, json: &[u8]) -> Result<TokenInfo, Error> {
+        use json::JsonValue;
+
+        let mut data = claims::object(json).map_err(err_msg)?;
+
+        let active = match data["active"] {
+            JsonValue::Null => true,
+            JsonValue::Boolean(active) => active,
+            ref invalid => bail!(
+                "Expected a boolean as the 'active' field but found a {:?}",
+                invalid
+            ),
+        };
+
+        let user_id = match data["username"].take() {
+            JsonValue::String(username) => Some(username),
+            username => username
+                .as_str()
+                .map(str::to_owned)
+                .or_else(|| data["sub"].take_string()),
+        }
+        .map(UserId::new);
+
+        let scope = match data["scope"].as_str() {
+            Some(scope) => claims::split_scopes(scope),
+            None => Vec::new(),
+        };
+
+        Ok(TokenInfo {
+            active,
+            user_id,
+            scope,
+            expires_in_seconds: claims::seconds_until(&data, "exp").map_err(err_msg)?,
+            certificate_thumbprint: claims::certificate_thumbprint(&data["cnf"]),
+            from_stale_cache: false,
+            extra: Default::default(),
+        })
+    }
+}
+
+/// Parses a `TokenInfo` from a response as defined in
+/// [RFC 7662 Sec. 2.2](https://tools.ietf.org/html/rfc7662#section-2.2)
+///
+/// * `active` is required and must be a boolean
+/// * The user id is taken from `sub`
+/// * `scope` is optional and space separated
+/// * `exp` is an absolute timestamp which is converted to the seconds left
+///
+/// ##Example
+///
+/// ```rust
+/// use tokkit::parsers::{Rfc7662TokenInfoParser, TokenInfoParser};
+/// use tokkit::*;
+///
+/// let sample = br#"
+/// {
+/// "active": true,
+/// "client_id": "l238j323ds-23ij4",
+/// "username": "jdoe",
+/// "scope": "read write dolphin",
+/// "sub": "Z5O3upPC88QrAjx00dis",
+/// "aud": "https://protected.example.net/resource",
+/// "iss": "https://server.example.com/"
+/// }
+/// "#;
+///
+/// let expected = TokenInfo {
+///     active: true,
+///     user_id: Some(UserId::new("Z5O3upPC88QrAjx00dis")),
+///     scope: vec![Scope::new("dolphin"), Scope::new("read"), Scope::new("write")],
+///     expires_in_seconds: None,
+///     certificate_thumbprint: None,
+///     from_stale_cache: false,
+///     extra: Default::default(),
+/// };
+///
+/// let token_info = Rfc7662TokenInfoParser.parse(sample).unwrap();
+///
+/// assert_eq!(expected, token_info);
+/// ```
+#[derive(Clone)]
+pub struct Rfc7662TokenInfoParser;
+
+impl TokenInfoParser for Rfc7662TokenInfoParser {
+    fn parse(&self, json: &[u8]) -> Result<TokenInfo, Error> {
+        use json::JsonValue;
+
+        let mut data = claims::object(json).map_err(err_msg)?;
+
+        let active = match data["active"] {
+            JsonValue::Boolean(active) => active,
+            ref invalid => bail!(
+                "Expected a boolean as the 'active' field but found a {:?}",
+                invalid
+            ),
+        };
+
+        let scope = match data["scope"].as_str() {
+            Some(scope) => claims::split_scopes(scope),
+            None => Vec::new(),
+        };
+
+        Ok(TokenInfo {
+            active,
+            user_id: data["sub"].take_string().map(UserId::new),
+            scope,
+            expires_in_seconds: claims::seconds_until(&data, "exp").map_err(err_msg)?,
+            certificate_thumbprint: claims::certificate_thumbprint(&data["cnf"]),
+            from_stale_cache: false,
+            extra: Default::default(),
+        })
+    }
+}
+
+/// A `TokenInfoParser` which selects one of several parsers for each
+/// response.
+///
+/// This is useful if the endpoints of a client answer in different formats,
+/// e.g. if the fallback endpoint runs another authorization server than the
+/// primary one. A parser is selected by
+///
+/// 1. the `Content-Type` of the response,
+/// 2. the first configured member found in the JSON object of the response
+///    and
+/// 3. the default parser
+///
+/// in this order. The response is rejected if no parser can be selected.
+///
+/// ```rust
+/// use tokkit::parsers::*;
+/// use tokkit::UserId;
+///
+/// let parser = MultiFormatTokenInfoParser::new()
+///     .with_field("uid", PlanBTokenInfoParser)
+///     .with_field("user_id", AmazonTokenInfoParser)
+///     .with_default(Rfc7662TokenInfoParser);
+///
+/// let token_info = parser.parse(br#"{"user_id": "alice", "exp": 60}"#).unwrap();
+/// assert_eq!(token_info.user_id, Some(UserId::new("alice")));
+/// let token_info = parser.parse(br#"{"active": true, "sub": "bob"}"#).unwrap();
+/// assert_eq!(token_info.user_id, Some(UserId::new("bob")));
+/// ```
+#[derive(Clone, Default)]
+pub struct MultiFormatTokenInfoParser {
+    by_content_type: Vec<(String, SharedTokenInfoParser)>,
+    by_field: Vec<(String, SharedTokenInfoParser)>,
+    default: Option<SharedTokenInfoParser>,
+}
+
+impl MultiFormatTokenInfoParser {
+    /// Creates a `MultiFormatTokenInfoParser` without any parsers.
+    pub fn new() -> MultiFormatTokenInfoParser {
+        MultiFormatTokenInfoParser::default()
+    }
+
+    /// Uses `parser` for responses with the media type `content_type`, e.g.
+    /// `application/json`. Parameters like a `charset` and the case are
+    /// ignored.
+    pub fn with_content_type<T, P>(mut self, content_type: T, parser: P) -> Self
+    where
+        T: Into<String>,
+        P: TokenInfoParser + Send + Sync,
+    {
+        self.by_content_type
+            .push((content_type.into(), Arc::new(parser)));
+        self
+    }
+
+    /// Uses `parser` for responses whose JSON object has the member
+    /// `field`, e.g. `uid`. Fields are probed in the order they were added.
+    pub fn with_field<T, P>(mut self, field: T, parser: P) -> Self
+    where
+        T: Into<String>,
+        P: TokenInfoParser + Send + Sync,
+    {
+        self.by_field.push((field.into(), Arc::new(parser)));
+        self
+    }
+
+    /// Uses `parser` for responses no other parser was selected for.
+    pub fn with_default<P>(mut self, parser: P) -> Self
+    where
+        P: TokenInfoParser + Send + Sync,
+    {
+        self.default = Some(Arc::new(parser));
+        self
+    }
+
+    fn select(&self, json: &[u8], content_type: Option<&str>) -> Option<&SharedTokenInfoParser> {
+        if let Some(content_type) = content_type {
+            let media_type = content_type.split(';').next().unwrap_or_default().trim();
+            let selected = self
+                .by_content_type
+                .iter()
+                .find(|(configured, _)| configured.eq_ignore_ascii_case(media_type));
+            if let Some((_, parser)) = selected {
+                return Some(parser);
+            }
+        }
+        if !self.by_field.is_empty() {
+            let fields = top_level_fields(json);
+            let selected = self
+                .by_field
+                .iter()
+                .find(|(field, _)| fields.iter().any(|present| present == field));
+            if let Some((_, parser)) = selected {
+                return Some(parser);
+            }
+        }
+        self.default.as_ref()
+    }
+}
+
+impl TokenInfoParser for MultiFormatTokenInfoParser {
+    fn parse(&self, json: &[u8]) -> Result<TokenInfo, Error> {
+        self.parse_with_content_type(json, None)
+    }
+
+    fn parse_with_content_type(
+        &self,
+        json: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<TokenInfo, Error> {
+        match self.select(json, content_type) {
+            Some(parser) => parser.parse_with_content_type(json, content_type),
+            None => bail!("No parser is configured for the format of the response"),
+        }
+    }
+}
+
+/// The names of the members of the JSON object in `json`. Nothing is
+/// returned for anything else.
+fn top_level_fields(json: &[u8]) -> Vec<String> {
+    let mut fields = Vec::new();
+    if let Ok(scanner::Value::Object(object)) = str::from_utf8(json)
+        .map_err(Error::from)
+        .and_then(scanner::parse)
+    {
+        let _ = scanner::for_each_member(object, |key, _| {
+            fields.push(key.into_owned());
+            Ok(())
+        });
+    }
+    fields
+}
+
+/// Parses a `TokenInfo` from the given fields of a JSON object.
+///
+/// The `active` field may also be a string or a number as described for
+/// `CustomTokenInfoParser::strict_active`.
 pub fn parse(
     json: &[u8],
     active_field: Option<&str>,
@@ -256,116 +664,221 @@ pub fn parse(
     scope_field: Option<&str>,
     expires_field: Option<&str>,
 ) -> ::std::result::Result<TokenInfo, Error> {
-    use json::*;
+    parse_fields(
+        json,
+        active_field,
+        user_id_field,
+        scope_field,
+        expires_field,
+        false,
+        false,
+    )
+}
+
+fn parse_fields(
+    json: &[u8],
+    active_field: Option<&str>,
+    user_id_field: Option<&str>,
+    scope_field: Option<&str>,
+    expires_field: Option<&str>,
+    strict_active: bool,
+    extra_claims: bool,
+) -> ::std::result::Result<TokenInfo, Error> {
+    use self::scanner::Value;
     let json = str::from_utf8(json).context("String was not UTF-8")?;
-    let json = ::json::parse(json)?;
-    match json {
-        JsonValue::Object(data) => {
-            let active = if let Some(active_field) = active_field {
-                match data.get(active_field) {
-                    Some(&JsonValue::Boolean(active)) => active,
-                    Some(&JsonValue::Short(s)) => s.parse()?,
-                    invalid => bail!(
-                        "Expected a boolean as the 'active' field in '{}' but found a {:?}",
-                        active_field,
-                        invalid
-                    ),
-                }
-            } else {
-                true
-            };
-            let user_id = if let Some(user_id_field) = user_id_field {
-                match data.get(user_id_field) {
-                    Some(&JsonValue::Short(ref user_id)) => Some(UserId::new(user_id.as_str())),
-                    Some(&JsonValue::String(ref user_id)) => Some(UserId::new(user_id.as_str())),
-                    invalid => bail!(
-                        "Expected a string as the user id in field '{}' but found a {:?}",
-                        user_id_field,
-                        invalid
-                    ),
-                }
-            } else {
-                None
-            };
-            let scope = if let Some(scope_field) = scope_field {
-                match data.get(scope_field) {
-                    Some(&JsonValue::Array(ref values)) => {
-                        let mut scopes = Vec::with_capacity(values.len());
-                        for elem in values {
-                            match elem {
-                                &JsonValue::String(ref v) => scopes.push(Scope(v.clone())),
-                                &JsonValue::Short(ref v) => scopes.push(Scope::new(v.as_str())),
-                                invalid => bail!(
-                                    "Expected a string as a scope in ['{}'] but found '{}'",
-                                    scope_field,
-                                    invalid
-                                ),
-                            }
-                        }
-                        scopes
-                    }
-                    Some(&JsonValue::String(ref scope)) => split_scopes(scope.as_ref()),
-                    Some(&JsonValue::Short(ref scope)) => split_scopes(scope.as_ref()),
-                    None => Vec::new(),
-                    invalid => bail!(
-                        "Expected an array or string for the \
-                         scope(s) in field '{}' but found a {:?}",
-                        scope_field,
-                        invalid
-                    ),
-                }
-            } else {
-                Vec::new()
-            };
-            let expires_in = if let Some(expires_field) = expires_field {
-                match data.get(expires_field) {
-                    Some(&JsonValue::Number(number)) => {
-                        let expires: f64 = number.into();
-                        let expires = expires.round() as i64;
-                        if expires >= 0 {
-                            Some(expires as u64)
-                        } else {
-                            bail!(
-                                "Field '{}' for expires_in_seconds \
-                                 must be greater than 0(is {}).",
-                                expires_field,
-                                expires
-                            )
-                        }
-                    }
-                    None => bail!(
-                        "Field '{}' for expires_in_seconds not found.",
-                        expires_field
-                    ),
-                    invalid => bail!(
-                        "Expected a number for field '{}' but found a {:?}",
-                        expires_field,
-                        invalid
-                    ),
-                }
-            } else {
-                None
-            };
-            Ok(TokenInfo {
-                active,
-                user_id,
-                scope,
-                expires_in_seconds: expires_in,
-            })
-        }
+    let data = match scanner::parse(json)? {
+        Value::Object(data) => data,
         _ => bail!(
             "Expected an object but found something else which i won't show\
              since it might contain a token."
         ),
+    };
+
+    let (mut active, mut user_id, mut scope, mut expires_in, mut cnf) =
+        (None, None, None, None, None);
+    let mut extra = Claims::default();
+    let mapped = [
+        active_field,
+        user_id_field,
+        scope_field,
+        expires_field,
+        Some("cnf"),
+    ];
+    scanner::for_each_member(data, |name, value| {
+        let key = Some(&*name);
+        if extra_claims && !mapped.contains(&key) {
+            extra.insert(&name, to_json(&value)?);
+        }
+        if key == active_field {
+            active = Some(value.clone());
+        }
+        if key == user_id_field {
+            user_id = Some(value.clone());
+        }
+        if key == scope_field {
+            scope = Some(value.clone());
+        }
+        if key == expires_field {
+            expires_in = Some(value.clone());
+        }
+        if key == Some("cnf") {
+            cnf = Some(value);
+        }
+        Ok(())
+    })?;
+
+    let active = if let Some(active_field) = active_field {
+        parse_active(active, active_field, strict_active)?
+    } else {
+        true
+    };
+    let user_id = if let Some(user_id_field) = user_id_field {
+        match user_id {
+            Some(Value::String(user_id)) => Some(UserId::new(user_id)),
+            invalid => bail!(
+                "Expected a string as the user id in field '{}' but found a {:?}",
+                user_id_field,
+                invalid
+            ),
+        }
+    } else {
+        None
+    };
+    let scope = if let Some(scope_field) = scope_field {
+        match scope {
+            Some(Value::Array(values)) => {
+                let mut scopes = Vec::new();
+                scanner::for_each_element(values, |elem| {
+                    match elem {
+                        Value::String(v) => scopes.extend(claims::server_scope(&v)),
+                        invalid => bail!(
+                            "Expected a string as a scope in ['{}'] but found '{:?}'",
+                            scope_field,
+                            invalid
+                        ),
+                    }
+                    Ok(())
+                })?;
+                ScopeList::from(scopes).into_vec()
+            }
+            Some(Value::String(scope)) => claims::split_scopes(&scope),
+            None => Vec::new(),
+            invalid => bail!(
+                "Expected an array or string for the \
+                 scope(s) in field '{}' but found a {:?}",
+                scope_field,
+                invalid
+            ),
+        }
+    } else {
+        Vec::new()
+    };
+    let expires_in = if let Some(expires_field) = expires_field {
+        let seconds = match expires_in {
+            Some(value) => claims::seconds_of(&to_json(&value)?, expires_field).map_err(err_msg)?,
+            None => None,
+        };
+        if seconds.is_none() {
+            bail!(
+                "Field '{}' for expires_in_seconds not found.",
+                expires_field
+            )
+        }
+        seconds
+    } else {
+        None
+    };
+    let certificate_thumbprint = match cnf {
+        Some(cnf) => claims::certificate_thumbprint(&to_json(&cnf)?),
+        None => None,
+    };
+    Ok(TokenInfo {
+        active,
+        user_id,
+        scope,
+        expires_in_seconds: expires_in,
+        certificate_thumbprint,
+        from_stale_cache: false,
+        extra,
+    })
+}
+
+/// Converts a value of the scanner into a `JsonValue`.
+fn to_json(value: &scanner::Value) -> Result<json::JsonValue, Error> {
+    use self::scanner::Value;
+    Ok(match value {
+        Value::Null => json::JsonValue::Null,
+        Value::Bool(value) => (*value).into(),
+        Value::String(value) => value.as_ref().into(),
+        Value::Number(raw) | Value::Array(raw) | Value::Object(raw) => json::parse(raw)?,
+    })
+}
+
+/// Runs all built-in parsers on arbitrary input.
+///
+/// This is the entry point for fuzzing. Parsing never panics for any input
+/// and inputs larger than `DEFAULT_MAX_RESPONSE_SIZE` are rejected before
+/// any allocation, like responses are by the clients. Returns the number
+/// of parsers which accepted the input.
+pub fn fuzz_parse(bytes: &[u8]) -> usize {
+    if bytes.len() > DEFAULT_MAX_RESPONSE_SIZE {
+        return 0;
     }
+    let parsers: [&dyn TokenInfoParser; 5] = [
+        &PlanBTokenInfoParser,
+        &GoogleV3TokenInfoParser,
+        &AmazonTokenInfoParser,
+        &CognitoTokenInfoParser,
+        &Rfc7662TokenInfoParser,
+    ];
+    let custom = parse(
+        bytes,
+        Some("active"),
+        Some("sub"),
+        Some("scope"),
+        Some("expires_in"),
+    );
+    parsers
+        .iter()
+        .map(|parser| parser.parse(bytes))
+        .chain(Some(custom))
+        .filter(Result::is_ok)
+        .count()
 }
 
-fn split_scopes(input: &str) -> Vec<Scope> {
-    input
-        .split(' ')
-        .filter(|s| !s.is_empty())
-        .map(Scope::new)
-        .collect()
+/// Reads a boolean from the `active` field. Unless `strict` is set the
+/// strings `"true"` and `"false"` and the numbers `1` and `0` are accepted.
+fn parse_active(
+    active: Option<scanner::Value>,
+    active_field: &str,
+    strict: bool,
+) -> Result<bool, Error> {
+    use self::scanner::Value;
+    match active {
+        Some(Value::Bool(active)) => return Ok(active),
+        Some(Value::String(ref s)) if !strict => match s.trim() {
+            "true" => return Ok(true),
+            "false" => return Ok(false),
+            _ => {}
+        },
+        Some(Value::Number("1")) if !strict => return Ok(true),
+        Some(Value::Number("0")) if !strict => return Ok(false),
+        _ => {}
+    }
+    if strict {
+        bail!(
+            "Expected a boolean as the 'active' field in '{}' but found a {:?}",
+            active_field,
+            active
+        )
+    } else {
+        bail!(
+            "Expected a boolean, 'true', 'false', 1 or 0 as the 'active' field in '{}' \
+             but found a {:?}",
+            active_field,
+            active
+        )
+    }
 }
 
 #[test]
@@ -385,10 +898,13 @@ fn google_v3_token_info_multiple_scopes() {
         scope: vec![
             Scope::new("a"),
             Scope::new("b"),
-            Scope::new("https://www.googleapis.com/auth/drive.metadata.readonly"),
             Scope::new("d"),
+            Scope::new("https://www.googleapis.com/auth/drive.metadata.readonly"),
         ],
         expires_in_seconds: Some(436),
+        certificate_thumbprint: None,
+        from_stale_cache: false,
+        extra: Default::default(),
     };
 
     let token_info = GoogleV3TokenInfoParser.parse(sample).unwrap();
@@ -413,10 +929,13 @@ fn google_v3_token_info_multiple_scopes_whitespaces() {
         scope: vec![
             Scope::new("a"),
             Scope::new("b"),
-            Scope::new("https://www.googleapis.com/auth/drive.metadata.readonly"),
             Scope::new("d"),
+            Scope::new("https://www.googleapis.com/auth/drive.metadata.readonly"),
         ],
         expires_in_seconds: Some(436),
+        certificate_thumbprint: None,
+        from_stale_cache: false,
+        extra: Default::default(),
     };
 
     let token_info = GoogleV3TokenInfoParser.parse(sample).unwrap();
@@ -425,3 +944,257 @@ fn google_v3_token_info_multiple_scopes_whitespaces() {
 }
 #[test]
 fn amazon_token_info() {}
+
+#[test]
+fn cognito_access_token_claims() {
+    let sample = br#"
+    {
+        "active": true,
+        "sub":"aaaaaaaa-bbbb-cccc-dddd-eeeeeeeeeeee",
+        "token_use":"access",
+        "scope":"resource/read resource/write",
+        "exp":1,
+        "client_id":"xxxxxxxxxxxx"
+    }
+    "#;
+
+    let expected = TokenInfo {
+        active: true,
+        user_id: Some(UserId::new("aaaaaaaa-bbbb-cccc-dddd-eeeeeeeeeeee")),
+        scope: vec![Scope::new("resource/read"), Scope::new("resource/write")],
+        expires_in_seconds: Some(0),
+        certificate_thumbprint: None,
+        from_stale_cache: false,
+        extra: Default::default(),
+    };
+
+    let token_info = CognitoTokenInfoParser.parse(sample).unwrap();
+
+    assert_eq!(expected, token_info);
+}
+
+#[test]
+fn certificate_bound_token_info() {
+    let sample = br#"
+    {
+        "active": true,
+        "uid": "test2",
+        "scope": ["cn"],
+        "expires_in": 28292,
+        "cnf": {
+            "x5t#S256": "bwcK0esc3ACC3DB2Y5_lESsXE8o9ltc05O89jdN-dg2"
+        }
+    }
+    "#;
+
+    let token_info = PlanBTokenInfoParser.parse(sample).unwrap();
+
+    assert_eq!(
+        token_info.certificate_thumbprint,
+        Some("bwcK0esc3ACC3DB2Y5_lESsXE8o9ltc05O89jdN-dg2".to_string())
+    );
+    assert!(token_info.is_certificate_bound());
+}
+
+#[cfg(feature = "mtls")]
+#[test]
+fn must_match_client_cert() {
+    let cert_der = b"not really a certificate";
+    let thumbprint =
+        base64::encode_config(openssl::sha::sha256(cert_der), base64::URL_SAFE_NO_PAD);
+    let token_info = TokenInfo {
+        active: true,
+        user_id: None,
+        scope: Vec::new(),
+        expires_in_seconds: None,
+        certificate_thumbprint: Some(thumbprint),
+        from_stale_cache: false,
+        extra: Default::default(),
+    };
+
+    assert!(token_info.must_match_client_cert(cert_der).is_ok());
+    assert!(token_info.must_match_client_cert(b"another one").is_err());
+
+    let unbound = TokenInfo {
+        certificate_thumbprint: None,
+        ..token_info
+    };
+    assert!(unbound.must_match_client_cert(cert_der).is_err());
+}
+
+#[test]
+fn scope_lists_are_canonical() {
+    let scopes: ScopeList = " write read\tadmin read ".parse().unwrap();
+    assert_eq!(scopes.to_string(), "admin read write");
+    assert_eq!(scopes.to_string().parse::<ScopeList>().unwrap(), scopes);
+    assert!(scopes.contains(&Scope::new("read")));
+
+    assert!(Scope::parse("a b").is_err());
+    assert!(Scope::parse("").is_err());
+    assert!(Scope::parse("say\"hi\"").is_err());
+    assert!(Scope::parse("https://example.com/read:all").is_ok());
+}
+
+#[test]
+fn active_fields_may_be_strings_or_numbers_unless_strict() {
+    let parser =
+        CustomTokenInfoParser::new(Some("active"), None::<&str>, None::<&str>, None::<&str>);
+    let samples: &[(&[u8], bool)] = &[
+        (br#"{"active": true}"#, true),
+        (br#"{"active": "true"}"#, true),
+        (br#"{"active": "false"}"#, false),
+        (br#"{"active": 1}"#, true),
+        (br#"{"active": 0}"#, false),
+    ];
+    for (sample, active) in samples {
+        assert_eq!(parser.parse(sample).unwrap().active, *active);
+    }
+    assert!(parser.parse(br#"{"active": 2}"#).is_err());
+    assert!(parser.parse(br#"{"active": "yes"}"#).is_err());
+
+    let strict = parser.with_strict_active(true);
+    assert!(!strict.parse(br#"{"active": false}"#).unwrap().active);
+    assert!(strict.parse(br#"{"active": "true"}"#).is_err());
+    assert!(strict.parse(br#"{"active": 1}"#).is_err());
+}
+
+#[test]
+fn extra_claims_are_captured_if_configured() {
+    let sample = br#"{"active": true, "sub": "user", "tenant": {"id": "acme"}, "seats": 3}"#;
+    let parser =
+        CustomTokenInfoParser::new(Some("active"), Some("sub"), None::<&str>, None::<&str>);
+    assert!(parser.parse(sample).unwrap().extra.is_empty());
+
+    let token_info = parser.with_extra_claims(true).parse(sample).unwrap();
+    assert_eq!(
+        token_info.extra.names().collect::<Vec<_>>(),
+        vec!["tenant", "seats"]
+    );
+    assert_eq!(token_info.claim::<String>("tenant.id").unwrap(), "acme");
+    assert_eq!(token_info.claim::<u64>("seats").unwrap(), 3);
+}
+
+#[test]
+fn invalid_scopes_are_skipped() {
+    let sample = br#"{"uid": "user", "scope": ["read", "not valid", "say\"hi\""]}"#;
+    let token_info = parse(sample, None, Some("uid"), Some("scope"), None).unwrap();
+    assert_eq!(token_info.scope, vec![Scope::new("read")]);
+
+    let sample = br#"{"active": true, "scope": "read \u0007 write"}"#;
+    let token_info = Rfc7662TokenInfoParser.parse(sample).unwrap();
+    assert_eq!(
+        token_info.scope,
+        vec![Scope::new("read"), Scope::new("write")]
+    );
+}
+
+#[test]
+fn hostile_input_does_not_panic() {
+    let samples: Vec<Vec<u8>> = vec![
+        br#"{"expires_in": 1e300, "exp": 1e300}"#.to_vec(),
+        br#"{"expires_in": -1e300, "exp": -1}"#.to_vec(),
+        br#"{"expires_in": 18446744073709551616, "exp": 18446744073709551615}"#.to_vec(),
+        br#"{"active": "maybe", "scope": [1, null, {}], "uid": []}"#.to_vec(),
+        br#"{"cnf": [], "scope": "\u0000 \ud800"}"#.to_vec(),
+        vec![b'['; 100_000],
+        vec![0xff; 64],
+    ];
+    for sample in samples {
+        fuzz_parse(&sample);
+    }
+
+    // A simple xorshift generator keeps the arbitrary inputs reproducible.
+    let mut state: u32 = 0x9e37_79b9;
+    let alphabet = br#"{}[]",:0123456789.eE+-truefalsn \/uidscopexp"#;
+    for _ in 0..2_000 {
+        let len = (state % 64) as usize;
+        let sample: Vec<u8> = (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                alphabet[state as usize % alphabet.len()]
+            })
+            .collect();
+        fuzz_parse(&sample);
+    }
+}
+
+#[test]
+fn unbounded_expiry_is_rejected() {
+    let sample = br#"{"uid": "user", "expires_in": 1e300}"#;
+    assert!(PlanBTokenInfoParser.parse(sample).is_err());
+    let sample = br#"{"uid": "user", "expires_in": 3600}"#;
+    assert!(PlanBTokenInfoParser.parse(sample).is_ok());
+    assert!(fuzz_parse(sample) > 0);
+}
+
+#[test]
+fn expiries_may_be_sent_as_strings() {
+    let sample = br#"{"uid": "user", "expires_in": "3600", "scope": ["read"]}"#;
+    let token_info = PlanBTokenInfoParser.parse(sample).unwrap();
+    assert_eq!(token_info.expires_in_seconds, Some(3600));
+    let sample = br#"{"uid": "user", "expires_in": 3599.6}"#;
+    let token_info = PlanBTokenInfoParser.parse(sample).unwrap();
+    assert_eq!(token_info.expires_in_seconds, Some(3600));
+    let sample = br#"{"uid": "user", "expires_in": "soon"}"#;
+    assert!(PlanBTokenInfoParser.parse(sample).is_err());
+}
+
+#[test]
+fn rfc7662_responses_must_state_whether_active() {
+    let token_info = Rfc7662TokenInfoParser
+        .parse(br#"{"active": false}"#)
+        .unwrap();
+    assert!(!token_info.active);
+    assert!(Rfc7662TokenInfoParser
+        .parse(br#"{"sub": "user", "scope": "read"}"#)
+        .is_err());
+    assert!(Rfc7662TokenInfoParser
+        .parse(br#"{"active": "true"}"#)
+        .is_err());
+}
+
+#[test]
+fn parsers_can_be_chosen_at_runtime() {
+    let sample = br#"{"uid": "user", "scope": ["read"], "expires_in": 3600}"#;
+    let shared: SharedTokenInfoParser = Arc::new(PlanBTokenInfoParser);
+    let boxed: Box<dyn TokenInfoParser + Send + Sync> = Box::new(PlanBTokenInfoParser);
+    let expected = PlanBTokenInfoParser.parse(sample).unwrap();
+    assert_eq!(shared.parse(sample).unwrap().user_id, expected.user_id);
+    assert_eq!(boxed.parse(sample).unwrap().scope, expected.scope);
+}
+
+#[test]
+fn multi_format_parsers_select_by_content_type_then_shape() {
+    let parser = MultiFormatTokenInfoParser::new()
+        .with_content_type(
+            "application/token-introspection+json",
+            Rfc7662TokenInfoParser,
+        )
+        .with_field("uid", PlanBTokenInfoParser)
+        .with_field("user_id", AmazonTokenInfoParser);
+
+    let rfc7662 = br#"{"active": true, "sub": "a", "uid": "b", "expires_in": 60}"#;
+    let token_info = parser
+        .parse_with_content_type(
+            rfc7662,
+            Some("Application/Token-Introspection+JSON; charset=utf-8"),
+        )
+        .unwrap();
+    assert_eq!(token_info.user_id, Some(UserId::new("a")));
+    let token_info = parser
+        .parse_with_content_type(rfc7662, Some("application/json"))
+        .unwrap();
+    assert_eq!(token_info.user_id, Some(UserId::new("b")));
+
+    let token_info = parser
+        .parse(br#"{"user_id": "c", "uid": "d", "expires_in": 60}"#)
+        .unwrap();
+    assert_eq!(token_info.user_id, Some(UserId::new("d")));
+    let token_info = parser.parse(br#"{"user_id": "c", "exp": 60}"#).unwrap();
+    assert_eq!(token_info.user_id, Some(UserId::new("c")));
+
+    assert!(parser.parse(br#"{"sub": "e"}"#).is_err());
+    assert!(parser.parse(b"[\"uid\"]").is_err());
+}
@@ -0,0 +1,430 @@
+//! A scanner for the JSON of token info responses.
+//!
+//! Instead of building a tree, only the members of an object are visited.
+//! Strings without escapes are borrowed from the input and nested arrays
+//! and objects are validated but returned as raw slices which can be
+//! visited the same way.
+use std::borrow::Cow;
+use std::char;
+
+use failure::Error;
+
+/// The maximum nesting of arrays and objects.
+const DEPTH_LIMIT: usize = 512;
+
+/// A JSON value borrowing from the input.
+#[derive(Debug, Clone)]
+pub(super) enum Value<'a> {
+    Null,
+    Bool(bool),
+    /// The number as written in the input.
+    Number(&'a str),
+    String(Cow<'a, str>),
+    /// The array including the brackets.
+    Array(&'a str),
+    /// The object including the braces.
+    Object(&'a str),
+}
+
+/// Parses a single JSON value which may be surrounded by whitespace.
+pub(super) fn parse(json: &str) -> Result<Value<'_>, Error> {
+    let mut scanner = Scanner::new(json);
+    let value = scanner.value()?;
+    scanner.skip_whitespace();
+    if scanner.pos != json.len() {
+        return Err(scanner.unexpected());
+    }
+    Ok(value)
+}
+
+/// Calls `f` with the key and the value of each member of an object
+/// returned as `Value::Object`.
+pub(super) fn for_each_member<'a, F>(object: &'a str, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(Cow<'a, str>, Value<'a>) -> Result<(), Error>,
+{
+    let mut scanner = Scanner::new(object);
+    scanner.expect(b'{')?;
+    scanner.skip_whitespace();
+    if scanner.peek() == Some(b'}') {
+        return Ok(());
+    }
+    loop {
+        let key = scanner.key()?;
+        f(key, scanner.value()?)?;
+        if scanner.separator(b'}')? {
+            return Ok(());
+        }
+    }
+}
+
+/// Calls `f` with each element of an array returned as `Value::Array`.
+pub(super) fn for_each_element<'a, F>(array: &'a str, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(Value<'a>) -> Result<(), Error>,
+{
+    let mut scanner = Scanner::new(array);
+    scanner.expect(b'[')?;
+    scanner.skip_whitespace();
+    if scanner.peek() == Some(b']') {
+        return Ok(());
+    }
+    loop {
+        f(scanner.value()?)?;
+        if scanner.separator(b']')? {
+            return Ok(());
+        }
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Scanner<'a> {
+        Scanner {
+            input,
+            bytes: input.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Reads the comma between two values or the closing bracket `close`.
+    /// Returns `true` if the closing bracket was read.
+    fn separator(&mut self, close: u8) -> Result<bool, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b',') => {
+                self.pos += 1;
+                Ok(false)
+            }
+            Some(byte) if byte == close => {
+                self.pos += 1;
+                Ok(true)
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str) -> Result<(), Error> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> Error {
+        match self
+            .input
+            .get(self.pos..)
+            .and_then(|rest| rest.chars().next())
+        {
+            Some(c) => format_err!("Unexpected character {:?} at byte {}", c, self.pos),
+            None if self.pos < self.input.len() => format_err!("Unexpected byte at {}", self.pos),
+            None => format_err!("Unexpected end of JSON"),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Reads a member's key and the following colon.
+    fn key(&mut self) -> Result<Cow<'a, str>, Error> {
+        self.skip_whitespace();
+        let key = self.string()?;
+        self.skip_whitespace();
+        self.expect(b':')?;
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => Ok(Value::Object(self.container()?)),
+            Some(b'[') => Ok(Value::Array(self.container()?)),
+            _ => self.scalar(),
+        }
+    }
+
+    fn scalar(&mut self) -> Result<Value<'a>, Error> {
+        match self.peek() {
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'n') => self.expect_literal("null").map(|_| Value::Null),
+            Some(b't') => self.expect_literal("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect_literal("false").map(|_| Value::Bool(false)),
+            Some(b'-') | Some(b'0'..=b'9') => Ok(Value::Number(self.number()?)),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Validates an array or an object without recursion and returns it.
+    fn container(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        // One bit per level which is set for objects.
+        let mut is_object = [0u64; DEPTH_LIMIT / 64];
+        let mut depth = 0;
+        loop {
+            // A value is expected.
+            self.skip_whitespace();
+            match self.peek() {
+                Some(open @ b'{') | Some(open @ b'[') => {
+                    if depth == DEPTH_LIMIT {
+                        bail!("JSON is nested deeper than {} levels", DEPTH_LIMIT);
+                    }
+                    self.pos += 1;
+                    if open == b'{' {
+                        is_object[depth / 64] |= 1 << (depth % 64);
+                    } else {
+                        is_object[depth / 64] &= !(1 << (depth % 64));
+                    }
+                    depth += 1;
+                    self.skip_whitespace();
+                    let close = if open == b'{' { b'}' } else { b']' };
+                    if self.peek() == Some(close) {
+                        self.pos += 1;
+                        depth -= 1;
+                    } else {
+                        if open == b'{' {
+                            self.key()?;
+                        }
+                        continue;
+                    }
+                }
+                _ => {
+                    self.scalar()?;
+                }
+            }
+            // A value is complete.
+            while depth > 0 {
+                let in_object = is_object[(depth - 1) / 64] & (1 << ((depth - 1) % 64)) != 0;
+                if self.separator(if in_object { b'}' } else { b']' })? {
+                    depth -= 1;
+                } else {
+                    if in_object {
+                        self.key()?;
+                    }
+                    break;
+                }
+            }
+            if depth == 0 {
+                return Ok(&self.input[start..self.pos]);
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.unexpected()),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits()?;
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+        Ok(&self.input[start..self.pos])
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            Some(b'0'..=b'9') => {
+                self.digits();
+                Ok(())
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Reads a string which is only copied if it contains escapes.
+    fn string(&mut self) -> Result<Cow<'a, str>, Error> {
+        self.expect(b'"')?;
+        let (chunk, mut closed) = self.chunk()?;
+        if closed {
+            return Ok(Cow::Borrowed(chunk));
+        }
+        let mut unescaped = String::from(chunk);
+        while !closed {
+            self.escape(&mut unescaped)?;
+            let (chunk, end) = self.chunk()?;
+            unescaped.push_str(chunk);
+            closed = end;
+        }
+        Ok(Cow::Owned(unescaped))
+    }
+
+    /// Reads the characters of a string up to and including the closing
+    /// quote or a backslash. Returns `true` if the string was closed.
+    fn chunk(&mut self) -> Result<(&'a str, bool), Error> {
+        let start = self.pos;
+        loop {
+            match self.peek() {
+                Some(byte @ b'"') | Some(byte @ b'\\') => {
+                    let chunk = &self.input[start..self.pos];
+                    self.pos += 1;
+                    return Ok((chunk, byte == b'"'));
+                }
+                Some(0x00..=0x1f) | None => return Err(self.unexpected()),
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    /// Decodes the escape sequence following a backslash.
+    fn escape(&mut self, into: &mut String) -> Result<(), Error> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                into.push(self.unicode_escape()?);
+                return Ok(());
+            }
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        into.push(c);
+        Ok(())
+    }
+
+    /// Decodes the hex digits of a `\\u` escape and a second escape if the
+    /// first one is a high surrogate.
+    fn unicode_escape(&mut self) -> Result<char, Error> {
+        let code_unit = self.code_unit()?;
+        if let Some(c) = char::from_u32(u32::from(code_unit)) {
+            return Ok(c);
+        }
+        self.expect_literal("\\u")?;
+        let low = self.code_unit()?;
+        match char::decode_utf16([code_unit, low].iter().cloned()).next() {
+            Some(Ok(c)) => Ok(c),
+            _ => bail!("Invalid surrogate pair in JSON string"),
+        }
+    }
+
+    fn code_unit(&mut self) -> Result<u16, Error> {
+        let hex = match self.input.get(self.pos..self.pos + 4) {
+            Some(hex) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => hex,
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 4;
+        Ok(u16::from_str_radix(hex, 16)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(json: &str) -> Result<Vec<(String, String)>, Error> {
+        let object = match parse(json)? {
+            Value::Object(object) => object,
+            other => bail!("not an object: {:?}", other),
+        };
+        let mut members = Vec::new();
+        for_each_member(object, |key, value| {
+            members.push((key.into_owned(), format!("{:?}", value)));
+            Ok(())
+        })?;
+        Ok(members)
+    }
+
+    #[test]
+    fn members_are_visited_in_order() {
+        let json = r#" {"a": [1, {"b": []}], "c\"d": "e\u00e9\ud83d\ude00", "f": -1.5e3,
+            "g": {}, "h": null, "i": true} "#;
+        let members = members(json).unwrap();
+        assert_eq!(
+            members,
+            vec![
+                ("a".to_string(), r#"Array("[1, {\"b\": []}]")"#.to_string()),
+                ("c\"d".to_string(), "String(\"eé😀\")".to_string()),
+                ("f".to_string(), "Number(\"-1.5e3\")".to_string()),
+                ("g".to_string(), "Object(\"{}\")".to_string()),
+                ("h".to_string(), "Null".to_string()),
+                ("i".to_string(), "Bool(true)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unescaped_strings_are_borrowed() {
+        match parse(r#""plain""#).unwrap() {
+            Value::String(Cow::Borrowed("plain")) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_json_is_rejected() {
+        let samples = [
+            "",
+            "{",
+            "{\"a\"}",
+            "{\"a\": 1,}",
+            "[1 2]",
+            "[1,]",
+            "{\"a\": 1]",
+            "01",
+            "1.",
+            "-",
+            "1e",
+            "\"\\ud800\"",
+            "\"\\x\"",
+            "\"a\nb\"",
+            "nul",
+            "{} {}",
+        ];
+        for sample in samples.iter() {
+            assert!(parse(sample).is_err(), "{}", sample);
+        }
+
+        let nested = "[".repeat(DEPTH_LIMIT) + &"]".repeat(DEPTH_LIMIT);
+        assert!(parse(&nested).is_ok());
+        let nested = "[".repeat(DEPTH_LIMIT + 1) + &"]".repeat(DEPTH_LIMIT + 1);
+        assert!(parse(&nested).is_err());
+    }
+}
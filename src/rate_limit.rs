@@ -0,0 +1,262 @@
+//! Limiting the introspections of tokens which keep failing
+//!
+//! A client replaying the same invalid token would otherwise cause an
+//! introspection, including its retries, with every request.
+//! `RateLimitingTokenInfoService` wraps a `TokenInfoService` and rejects
+//! the introspections of a token without asking the wrapped service once
+//! too many of them failed within a window. The rejections fail fast with
+//! `TokenInfoErrorKind::TooManyRequests`.
+//!
+//! Tokens are tracked by their `TokenKey` so the limiter never holds the
+//! tokens themselves.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use tokkit::client::TokenInfoServiceClientBuilder;
+//! use tokkit::rate_limit::{RateLimitConfig, RateLimitingTokenInfoService};
+//!
+//! let service = TokenInfoServiceClientBuilder::google_v3().build().unwrap();
+//!
+//! let service = RateLimitingTokenInfoService::new(
+//!     service,
+//!     RateLimitConfig::default()
+//!         .with_max_failures(5)
+//!         .with_window(Duration::from_secs(60)),
+//! );
+//! ```
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cache::{TokenHasher, TokenKey};
+use crate::{
+    AccessToken, Credential, IntrospectionContext, TokenInfo, TokenInfoErrorKind, TokenInfoResult,
+    TokenInfoService,
+};
+
+/// Configures a `RateLimitingTokenInfoService`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// The number of failed introspections of a token within `window`
+    /// after which further introspections are rejected.
+    pub max_failures: u32,
+    /// The time after the first failure in which failures are counted.
+    pub window: Duration,
+    /// The maximum number of tokens tracked. Failing tokens are not
+    /// limited while this many other tokens are tracked.
+    pub max_tracked_tokens: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            max_failures: 10,
+            window: Duration::from_secs(60),
+            max_tracked_tokens: 10_000,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn with_max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures;
+        self
+    }
+
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_max_tracked_tokens(mut self, max_tracked_tokens: usize) -> Self {
+        self.max_tracked_tokens = max_tracked_tokens;
+        self
+    }
+}
+
+struct Failures {
+    since: Instant,
+    count: u32,
+}
+
+/// A `TokenInfoService` which rejects the introspections of a token once
+/// too many of them failed.
+///
+/// Only `Credential::Bearer` is limited. A successful introspection
+/// resets the count of the token.
+pub struct RateLimitingTokenInfoService<S> {
+    service: S,
+    config: RateLimitConfig,
+    hasher: TokenHasher,
+    failures: Mutex<HashMap<TokenKey, Failures>>,
+}
+
+impl<S> RateLimitingTokenInfoService<S>
+where
+    S: TokenInfoService,
+{
+    pub fn new(service: S, config: RateLimitConfig) -> Self {
+        RateLimitingTokenInfoService {
+            service,
+            config,
+            hasher: TokenHasher::random(),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The number of tokens currently tracked.
+    pub fn tracked_tokens(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    fn limited<F>(&self, token: &AccessToken, introspect: F) -> TokenInfoResult<TokenInfo>
+    where
+        F: FnOnce() -> TokenInfoResult<TokenInfo>,
+    {
+        let key = self.hasher.key(token);
+        if let Some(retry_after) = self.rejected_for(&key) {
+            debug!("Rejected the introspection of the token {}.", key);
+            return Err(TokenInfoErrorKind::TooManyRequests { retry_after }.into());
+        }
+        let result = introspect();
+        match result {
+            Ok(_) => {
+                self.failures.lock().unwrap().remove(&key);
+            }
+            Err(_) => self.record_failure(key),
+        }
+        result
+    }
+
+    /// The time until the window of the token ends if it is exhausted.
+    fn rejected_for(&self, key: &TokenKey) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let entry = failures.get(key)?;
+        let elapsed = entry.since.elapsed();
+        if entry.count >= self.config.max_failures && elapsed < self.config.window {
+            Some(self.config.window - elapsed)
+        } else {
+            None
+        }
+    }
+
+    fn record_failure(&self, key: TokenKey) {
+        let window = self.config.window;
+        let mut failures = self.failures.lock().unwrap();
+        if !failures.contains_key(&key) && failures.len() >= self.config.max_tracked_tokens {
+            failures.retain(|_, entry| entry.since.elapsed() < window);
+            if failures.len() >= self.config.max_tracked_tokens {
+                warn!(
+                    "Not tracking the failing token {} since {} tokens are tracked.",
+                    key,
+                    failures.len()
+                );
+                return;
+            }
+        }
+        let now = Instant::now();
+        let entry = failures.entry(key).or_insert(Failures {
+            since: now,
+            count: 0,
+        });
+        if now.duration_since(entry.since) >= window {
+            entry.since = now;
+            entry.count = 0;
+        }
+        entry.count += 1;
+    }
+}
+
+impl<S> TokenInfoService for RateLimitingTokenInfoService<S>
+where
+    S: TokenInfoService,
+{
+    fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+        self.limited(token, || self.service.introspect(token))
+    }
+
+    fn introspect_credential(&self, credential: &Credential) -> TokenInfoResult<TokenInfo> {
+        match *credential {
+            Credential::Bearer(ref token) => self.introspect(token),
+            _ => self.service.introspect_credential(credential),
+        }
+    }
+
+    fn introspect_with_context(
+        &self,
+        token: &AccessToken,
+        context: &IntrospectionContext,
+    ) -> TokenInfoResult<TokenInfo> {
+        self.limited(token, || {
+            self.service.introspect_with_context(token, context)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct CountingService(AtomicUsize);
+
+    impl TokenInfoService for CountingService {
+        fn introspect(&self, token: &AccessToken) -> TokenInfoResult<TokenInfo> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            if token.reveal() == "invalid" {
+                return Err(TokenInfoErrorKind::not_authenticated("invalid").into());
+            }
+            Ok(TokenInfo::new(true))
+        }
+    }
+
+    #[test]
+    fn failing_tokens_are_rejected_without_introspection() {
+        let service = RateLimitingTokenInfoService::new(
+            CountingService(AtomicUsize::new(0)),
+            RateLimitConfig::default().with_max_failures(2),
+        );
+
+        let invalid = AccessToken::new("invalid");
+        for _ in 0..2 {
+            match *service.introspect(&invalid).unwrap_err().kind() {
+                TokenInfoErrorKind::NotAuthenticated { .. } => {}
+                ref other => panic!("unexpected error: {:?}", other),
+            }
+        }
+        match *service.introspect(&invalid).unwrap_err().kind() {
+            TokenInfoErrorKind::TooManyRequests { retry_after } => {
+                assert!(retry_after <= Duration::from_secs(60))
+            }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(service.service.0.load(Ordering::SeqCst), 2);
+
+        for _ in 0..3 {
+            service.introspect(&AccessToken::new("valid")).unwrap();
+        }
+        assert_eq!(service.service.0.load(Ordering::SeqCst), 5);
+        assert_eq!(service.tracked_tokens(), 1);
+    }
+
+    #[test]
+    fn the_count_starts_over_after_the_window() {
+        let service = RateLimitingTokenInfoService::new(
+            CountingService(AtomicUsize::new(0)),
+            RateLimitConfig::default()
+                .with_max_failures(1)
+                .with_window(Duration::from_secs(0)),
+        );
+
+        let invalid = AccessToken::new("invalid");
+        for _ in 0..3 {
+            match *service.introspect(&invalid).unwrap_err().kind() {
+                TokenInfoErrorKind::NotAuthenticated { .. } => {}
+                ref other => panic!("unexpected error: {:?}", other),
+            }
+        }
+        assert_eq!(service.service.0.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,157 @@
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::Url;
+
+use crate::tls::{self, TlsPolicy};
+use crate::InitializationResult;
+
+/// The number of redirects followed by the default `RedirectPolicy`.
+pub const DEFAULT_MAX_REDIRECTS: usize = 3;
+
+/// Decides which redirects of introspection and token endpoints are
+/// followed.
+///
+/// Gateways sometimes redirect to a regional host. Following such a
+/// redirect sends the request to the new host again. The HTTP client drops
+/// the `Authorization` header when the host changes, but tokens sent in
+/// the URL or in a form body of a `307` or `308` are sent along. Therefore
+/// only redirects to the origin of the endpoint are followed unless other
+/// origins are allowed explicitly.
+///
+/// Redirects from `https` to `http` are never followed.
+///
+/// ```rust
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+/// use tokkit::RedirectPolicy;
+///
+/// let mut builder = TokenInfoServiceClientBuilder::google_v3();
+/// builder.with_redirect_policy(
+///     RedirectPolicy::same_origin(2).with_allowed_origin("https://eu.auth.example.com"),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    max_hops: usize,
+    allowed_origins: Vec<String>,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy::same_origin(DEFAULT_MAX_REDIRECTS)
+    }
+}
+
+impl RedirectPolicy {
+    /// Follows no redirects. The redirect itself is the response.
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy::same_origin(0)
+    }
+
+    /// Follows at most `max_hops` redirects within the origin of the
+    /// endpoint.
+    pub fn same_origin(max_hops: usize) -> RedirectPolicy {
+        RedirectPolicy {
+            max_hops,
+            allowed_origins: Vec::new(),
+        }
+    }
+
+    /// Also follows redirects to `origin`, e.g. `https://eu.auth.example.com`.
+    pub fn with_allowed_origin<T: Into<String>>(mut self, origin: T) -> Self {
+        self.allowed_origins
+            .push(origin.into().trim_end_matches('/').to_ascii_lowercase());
+        self
+    }
+
+    /// The policy for a `reqwest` client, e.g. for a client passed to a
+    /// `ResourceOwnerPasswordCredentialsGrantProvider`.
+    pub fn to_reqwest_policy(&self) -> Policy {
+        if self.max_hops == 0 {
+            return Policy::none();
+        }
+        let policy = self.clone();
+        Policy::custom(move |attempt: Attempt| {
+            match policy.check(attempt.url(), attempt.previous()) {
+                Ok(()) => attempt.follow(),
+                Err(message) => attempt.error(message),
+            }
+        })
+    }
+
+    /// Creates a blocking `Client` following this policy.
+    pub fn blocking_client(&self) -> InitializationResult<reqwest::blocking::Client> {
+        tls::blocking_client(self, &TlsPolicy::default())
+    }
+
+    /// Checks the redirect to `next` after the requests to `previous`
+    /// starting with the request to the endpoint.
+    fn check(&self, next: &Url, previous: &[Url]) -> Result<(), String> {
+        if previous.len() > self.max_hops {
+            return Err(format!(
+                "Refused to follow more than {} redirects",
+                self.max_hops
+            ));
+        }
+        let endpoint = match previous.first() {
+            Some(endpoint) => endpoint,
+            None => return Ok(()),
+        };
+        if endpoint.scheme() == "https" && next.scheme() != "https" {
+            return Err(format!("Refused to follow a redirect to {}", origin(next)));
+        }
+        let next_origin = origin(next);
+        if next_origin == origin(endpoint) || self.allowed_origins.contains(&next_origin) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Refused to follow a redirect to the origin {}",
+                next_origin
+            ))
+        }
+    }
+}
+
+/// The origin of `url` like `https://host:port` with the port omitted if
+/// it is the default of the scheme.
+fn origin(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        urls.iter().map(|url| url.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn only_allowed_origins_are_followed() {
+        let policy = RedirectPolicy::default().with_allowed_origin("https://EU.auth.example.com/");
+        let previous = urls(&["https://auth.example.com/introspect"]);
+        let check = |next: &str| policy.check(&next.parse().unwrap(), &previous);
+
+        assert!(check("https://auth.example.com:443/v2/introspect").is_ok());
+        assert!(check("https://eu.auth.example.com/introspect").is_ok());
+        assert!(check("https://evil.example.com/introspect").is_err());
+        assert!(check("https://auth.example.com:8443/introspect").is_err());
+        assert!(check("http://auth.example.com/introspect").is_err());
+    }
+
+    #[test]
+    fn hops_are_limited() {
+        let policy = RedirectPolicy::same_origin(2);
+        let next = "https://auth.example.com/c".parse().unwrap();
+        let previous = urls(&["https://auth.example.com/a", "https://auth.example.com/b"]);
+        assert!(policy.check(&next, &previous).is_ok());
+        let previous = urls(&[
+            "https://auth.example.com/a",
+            "https://auth.example.com/b",
+            "https://auth.example.com/c",
+        ]);
+        assert!(policy.check(&next, &previous).is_err());
+    }
+}
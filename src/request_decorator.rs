@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Url};
+
+/// Mutates outgoing requests right before they are sent.
+///
+/// Decorators are called for every attempt including retries and may add
+/// API gateway keys, signatures or tracing headers like `X-Request-Id`.
+///
+/// Any `Fn(&Method, &Url, &mut HeaderMap, Option<&[u8]>)` is a
+/// `RequestDecorator`:
+///
+/// ```rust
+/// use reqwest::header::{HeaderMap, HeaderValue};
+/// use reqwest::{Method, Url};
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+///
+/// let mut builder = TokenInfoServiceClientBuilder::google_v3();
+/// builder.with_request_decorator(
+///     |_: &Method, _: &Url, headers: &mut HeaderMap, _: Option<&[u8]>| {
+///         headers.insert("x-api-key", HeaderValue::from_static("gateway-key"));
+///     },
+/// );
+/// ```
+pub trait RequestDecorator {
+    /// Decorates a request to `url`. `body` is the body to be sent, if any.
+    fn decorate(&self, method: &Method, url: &Url, headers: &mut HeaderMap, body: Option<&[u8]>);
+}
+
+impl<F> RequestDecorator for F
+where
+    F: Fn(&Method, &Url, &mut HeaderMap, Option<&[u8]>),
+{
+    fn decorate(&self, method: &Method, url: &Url, headers: &mut HeaderMap, body: Option<&[u8]>) {
+        self(method, url, headers, body)
+    }
+}
+
+/// The `RequestDecorator`s of a client or provider applied in order.
+pub(crate) type RequestDecorators = Vec<Arc<dyn RequestDecorator + Send + Sync + 'static>>;
+
+pub(crate) fn decorate(
+    decorators: &[Arc<dyn RequestDecorator + Send + Sync + 'static>],
+    method: &Method,
+    url: &Url,
+    headers: &mut HeaderMap,
+    body: Option<&[u8]>,
+) {
+    for decorator in decorators {
+        decorator.decorate(method, url, headers, body);
+    }
+}
@@ -0,0 +1,313 @@
+//! Debug logging of the requests to the introspection service.
+//!
+//! Each request results in a single line logged with level `debug` and
+//! target `tokkit::request_log` containing the method, the endpoint, the
+//! status, the latency and the names of the claims in the response.
+//! Claim values and headers are never logged. Tokens and anything that
+//! looks like a secret are removed from the endpoint according to a
+//! `RedactionPolicy`.
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use percent_encoding::percent_decode_str;
+use reqwest::{Method, StatusCode, Url};
+
+use crate::{AccessToken, IntrospectionContext, TokenInfoError};
+
+/// Decides which parts of a request are replaced before it is logged.
+///
+/// A value is redacted if it is the introspected token, if its name
+/// contains one of the secret names (ignoring case) or if it looks like
+/// a secret: at least `min_secret_length` characters of letters, digits
+/// and `-_.~+/=` with at least one letter and one digit.
+#[derive(Debug, Clone)]
+pub struct RedactionPolicy {
+    secret_names: Vec<String>,
+    min_secret_length: usize,
+    replacement: String,
+}
+
+impl RedactionPolicy {
+    /// Creates the default `RedactionPolicy`.
+    pub fn new() -> RedactionPolicy {
+        RedactionPolicy::default()
+    }
+
+    /// Adds a name which marks a value as secret if it is part of a
+    /// parameter name.
+    pub fn with_secret_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.secret_names.push(name.into().to_lowercase());
+        self
+    }
+
+    /// Sets the minimum length of values which look like a secret. The
+    /// default is 24. Use `usize::MAX` to only redact by name.
+    pub fn with_min_secret_length(mut self, min_secret_length: usize) -> Self {
+        self.min_secret_length = min_secret_length;
+        self
+    }
+
+    /// Sets the text redacted values are replaced with. The default is
+    /// `<redacted>`.
+    pub fn with_replacement<T: Into<String>>(mut self, replacement: T) -> Self {
+        self.replacement = replacement.into();
+        self
+    }
+
+    /// Returns `true` if values named `name` are secret.
+    pub fn is_secret_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.secret_names
+            .iter()
+            .any(|secret_name| name.contains(secret_name.as_str()))
+    }
+
+    /// Returns `true` if `value` looks like a secret.
+    pub fn looks_secret(&self, value: &str) -> bool {
+        value.chars().count() >= self.min_secret_length
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.~+/=".contains(c))
+            && value.chars().any(|c| c.is_ascii_alphabetic())
+            && value.chars().any(|c| c.is_ascii_digit())
+    }
+
+    /// Returns the replacement if the value named `name` is secret and
+    /// `value` otherwise.
+    pub fn redact<'a>(&self, name: &str, value: &'a str) -> Cow<'a, str> {
+        if self.is_secret_name(name) || self.looks_secret(value) {
+            Cow::Owned(self.replacement.clone())
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    /// Returns `url` with the `token`, user info, secret path segments and
+    /// secret query values replaced. The fragment is removed.
+    pub fn redact_url(&self, url: &Url, token: Option<&AccessToken>) -> String {
+        let is_token = |value: &str| {
+            let value = percent_decode_str(value).decode_utf8_lossy();
+            token.is_some_and(|token| token.reveal() == value) || self.looks_secret(&value)
+        };
+
+        let mut redacted = format!("{}://", url.scheme());
+        if !url.username().is_empty() || url.password().is_some() {
+            redacted.push_str(&self.replacement);
+            redacted.push('@');
+        }
+        if let Some(host) = url.host_str() {
+            redacted.push_str(host);
+        }
+        if let Some(port) = url.port() {
+            redacted.push_str(&format!(":{}", port));
+        }
+
+        let segments: Vec<&str> = url
+            .path()
+            .split('/')
+            .map(|segment| {
+                if is_token(segment) {
+                    self.replacement.as_str()
+                } else {
+                    segment
+                }
+            })
+            .collect();
+        redacted.push_str(&segments.join("/"));
+
+        if let Some(query) = url.query() {
+            let pairs: Vec<String> = query
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((name, value)) if self.is_secret_name(name) || is_token(value) => {
+                        format!("{}={}", name, self.replacement)
+                    }
+                    _ => pair.to_string(),
+                })
+                .collect();
+            redacted.push('?');
+            redacted.push_str(&pairs.join("&"));
+        }
+
+        redacted
+    }
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> RedactionPolicy {
+        RedactionPolicy {
+            secret_names: [
+                "token",
+                "secret",
+                "password",
+                "key",
+                "auth",
+                "credential",
+                "assertion",
+                "code",
+                "signature",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+            min_secret_length: 24,
+            replacement: "<redacted>".to_string(),
+        }
+    }
+}
+
+/// A request to the introspection service as it appears in the log.
+#[derive(Clone)]
+pub(crate) struct RequestLog {
+    policy: Arc<RedactionPolicy>,
+    target: String,
+    token: Option<AccessToken>,
+}
+
+impl RequestLog {
+    /// `token` is the token sent in the URL, if any.
+    pub fn new(
+        policy: &Arc<RedactionPolicy>,
+        method: &Method,
+        url: &Url,
+        token: Option<&AccessToken>,
+    ) -> RequestLog {
+        RequestLog {
+            policy: policy.clone(),
+            target: format!("{} {}", method, policy.redact_url(url, token)),
+            token: token.cloned(),
+        }
+    }
+
+    /// Adds the `context` the request is sent for to the log messages.
+    pub fn with_context(mut self, context: Option<&IntrospectionContext>) -> Self {
+        if let Some(context) = context {
+            self.target = format!("{} ({})", self.target, context);
+        }
+        self
+    }
+
+    /// Logs a response with the names of its claims if it was successful.
+    /// `body` must already be decoded.
+    pub fn response(&self, start: Instant, status: StatusCode, body: &[u8]) {
+        let latency = start.elapsed().as_millis();
+        if status == StatusCode::OK {
+            debug!(
+                "{} -> {} in {} ms, claims: [{}]",
+                self.target,
+                status,
+                latency,
+                claim_names(body).join(", ")
+            );
+        } else {
+            debug!("{} -> {} in {} ms", self.target, status, latency);
+        }
+    }
+
+    /// Logs a request which did not result in a complete response.
+    pub fn failure(&self, start: Instant, status: Option<StatusCode>, err: &TokenInfoError) {
+        let latency = start.elapsed().as_millis();
+        let mut message = err.to_string();
+        if let Some(ref token) = self.token {
+            message = message.replace(token.reveal(), &self.policy.replacement);
+        }
+        match status {
+            Some(status) => debug!(
+                "{} -> {} failed after {} ms: {}",
+                self.target, status, latency, message
+            ),
+            None => debug!("{} failed after {} ms: {}", self.target, latency, message),
+        }
+    }
+}
+
+/// Logs the failure of a request if it is logged and passes `err` on.
+pub(crate) fn log_failure(
+    request_log: Option<(&RequestLog, Instant)>,
+    status: Option<StatusCode>,
+    err: TokenInfoError,
+) -> TokenInfoError {
+    if let Some((request_log, start)) = request_log {
+        request_log.failure(start, status, &err);
+    }
+    err
+}
+
+impl fmt::Debug for RequestLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RequestLog({})", self.target)
+    }
+}
+
+/// The names of the top level fields of a JSON object.
+fn claim_names(body: &[u8]) -> Vec<String> {
+    let parsed = std::str::from_utf8(body)
+        .ok()
+        .and_then(|body| json::parse(body).ok());
+    match parsed {
+        Some(json::JsonValue::Object(object)) => {
+            object.iter().map(|(name, _)| name.to_string()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TOKEN: &str = "opaque";
+
+    #[test]
+    fn tokens_and_secrets_are_removed_from_urls() {
+        let policy = RedactionPolicy::default();
+        let token = AccessToken::new(TOKEN);
+
+        let url: Url = "https://example.com/oauth2/tokeninfo/opaque"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy.redact_url(&url, Some(&token)),
+            "https://example.com/oauth2/tokeninfo/<redacted>"
+        );
+
+        let url: Url = "https://user:pw@example.com:8443/info?access_token=abc&realm=services#x"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy.redact_url(&url, None),
+            "https://<redacted>@example.com:8443/info?access_token=<redacted>&realm=services"
+        );
+
+        let url: Url = "https://example.com/keys/eyJhbGciOiJSUzI1NiJ9.e30.c2ln"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy.redact_url(&url, None),
+            "https://example.com/keys/<redacted>"
+        );
+    }
+
+    #[test]
+    fn the_policy_can_be_customized() {
+        let policy = RedactionPolicy::default()
+            .with_secret_name("Realm")
+            .with_min_secret_length(usize::MAX)
+            .with_replacement("***");
+
+        assert_eq!(policy.redact("x-realm", "services"), "***");
+        assert_eq!(
+            policy.redact("id", "eyJhbGciOiJSUzI1NiJ9.e30.c2ln"),
+            "eyJhbGciOiJSUzI1NiJ9.e30.c2ln"
+        );
+    }
+
+    #[test]
+    fn only_claim_names_are_extracted() {
+        let body = br#"{"uid": "user", "scope": ["read"], "access_token": "opaque"}"#;
+        assert_eq!(claim_names(body), vec!["uid", "scope", "access_token"]);
+        assert!(claim_names(b"not json").is_empty());
+    }
+}
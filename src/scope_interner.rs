@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::{InvalidScope, Scope};
+
+/// The number of distinct scopes kept by the global `ScopeInterner`.
+pub const DEFAULT_INTERNER_CAPACITY: usize = 1024;
+
+/// Shares the string of equal `Scope`s.
+///
+/// Interned scopes are cheap to clone into many cached `TokenInfo`s and
+/// are compared by pointer. The scopes in introspection responses are
+/// chosen by the authorization server, so only up to `capacity` distinct
+/// scopes are kept. Further scopes are created without being interned.
+///
+/// Known scopes are looked up under a shared lock so that parsers on
+/// different threads do not wait for each other. Once the interner is
+/// full, unknown scopes are created without taking the lock at all.
+#[derive(Debug)]
+pub struct ScopeInterner {
+    scopes: RwLock<HashSet<Arc<str>>>,
+    capacity: usize,
+    is_full: AtomicBool,
+}
+
+impl ScopeInterner {
+    /// Creates an interner keeping up to `capacity` distinct scopes.
+    pub fn new(capacity: usize) -> ScopeInterner {
+        ScopeInterner {
+            scopes: RwLock::new(HashSet::new()),
+            capacity,
+            is_full: AtomicBool::new(capacity == 0),
+        }
+    }
+
+    /// The interner used by the parsers with a capacity of
+    /// `DEFAULT_INTERNER_CAPACITY`.
+    pub fn global() -> &'static ScopeInterner {
+        static GLOBAL: OnceLock<ScopeInterner> = OnceLock::new();
+        GLOBAL.get_or_init(|| ScopeInterner::new(DEFAULT_INTERNER_CAPACITY))
+    }
+
+    /// Returns the interned `Scope` equal to `scope`.
+    pub fn intern(&self, scope: &str) -> Scope {
+        if let Some(interned) = self.scopes.read().unwrap().get(scope) {
+            return Scope(interned.clone());
+        }
+        if self.is_full.load(Ordering::Relaxed) {
+            return Scope(Arc::from(scope));
+        }
+
+        let mut scopes = self.scopes.write().unwrap();
+        if let Some(interned) = scopes.get(scope) {
+            return Scope(interned.clone());
+        }
+        let interned: Arc<str> = Arc::from(scope);
+        if scopes.len() < self.capacity {
+            scopes.insert(interned.clone());
+        }
+        if scopes.len() >= self.capacity {
+            self.is_full.store(true, Ordering::Relaxed);
+        }
+        Scope(interned)
+    }
+
+    /// Like `Scope::parse` but returns an interned `Scope`.
+    pub fn parse(&self, scope: &str) -> Result<Scope, InvalidScope> {
+        Scope::validate(scope)?;
+        Ok(self.intern(scope))
+    }
+
+    /// The number of distinct scopes kept.
+    pub fn len(&self) -> usize {
+        self.scopes.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ScopeInterner {
+    fn default() -> ScopeInterner {
+        ScopeInterner::new(DEFAULT_INTERNER_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn equal_scopes_share_their_string() {
+        let interner = ScopeInterner::new(2);
+        let a = interner.intern("read");
+        let b = interner.intern("read");
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, Scope::new("read"));
+
+        interner.intern("write");
+        let c = interner.intern("admin");
+        let d = interner.intern("admin");
+        assert!(!Arc::ptr_eq(&c.0, &d.0));
+        assert_eq!(c, d);
+        assert_eq!(interner.len(), 2);
+        assert!(interner.is_full.load(Ordering::Relaxed));
+        assert!(Arc::ptr_eq(&interner.intern("read").0, &a.0));
+
+        assert!(interner.parse("a b").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{NotAuthorized, Scope, TokenInfo};
+
+/// Decides whether a `TokenInfo` has a required `Scope`.
+///
+/// By default `Scope`s must be equal like with `TokenInfo::has_scope`.
+/// Some authorization servers normalize the casing of scopes differently
+/// than the values configured in the resource server. A matcher can be
+/// told to ignore the case and surrounding whitespace.
+///
+/// ```rust
+/// use tokkit::{Scope, ScopeMatcher, TokenInfo};
+///
+/// let token_info = TokenInfo::new(true).with_scopes(vec![Scope::new("Orders.Read")]);
+/// let matcher = ScopeMatcher::exact().case_insensitive(true);
+///
+/// assert!(!token_info.has_scope(&Scope::new("orders.read")));
+/// assert!(matcher.has_scope(&token_info, &Scope::new("orders.read")));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScopeMatcher {
+    case_insensitive: bool,
+    trim_whitespace: bool,
+}
+
+impl ScopeMatcher {
+    /// A matcher which requires the `Scope`s to be equal.
+    pub fn exact() -> ScopeMatcher {
+        ScopeMatcher::default()
+    }
+
+    /// Compares `Scope`s case insensitively.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Ignores whitespace surrounding the `Scope`s.
+    pub fn trim_whitespace(mut self, trim_whitespace: bool) -> Self {
+        self.trim_whitespace = trim_whitespace;
+        self
+    }
+
+    /// Checks whether the `granted` `Scope` satisfies the `required` one.
+    pub fn matches(&self, granted: &Scope, required: &Scope) -> bool {
+        let (mut granted, mut required) = (granted.as_str(), required.as_str());
+        if self.trim_whitespace {
+            granted = granted.trim();
+            required = required.trim();
+        }
+        if self.case_insensitive {
+            granted
+                .chars()
+                .flat_map(char::to_lowercase)
+                .eq(required.chars().flat_map(char::to_lowercase))
+        } else {
+            granted == required
+        }
+    }
+
+    /// Checks whether the `TokenInfo` has the given `Scope`.
+    pub fn has_scope(&self, token_info: &TokenInfo, scope: &Scope) -> bool {
+        token_info
+            .scope
+            .iter()
+            .any(|granted| self.matches(granted, scope))
+    }
+
+    /// Checks whether the `TokenInfo` has all of the given `Scope`s.
+    pub fn has_scopes(&self, token_info: &TokenInfo, scopes: &[Scope]) -> bool {
+        scopes.iter().all(|scope| self.has_scope(token_info, scope))
+    }
+
+    /// The required `Scope`s the `TokenInfo` does not have.
+    pub fn missing_scopes(&self, token_info: &TokenInfo, required: &[Scope]) -> Vec<Scope> {
+        required
+            .iter()
+            .filter(|scope| !self.has_scope(token_info, scope))
+            .cloned()
+            .collect()
+    }
+
+    /// Fails if the `TokenInfo` does not have the given `Scope`.
+    pub fn must_have_scope(
+        &self,
+        token_info: &TokenInfo,
+        scope: &Scope,
+    ) -> ::std::result::Result<(), NotAuthorized> {
+        if self.has_scope(token_info, scope) {
+            Ok(())
+        } else {
+            Err(NotAuthorized(format!(
+                "Required scope '{}' not present.",
+                scope
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scopes_can_be_matched_ignoring_case_and_whitespace() {
+        let token_info = TokenInfo::new(true).with_scopes(vec![Scope::new("Orders.READ ")]);
+        let required = Scope::new("orders.read");
+
+        assert!(!ScopeMatcher::exact().has_scope(&token_info, &required));
+        assert!(!ScopeMatcher::exact()
+            .case_insensitive(true)
+            .has_scope(&token_info, &required));
+        let lenient = ScopeMatcher::exact()
+            .case_insensitive(true)
+            .trim_whitespace(true);
+        assert!(lenient.has_scope(&token_info, &required));
+        assert!(lenient.must_have_scope(&token_info, &required).is_ok());
+        assert!(lenient
+            .must_have_scope(&token_info, &Scope::new("orders.write"))
+            .is_err());
+    }
+}
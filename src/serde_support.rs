@@ -0,0 +1,204 @@
+//! `Serialize` and `Deserialize` for the types of an introspection result.
+//!
+//! A `TokenInfo` is serialized like an introspection response as defined
+//! in [RFC 7662](https://tools.ietf.org/html/rfc7662#section-2.2): The
+//! scopes become a single space-separated string and absent values are
+//! skipped. When deserializing, the scopes may also be given as a sequence.
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::{Scope, TokenInfo, UserId};
+
+const FIELDS: &[&str] = &[
+    "active",
+    "user_id",
+    "scope",
+    "expires_in_seconds",
+    "certificate_thumbprint",
+    "from_stale_cache",
+];
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Scope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Scope::new)
+    }
+}
+
+impl Serialize for UserId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(UserId)
+    }
+}
+
+impl Serialize for TokenInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TokenInfo", FIELDS.len())?;
+        state.serialize_field("active", &self.active)?;
+        match self.user_id {
+            Some(ref user_id) => state.serialize_field("user_id", user_id)?,
+            None => state.skip_field("user_id")?,
+        }
+        let scope: Vec<&str> = self.scope.iter().map(|scope| scope.as_str()).collect();
+        state.serialize_field("scope", &scope.join(" "))?;
+        match self.expires_in_seconds {
+            Some(expires_in_seconds) => {
+                state.serialize_field("expires_in_seconds", &expires_in_seconds)?
+            }
+            None => state.skip_field("expires_in_seconds")?,
+        }
+        match self.certificate_thumbprint {
+            Some(ref thumbprint) => state.serialize_field("certificate_thumbprint", thumbprint)?,
+            None => state.skip_field("certificate_thumbprint")?,
+        }
+        if self.from_stale_cache {
+            state.serialize_field("from_stale_cache", &true)?;
+        } else {
+            state.skip_field("from_stale_cache")?;
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("TokenInfo", FIELDS, TokenInfoVisitor)
+    }
+}
+
+struct TokenInfoVisitor;
+
+impl<'de> Visitor<'de> for TokenInfoVisitor {
+    type Value = TokenInfo;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a token info")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TokenInfo, A::Error> {
+        let mut active = None;
+        let mut user_id = None;
+        let mut scope = None;
+        let mut expires_in_seconds = None;
+        let mut certificate_thumbprint = None;
+        let mut from_stale_cache = false;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "active" => active = Some(map.next_value()?),
+                "user_id" => user_id = map.next_value()?,
+                "scope" => scope = Some(map.next_value::<Scopes>()?.0),
+                "expires_in_seconds" => expires_in_seconds = map.next_value()?,
+                "certificate_thumbprint" => certificate_thumbprint = map.next_value()?,
+                "from_stale_cache" => from_stale_cache = map.next_value()?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(TokenInfo {
+            active: active.ok_or_else(|| de::Error::missing_field("active"))?,
+            user_id,
+            scope: scope.unwrap_or_default(),
+            expires_in_seconds,
+            certificate_thumbprint,
+            from_stale_cache,
+            extra: Default::default(),
+        })
+    }
+}
+
+/// Scopes given as a space-separated string or as a sequence.
+struct Scopes(Vec<Scope>);
+
+impl<'de> Deserialize<'de> for Scopes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ScopesVisitor)
+    }
+}
+
+struct ScopesVisitor;
+
+impl<'de> Visitor<'de> for ScopesVisitor {
+    type Value = Scopes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a space-separated string or a sequence of scopes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Scopes, E> {
+        Ok(Scopes(value.split_whitespace().map(Scope::new).collect()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Scopes, A::Error> {
+        let mut scopes = Vec::new();
+        while let Some(scope) = seq.next_element()? {
+            scopes.push(scope);
+        }
+        Ok(Scopes(scopes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::de::value::{Error, SeqDeserializer, StrDeserializer};
+    use serde::de::IntoDeserializer;
+
+    use super::*;
+
+    fn token_info() -> TokenInfo {
+        TokenInfo {
+            active: true,
+            user_id: Some(UserId::new("user")),
+            scope: vec![Scope::new("read"), Scope::new("write")],
+            expires_in_seconds: Some(3600),
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn token_info_is_serialized_like_an_introspection_response() {
+        let serialized = serde_urlencoded::to_string(token_info()).unwrap();
+        assert_eq!(
+            serialized,
+            "active=true&user_id=user&scope=read+write&expires_in_seconds=3600"
+        );
+        let deserialized: TokenInfo = serde_urlencoded::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, token_info());
+    }
+
+    #[test]
+    fn missing_optional_fields_are_defaulted() {
+        let deserialized: TokenInfo = serde_urlencoded::from_str("active=false&x=1").unwrap();
+        assert!(!deserialized.active);
+        assert!(deserialized.user_id.is_none());
+        assert!(deserialized.scope.is_empty());
+
+        assert!(serde_urlencoded::from_str::<TokenInfo>("scope=read").is_err());
+    }
+
+    #[test]
+    fn scopes_can_be_a_sequence() {
+        let seq: SeqDeserializer<_, Error> = vec!["read", "write"].into_deserializer();
+        assert_eq!(Scopes::deserialize(seq).unwrap().0, token_info().scope);
+
+        let single: StrDeserializer<Error> = "read".into_deserializer();
+        assert_eq!(Scope::deserialize(single).unwrap(), Scope::new("read"));
+    }
+}
@@ -0,0 +1,98 @@
+//! SHA-256 and HMAC-SHA256 backed by OpenSSL.
+//!
+//! Used to derive the keys of cached entries with HMAC-SHA256 so that
+//! tokens never have to be stored in caches, to authenticate offline
+//! snapshots and to sign RDS auth tokens.
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Computes the SHA-256 digest of `data`.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    openssl::sha::sha256(data)
+}
+
+/// Encodes `bytes` as lower case hex.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes upper or lower case hex. Returns `None` if `hex` is not hex.
+pub(crate) fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let digit = |b: u8| char::from(b).to_digit(16);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| Some((digit(pair[0])? << 4 | digit(pair[1])?) as u8))
+        .collect()
+}
+
+/// Computes the HMAC-SHA256 of `data` as specified in
+/// [RFC 2104](https://tools.ietf.org/html/rfc2104).
+///
+/// Panics if OpenSSL fails, which only happens if it can not allocate.
+pub(crate) fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mac = PKey::hmac(key)
+        .and_then(|key| {
+            let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+            signer.update(data)?;
+            signer.sign_to_vec()
+        })
+        .expect("OpenSSL failed to compute an HMAC-SHA256");
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&mac);
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fips_180_examples() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn hex_is_decoded_regardless_of_case() {
+        assert_eq!(unhex("00ff7F"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(unhex(&hex(&sha256(b"abc"))), Some(sha256(b"abc").to_vec()));
+        assert_eq!(unhex(""), Some(Vec::new()));
+        assert_eq!(unhex("abc"), None);
+        assert_eq!(unhex("+f"), None);
+        assert_eq!(unhex("zz"), None);
+    }
+
+    #[test]
+    fn rfc_4231_hmac_examples() {
+        let hex = |digest: [u8; 32]| -> String {
+            digest.iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        assert_eq!(
+            hex(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Coalesces identical introspection requests that are in flight at the
+//! same time.
+//!
+//! The first caller for a key becomes the leader and does the actual
+//! request. Callers arriving while the leader's request is in flight
+//! wait for and share its result. Once the request finished the key is
+//! free again so nothing is cached beyond the duration of the request.
+//!
+//! If the leader goes away without a result the waiting callers do the
+//! request on their own.
+//!
+//! The requests are keyed by the `TokenKey` of the token so that the
+//! tokens are not kept in plain text.
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::cache::{TokenHasher, TokenKey};
+use crate::{AccessToken, TokenInfo, TokenInfoErrorKind, TokenInfoResult};
+
+/// The result shared with the waiting callers.
+///
+/// `TokenInfoError` can not be cloned so waiting callers get a
+/// new error of the same kind.
+type Outcome = Result<TokenInfo, TokenInfoErrorKind>;
+
+fn to_outcome(result: &TokenInfoResult<TokenInfo>) -> Outcome {
+    match result {
+        Ok(token_info) => Ok(token_info.clone()),
+        Err(err) => Err(err.kind().clone()),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Blocking request coalescing where waiting callers park on a `Condvar`.
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    hasher: TokenHasher,
+    in_flight: Mutex<HashMap<TokenKey, Arc<Flight>>>,
+}
+
+#[derive(Default)]
+struct Flight {
+    /// `None` while in flight, `Some(None)` if the leader gave up.
+    outcome: Mutex<Option<Option<Outcome>>>,
+    done: Condvar,
+}
+
+impl SingleFlight {
+    pub fn run<F>(&self, token: &AccessToken, f: F) -> TokenInfoResult<TokenInfo>
+    where
+        F: FnOnce() -> TokenInfoResult<TokenInfo>,
+    {
+        let key = self.hasher.key(token);
+        let (flight, is_leader) = {
+            let mut in_flight = lock(&self.in_flight);
+            match in_flight.get(&key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    in_flight.insert(key.clone(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if is_leader {
+            let mut leader = Leader {
+                single_flight: self,
+                key,
+                flight,
+                outcome: None,
+            };
+            let result = f();
+            leader.outcome = Some(to_outcome(&result));
+            result
+        } else {
+            let mut outcome = lock(&flight.outcome);
+            while outcome.is_none() {
+                outcome = flight
+                    .done
+                    .wait(outcome)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            match outcome.clone() {
+                Some(Some(outcome)) => outcome.map_err(Into::into),
+                _ => {
+                    drop(outcome);
+                    f()
+                }
+            }
+        }
+    }
+}
+
+/// Publishes the outcome of the leader and frees the key even if the
+/// leader panics.
+struct Leader<'a> {
+    single_flight: &'a SingleFlight,
+    key: TokenKey,
+    flight: Arc<Flight>,
+    outcome: Option<Outcome>,
+}
+
+impl<'a> Drop for Leader<'a> {
+    fn drop(&mut self) {
+        lock(&self.single_flight.in_flight).remove(&self.key);
+        *lock(&self.flight.outcome) = Some(self.outcome.take());
+        self.flight.done.notify_all();
+    }
+}
+
+#[cfg(feature = "async")]
+pub(crate) use self::non_blocking::AsyncSingleFlight;
+
+#[cfg(feature = "async")]
+mod non_blocking {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use futures::channel::oneshot;
+    use futures::future::{BoxFuture, FutureExt, Shared};
+
+    use super::{lock, to_outcome, Outcome};
+    use crate::cache::{TokenHasher, TokenKey};
+    use crate::{AccessToken, TokenInfo, TokenInfoError};
+
+    /// Requests with and without retries are not shared with each other.
+    type FlightKey = (bool, TokenKey);
+
+    /// Non blocking request coalescing where waiting callers share a
+    /// future resolving to the leader's result.
+    #[derive(Default)]
+    pub(crate) struct AsyncSingleFlight {
+        hasher: TokenHasher,
+        in_flight: Mutex<HashMap<FlightKey, Shared<oneshot::Receiver<Outcome>>>>,
+    }
+
+    impl AsyncSingleFlight {
+        pub fn run<'a, F>(
+            &'a self,
+            token: &AccessToken,
+            with_retries: bool,
+            f: F,
+        ) -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>>
+        where
+            F: FnOnce() -> BoxFuture<'a, Result<TokenInfo, TokenInfoError>> + Send + 'a,
+        {
+            let key = (with_retries, self.hasher.key(token));
+            let mut in_flight = lock(&self.in_flight);
+            if let Some(shared) = in_flight.get(&key) {
+                let shared = shared.clone();
+                drop(in_flight);
+                return async move {
+                    match shared.await {
+                        Ok(outcome) => outcome.map_err(Into::into),
+                        Err(oneshot::Canceled) => f().await,
+                    }
+                }
+                .boxed();
+            }
+
+            let (tx, rx) = oneshot::channel();
+            in_flight.insert(key.clone(), rx.shared());
+            drop(in_flight);
+
+            let leader = Leader {
+                single_flight: self,
+                key,
+                tx: Some(tx),
+            };
+            let request = f();
+            async move {
+                let mut leader = leader;
+                let result = request.await;
+                leader.complete(to_outcome(&result));
+                result
+            }
+            .boxed()
+        }
+    }
+
+    /// Frees the key once the leader is done or dropped. Dropping the
+    /// sender without a result lets the waiting callers do the request.
+    struct Leader<'a> {
+        single_flight: &'a AsyncSingleFlight,
+        key: FlightKey,
+        tx: Option<oneshot::Sender<Outcome>>,
+    }
+
+    impl<'a> Leader<'a> {
+        fn complete(&mut self, outcome: Outcome) {
+            lock(&self.single_flight.in_flight).remove(&self.key);
+            if let Some(tx) = self.tx.take() {
+                let _ = tx.send(outcome);
+            }
+        }
+    }
+
+    impl<'a> Drop for Leader<'a> {
+        fn drop(&mut self) {
+            if self.tx.is_some() {
+                lock(&self.single_flight.in_flight).remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn token_info() -> TokenInfo {
+        TokenInfo {
+            active: true,
+            user_id: None,
+            scope: Vec::new(),
+            expires_in_seconds: None,
+            certificate_thumbprint: None,
+            from_stale_cache: false,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn concurrent_calls_are_coalesced() {
+        let single_flight = Arc::new(SingleFlight::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let single_flight = single_flight.clone();
+                let calls = calls.clone();
+                thread::spawn(move || {
+                    single_flight.run(&AccessToken::new("token"), || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        Ok(token_info())
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().unwrap(), token_info());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(lock(&single_flight.in_flight).is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn concurrent_futures_are_coalesced() {
+        use futures::channel::oneshot;
+        use futures::future::{self, FutureExt};
+
+        let single_flight = AsyncSingleFlight::default();
+        let (release, released) = oneshot::channel::<()>();
+
+        let token = AccessToken::new("token");
+        let leader = single_flight.run(&token, true, || {
+            async move {
+                released.await.unwrap();
+                Ok(token_info())
+            }
+            .boxed()
+        });
+        let follower = single_flight.run(&token, true, || {
+            panic!("The follower must not do a request");
+        });
+        let release = async move {
+            release.send(()).unwrap();
+        };
+
+        let (leader, follower, ()) =
+            futures::executor::block_on(future::join3(leader, follower, release));
+        assert_eq!(leader.unwrap(), token_info());
+        assert_eq!(follower.unwrap(), token_info());
+    }
+}
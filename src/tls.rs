@@ -0,0 +1,196 @@
+use native_tls::{Certificate, Protocol, TlsConnector};
+use reqwest::blocking;
+
+use crate::redirect::RedirectPolicy;
+use crate::{InitializationError, InitializationResult};
+
+/// Hardens the TLS connections to introspection and token endpoints.
+///
+/// The default trusts the root certificates of the system, accepts every
+/// TLS version the system accepts and connects via the proxies configured
+/// in the environment like any other HTTP client.
+///
+/// Token endpoints receive credentials and introspection endpoints receive
+/// the tokens of all callers. A `TlsPolicy` can therefore
+///
+/// * require TLS 1.2 or newer
+/// * pin the certificates of the authorization server so that only
+///   connections to servers presenting a chain to one of them succeed
+/// * ignore the proxies configured in the environment, e.g. `HTTPS_PROXY`,
+///   which could otherwise see all requests
+///
+/// ```rust,no_run
+/// use tokkit::client::TokenInfoServiceClientBuilder;
+/// use tokkit::TlsPolicy;
+///
+/// let ca = std::fs::read("/etc/auth/ca.pem").unwrap();
+/// let tls_policy = TlsPolicy::strict().with_pinned_certificate_pem(&ca).unwrap();
+///
+/// let mut builder = TokenInfoServiceClientBuilder::google_v3();
+/// builder.with_tls_policy(tls_policy);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsPolicy {
+    require_tls_1_2: bool,
+    pinned_certificates: Vec<Vec<u8>>,
+    use_system_proxy: bool,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        TlsPolicy {
+            require_tls_1_2: false,
+            pinned_certificates: Vec::new(),
+            use_system_proxy: true,
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// Requires TLS 1.2 or newer and ignores the proxies configured in the
+    /// environment.
+    pub fn strict() -> TlsPolicy {
+        TlsPolicy::default()
+            .require_tls_1_2()
+            .without_system_proxy()
+    }
+
+    /// Refuses connections with a TLS version older than 1.2.
+    pub fn require_tls_1_2(mut self) -> Self {
+        self.require_tls_1_2 = true;
+        self
+    }
+
+    /// Ignores the proxies configured in the environment and always
+    /// connects to the endpoints directly.
+    pub fn without_system_proxy(mut self) -> Self {
+        self.use_system_proxy = false;
+        self
+    }
+
+    /// Pins a PEM encoded certificate.
+    ///
+    /// Once a certificate is pinned the root certificates of the system are
+    /// no longer trusted. Only servers presenting a chain to one of the
+    /// pinned certificates are accepted, so pin the CA of the authorization
+    /// server or its self-signed certificate.
+    ///
+    /// Fails if `pem` is not a certificate.
+    pub fn with_pinned_certificate_pem(self, pem: &[u8]) -> InitializationResult<Self> {
+        let certificate = Certificate::from_pem(pem).map_err(|err| {
+            InitializationError::Other(format!("Invalid pinned certificate: {}", err))
+        })?;
+        self.with_pinned_certificate(certificate)
+    }
+
+    /// Pins a DER encoded certificate like `with_pinned_certificate_pem`.
+    ///
+    /// Fails if `der` is not a certificate.
+    pub fn with_pinned_certificate_der(self, der: &[u8]) -> InitializationResult<Self> {
+        let certificate = Certificate::from_der(der).map_err(|err| {
+            InitializationError::Other(format!("Invalid pinned certificate: {}", err))
+        })?;
+        self.with_pinned_certificate(certificate)
+    }
+
+    fn with_pinned_certificate(mut self, certificate: Certificate) -> InitializationResult<Self> {
+        let der = certificate.to_der().map_err(|err| {
+            InitializationError::Other(format!("Invalid pinned certificate: {}", err))
+        })?;
+        self.pinned_certificates.push(der);
+        Ok(self)
+    }
+
+    /// Applies the policy to a `ClientBuilder` of an async client, e.g. in
+    /// a configurator set with
+    /// `AsyncTokenInfoServiceClientLight::with_http_client_configurator`.
+    pub fn configure(
+        &self,
+        builder: reqwest::ClientBuilder,
+    ) -> InitializationResult<reqwest::ClientBuilder> {
+        let builder = if self.use_system_proxy {
+            builder
+        } else {
+            builder.no_proxy()
+        };
+        Ok(match self.connector()? {
+            Some(connector) => builder.use_preconfigured_tls(connector),
+            None => builder,
+        })
+    }
+
+    /// Applies the policy to a `ClientBuilder` of a blocking client.
+    pub fn configure_blocking(
+        &self,
+        builder: blocking::ClientBuilder,
+    ) -> InitializationResult<blocking::ClientBuilder> {
+        let builder = if self.use_system_proxy {
+            builder
+        } else {
+            builder.no_proxy()
+        };
+        Ok(match self.connector()? {
+            Some(connector) => builder.use_preconfigured_tls(connector),
+            None => builder,
+        })
+    }
+
+    /// The connector enforcing the policy or `None` if the defaults of the
+    /// HTTP client already do.
+    fn connector(&self) -> InitializationResult<Option<TlsConnector>> {
+        if !self.require_tls_1_2 && self.pinned_certificates.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = TlsConnector::builder();
+        if self.require_tls_1_2 {
+            builder.min_protocol_version(Some(Protocol::Tlsv12));
+        }
+        if !self.pinned_certificates.is_empty() {
+            builder.disable_built_in_roots(true);
+            for der in &self.pinned_certificates {
+                let certificate = Certificate::from_der(der).map_err(|err| {
+                    InitializationError::Other(format!("Invalid pinned certificate: {}", err))
+                })?;
+                builder.add_root_certificate(certificate);
+            }
+        }
+        builder
+            .build()
+            .map(Some)
+            .map_err(|err| InitializationError::Other(format!("Invalid TLS policy: {}", err)))
+    }
+}
+
+/// Creates a blocking `Client` following both policies.
+pub(crate) fn blocking_client(
+    redirect_policy: &RedirectPolicy,
+    tls_policy: &TlsPolicy,
+) -> InitializationResult<blocking::Client> {
+    let builder = blocking::Client::builder().redirect(redirect_policy.to_reqwest_policy());
+    tls_policy
+        .configure_blocking(builder)?
+        .build()
+        .map_err(|err| InitializationError::Other(err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_certificates_are_rejected() {
+        assert!(TlsPolicy::strict()
+            .with_pinned_certificate_pem(b"-----BEGIN CERTIFICATE-----\nnope\n")
+            .is_err());
+        assert!(TlsPolicy::default()
+            .with_pinned_certificate_der(b"nope")
+            .is_err());
+    }
+
+    #[test]
+    fn strict_policies_create_clients() {
+        let policy = TlsPolicy::strict();
+        assert!(!policy.use_system_proxy);
+        assert!(blocking_client(&RedirectPolicy::default(), &policy).is_ok());
+    }
+}
@@ -1,7 +1,11 @@
+use std::error::Error as StdError;
 use std::fmt;
+use std::time::{Duration, Instant};
 
 use failure::*;
 
+use super::token_provider::AccessTokenProviderError;
+
 pub type TokenResult<T> = ::std::result::Result<T, TokenError>;
 
 #[derive(Debug)]
@@ -13,6 +17,17 @@ impl TokenError {
     pub fn kind(&self) -> &TokenErrorKind {
         &self.inner.get_context()
     }
+
+    /// Returns `true` if asking for the token again later may succeed.
+    pub fn is_retry_suggested(&self) -> bool {
+        match *self.kind() {
+            TokenErrorKind::NoToken(_) => false,
+            TokenErrorKind::NotInitialized(_) => true,
+            TokenErrorKind::AccessTokenProvider(ref err) => err.is_retry_suggested(),
+            TokenErrorKind::RefreshNotCompleted(_) => true,
+            TokenErrorKind::Tenant(_) => false,
+        }
+    }
 }
 
 impl Fail for TokenError {
@@ -45,14 +60,185 @@ impl fmt::Display for TokenError {
     }
 }
 
+/// The identifier of a managed token in a `TokenError`.
+///
+/// It is created from the `Debug` representation of the identifier so that
+/// identifiers of any type can be reported.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TokenIdRepr(String);
+
+impl TokenIdRepr {
+    pub fn of<T: fmt::Debug + ?Sized>(token_id: &T) -> TokenIdRepr {
+        TokenIdRepr(format!("{:?}", token_id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TokenIdRepr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// There is no managed token with the given identifier.
+///
+/// Retrying does not help since the managed tokens are fixed when the
+/// `AccessTokenManager` is started.
+#[derive(Debug, Clone, PartialEq, Eq, Fail)]
+#[fail(display = "There is no managed token {}", token_id)]
+pub struct TokenLookupError {
+    pub token_id: TokenIdRepr,
+}
+
+/// The token has not been fetched for the first time yet.
+///
+/// The token may be available if asked for again later.
+#[derive(Debug, Clone, Fail)]
+pub struct TokenPendingError {
+    pub token_id: TokenIdRepr,
+    since: Instant,
+}
+
+impl TokenPendingError {
+    /// Creates an error for a token waited for since `since`.
+    pub fn new(token_id: TokenIdRepr, since: Instant) -> TokenPendingError {
+        TokenPendingError { token_id, since }
+    }
+
+    /// The time passed since the `AccessTokenManager` started to
+    /// initialize the token.
+    pub fn elapsed(&self) -> Duration {
+        self.since.elapsed()
+    }
+}
+
+impl fmt::Display for TokenPendingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The managed token {} is not yet initialized after {:.3} seconds",
+            self.token_id,
+            self.elapsed().as_secs_f64()
+        )
+    }
+}
+
+/// The `AccessTokenProvider` could not fetch the token.
+///
+/// The messages of the causes of the `AccessTokenProviderError` are kept
+/// since the error is cloned for every caller asking for the token.
+#[derive(Debug, Clone, Fail)]
+pub struct TokenRefreshError {
+    message: String,
+    causes: Vec<String>,
+    retry_suggested: bool,
+}
+
+impl TokenRefreshError {
+    /// Creates an error without causes for which no retry is suggested.
+    pub fn new<T: Into<String>>(message: T) -> TokenRefreshError {
+        TokenRefreshError {
+            message: message.into(),
+            causes: Vec::new(),
+            retry_suggested: false,
+        }
+    }
+
+    /// Creates an error keeping the messages of the chain of causes of
+    /// `err`.
+    pub fn from_provider_error(err: &AccessTokenProviderError) -> TokenRefreshError {
+        let mut causes = Vec::new();
+        let mut cause = err.source();
+        while let Some(inner) = cause {
+            causes.push(inner.to_string());
+            cause = inner.source();
+        }
+        TokenRefreshError {
+            message: err.to_string(),
+            causes,
+            retry_suggested: err.is_retry_suggested(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The messages of the causes starting with the direct cause.
+    pub fn causes(&self) -> &[String] {
+        &self.causes
+    }
+
+    /// Returns `true` if the `AccessTokenProvider` failed with an error
+    /// which usually goes away, e.g. a connection error.
+    pub fn is_retry_suggested(&self) -> bool {
+        self.retry_suggested
+    }
+}
+
+impl fmt::Display for TokenRefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 #[derive(Debug, Clone, Fail)]
 pub enum TokenErrorKind {
+    /// There is no managed token with the given identifier
     #[fail(display = "{}", _0)]
-    NoToken(String),
+    NoToken(TokenLookupError),
     /// The token with the given identifier is not yet initialized
     #[fail(display = "{}", _0)]
-    NotInitialized(String),
+    NotInitialized(TokenPendingError),
     /// An error from the `AccessTokenProvider`
     #[fail(display = "{}", _0)]
-    AccessTokenProvider(String),
+    AccessTokenProvider(TokenRefreshError),
+    /// A refresh that was waited for did not complete in time or the
+    /// `AccessTokenManager` stopped
+    #[fail(display = "{}", _0)]
+    RefreshNotCompleted(String),
+    /// The tokens of a tenant of a `TenantTokenManager` could not be set up
+    #[fail(display = "{}", _0)]
+    Tenant(String),
+}
+
+impl TokenErrorKind {
+    pub fn no_token<T: fmt::Debug + ?Sized>(token_id: &T) -> TokenErrorKind {
+        TokenErrorKind::NoToken(TokenLookupError {
+            token_id: TokenIdRepr::of(token_id),
+        })
+    }
+
+    pub fn not_initialized<T: fmt::Debug + ?Sized>(token_id: &T, since: Instant) -> TokenErrorKind {
+        TokenErrorKind::NotInitialized(TokenPendingError::new(TokenIdRepr::of(token_id), since))
+    }
+}
+
+impl From<AccessTokenProviderError> for TokenErrorKind {
+    fn from(err: AccessTokenProviderError) -> TokenErrorKind {
+        TokenErrorKind::AccessTokenProvider(TokenRefreshError::from_provider_error(&err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token_manager::token_provider::credentials::CredentialsError;
+
+    #[test]
+    fn errors_tell_whether_to_retry() {
+        let err = AccessTokenProviderError::Credentials(CredentialsError::Io("gone".to_string()));
+        let refresh_err = TokenRefreshError::from_provider_error(&err);
+        assert_eq!(refresh_err.causes(), &["Io error: gone".to_string()]);
+        assert!(TokenError::from(err).is_retry_suggested());
+
+        let err = AccessTokenProviderError::Unauthorized("invalid client".to_string());
+        assert!(!TokenError::from(err).is_retry_suggested());
+        assert!(!TokenError::from(TokenErrorKind::no_token("token")).is_retry_suggested());
+        let pending = TokenError::from(TokenErrorKind::not_initialized("token", Instant::now()));
+        assert!(pending.is_retry_suggested());
+    }
 }
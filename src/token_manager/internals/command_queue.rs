@@ -0,0 +1,333 @@
+//! The bounded queue of `ManagerCommand`s for the updater.
+//!
+//! If the updater falls behind, e.g. because an `AccessTokenProvider` is
+//! slow, the oldest command is dropped once the queue is full instead of
+//! letting the commands pile up. At most one refresh per token is in the
+//! queue or being executed at any time.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{RecvError, SendError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::*;
+
+/// Creates a queue which holds at most `capacity` commands.
+///
+/// Refreshes are tracked as in flight in `in_flight` from the time they
+/// are sent until the updater completed them.
+pub fn channel<T>(
+    capacity: usize,
+    in_flight: Arc<InFlightRefreshes>,
+) -> (CommandSender<T>, CommandReceiver<T>) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(QueueState {
+            commands: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        available: Condvar::new(),
+        capacity,
+        dropped: AtomicU64::new(0),
+        in_flight,
+    });
+    (
+        CommandSender {
+            queue: queue.clone(),
+        },
+        CommandReceiver { queue },
+    )
+}
+
+/// Tracks the rows for which a refresh is queued or being executed.
+pub struct InFlightRefreshes {
+    in_flight: Vec<AtomicBool>,
+    /// Set if a scheduled refresh of the row was dropped from the queue.
+    dropped: Vec<AtomicBool>,
+}
+
+impl InFlightRefreshes {
+    pub fn new(rows: usize) -> InFlightRefreshes {
+        InFlightRefreshes {
+            in_flight: (0..rows).map(|_| AtomicBool::new(false)).collect(),
+            dropped: (0..rows).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// Marks a refresh of the row as in flight. Returns `false` if there
+    /// already is one.
+    pub fn begin(&self, idx: usize) -> bool {
+        !self.in_flight[idx].swap(true, Ordering::SeqCst)
+    }
+
+    /// Marks the refresh of the row as executed.
+    pub fn complete(&self, idx: usize) {
+        self.in_flight[idx].store(false, Ordering::SeqCst);
+    }
+
+    /// Returns `true` once after a scheduled refresh of the row was
+    /// dropped from the queue.
+    pub fn take_dropped(&self, idx: usize) -> bool {
+        self.dropped[idx].swap(false, Ordering::SeqCst)
+    }
+
+    fn drop_refresh(&self, idx: usize, scheduled: bool) {
+        if scheduled {
+            self.dropped[idx].store(true, Ordering::SeqCst);
+        }
+        self.complete(idx);
+    }
+}
+
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    available: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+    in_flight: Arc<InFlightRefreshes>,
+}
+
+struct QueueState<T> {
+    /// The commands with the row of the refresh if it is tracked.
+    commands: VecDeque<(Option<usize>, ManagerCommand<T>)>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+impl<T: Debug> Queue<T> {
+    fn drop_command(&self, idx: Option<usize>, command: ManagerCommand<T>) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "The command queue is full. Dropped the oldest command {:?}.",
+            command
+        );
+        let scheduled = match command {
+            ManagerCommand::ScheduledRefresh(..) | ManagerCommand::RefreshOnError(..) => true,
+            ManagerCommand::ForceRefresh(..) => false,
+            ManagerCommand::ForceRefreshAndAck(token_id, _, ack) => {
+                ack.send(Err(TokenErrorKind::RefreshNotCompleted(format!(
+                    "The refresh of token {:?} was dropped because the command queue is full.",
+                    token_id
+                ))));
+                false
+            }
+        };
+        if let Some(idx) = idx {
+            self.in_flight.drop_refresh(idx, scheduled);
+        }
+    }
+}
+
+/// Sends `ManagerCommand`s to the updater.
+pub struct CommandSender<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Debug> CommandSender<T> {
+    /// Queues a command which is not tracked as in flight.
+    pub fn send(&self, command: ManagerCommand<T>) -> StdResult<(), SendError<ManagerCommand<T>>> {
+        self.push(None, command)
+    }
+
+    /// Queues a refresh of the row `idx` unless there already is one in
+    /// flight. Returns `Ok(false)` if the refresh was skipped.
+    pub fn send_refresh(
+        &self,
+        idx: usize,
+        command: ManagerCommand<T>,
+    ) -> StdResult<bool, SendError<ManagerCommand<T>>> {
+        if !self.queue.in_flight.begin(idx) {
+            return Ok(false);
+        }
+        match self.push(Some(idx), command) {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                self.queue.in_flight.complete(idx);
+                Err(err)
+            }
+        }
+    }
+
+    /// Queues a command taken from another queue with
+    /// `CommandReceiver::recv_tracked` which keeps the row of its refresh
+    /// tracked as in flight.
+    pub fn forward(
+        &self,
+        idx: Option<usize>,
+        command: ManagerCommand<T>,
+    ) -> StdResult<(), SendError<ManagerCommand<T>>> {
+        let forwarded = self.push(idx, command);
+        if let (Err(_), Some(idx)) = (&forwarded, idx) {
+            self.queue.in_flight.complete(idx);
+        }
+        forwarded
+    }
+
+    pub fn in_flight(&self) -> &InFlightRefreshes {
+        &self.queue.in_flight
+    }
+
+    /// The number of commands dropped because the queue was full.
+    pub fn dropped_commands(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    fn push(
+        &self,
+        idx: Option<usize>,
+        command: ManagerCommand<T>,
+    ) -> StdResult<(), SendError<ManagerCommand<T>>> {
+        let mut state = self.queue.state.lock().unwrap();
+        if !state.receiver_alive {
+            return Err(SendError(command));
+        }
+        if state.commands.len() >= self.queue.capacity {
+            if let Some((dropped_idx, dropped)) = state.commands.pop_front() {
+                self.queue.drop_command(dropped_idx, dropped);
+            }
+        }
+        state.commands.push_back((idx, command));
+        self.queue.available.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for CommandSender<T> {
+    fn clone(&self) -> Self {
+        self.queue.state.lock().unwrap().senders += 1;
+        CommandSender {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Drop for CommandSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.queue.available.notify_all();
+        }
+    }
+}
+
+/// Receives the `ManagerCommand`s in the order they were sent.
+pub struct CommandReceiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> CommandReceiver<T> {
+    /// Blocks until there is a command. Fails once all senders are gone
+    /// and the queue is empty.
+    #[cfg(test)]
+    pub fn recv(&self) -> StdResult<ManagerCommand<T>, RecvError> {
+        self.recv_tracked().map(|(_, command)| command)
+    }
+
+    /// Like `recv` but also returns the row of the refresh if it is
+    /// tracked as in flight so that it can be forwarded to another queue.
+    pub fn recv_tracked(&self) -> StdResult<(Option<usize>, ManagerCommand<T>), RecvError> {
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if let Some(tracked) = state.commands.pop_front() {
+                return Ok(tracked);
+            }
+            if state.senders == 0 {
+                return Err(RecvError);
+            }
+            state = self.queue.available.wait(state).unwrap();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn try_recv(&self) -> StdResult<ManagerCommand<T>, TryRecvError> {
+        self.try_recv_tracked().map(|(_, command)| command)
+    }
+
+    /// Like `try_recv` but also returns the row of the refresh if it is
+    /// tracked as in flight.
+    pub fn try_recv_tracked(&self) -> StdResult<(Option<usize>, ManagerCommand<T>), TryRecvError> {
+        let mut state = self.queue.state.lock().unwrap();
+        match state.commands.pop_front() {
+            Some(tracked) => Ok(tracked),
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for CommandReceiver<T> {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().receiver_alive = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_oldest_command_is_dropped_when_the_queue_is_full() {
+        let in_flight = Arc::new(InFlightRefreshes::new(3));
+        let (tx, rx) = channel::<&'static str>(2, in_flight.clone());
+
+        assert_eq!(
+            tx.send_refresh(0, ManagerCommand::ScheduledRefresh(0, 1)),
+            Ok(true)
+        );
+        assert_eq!(
+            tx.send_refresh(0, ManagerCommand::ScheduledRefresh(0, 2)),
+            Ok(false)
+        );
+        assert_eq!(
+            tx.send_refresh(1, ManagerCommand::ForceRefresh("b", 3)),
+            Ok(true)
+        );
+        assert_eq!(
+            tx.send_refresh(2, ManagerCommand::RefreshOnError(2, 4)),
+            Ok(true)
+        );
+
+        assert_eq!(tx.dropped_commands(), 1);
+        assert!(in_flight.take_dropped(0));
+        assert!(!in_flight.take_dropped(0));
+        assert_eq!(rx.try_recv(), Ok(ManagerCommand::ForceRefresh("b", 3)));
+        assert_eq!(rx.try_recv(), Ok(ManagerCommand::RefreshOnError(2, 4)));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert!(in_flight.begin(0));
+
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn forwarded_refreshes_stay_in_flight() {
+        let in_flight = Arc::new(InFlightRefreshes::new(2));
+        let (tx, rx) = channel::<&'static str>(2, in_flight.clone());
+        let (partition_tx, partition_rx) = channel::<&'static str>(2, in_flight.clone());
+
+        tx.send_refresh(0, ManagerCommand::ScheduledRefresh(0, 1))
+            .unwrap();
+        tx.send(ManagerCommand::ForceRefresh("b", 2)).unwrap();
+        for _ in 0..2 {
+            let (idx, command) = rx.recv_tracked().unwrap();
+            partition_tx.forward(idx, command).unwrap();
+        }
+
+        assert!(!in_flight.begin(0));
+        assert!(in_flight.begin(1));
+        assert_eq!(
+            partition_rx.try_recv(),
+            Ok(ManagerCommand::ScheduledRefresh(0, 1))
+        );
+        assert_eq!(
+            partition_rx.try_recv(),
+            Ok(ManagerCommand::ForceRefresh("b", 2))
+        );
+
+        drop(partition_rx);
+        assert!(partition_tx
+            .forward(Some(0), ManagerCommand::ScheduledRefresh(0, 3))
+            .is_err());
+        assert!(in_flight.begin(0));
+    }
+}
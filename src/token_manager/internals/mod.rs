@@ -1,68 +1,135 @@
+use std::any::Any;
 use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-mod request_scheduler;
-mod token_updater;
+pub mod command_queue;
+pub mod request_scheduler;
+pub mod schedule;
+pub mod token_updater;
 
 use super::*;
+use crate::token_manager::rotation::RotationListeners;
 use crate::token_manager::token_provider::AccessTokenProvider;
+use crate::TokenInfoService;
+#[cfg(feature = "async")]
+use crate::token_manager::watch::TokenWatchers;
 
 pub type EpochMillis = u64;
 
+/// The current `AccessToken` or error of each token by its id together
+/// with the index of its `TokenRow`.
+pub type TokenSlots<T> = BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>;
+
+/// The number of ms a scheduling cycle should take at max.
+pub const MAX_CYCLE_DUR_MS: u64 = 500;
+/// The number of ms that must at least elapse between 2 notifications
+/// for the same token.
+pub const MIN_NOTIFICATION_INTERVAL_MS: u64 = 10_000;
+/// The number of commands the queue of the updater holds in addition to one
+/// refresh per token.
+pub const COMMAND_QUEUE_CAPACITY: usize = 64;
+/// The time to wait before restarting a worker thread which panicked.
+pub const WORKER_RESTART_DELAY_MS: u64 = 1_000;
+
+pub const SCHEDULER_THREAD_NAME: &str = "tokkit-refresh-scheduler";
+pub const UPDATER_THREAD_NAME: &str = "tokkit-refresh-updater";
+pub const ROUTER_THREAD_NAME: &str = "tokkit-refresh-router";
+
 pub fn initialize<
-    T: Eq + Ord + Send + Sync + Clone + Display + 'static,
+    T: Eq + Ord + Send + Sync + Clone + Debug + 'static,
     C: Clock + Clone + Send + 'static,
 >(
     groups: Vec<ManagedTokenGroup<T>>,
     clock: C,
-) -> (Inner<T>, mpsc::Sender<ManagerCommand<T>>) {
+    listener: Option<Arc<dyn TokenLifecycleListener<T>>>,
+) -> (Inner<T>, command_queue::CommandSender<T>) {
     let tokens = Arc::new(create_tokens(&groups));
-    let rows = create_rows(groups, clock.now());
+    let now = clock.now();
+    let acquire = Arc::new(create_acquisitions(&groups, now));
+    let partitions = create_partitions(&groups);
+    let rows = create_rows(groups, now);
 
-    let (tx, rx) = mpsc::channel::<ManagerCommand<T>>();
+    let in_flight = Arc::new(command_queue::InFlightRefreshes::new(rows.len()));
+    let (tx, rx) = command_queue::channel(rows.len() + COMMAND_QUEUE_CAPACITY, in_flight.clone());
 
     let is_running = Arc::new(AtomicBool::new(true));
 
-    let inner = Inner { tokens, is_running };
+    let inner = Inner {
+        paused: Arc::new(create_pause_flags(&tokens)),
+        refresh_on_access_at: Arc::new(create_refresh_on_access_times(&tokens)),
+        expires_at: Arc::new(create_expiry_times(&tokens)),
+        rotation_listeners: Arc::new(create_rotation_listeners(&tokens)),
+        acquire,
+        #[cfg(feature = "async")]
+        watchers: Arc::new(create_watchers(&tokens)),
+        tokens,
+        is_running,
+    };
 
-    start(rows, inner.clone(), tx.clone(), rx, clock);
+    let queue = Queue {
+        sender: tx.clone(),
+        receiver: rx,
+        in_flight,
+    };
+    start(rows, partitions, queue, inner.clone(), clock, listener);
 
     (inner, tx)
 }
 
-fn create_rows<T: Clone>(
+pub fn create_rows<T: Clone + Debug>(
     groups: Vec<ManagedTokenGroup<T>>,
     now: EpochMillis,
 ) -> Vec<Mutex<TokenRow<T>>> {
     let mut states = Vec::new();
     for group in groups {
         for managed_token in group.managed_tokens {
+            let acquire = initial_acquire(&managed_token, group.schedule_file.as_ref(), now);
             states.push(Mutex::new(TokenRow {
                 token_id: managed_token.token_id.clone(),
                 scopes: managed_token.scopes,
+                params: managed_token.params,
                 refresh_threshold: group.refresh_threshold,
                 warning_threshold: group.warning_threshold,
+                escalation_window_ms: group
+                    .escalation_window
+                    .map(|window| window.as_millis() as u64),
                 last_touched: now,
                 refresh_at: now,
                 warn_at: now,
                 expires_at: now,
-                scheduled_for: now,
+                // Lazily acquired tokens are fetched once they are requested.
+                scheduled_for: match acquire {
+                    Acquire::Eager => now,
+                    Acquire::Lazy { .. } => u64::MAX,
+                },
                 token_state: TokenState::Uninitialized,
                 last_notification_at: None,
+                last_notified_severity: None,
                 token_provider: group.token_provider.clone(),
+                scope_introspection: group.scope_introspection.clone(),
+                verification: group.verification.clone(),
+                expected_token_type: group.expected_token_type.clone(),
+                default_expires_in: group.default_expires_in,
+                min_expires_in: group.min_expires_in,
+                refresh_on_access_window_ms: group
+                    .refresh_on_access_within
+                    .map(|window| window.as_millis() as u64),
+                schedule_file: group.schedule_file.clone(),
             }));
         }
     }
     states
 }
 
-fn create_tokens<T: Eq + Ord + Clone + Display>(
+pub fn create_tokens<T: Eq + Ord + Clone + Debug>(
     groups: &[ManagedTokenGroup<T>],
-) -> BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)> {
-    let mut tokens: BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)> =
-        Default::default();
+) -> TokenSlots<T> {
+    let mut tokens: TokenSlots<T> = Default::default();
+    let started = Instant::now();
     let mut idx = 0;
     for group in groups {
         for managed_token in &group.managed_tokens {
@@ -70,8 +137,9 @@ fn create_tokens<T: Eq + Ord + Clone + Display>(
                 managed_token.token_id.clone(),
                 (
                     idx,
-                    Mutex::new(Err(TokenErrorKind::NotInitialized(
-                        managed_token.token_id.to_string(),
+                    Mutex::new(Err(TokenErrorKind::not_initialized(
+                        &managed_token.token_id,
+                        started,
                     ))),
                 ),
             );
@@ -81,57 +149,304 @@ fn create_tokens<T: Eq + Ord + Clone + Display>(
     tokens
 }
 
-fn start<
-    T: Eq + Ord + Send + Sync + Clone + Display + 'static,
-    C: Clock + Clone + Send + 'static,
->(
+/// Creates the `Acquire` of each token indexed like the rows.
+pub fn create_acquisitions<T: Debug>(
+    groups: &[ManagedTokenGroup<T>],
+    now: EpochMillis,
+) -> Vec<Acquire> {
+    groups
+        .iter()
+        .flat_map(|group| {
+            group.managed_tokens.iter().map(move |managed_token| {
+                initial_acquire(managed_token, group.schedule_file.as_ref(), now)
+            })
+        })
+        .collect()
+}
+
+/// Tokens which were refreshed shortly before a restart according to the
+/// `ScheduleFile` of their group are acquired lazily.
+fn initial_acquire<T: Debug>(
+    managed_token: &ManagedToken<T>,
+    schedule_file: Option<&schedule::ScheduleFile>,
+    now: EpochMillis,
+) -> Acquire {
+    match (managed_token.acquire, schedule_file) {
+        (Acquire::Eager, Some(schedule_file))
+            if schedule_file.is_fresh(&managed_token.token_id, now) =>
+        {
+            info!(
+                "Token {:?} was refreshed before the restart and is acquired once requested.",
+                managed_token.token_id
+            );
+            Acquire::lazy()
+        }
+        (acquire, _) => acquire,
+    }
+}
+
+/// Creates the partition of each token indexed like the rows. Each
+/// partition is refreshed by an updater thread of its own.
+///
+/// Isolated groups get a partition of their own while all other groups
+/// share one.
+pub fn create_partitions<T>(groups: &[ManagedTokenGroup<T>]) -> Vec<usize> {
+    let mut partitions = Vec::new();
+    let mut shared = None;
+    let mut next = 0;
+    for group in groups {
+        let partition = match (group.isolated, shared) {
+            (false, Some(shared)) => shared,
+            (isolated, _) => {
+                next += 1;
+                if !isolated {
+                    shared = Some(next - 1);
+                }
+                next - 1
+            }
+        };
+        partitions.extend(group.managed_tokens.iter().map(|_| partition));
+    }
+    partitions
+}
+
+/// Creates a flag for each token which is set while its scheduled
+/// refreshes are paused. The flags are indexed like the rows.
+pub fn create_pause_flags<T>(tokens: &TokenSlots<T>) -> Vec<AtomicBool> {
+    tokens.iter().map(|_| AtomicBool::new(false)).collect()
+}
+
+/// Creates the time for each token from which on a request for the token
+/// triggers a refresh. The times are indexed like the rows and stay at
+/// `u64::MAX` for tokens which are not refreshed on access.
+pub fn create_refresh_on_access_times<T>(tokens: &TokenSlots<T>) -> Vec<AtomicU64> {
+    tokens.iter().map(|_| AtomicU64::new(u64::MAX)).collect()
+}
+
+/// Creates the expiry of each token which is `0` until it was received.
+pub fn create_expiry_times<T>(tokens: &TokenSlots<T>) -> Vec<AtomicU64> {
+    tokens.iter().map(|_| AtomicU64::new(0)).collect()
+}
+
+/// Creates the rotation listeners of the tokens without any callbacks.
+pub fn create_rotation_listeners<T: Ord + Clone + Debug>(
+    tokens: &TokenSlots<T>,
+) -> RotationListeners<T> {
+    RotationListeners::new(tokens.keys().cloned())
+}
+
+/// Creates a watch channel for each token starting with its current state.
+#[cfg(feature = "async")]
+pub fn create_watchers<T: Ord + Clone>(tokens: &TokenSlots<T>) -> TokenWatchers<T> {
+    TokenWatchers::new(
+        tokens
+            .iter()
+            .map(|(id, (_, token))| (id.clone(), token.lock().unwrap().clone())),
+    )
+}
+
+/// The command queue between the scheduler and the updaters.
+struct Queue<T> {
+    sender: command_queue::CommandSender<T>,
+    receiver: command_queue::CommandReceiver<T>,
+    in_flight: Arc<command_queue::InFlightRefreshes>,
+}
+
+fn start<T: Eq + Ord + Send + Sync + Clone + Debug + 'static, C: Clock + Clone + Send + 'static>(
     rows: Vec<Mutex<TokenRow<T>>>,
+    partitions: Vec<usize>,
+    queue: Queue<T>,
     inner: Inner<T>,
-    sender: mpsc::Sender<ManagerCommand<T>>,
-    receiver: mpsc::Receiver<ManagerCommand<T>>,
     clock: C,
+    listener: Option<Arc<dyn TokenLifecycleListener<T>>>,
 ) {
+    let Queue {
+        sender,
+        receiver,
+        in_flight,
+    } = queue;
     let rows1 = Arc::new(rows);
     let rows2 = rows1.clone();
     let inner1 = inner.clone();
     let clock1 = clock.clone();
-    thread::spawn(move || {
-        let scheduler = request_scheduler::RefreshScheduler::new(
-            &*rows1,
-            &sender,
-            500,
-            10_000,
-            &inner1.is_running,
-            &clock1,
-        );
-        scheduler.start();
-    });
-    thread::spawn(move || {
-        let token_updater = token_updater::TokenUpdater::new(
-            &*rows2,
-            &inner.tokens,
+    let listener1 = listener.clone();
+    spawn_worker(
+        SCHEDULER_THREAD_NAME.to_string(),
+        inner.is_running.clone(),
+        move || {
+            let scheduler = request_scheduler::RefreshScheduler::new(
+                &rows1,
+                &sender,
+                MAX_CYCLE_DUR_MS,
+                MIN_NOTIFICATION_INTERVAL_MS,
+                &inner1.is_running,
+                &clock1,
+            )
+            .with_pause_flags(&inner1.paused);
+            let scheduler = match listener1 {
+                Some(ref listener) => scheduler.with_listener(&**listener),
+                None => scheduler,
+            };
+            scheduler.start();
+        },
+    );
+    let count = partitions.iter().max().map_or(1, |max| max + 1);
+    if count <= 1 {
+        spawn_updater(
+            UPDATER_THREAD_NAME.to_string(),
+            rows2,
+            inner,
             receiver,
-            &inner.is_running,
-            &clock,
+            in_flight,
+            clock,
+            listener,
         );
-        token_updater.start();
+        return;
+    }
+
+    let mut senders = Vec::with_capacity(count);
+    for partition in 0..count {
+        let rows = partitions.iter().filter(|&&p| p == partition).count();
+        let (tx, rx) = command_queue::channel(rows + COMMAND_QUEUE_CAPACITY, in_flight.clone());
+        senders.push(tx);
+        spawn_updater(
+            format!("{}-{}", UPDATER_THREAD_NAME, partition),
+            rows2.clone(),
+            inner.clone(),
+            rx,
+            in_flight.clone(),
+            clock.clone(),
+            listener.clone(),
+        );
+    }
+    let tokens = inner.tokens.clone();
+    spawn_worker(
+        ROUTER_THREAD_NAME.to_string(),
+        inner.is_running,
+        move || route(&receiver, &senders, &partitions, &tokens),
+    );
+}
+
+fn spawn_updater<
+    T: Eq + Ord + Send + Sync + Clone + Debug + 'static,
+    C: Clock + Clone + Send + 'static,
+>(
+    name: String,
+    rows: Arc<Vec<Mutex<TokenRow<T>>>>,
+    inner: Inner<T>,
+    receiver: command_queue::CommandReceiver<T>,
+    in_flight: Arc<command_queue::InFlightRefreshes>,
+    clock: C,
+    listener: Option<Arc<dyn TokenLifecycleListener<T>>>,
+) {
+    spawn_worker(name, inner.is_running.clone(), move || {
+        let token_updater =
+            token_updater::TokenUpdater::new(&rows, &inner.tokens, &inner.is_running, &clock)
+                .with_in_flight(&in_flight)
+                .with_refresh_on_access_times(&inner.refresh_on_access_at)
+                .with_expiry_times(&inner.expires_at)
+                .with_rotation_listeners(&inner.rotation_listeners);
+        #[cfg(feature = "async")]
+        let token_updater = token_updater.with_watchers(&inner.watchers);
+        let token_updater = match listener {
+            Some(ref listener) => token_updater.with_listener(&**listener),
+            None => token_updater,
+        };
+        token_updater.start(&receiver);
     });
 }
 
+/// Forwards each command to the updater of the partition of its token
+/// until all senders are gone.
+fn route<T: Ord + Debug>(
+    receiver: &command_queue::CommandReceiver<T>,
+    senders: &[command_queue::CommandSender<T>],
+    partitions: &[usize],
+    tokens: &TokenSlots<T>,
+) {
+    while let Ok((tracked, command)) = receiver.recv_tracked() {
+        let idx = match command {
+            ManagerCommand::ScheduledRefresh(idx, _) | ManagerCommand::RefreshOnError(idx, _) => {
+                Some(idx)
+            }
+            ManagerCommand::ForceRefresh(ref token_id, _)
+            | ManagerCommand::ForceRefreshAndAck(ref token_id, _, _) => {
+                tokens.get(token_id).map(|(idx, _)| *idx)
+            }
+        };
+        // Unknown tokens are reported by the updater of any partition.
+        let partition = idx.map_or(0, |idx| partitions[idx]);
+        if let Err(mpsc::SendError(command)) = senders[partition].forward(tracked, command) {
+            warn!(
+                "The updater of partition {} is gone. Dropped command {:?}.",
+                partition, command
+            );
+        }
+    }
+}
+
+/// Runs `work` on a thread named `name`.
+///
+/// If `work` panics while the manager is still running it is started again
+/// on a new thread.
+fn spawn_worker<F>(name: String, is_running: Arc<AtomicBool>, mut work: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    let thread_name = name.clone();
+    let spawned = thread::Builder::new()
+        .name(thread_name.clone())
+        .spawn(move || {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(&mut work)) {
+                if !is_running.load(Ordering::Relaxed) {
+                    return;
+                }
+                error!(
+                    "Thread '{}' panicked and will be restarted: {}",
+                    name,
+                    panic_message(&*panic)
+                );
+                thread::sleep(Duration::from_millis(WORKER_RESTART_DELAY_MS));
+                spawn_worker(name, is_running, work);
+            }
+        });
+    if let Err(err) = spawned {
+        error!("Could not start thread '{}': {}", thread_name, err);
+    }
+}
+
+/// The message a panic was started with.
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown cause".to_string()
+    }
+}
+
 #[derive(Clone)]
 pub struct Inner<T> {
-    pub tokens: Arc<BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>>,
+    pub tokens: Arc<TokenSlots<T>>,
     pub is_running: Arc<AtomicBool>,
+    pub paused: Arc<Vec<AtomicBool>>,
+    pub refresh_on_access_at: Arc<Vec<AtomicU64>>,
+    pub expires_at: Arc<Vec<AtomicU64>>,
+    pub rotation_listeners: Arc<RotationListeners<T>>,
+    pub acquire: Arc<Vec<Acquire>>,
+    #[cfg(feature = "async")]
+    pub watchers: Arc<TokenWatchers<T>>,
 }
 
-impl<T: Eq + Ord + Clone + Display> Inner<T> {
+impl<T: Eq + Ord + Clone + Debug> Inner<T> {
     pub fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
         match self.tokens.get(&token_id) {
             Some((_, guard)) => match &*guard.lock().unwrap() {
                 Ok(token) => Ok(token.clone()),
                 Err(err) => Err(err.clone().into()),
             },
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
+            None => Err(TokenErrorKind::no_token(token_id).into()),
         }
     }
 }
@@ -154,6 +469,15 @@ impl TokenState {
         }
     }
 
+    /// The state before the pending refresh was requested.
+    pub fn without_pending_refresh(&self) -> TokenState {
+        match *self {
+            TokenState::Uninitialized | TokenState::Initializing => TokenState::Uninitialized,
+            TokenState::Ok | TokenState::OkPending => TokenState::Ok,
+            TokenState::Error | TokenState::ErrorPending => TokenState::Error,
+        }
+    }
+
     pub fn is_uninitialized(&self) -> bool {
         match *self {
             TokenState::Uninitialized | TokenState::Initializing => true,
@@ -165,8 +489,11 @@ impl TokenState {
 pub struct TokenRow<T> {
     token_id: T,
     scopes: Vec<Scope>,
+    params: TokenRequestParams,
     refresh_threshold: f32,
     warning_threshold: f32,
+    /// Notifications are escalated within this time before the expiry.
+    escalation_window_ms: Option<u64>,
     last_touched: EpochMillis,
     refresh_at: EpochMillis,
     warn_at: EpochMillis,
@@ -174,16 +501,138 @@ pub struct TokenRow<T> {
     scheduled_for: EpochMillis,
     token_state: TokenState,
     last_notification_at: Option<EpochMillis>,
+    /// Reset once a token no longer needs a notification.
+    last_notified_severity: Option<NotificationSeverity>,
     token_provider: Arc<dyn AccessTokenProvider + Send + Sync + 'static>,
+    scope_introspection: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    /// Refreshed tokens are only published if they pass an introspection
+    /// with this service.
+    verification: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    /// Tokens of another `token_type` are rejected.
+    expected_token_type: Option<String>,
+    /// Replaces a missing "expires in".
+    default_expires_in: Option<Duration>,
+    min_expires_in: Duration,
+    /// The token is refreshed when it is requested within this time
+    /// before its expiry and otherwise once it expired.
+    refresh_on_access_window_ms: Option<u64>,
+    /// Receives the schedule after every refresh.
+    schedule_file: Option<schedule::ScheduleFile>,
+}
+
+/// What a notification about a token was issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    /// The token could not be refreshed.
+    Error,
+    /// The token expired.
+    Expired,
+    /// The token passed its warning threshold.
+    ExpiresSoon,
+    /// The `AccessTokenProvider` panicked while refreshing the token which
+    /// was put into the error state.
+    ProviderPanicked,
+    /// The authorization server sent no or a too short "expires in" for
+    /// the token which was replaced by the configured one.
+    ExpiryAdjusted,
+}
+
+/// How urgent a notification is.
+///
+/// A notification with a higher severity than the previous one for the same
+/// token is issued immediately instead of waiting for the minimum interval
+/// between notifications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NotificationSeverity {
+    /// The token passed its warning threshold or could not be refreshed
+    /// while there is still time left.
+    Warning,
+    /// The token expires within the escalation window of its group.
+    Escalated,
+    /// The token expired or there never was one.
+    Critical,
+}
+
+/// A notification issued by the scheduler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenNotification<T> {
+    pub token_id: T,
+    pub kind: NotificationKind,
+    pub severity: NotificationSeverity,
+    /// The time of the `Clock` the notification was issued at.
+    pub at: Duration,
+}
+
+/// Receives the notifications about managed tokens.
+///
+/// The listener is called on the scheduler thread so it should return
+/// quickly.
+pub trait TokenLifecycleListener<T>: Send + Sync {
+    fn on_notification(&self, notification: &TokenNotification<T>);
+
+    /// Called on the updater thread when a token was refreshed without all
+    /// of its requested `Scope`s.
+    fn on_scopes_downgraded(&self, _downgrade: &ScopesDowngraded<T>) {}
+}
+
+/// A token was granted fewer `Scope`s than requested.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopesDowngraded<T> {
+    pub token_id: T,
+    pub requested: Vec<Scope>,
+    pub granted: Vec<Scope>,
+    /// The requested `Scope`s which were not granted.
+    pub missing: Vec<Scope>,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum ManagerCommand<T> {
     ScheduledRefresh(usize, u64),
     ForceRefresh(T, u64),
+    /// A forced refresh whose outcome is sent to the `RefreshAck`.
+    ForceRefreshAndAck(T, u64, RefreshAck),
     RefreshOnError(usize, u64),
 }
 
+/// The `AccessToken` after a refresh or the error of the refresh.
+pub type RefreshOutcome = StdResult<AccessToken, TokenErrorKind>;
+
+/// Receives the `RefreshOutcome` of a forced refresh.
+pub enum RefreshAck {
+    Blocking(mpsc::Sender<RefreshOutcome>),
+    #[cfg(feature = "async")]
+    Async(futures::channel::oneshot::Sender<RefreshOutcome>),
+}
+
+impl RefreshAck {
+    pub fn send(self, outcome: RefreshOutcome) {
+        // The caller may have given up waiting which is fine.
+        match self {
+            RefreshAck::Blocking(sender) => {
+                let _ = sender.send(outcome);
+            }
+            #[cfg(feature = "async")]
+            RefreshAck::Async(sender) => {
+                let _ = sender.send(outcome);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RefreshAck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RefreshAck")
+    }
+}
+
+/// Acknowledgements carry no data so commands only differ in their other
+/// fields.
+impl PartialEq for RefreshAck {
+    fn eq(&self, _other: &RefreshAck) -> bool {
+        true
+    }
+}
+
 pub trait Clock {
     fn now(&self) -> EpochMillis;
 }
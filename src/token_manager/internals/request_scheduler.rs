@@ -1,22 +1,28 @@
+use super::command_queue::CommandSender;
 use super::*;
 use std::cmp;
-use std::sync::mpsc;
 
 pub struct RefreshScheduler<'a, T: 'a> {
     rows: &'a [Mutex<TokenRow<T>>],
-    sender: &'a mpsc::Sender<ManagerCommand<T>>,
+    /// Refreshes are only sent for rows without one in flight.
+    sender: &'a CommandSender<T>,
     /// The time that must at least elapse between 2 notifications
     min_notification_interval_ms: u64,
     /// The number of ms a cycle should take at max.
     max_cycle_dur_ms: u64,
     is_running: &'a AtomicBool,
     clock: &'a dyn Clock,
+    /// Receives a copy of every notification if set.
+    notification_log: Option<&'a Mutex<Vec<TokenNotification<T>>>>,
+    listener: Option<&'a dyn TokenLifecycleListener<T>>,
+    /// Scheduled refreshes are skipped for rows whose flag is set.
+    paused: Option<&'a [AtomicBool]>,
 }
 
-impl<'a, T: Eq + Ord + Send + Clone + Display> RefreshScheduler<'a, T> {
+impl<'a, T: Eq + Ord + Send + Clone + Debug> RefreshScheduler<'a, T> {
     pub fn new(
         rows: &'a [Mutex<TokenRow<T>>],
-        sender: &'a mpsc::Sender<ManagerCommand<T>>,
+        sender: &'a CommandSender<T>,
         max_cycle_dur_ms: u64,
         min_notification_interval_ms: u64,
         is_running: &'a AtomicBool,
@@ -29,9 +35,33 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> RefreshScheduler<'a, T> {
             max_cycle_dur_ms,
             is_running,
             clock,
+            notification_log: None,
+            listener: None,
+            paused: None,
         }
     }
 
+    /// Records all notifications in `notification_log`.
+    pub fn with_notification_log(
+        mut self,
+        notification_log: &'a Mutex<Vec<TokenNotification<T>>>,
+    ) -> Self {
+        self.notification_log = Some(notification_log);
+        self
+    }
+
+    /// Delivers all notifications to `listener`.
+    pub fn with_listener(mut self, listener: &'a dyn TokenLifecycleListener<T>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Skips the scheduled refreshes of the rows whose flag is set.
+    pub fn with_pause_flags(mut self, paused: &'a [AtomicBool]) -> Self {
+        self.paused = Some(paused);
+        self
+    }
+
     pub fn start(&self) {
         self.run_scheduler_loop();
     }
@@ -55,43 +85,61 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> RefreshScheduler<'a, T> {
         info!("Scheduler loop exited.")
     }
 
-    fn do_a_scheduling_round(&self) -> EpochMillis {
+    pub fn do_a_scheduling_round(&self) -> EpochMillis {
         let mut next_at = u64::max_value();
         let mut is_refresh_pending = false;
         for (idx, row) in self.rows.iter().enumerate() {
             let row = &mut *row.lock().unwrap();
+            if self.is_paused(idx) {
+                self.check_notifications(row);
+                continue;
+            }
             if row.scheduled_for <= self.clock.now() {
                 is_refresh_pending = true;
+                if row.token_state.is_refresh_pending() && self.sender.in_flight().take_dropped(idx)
+                {
+                    warn!(
+                        "The refresh command for token {:?} was dropped and is sent again.",
+                        row.token_id
+                    );
+                    row.token_state = row.token_state.without_pending_refresh();
+                }
                 row.token_state = match row.token_state {
-                    TokenState::Uninitialized => {
-                        if let Err(err) = self.sender
-                            .send(ManagerCommand::ScheduledRefresh(idx, self.clock.now()))
-                        {
+                    TokenState::Uninitialized => match self
+                        .sender
+                        .send_refresh(idx, ManagerCommand::ScheduledRefresh(idx, self.clock.now()))
+                    {
+                        Ok(true) => TokenState::Initializing,
+                        Ok(false) => TokenState::Uninitialized,
+                        Err(err) => {
                             error!("Could not send initial refresh command: {}", err);
                             break;
                         }
-                        TokenState::Initializing
-                    }
+                    },
                     TokenState::Initializing => TokenState::Initializing,
-                    TokenState::Ok => {
-                        if let Err(err) = self.sender
-                            .send(ManagerCommand::ScheduledRefresh(idx, self.clock.now()))
-                        {
+                    TokenState::Ok => match self
+                        .sender
+                        .send_refresh(idx, ManagerCommand::ScheduledRefresh(idx, self.clock.now()))
+                    {
+                        Ok(true) => TokenState::OkPending,
+                        Ok(false) => TokenState::Ok,
+                        Err(err) => {
                             error!("Could not send regular refresh command: {}", err);
                             break;
                         }
-                        TokenState::OkPending
-                    }
+                    },
                     TokenState::OkPending => TokenState::OkPending,
-                    TokenState::Error => {
-                        if let Err(err) = self.sender
-                            .send(ManagerCommand::RefreshOnError(idx, self.clock.now()))
-                        {
+                    TokenState::Error => match self
+                        .sender
+                        .send_refresh(idx, ManagerCommand::RefreshOnError(idx, self.clock.now()))
+                    {
+                        Ok(true) => TokenState::ErrorPending,
+                        Ok(false) => TokenState::Error,
+                        Err(err) => {
                             error!("Could not send refresh on error command: {}", err);
                             break;
                         }
-                        TokenState::ErrorPending
-                    }
+                    },
                     TokenState::ErrorPending => TokenState::ErrorPending,
                 };
             } else {
@@ -107,54 +155,111 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> RefreshScheduler<'a, T> {
         }
     }
 
+    fn is_paused(&self, idx: usize) -> bool {
+        self.paused
+            .and_then(|paused| paused.get(idx))
+            .is_some_and(|paused| paused.load(Ordering::Relaxed))
+    }
+
     fn check_notifications(&self, row: &mut TokenRow<T>) {
         let now = self.clock.now();
-        let notify = if let Some(last_notified) = row.last_notification_at {
+        let kind = match row.token_state {
+            TokenState::Error | TokenState::ErrorPending => Some(NotificationKind::Error),
+            TokenState::Ok | TokenState::OkPending => {
+                if row.expires_at <= now {
+                    Some(NotificationKind::Expired)
+                } else if row.warn_at <= now {
+                    Some(NotificationKind::ExpiresSoon)
+                } else {
+                    None
+                }
+            }
+            TokenState::Uninitialized | TokenState::Initializing => None,
+        };
+        let kind = if let Some(kind) = kind {
+            kind
+        } else {
+            row.last_notified_severity = None;
+            return;
+        };
+
+        let severity = severity_at(row, now);
+        let escalated = row
+            .last_notified_severity
+            .is_some_and(|last| severity > last);
+        let notify = escalated || if let Some(last_notified) = row.last_notification_at {
             minus_millis(now, last_notified) >= self.min_notification_interval_ms
         } else {
             true
         };
-        if notify {
-            let notified = match row.token_state {
-                TokenState::Error | TokenState::ErrorPending => {
-                    warn!("Token '{}' is in error row.", row.token_id);
-                    true
-                }
-                TokenState::Ok | TokenState::OkPending => {
-                    if row.expires_at <= now {
-                        warn!(
-                            "Token '{}' expired {:.2} minutes ago.",
-                            row.token_id,
-                            (now - row.expires_at) as f64 / 60_000.0
-                        );
-                        true
-                    } else if row.warn_at <= now {
-                        warn!(
-                            "Token '{}' expires in {:.2} minutes.",
-                            row.token_id,
-                            (row.expires_at - now) as f64 / 60_000.0
-                        );
-                        true
-                    } else {
-                        false
-                    }
-                }
-                TokenState::Uninitialized | TokenState::Initializing => false,
-            };
-            if notified {
-                row.last_notification_at = Some(now);
+        if !notify {
+            return;
+        }
+
+        match kind {
+            NotificationKind::Error => {
+                warn!("Token {:?} is in error row ({:?}).", row.token_id, severity)
             }
+            NotificationKind::Expired => warn!(
+                "Token {:?} expired {:.2} minutes ago ({:?}).",
+                row.token_id,
+                (now - row.expires_at) as f64 / 60_000.0,
+                severity
+            ),
+            NotificationKind::ExpiresSoon => warn!(
+                "Token {:?} expires in {:.2} minutes ({:?}).",
+                row.token_id,
+                (row.expires_at - now) as f64 / 60_000.0,
+                severity
+            ),
+            NotificationKind::ProviderPanicked => error!(
+                "The provider of token {:?} panicked ({:?}).",
+                row.token_id, severity
+            ),
+            NotificationKind::ExpiryAdjusted => warn!(
+                "The expiry of token {:?} was adjusted ({:?}).",
+                row.token_id, severity
+            ),
+        }
+
+        row.last_notification_at = Some(now);
+        row.last_notified_severity = Some(severity);
+        let notification = TokenNotification {
+            token_id: row.token_id.clone(),
+            kind,
+            severity,
+            at: Duration::from_millis(now),
+        };
+        if let Some(listener) = self.listener {
+            listener.on_notification(&notification);
+        }
+        if let Some(notification_log) = self.notification_log {
+            notification_log.lock().unwrap().push(notification);
         }
     }
 }
 
+/// The severity of a notification about the token in `row` at `now`.
+fn severity_at<T>(row: &TokenRow<T>, now: EpochMillis) -> NotificationSeverity {
+    if row.expires_at <= now {
+        NotificationSeverity::Critical
+    } else if row
+        .escalation_window_ms
+        .is_some_and(|window| row.expires_at - now <= window)
+    {
+        NotificationSeverity::Escalated
+    } else {
+        NotificationSeverity::Warning
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::token_manager::internals::command_queue::{self, InFlightRefreshes};
     use std::cell::Cell;
     use std::rc::Rc;
     use std::sync::atomic::AtomicBool;
-    use std::sync::mpsc;
 
     #[derive(Clone)]
     struct TestClock {
@@ -232,7 +337,7 @@ mod test {
 
     #[test]
     fn scheduler_sends_initial_refresh_while_nothing_happens() {
-        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = command_queue::channel(16, Arc::new(InFlightRefreshes::new(1)));
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let rows = create_token_rows();
@@ -283,7 +388,8 @@ mod test {
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn scheduler_workflow() {
-        let (tx, rx) = mpsc::channel();
+        let in_flight = Arc::new(InFlightRefreshes::new(1));
+        let (tx, rx) = command_queue::channel(16, in_flight.clone());
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let rows = create_token_rows();
@@ -317,6 +423,7 @@ mod test {
 
         // The token comes in at 1000
         clock.set(1000);
+        in_flight.complete(0);
         {
             let mut row = rows[0].lock().unwrap();
             row.refresh_at = clock.now() + 7500;
@@ -453,6 +560,7 @@ mod test {
 
         // At 10600 the token comes in
         clock.set(10600);
+        in_flight.complete(0);
         {
             let mut row = rows[0].lock().unwrap();
             row.refresh_at = clock.now() + 7500;
@@ -495,6 +603,7 @@ mod test {
         // at 20000 the token enters error state
         // and only a notification takes place
         clock.set(20000);
+        in_flight.complete(0);
         {
             let mut row = rows[0].lock().unwrap();
             row.refresh_at = clock.now();
@@ -535,6 +644,7 @@ mod test {
 
         // at 21000 the error is resolved and nothing should happen
         clock.set(21000);
+        in_flight.complete(0);
         {
             let mut row = rows[0].lock().unwrap();
             row.refresh_at = clock.now() + 7500;
@@ -575,4 +685,52 @@ mod test {
 
         // and so on .....
     }
+
+    #[test]
+    fn refreshes_are_only_sent_if_none_is_in_flight() {
+        let in_flight = Arc::new(InFlightRefreshes::new(1));
+        let (tx, rx) = command_queue::channel(1, in_flight.clone());
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let rows = create_token_rows();
+
+        let scheduler = RefreshScheduler::new(&rows, &tx, 0, 1000, &is_running, &clock);
+
+        // A forced refresh is in flight
+        assert!(in_flight.begin(0));
+        clock.set(100);
+        scheduler.do_a_scheduling_round();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(
+            TokenState::Uninitialized,
+            rows[0].lock().unwrap().token_state
+        );
+
+        in_flight.complete(0);
+        scheduler.do_a_scheduling_round();
+        assert_eq!(
+            TokenState::Initializing,
+            rows[0].lock().unwrap().token_state
+        );
+
+        // The scheduled refresh is pushed out of the full queue
+        tx.send(ManagerCommand::ForceRefreshAndAck(
+            "token",
+            150,
+            RefreshAck::Blocking(std::sync::mpsc::channel().0),
+        ))
+        .unwrap();
+        assert_eq!(1, tx.dropped_commands());
+
+        clock.set(200);
+        scheduler.do_a_scheduling_round();
+        assert_eq!(
+            ManagerCommand::ScheduledRefresh(0, 200),
+            rx.try_recv().unwrap()
+        );
+        assert_eq!(
+            TokenState::Initializing,
+            rows[0].lock().unwrap().token_state
+        );
+    }
 }
@@ -0,0 +1,219 @@
+//! Persisting the refresh schedule of managed tokens across restarts.
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use json::JsonValue;
+
+/// When a managed token is due for a refresh and when it expires, both in
+/// milliseconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSchedule {
+    pub refresh_at: u64,
+    pub expires_at: u64,
+}
+
+/// A file the `AccessTokenManager` keeps the `TokenSchedule`s of the
+/// managed tokens in. The tokens themselves are never written.
+///
+/// After a restart, e.g. during a deployment of a whole fleet, tokens
+/// which were refreshed shortly before the restart are not fetched right
+/// away. Their previous token would not have been refreshed yet so they
+/// are acquired like with `Acquire::lazy()` once they are requested
+/// instead. Tokens which are never requested cause no request to the
+/// authorization server until then.
+///
+/// The file is rewritten by the updater threads after every refresh. A
+/// `ScheduleFile` can be shared by several `ManagedTokenGroup`s since the
+/// token ids are unique. The entries are keyed by the `Debug`
+/// representation of the token ids.
+///
+/// A missing or unreadable file is treated as empty.
+#[derive(Clone)]
+pub struct ScheduleFile {
+    path: Arc<PathBuf>,
+    schedules: Arc<Mutex<BTreeMap<String, TokenSchedule>>>,
+}
+
+impl ScheduleFile {
+    /// Reads the `TokenSchedule`s from the file at `path` if it exists.
+    pub fn open<P: Into<PathBuf>>(path: P) -> ScheduleFile {
+        let path = path.into();
+        let schedules = match fs::read_to_string(&path) {
+            Ok(contents) => parse_schedules(&contents).unwrap_or_else(|err| {
+                warn!(
+                    "Ignoring the schedule file '{}' which is invalid: {}",
+                    path.display(),
+                    err
+                );
+                BTreeMap::new()
+            }),
+            Err(err) => {
+                info!(
+                    "Starting without the schedule file '{}': {}",
+                    path.display(),
+                    err
+                );
+                BTreeMap::new()
+            }
+        };
+        ScheduleFile {
+            path: Arc::new(path),
+            schedules: Arc::new(Mutex::new(schedules)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The last recorded `TokenSchedule` of the token.
+    pub fn schedule<T: Debug>(&self, token_id: &T) -> Option<TokenSchedule> {
+        self.schedules
+            .lock()
+            .unwrap()
+            .get(&format!("{:?}", token_id))
+            .cloned()
+    }
+
+    /// Checks whether the token was refreshed recently enough before a
+    /// restart that it would not have been due for a refresh at `now`.
+    pub(crate) fn is_fresh<T: Debug>(&self, token_id: &T, now: u64) -> bool {
+        self.schedule(token_id)
+            .is_some_and(|schedule| schedule.refresh_at > now)
+    }
+
+    /// Records the `TokenSchedule` of the token and rewrites the file.
+    pub(crate) fn record<T: Debug>(&self, token_id: &T, schedule: TokenSchedule) {
+        let mut schedules = self.schedules.lock().unwrap();
+        schedules.insert(format!("{:?}", token_id), schedule);
+        if let Err(err) = self.write(&schedules) {
+            warn!(
+                "Could not write the schedule file '{}': {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+
+    /// Writes to a temporary file first so that a crash never leaves a
+    /// truncated file behind.
+    fn write(&self, schedules: &BTreeMap<String, TokenSchedule>) -> ::std::io::Result<()> {
+        let mut contents = JsonValue::new_object();
+        for (token_id, schedule) in schedules {
+            contents[token_id.as_str()] = json::object! {
+                "refresh_at" => schedule.refresh_at,
+                "expires_at" => schedule.expires_at,
+            };
+        }
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents.dump())?;
+        fs::rename(&tmp, &*self.path)
+    }
+}
+
+fn parse_schedules(contents: &str) -> Result<BTreeMap<String, TokenSchedule>, String> {
+    let contents = crate::claims::object(contents.as_bytes())?;
+    let mut schedules = BTreeMap::new();
+    for (token_id, schedule) in contents.entries() {
+        match (
+            schedule["refresh_at"].as_u64(),
+            schedule["expires_at"].as_u64(),
+        ) {
+            (Some(refresh_at), Some(expires_at)) => {
+                schedules.insert(
+                    token_id.to_string(),
+                    TokenSchedule {
+                        refresh_at,
+                        expires_at,
+                    },
+                );
+            }
+            _ => return Err(format!("Invalid schedule of token {}", token_id)),
+        }
+    }
+    Ok(schedules)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token_manager::internals::{create_acquisitions, create_rows};
+    use crate::token_manager::testing::ScriptedTokenProvider;
+    use crate::token_manager::{Acquire, ManagedTokenGroupBuilder};
+    use crate::Scope;
+
+    fn schedule_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "tokkit-schedule-{}-{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn schedules_survive_a_restart() {
+        let path = schedule_path("restart");
+        let schedule = TokenSchedule {
+            refresh_at: 750,
+            expires_at: 1_000,
+        };
+
+        ScheduleFile::open(&path).record(&"token", schedule);
+        let reopened = ScheduleFile::open(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(reopened.schedule(&"token"), Some(schedule));
+        assert_eq!(reopened.schedule(&"other"), None);
+        assert!(reopened.is_fresh(&"token", 749));
+        assert!(!reopened.is_fresh(&"token", 750));
+    }
+
+    #[test]
+    fn fresh_tokens_are_acquired_on_request() {
+        let path = schedule_path("lazy");
+        let file = ScheduleFile::open(&path);
+        file.record(
+            &"fresh",
+            TokenSchedule {
+                refresh_at: 750,
+                expires_at: 1_000,
+            },
+        );
+        file.record(
+            &"due",
+            TokenSchedule {
+                refresh_at: 50,
+                expires_at: 1_000,
+            },
+        );
+        let _ = fs::remove_file(&path);
+
+        let groups = ["fresh", "due", "unknown"]
+            .iter()
+            .map(|&token_id| {
+                let mut builder = ManagedTokenGroupBuilder::single_token(
+                    token_id,
+                    vec![Scope::new("scope")],
+                    ScriptedTokenProvider::new(),
+                );
+                builder.with_schedule_file(file.clone());
+                builder.build().unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let acquire = create_acquisitions(&groups, 100);
+        assert_eq!(acquire, [Acquire::lazy(), Acquire::Eager, Acquire::Eager]);
+        let rows = create_rows(groups, 100);
+        let scheduled_for: Vec<_> = rows
+            .iter()
+            .map(|row| row.lock().unwrap().scheduled_for)
+            .collect();
+        assert_eq!(scheduled_for, [u64::MAX, 100, 100]);
+    }
+}
@@ -1,43 +1,171 @@
 use backoff::{Error as BError, ExponentialBackoff, Operation};
-use std::collections::BTreeMap;
-use std::sync::mpsc;
+use std::cell::Cell;
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Mutex;
 
+use super::command_queue::{CommandReceiver, InFlightRefreshes};
+use super::schedule::TokenSchedule;
 use super::*;
+use crate::core::RetryAfter;
+use crate::token_manager::rotation::RotationListeners;
+#[cfg(feature = "async")]
+use crate::token_manager::watch::TokenWatchers;
+
+thread_local! {
+    static REFRESHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns `true` if the current thread is requesting an `AccessToken` from
+/// an `AccessTokenProvider`.
+///
+/// Anything waiting for a managed token on this thread would wait for
+/// itself.
+pub fn is_refreshing_on_current_thread() -> bool {
+    REFRESHING.with(Cell::get)
+}
+
+/// Marks the current thread as refreshing a token while alive.
+pub(crate) struct RefreshingGuard {
+    was_refreshing: bool,
+}
+
+impl RefreshingGuard {
+    pub fn enter() -> RefreshingGuard {
+        RefreshingGuard {
+            was_refreshing: REFRESHING.with(|refreshing| refreshing.replace(true)),
+        }
+    }
+}
+
+impl Drop for RefreshingGuard {
+    fn drop(&mut self) {
+        let was_refreshing = self.was_refreshing;
+        REFRESHING.with(|refreshing| refreshing.set(was_refreshing));
+    }
+}
+
+/// The result of a refresh which is announced once the row was released.
+struct Refreshed<T> {
+    token_id: T,
+    outcome: RefreshOutcome,
+    /// Set if the token was rotated.
+    rotation: Option<Rotation>,
+}
+
+/// A new `AccessToken` together with what is needed to check its `Scope`s.
+struct Rotation {
+    access_token: AccessToken,
+    requested: Vec<Scope>,
+    /// The `Scope`s granted according to the authorization server.
+    granted: Option<Vec<Scope>>,
+    scope_introspection: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+}
 
 pub struct TokenUpdater<'a, T: 'a> {
     rows: &'a [Mutex<TokenRow<T>>],
-    tokens: &'a BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>,
-    receiver: mpsc::Receiver<ManagerCommand<T>>,
+    tokens: &'a TokenSlots<T>,
     is_running: &'a AtomicBool,
     clock: &'a dyn Clock,
+    /// Whether transient errors of the provider are retried with a backoff.
+    retry: bool,
+    /// Receive every change of a token if set.
+    #[cfg(feature = "async")]
+    watchers: Option<&'a TokenWatchers<T>>,
+    /// Is told about panics of the providers.
+    listener: Option<&'a dyn TokenLifecycleListener<T>>,
+    /// Refreshes are marked as completed here if set.
+    in_flight: Option<&'a InFlightRefreshes>,
+    /// Receives the times from which on requests for a token trigger a
+    /// refresh if set.
+    refresh_on_access_at: Option<&'a [AtomicU64]>,
+    /// Receives the expiry of each received token if set.
+    expires_at: Option<&'a [AtomicU64]>,
+    /// Receive every successfully refreshed token if set.
+    rotation_listeners: Option<&'a RotationListeners<T>>,
 }
 
-impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
+impl<'a, T: Eq + Ord + Send + Clone + Debug> TokenUpdater<'a, T> {
     pub fn new(
         rows: &'a [Mutex<TokenRow<T>>],
-        tokens: &'a BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>,
-        receiver: mpsc::Receiver<ManagerCommand<T>>,
+        tokens: &'a TokenSlots<T>,
         is_running: &'a AtomicBool,
         clock: &'a dyn Clock,
     ) -> Self {
         TokenUpdater {
             rows,
             tokens,
-            receiver,
             is_running,
             clock,
+            retry: true,
+            #[cfg(feature = "async")]
+            watchers: None,
+            listener: None,
+            in_flight: None,
+            refresh_on_access_at: None,
+            expires_at: None,
+            rotation_listeners: None,
         }
     }
 
-    pub fn start(&self) {
-        self.run_updater_loop();
+    /// Publishes every change of a token to `watchers`.
+    #[cfg(feature = "async")]
+    pub fn with_watchers(mut self, watchers: &'a TokenWatchers<T>) -> Self {
+        self.watchers = Some(watchers);
+        self
+    }
+
+    /// Delivers a `NotificationKind::ProviderPanicked` to `listener` when a
+    /// provider panics, a `NotificationKind::ExpiryAdjusted` when the expiry
+    /// of a token was replaced and a `ScopesDowngraded` when a token lacks requested
+    /// `Scope`s.
+    pub fn with_listener(mut self, listener: &'a dyn TokenLifecycleListener<T>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Marks the refreshes of `in_flight` as completed once they were
+    /// executed.
+    pub fn with_in_flight(mut self, in_flight: &'a InFlightRefreshes) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+
+    /// Updates the times in `refresh_on_access_at` from which on requests
+    /// for a token refreshed on access trigger a refresh.
+    pub fn with_refresh_on_access_times(mut self, refresh_on_access_at: &'a [AtomicU64]) -> Self {
+        self.refresh_on_access_at = Some(refresh_on_access_at);
+        self
+    }
+
+    /// Updates the times in `expires_at` at which the received tokens
+    /// expire.
+    pub fn with_expiry_times(mut self, expires_at: &'a [AtomicU64]) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Invokes the callbacks of `rotation_listeners` with each successfully
+    /// refreshed token.
+    pub fn with_rotation_listeners(mut self, rotation_listeners: &'a RotationListeners<T>) -> Self {
+        self.rotation_listeners = Some(rotation_listeners);
+        self
     }
 
-    fn run_updater_loop(&self) {
+    /// Passes errors of the provider on without retrying.
+    pub fn without_retries(mut self) -> Self {
+        self.retry = false;
+        self
+    }
+
+    pub fn start(&self, receiver: &CommandReceiver<T>) {
+        self.run_updater_loop(receiver);
+    }
+
+    fn run_updater_loop(&self, receiver: &CommandReceiver<T>) {
         debug!("Starting updater loop");
         while self.is_running.load(Ordering::Relaxed) {
-            match self.next_command() {
+            match self.next_command(receiver) {
                 Err(err) => {
                     error!("{}", err);
                     break;
@@ -49,58 +177,246 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
         info!("Updater loop exited.")
     }
 
-    fn next_command(&self) -> StdResult<bool, String> {
-        match self.receiver.recv() {
-            Ok(cmd) => Ok(self.on_command(cmd)),
+    fn next_command(&self, receiver: &CommandReceiver<T>) -> StdResult<bool, String> {
+        match receiver.recv_tracked() {
+            Ok((tracked, cmd)) => Ok(self.on_tracked_command(tracked, cmd)),
             Err(err) => Err(format!("Failed to receive command from channel: {}", err)),
         }
     }
 
-    fn on_command(&self, cmd: ManagerCommand<T>) -> bool {
+    /// Executes the command and marks the refresh of the row `tracked` as
+    /// completed. Only refreshes marked by `InFlightRefreshes` are tracked.
+    pub fn on_tracked_command(&self, tracked: Option<usize>, cmd: ManagerCommand<T>) -> bool {
+        let go_on = self.on_command(cmd);
+        if let (Some(idx), Some(in_flight)) = (tracked, self.in_flight) {
+            in_flight.complete(idx);
+        }
+        go_on
+    }
+
+    pub fn on_command(&self, cmd: ManagerCommand<T>) -> bool {
         match cmd {
             ManagerCommand::ScheduledRefresh(idx, timestamp) => {
                 let row = &self.rows[idx];
                 let token_id = &row.lock().unwrap().token_id.clone();
-                debug!("Scheduled refresh for token '{}'", token_id);
+                debug!("Scheduled refresh for token {:?}", token_id);
                 let &(_, ref token) = self.tokens.get(token_id).unwrap();
-                self.refresh_token(row, token, timestamp);
+                let _ = self.refresh_token(row, token, timestamp);
                 true
             }
             ManagerCommand::ForceRefresh(token_id, timestamp) => {
-                info!("Forced refresh for token '{}'", token_id);
+                info!("Forced refresh for token {:?}", token_id);
                 let &(idx, ref token) = self.tokens.get(&token_id).unwrap();
                 let token_state = &self.rows[idx];
-                self.refresh_token(token_state, token, timestamp);
+                let _ = self.refresh_token(token_state, token, timestamp);
+                true
+            }
+            ManagerCommand::ForceRefreshAndAck(token_id, timestamp, ack) => {
+                info!("Forced refresh for token {:?} awaiting completion", token_id);
+                let &(idx, ref token) = self.tokens.get(&token_id).unwrap();
+                let token_state = &self.rows[idx];
+                ack.send(self.refresh_token(token_state, token, timestamp));
                 true
             }
             ManagerCommand::RefreshOnError(idx, timestamp) => {
                 let row = &self.rows[idx];
                 let token_id = &row.lock().unwrap().token_id.clone();
-                info!("Refresh on error for token '{}'", token_id);
+                info!("Refresh on error for token {:?}", token_id);
                 let &(_, ref token) = self.tokens.get(token_id).unwrap();
-                self.refresh_token(row, token, timestamp);
+                let _ = self.refresh_token(row, token, timestamp);
                 true
             }
         }
     }
 
+    /// Refreshes the token and returns the new `AccessToken` or the error
+    /// of the provider even if the current `AccessToken` was kept.
+    ///
+    /// Listeners are notified once the row was released so that they may
+    /// use the token themselves.
     fn refresh_token(
         &self,
         row: &Mutex<TokenRow<T>>,
         token: &Mutex<StdResult<AccessToken, TokenErrorKind>>,
         command_timestamp: u64,
-    ) {
-        let row: &mut TokenRow<T> = &mut *row.lock().unwrap();
-        if row.last_touched <= command_timestamp || row.token_state.is_uninitialized() {
-            match call_token_service(&*row.token_provider, &row.scopes) {
-                Ok(rsp) => {
-                    debug!("Update received token data");
-                    update_token_ok(rsp, row, token, self.clock);
+    ) -> RefreshOutcome {
+        let refreshed = self.refresh_row(&mut row.lock().unwrap(), token, command_timestamp);
+        let refreshed = match refreshed {
+            Some(refreshed) => refreshed,
+            None => {
+                info!("Skipping refresh because the command was too old.");
+                return token.lock().unwrap().clone();
+            }
+        };
+        if let Some(rotation) = refreshed.rotation {
+            let access_token = rotation.access_token.clone();
+            self.check_granted_scopes(&refreshed.token_id, rotation);
+            if let Some(rotation_listeners) = self.rotation_listeners {
+                rotation_listeners.rotated(&refreshed.token_id, &access_token);
+            }
+        }
+        #[cfg(feature = "async")]
+        {
+            if let Some(watchers) = self.watchers {
+                watchers.publish(&refreshed.token_id, &token.lock().unwrap());
+            }
+        }
+        refreshed.outcome
+    }
+
+    /// Refreshes the token of the locked `row` unless the command is older
+    /// than the last change of the row.
+    fn refresh_row(
+        &self,
+        row: &mut TokenRow<T>,
+        token: &Mutex<StdResult<AccessToken, TokenErrorKind>>,
+        command_timestamp: u64,
+    ) -> Option<Refreshed<T>> {
+        if row.last_touched > command_timestamp && !row.token_state.is_uninitialized() {
+            return None;
+        }
+        // A panicking provider must neither poison the row nor kill
+        // the updater.
+        let called = panic::catch_unwind(AssertUnwindSafe(|| {
+            call_token_service(&*row.token_provider, &row.scopes, &row.params, self.retry)
+                .and_then(|rsp| check_token_type(row, rsp))
+                .and_then(|rsp| verify_token(row, rsp))
+        }));
+        let (outcome, rotation) = match called {
+            Ok(Ok(mut rsp)) => {
+                debug!("Update received token data");
+                let access_token = rsp.access_token.clone();
+                let granted = rsp.scopes.take();
+                if let Some(expires_in) = adjusted_expires_in(row, rsp.expires_in) {
+                    rsp.expires_in = expires_in;
+                    self.notify_expiry_adjusted(row);
                 }
-                Err(err) => self.handle_error(err, row, token),
+                update_token_ok(rsp, row, token, self.clock);
+                self.update_refresh_on_access_at(row);
+                self.update_expires_at(row);
+                let rotation = Rotation {
+                    access_token: access_token.clone(),
+                    requested: row.scopes.clone(),
+                    granted,
+                    scope_introspection: row.scope_introspection.clone(),
+                };
+                (Ok(access_token), Some(rotation))
+            }
+            Ok(Err(err)) => {
+                let outcome = Err(TokenErrorKind::from(err.clone()));
+                self.handle_error(err, row, token);
+                (outcome, None)
             }
-        } else {
-            info!("Skipping refresh because the command was too old.");
+            Err(panic) => {
+                let err = AccessTokenProviderError::Other(format!(
+                    "The AccessTokenProvider panicked: {}",
+                    panic_message(&*panic)
+                ));
+                error!("Refreshing token {:?} failed: {}", row.token_id, err);
+                let outcome = Err(TokenErrorKind::from(err.clone()));
+                update_token_err(err, row, token, self.clock);
+                self.notify_panic(row);
+                (outcome, None)
+            }
+        };
+        if let Some(ref schedule_file) = row.schedule_file {
+            schedule_file.record(
+                &row.token_id,
+                TokenSchedule {
+                    refresh_at: row.refresh_at,
+                    expires_at: row.expires_at,
+                },
+            );
+        }
+        Some(Refreshed {
+            token_id: row.token_id.clone(),
+            outcome,
+            rotation,
+        })
+    }
+
+    /// Reports the requested `Scope`s missing from `granted` or from the
+    /// introspected token if the response contained no `Scope`s.
+    fn check_granted_scopes(&self, token_id: &T, rotation: Rotation) {
+        let granted = match (rotation.granted, &rotation.scope_introspection) {
+            (Some(granted), _) => granted,
+            (None, Some(service)) => match service.introspect(&rotation.access_token) {
+                Ok(token_info) => token_info.scope,
+                Err(err) => {
+                    warn!(
+                        "Could not introspect token {:?} for its scopes: {}",
+                        token_id, err
+                    );
+                    return;
+                }
+            },
+            (None, None) => return,
+        };
+
+        let missing: Vec<Scope> = rotation
+            .requested
+            .iter()
+            .filter(|scope| !granted.contains(scope))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        warn!(
+            "Token {:?} was granted without the requested scopes {}.",
+            token_id,
+            ScopeList::from(missing.clone())
+        );
+        if let Some(listener) = self.listener {
+            listener.on_scopes_downgraded(&ScopesDowngraded {
+                token_id: token_id.clone(),
+                requested: rotation.requested,
+                granted,
+                missing,
+            });
+        }
+    }
+
+    fn update_refresh_on_access_at(&self, row: &TokenRow<T>) {
+        let (refresh_on_access_at, window) =
+            match (self.refresh_on_access_at, row.refresh_on_access_window_ms) {
+                (Some(refresh_on_access_at), Some(window)) => (refresh_on_access_at, window),
+                _ => return,
+            };
+        if let Some((idx, _)) = self.tokens.get(&row.token_id) {
+            refresh_on_access_at[*idx]
+                .store(row.expires_at.saturating_sub(window), Ordering::SeqCst);
+        }
+    }
+
+    fn update_expires_at(&self, row: &TokenRow<T>) {
+        if let (Some(expires_at), Some((idx, _))) =
+            (self.expires_at, self.tokens.get(&row.token_id))
+        {
+            expires_at[*idx].store(row.expires_at, Ordering::SeqCst);
+        }
+    }
+
+    fn notify_panic(&self, row: &TokenRow<T>) {
+        if let Some(listener) = self.listener {
+            listener.on_notification(&TokenNotification {
+                token_id: row.token_id.clone(),
+                kind: NotificationKind::ProviderPanicked,
+                severity: NotificationSeverity::Critical,
+                at: Duration::from_millis(self.clock.now()),
+            });
+        }
+    }
+
+    fn notify_expiry_adjusted(&self, row: &TokenRow<T>) {
+        if let Some(listener) = self.listener {
+            listener.on_notification(&TokenNotification {
+                token_id: row.token_id.clone(),
+                kind: NotificationKind::ExpiryAdjusted,
+                severity: NotificationSeverity::Warning,
+                at: Duration::from_millis(self.clock.now()),
+            });
         }
     }
 
@@ -113,7 +429,7 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
         match row.token_state {
             TokenState::Uninitialized | TokenState::Initializing => {
                 error!(
-                    "Received an error for token '{}' which is not even initialized! \
+                    "Received an error for token {:?} which is not even initialized! \
                      Error: {}",
                     row.token_id, err
                 );
@@ -121,14 +437,14 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
             }
             TokenState::Ok | TokenState::OkPending => if row.expires_at <= self.clock.now() {
                 error!(
-                    "Received an error for token '{}' and the token has already expired! \
+                    "Received an error for token {:?} and the token has already expired! \
                      Error: {}",
                     row.token_id, err
                 );
                 update_token_err(err, row, token, self.clock);
             } else {
                 error!(
-                    "Received an error for token '{}'. Will not update the \
+                    "Received an error for token {:?}. Will not update the \
                      token because it is still valid. \
                      Error: {}",
                     row.token_id, err
@@ -136,7 +452,7 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
             },
             TokenState::Error | TokenState::ErrorPending => {
                 error!(
-                    "Received an error for token '{}' and the token is already \
+                    "Received an error for token {:?} and the token is already \
                      in error token_state! \
                      Error: {}",
                     row.token_id, err
@@ -147,7 +463,25 @@ impl<'a, T: Eq + Ord + Send + Clone + Display> TokenUpdater<'a, T> {
     }
 }
 
-fn update_token_ok<T: Display>(
+/// Returns the "expires in" to use instead of `expires_in` if it is
+/// missing or shorter than the minimum of the token's group.
+fn adjusted_expires_in<T: Debug>(row: &TokenRow<T>, expires_in: Duration) -> Option<Duration> {
+    let adjusted = match row.default_expires_in {
+        Some(default_expires_in) if expires_in == Duration::from_secs(0) => default_expires_in,
+        _ => expires_in,
+    }
+    .max(row.min_expires_in);
+    if adjusted == expires_in {
+        return None;
+    }
+    warn!(
+        "Token {:?} was issued with an expiry of {:?}. Assuming {:?} instead.",
+        row.token_id, expires_in, adjusted
+    );
+    Some(adjusted)
+}
+
+fn update_token_ok<T: Debug>(
     rsp: AuthorizationServerResponse,
     row: &mut TokenRow<T>,
     token: &Mutex<StdResult<AccessToken, TokenErrorKind>>,
@@ -159,12 +493,19 @@ fn update_token_ok<T: Display>(
     let old_last_touched = row.last_touched;
     row.last_touched = now;
     row.expires_at = now + expires_in_ms;
-    row.refresh_at = now + (expires_in_ms as f32 * row.refresh_threshold) as u64;
+    if row.refresh_on_access_window_ms.is_some() {
+        // Requests for the token trigger its refresh. Not refreshing it
+        // before it expired is no reason for a warning.
+        row.refresh_at = row.expires_at;
+        row.warn_at = row.expires_at;
+    } else {
+        row.refresh_at = now + (expires_in_ms as f32 * row.refresh_threshold) as u64;
+        row.warn_at = now + (expires_in_ms as f32 * row.warning_threshold) as u64;
+    }
     row.scheduled_for = row.refresh_at;
     row.token_state = TokenState::Ok;
-    row.warn_at = now + (expires_in_ms as f32 * row.warning_threshold) as u64;
     info!(
-        "Refreshed token '{}' after {:.3} minutes. New token will expire in {:.3} minutes. \
+        "Refreshed token {:?} after {:.3} minutes. New token will expire in {:.3} minutes. \
          Refresh in {:.3} minutes.",
         row.token_id,
         diff_millis(old_last_touched, now) as f64 / (60.0 * 1000.0),
@@ -173,13 +514,13 @@ fn update_token_ok<T: Display>(
     );
 }
 
-fn update_token_err<T: Display>(
+fn update_token_err<T: Debug>(
     err: AccessTokenProviderError,
     row: &mut TokenRow<T>,
     token: &Mutex<StdResult<AccessToken, TokenErrorKind>>,
     clock: &dyn Clock,
 ) {
-    *token.lock().unwrap() = Err(TokenErrorKind::AccessTokenProvider(err.to_string()));
+    *token.lock().unwrap() = Err(TokenErrorKind::from(err.clone()));
     let now = clock.now();
     row.last_touched = now;
     row.expires_at = now;
@@ -190,21 +531,97 @@ fn update_token_err<T: Display>(
         TokenState::Ok | TokenState::OkPending => now + 1_000,
         TokenState::Error | TokenState::ErrorPending => now + 5_000,
     };
+    // Do not ask again before the authorization server is willing to answer.
+    if let AccessTokenProviderError::RateLimited {
+        retry_after: Some(retry_after),
+    } = err
+    {
+        row.scheduled_for = cmp::max(row.scheduled_for, now + millis_from_duration(retry_after));
+    }
     row.token_state = TokenState::Error;
 }
 
+/// Fails if the received token is not of the expected `token_type`.
+fn check_token_type<T>(
+    row: &TokenRow<T>,
+    rsp: AuthorizationServerResponse,
+) -> AccessTokenProviderResult {
+    match (&row.expected_token_type, &rsp.token_type) {
+        (Some(expected), Some(received)) if !expected.eq_ignore_ascii_case(received) => {
+            Err(AccessTokenProviderError::Other(format!(
+                "Expected a token of type '{}' but received one of type '{}'",
+                expected, received
+            )))
+        }
+        _ => Ok(rsp),
+    }
+}
+
+/// Introspects the received token with the verification service of the row
+/// and fails unless the token is active and has all requested `Scope`s.
+fn verify_token<T>(row: &TokenRow<T>, rsp: AuthorizationServerResponse) -> AccessTokenProviderResult {
+    let service = match row.verification {
+        Some(ref service) => service,
+        None => return Ok(rsp),
+    };
+    let failed = |reason: String| {
+        AccessTokenProviderError::Other(format!(
+            "The received token failed its verification: {}",
+            reason
+        ))
+    };
+
+    let token_info = service
+        .introspect(&rsp.access_token)
+        .map_err(|err| failed(err.to_string()))?;
+    if !token_info.active {
+        return Err(failed("The token is not active".to_string()));
+    }
+    let missing: Vec<Scope> = row
+        .scopes
+        .iter()
+        .filter(|scope| !token_info.scope.contains(scope))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(failed(format!(
+            "The token lacks the scopes {}",
+            ScopeList::from(missing)
+        )));
+    }
+    Ok(rsp)
+}
+
 fn call_token_service(
     provider: &dyn AccessTokenProvider,
     scopes: &[Scope],
+    params: &TokenRequestParams,
+    retry: bool,
 ) -> AccessTokenProviderResult {
+    let _refreshing = RefreshingGuard::enter();
+    let mut backoff = ExponentialBackoff::default();
+    let budget = if retry {
+        backoff.max_elapsed_time.unwrap_or_default()
+    } else {
+        Duration::from_secs(0)
+    };
+    let start = Instant::now();
     let mut call =
         || -> StdResult<AuthorizationServerResponse, BError<AccessTokenProviderError>> {
-            match provider.request_access_token(scopes) {
+            match provider.request_access_token_with_params(scopes, params) {
                 Ok(rsp) => Ok(rsp),
                 Err(err @ AccessTokenProviderError::Server(_)) => {
                     warn!("Call to token service failed: {}", err);
                     Err(BError::Transient(err))
                 }
+                Err(AccessTokenProviderError::BadAuthorizationRequest(err))
+                    if err.error.is_transient() =>
+                {
+                    warn!("Call to token service failed: {:?}", err.error);
+                    Err(BError::Transient(
+                        AccessTokenProviderError::BadAuthorizationRequest(err),
+                    ))
+                }
                 Err(AccessTokenProviderError::BadAuthorizationRequest(err)) => {
                     warn!("Call to token service failed: {:?}", err.error);
                     Err(BError::Permanent(
@@ -225,12 +642,36 @@ fn call_token_service(
                 }
                 Err(err @ AccessTokenProviderError::Parse(_)) => Err(BError::Permanent(err)),
                 Err(err @ AccessTokenProviderError::Client(_)) => Err(BError::Permanent(err)),
+                Err(err @ AccessTokenProviderError::Unauthorized(_))
+                | Err(err @ AccessTokenProviderError::Forbidden(_))
+                | Err(err @ AccessTokenProviderError::EndpointNotFound(_)) => {
+                    // Retrying would not help until the configuration is fixed.
+                    error!("Call to token service was refused: {}", err);
+                    Err(BError::Permanent(err))
+                }
+                Err(AccessTokenProviderError::RateLimited { retry_after }) => {
+                    let err = AccessTokenProviderError::RateLimited { retry_after };
+                    warn!("Call to token service failed: {}", err);
+                    let remaining = budget.checked_sub(start.elapsed()).unwrap_or_default();
+                    match RetryAfter::within(retry_after, remaining) {
+                        RetryAfter::Unspecified => Err(BError::Transient(err)),
+                        RetryAfter::Wait(retry_after) => {
+                            thread::sleep(retry_after);
+                            Err(BError::Transient(err))
+                        }
+                        RetryAfter::ExceedsBudget => Err(BError::Permanent(err)),
+                    }
+                }
             }
         };
 
-    let mut backoff = ExponentialBackoff::default();
+    let result = if retry {
+        call.retry(&mut backoff)
+    } else {
+        call()
+    };
 
-    call.retry(&mut backoff).map_err(|err| match err {
+    result.map_err(|err| match err {
         BError::Transient(inner) => inner,
         BError::Permanent(inner) => inner,
     })
@@ -242,7 +683,6 @@ mod refresh_tests {
     use std::cell::Cell;
     use std::rc::Rc;
     use std::sync::atomic::AtomicBool;
-    use std::sync::mpsc;
     use std::sync::{Arc, Mutex};
     use crate::token_manager::AuthorizationServerResponse;
 
@@ -293,16 +733,15 @@ mod refresh_tests {
                 access_token: AccessToken::new(c.to_string()),
                 expires_in: Duration::from_secs(1),
                 refresh_token: None,
+                scopes: None,
+                token_type: None,
             });
             *c += 1;
             res
         }
     }
 
-    fn create_data() -> (
-        Vec<Mutex<TokenRow<&'static str>>>,
-        BTreeMap<&'static str, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>,
-    ) {
+    fn create_data() -> (Vec<Mutex<TokenRow<&'static str>>>, TokenSlots<&'static str>) {
         let mut groups = Vec::default();
         groups.push(
             ManagedTokenGroupBuilder::single_token(
@@ -344,12 +783,11 @@ mod refresh_tests {
 
     #[test]
     fn initializes_token_when_time_did_not_increase() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         clock.set(0);
         updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
@@ -372,18 +810,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn does_initialize_token_twice_when_time_did_not_increase() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         clock.set(0);
         updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
@@ -406,7 +843,7 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
 
         updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
@@ -429,18 +866,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn initializes_token_when_time_increased() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         clock.set(1);
         updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
@@ -463,18 +899,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn refreshes_initilalizing_token() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         {
             let mut row = rows[0].lock().unwrap();
@@ -505,18 +940,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn refreshes_ok_pending_token() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         {
             let mut row = rows[0].lock().unwrap();
@@ -547,18 +981,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn refreshes_error_token() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         {
             let mut row = rows[0].lock().unwrap();
@@ -589,18 +1022,17 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
         );
     }
 
     #[test]
     fn refreshes_error_pending_token() {
-        let (_, rx) = mpsc::channel();
         let is_running = AtomicBool::new(true);
         let clock = TestClock::new();
         let (rows, tokens) = create_data();
 
-        let updater = TokenUpdater::new(&rows, &tokens, rx, &is_running, &clock);
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock);
 
         {
             let mut row = rows[0].lock().unwrap();
@@ -631,8 +1063,415 @@ mod refresh_tests {
                 .unwrap()
                 .clone()
                 .unwrap()
-                .0
+                .into_inner()
+        );
+    }
+
+    struct EchoParamsProvider;
+
+    impl AccessTokenProvider for EchoParamsProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            unreachable!()
+        }
+
+        fn request_access_token_with_params(
+            &self,
+            _scopes: &[Scope],
+            params: &TokenRequestParams,
+        ) -> AccessTokenProviderResult {
+            let mut form = url::form_urlencoded::Serializer::new(String::new());
+            params.append_to(&mut form);
+            Ok(AuthorizationServerResponse {
+                access_token: AccessToken::new(form.finish()),
+                expires_in: Duration::from_secs(1),
+                refresh_token: None,
+                scopes: None,
+                token_type: None,
+            })
+        }
+    }
+
+    #[test]
+    fn params_of_the_managed_token_are_sent() {
+        let mut builder = ManagedTokenBuilder::default();
+        builder
+            .with_identifier("token")
+            .with_realm("/services")
+            .with_audience("api")
+            .with_resource("https://a.example.com")
+            .with_resource("https://b.example.com");
+        let managed_token = builder.build().unwrap();
+
+        let rsp = call_token_service(
+            &EchoParamsProvider,
+            &managed_token.scopes,
+            &managed_token.params,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            rsp.access_token.reveal(),
+            "realm=%2Fservices&audience=api&resource=https%3A%2F%2Fa.example.com\
+             &resource=https%3A%2F%2Fb.example.com"
+        );
+    }
+
+    struct PanickingProvider;
+
+    impl AccessTokenProvider for PanickingProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            panic!("provider bug")
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingListener(
+        Mutex<Vec<TokenNotification<&'static str>>>,
+        Mutex<Vec<ScopesDowngraded<&'static str>>>,
+    );
+
+    impl TokenLifecycleListener<&'static str> for RecordingListener {
+        fn on_notification(&self, notification: &TokenNotification<&'static str>) {
+            self.0.lock().unwrap().push(notification.clone());
+        }
+
+        fn on_scopes_downgraded(&self, downgrade: &ScopesDowngraded<&'static str>) {
+            self.1.lock().unwrap().push(downgrade.clone());
+        }
+    }
+
+    #[test]
+    fn panics_of_the_provider_put_the_token_into_error_state() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let groups = vec![ManagedTokenGroupBuilder::single_token(
+            "token",
+            vec![],
+            PanickingProvider,
+        )
+        .build()
+        .unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+        let listener = RecordingListener::default();
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock)
+            .with_listener(&listener)
+            .without_retries();
+
+        clock.set(10);
+        assert!(updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now())));
+        assert_eq!(TokenState::Error, rows[0].lock().unwrap().token_state);
+        match tokens.get("token").unwrap().1.lock().unwrap().clone() {
+            Err(TokenErrorKind::AccessTokenProvider(err)) => {
+                assert!(err.message().contains("provider bug"), "{}", err)
+            }
+            other => panic!("unexpected state: {:?}", other),
+        }
+        assert_eq!(
+            *listener.0.lock().unwrap(),
+            vec![TokenNotification {
+                token_id: "token",
+                kind: NotificationKind::ProviderPanicked,
+                severity: NotificationSeverity::Critical,
+                at: Duration::from_millis(10),
+            }]
+        );
+    }
+
+    struct GrantingProvider(&'static str);
+
+    impl AccessTokenProvider for GrantingProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            Ok(AuthorizationServerResponse {
+                access_token: AccessToken::new("token"),
+                expires_in: Duration::from_secs(1),
+                refresh_token: None,
+                scopes: Some(self.0.split(' ').map(Scope::new).collect()),
+                token_type: None,
+            })
+        }
+    }
+
+    #[test]
+    fn missing_scopes_are_reported() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let groups = vec![ManagedTokenGroupBuilder::single_token(
+            "token",
+            vec![Scope::new("read"), Scope::new("write")],
+            GrantingProvider("read"),
+        )
+        .build()
+        .unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+        let listener = RecordingListener::default();
+
+        let updater =
+            TokenUpdater::new(&rows, &tokens, &is_running, &clock).with_listener(&listener);
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+
+        assert_eq!(TokenState::Ok, rows[0].lock().unwrap().token_state);
+        assert_eq!(
+            *listener.1.lock().unwrap(),
+            vec![ScopesDowngraded {
+                token_id: "token",
+                requested: vec![Scope::new("read"), Scope::new("write")],
+                granted: vec![Scope::new("read")],
+                missing: vec![Scope::new("write")],
+            }]
         );
     }
 
+    struct FixedTokenInfoService {
+        active: bool,
+    }
+
+    impl TokenInfoService for FixedTokenInfoService {
+        fn introspect(&self, _token: &AccessToken) -> crate::TokenInfoResult<crate::TokenInfo> {
+            Ok(crate::TokenInfo {
+                active: self.active,
+                user_id: None,
+                scope: vec![Scope::new("scope")],
+                expires_in_seconds: None,
+                certificate_thumbprint: None,
+                from_stale_cache: false,
+                extra: Default::default(),
+            })
+        }
+    }
+
+    fn create_verified_data(
+        active: bool,
+    ) -> (Vec<Mutex<TokenRow<&'static str>>>, TokenSlots<&'static str>) {
+        let mut builder = ManagedTokenGroupBuilder::single_token(
+            "token",
+            vec![Scope::new("scope")],
+            DummyAccessTokenProvider::new(),
+        );
+        builder.verify_after_refresh(FixedTokenInfoService { active });
+        let groups = vec![builder.build().unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+        (rows, tokens)
+    }
+
+    #[test]
+    fn verified_tokens_are_published() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let (rows, tokens) = create_verified_data(true);
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock).without_retries();
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+
+        assert_eq!(TokenState::Ok, rows[0].lock().unwrap().token_state);
+        assert!(tokens.get("token").unwrap().1.lock().unwrap().is_ok());
+    }
+
+    #[test]
+    fn only_refreshed_tokens_are_passed_to_rotation_callbacks() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let rotated = Arc::new(Mutex::new(Vec::new()));
+
+        for &active in &[false, true] {
+            let (rows, tokens) = create_verified_data(active);
+            let listeners = create_rotation_listeners(&tokens);
+            let rotated = rotated.clone();
+            listeners.register(&"token", move |token: &AccessToken| {
+                rotated.lock().unwrap().push(token.reveal().to_string())
+            });
+
+            let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock)
+                .without_retries()
+                .with_rotation_listeners(&listeners);
+            updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+        }
+
+        assert_eq!(rotated.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rotation_callbacks_may_read_the_token() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let (rows, tokens) = create_verified_data(true);
+        let (rows, tokens) = (Arc::new(rows), Arc::new(tokens));
+        let listeners = create_rotation_listeners(&tokens);
+        let read = Arc::new(Mutex::new(Vec::new()));
+        {
+            let (rows, tokens, read) = (rows.clone(), tokens.clone(), read.clone());
+            listeners.register(&"token", move |rotated: &AccessToken| {
+                let row_released = rows[0].try_lock().is_ok();
+                let current = tokens.get("token").unwrap().1.lock().unwrap().clone();
+                let is_current = current
+                    .map(|token| token.reveal() == rotated.reveal())
+                    .unwrap_or(false);
+                read.lock().unwrap().push((row_released, is_current));
+            });
+        }
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock)
+            .without_retries()
+            .with_rotation_listeners(&listeners);
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+
+        assert_eq!(*read.lock().unwrap(), vec![(true, true)]);
+    }
+
+    #[test]
+    fn inactive_tokens_fail_the_refresh() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let (rows, tokens) = create_verified_data(false);
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock).without_retries();
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+
+        assert_eq!(TokenState::Error, rows[0].lock().unwrap().token_state);
+        let token = tokens.get("token").unwrap().1.lock().unwrap().clone();
+        match token {
+            Err(TokenErrorKind::AccessTokenProvider(err)) => {
+                assert!(err.message().contains("not active"), "{}", err)
+            }
+            other => panic!("unexpected state: {:?}", other),
+        }
+    }
+
+    struct RateLimitedProvider;
+
+    impl AccessTokenProvider for RateLimitedProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            Err(AccessTokenProviderError::RateLimited {
+                retry_after: Some(Duration::from_secs(60)),
+            })
+        }
+    }
+
+    #[test]
+    fn rate_limited_tokens_are_not_refreshed_before_the_retry_after() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let groups = vec![ManagedTokenGroupBuilder::single_token(
+            "token",
+            vec![],
+            RateLimitedProvider,
+        )
+        .build()
+        .unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock).without_retries();
+        clock.set(10);
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+
+        let row = rows[0].lock().unwrap();
+        assert_eq!(TokenState::Error, row.token_state);
+        assert_eq!(60_010, row.scheduled_for);
+    }
+
+    struct TypedTokenProvider;
+
+    impl AccessTokenProvider for TypedTokenProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            Ok(AuthorizationServerResponse {
+                access_token: AccessToken::new("token"),
+                expires_in: Duration::from_secs(1),
+                refresh_token: None,
+                scopes: None,
+                token_type: Some("mac".to_string()),
+            })
+        }
+    }
+
+    #[test]
+    fn unexpected_token_types_fail_the_refresh() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let bearer = ManagedTokenGroupBuilder::single_token("bearer", vec![], TypedTokenProvider);
+        let mut mac = ManagedTokenGroupBuilder::single_token("mac", vec![], TypedTokenProvider);
+        mac.with_expected_token_type("MAC");
+        let groups = vec![bearer.build().unwrap(), mac.build().unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock).without_retries();
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+        updater.on_command(ManagerCommand::ScheduledRefresh(1, clock.now()));
+
+        assert_eq!(TokenState::Error, rows[0].lock().unwrap().token_state);
+        assert_eq!(TokenState::Ok, rows[1].lock().unwrap().token_state);
+    }
+
+    struct NeverExpiringProvider;
+
+    impl AccessTokenProvider for NeverExpiringProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            Ok(AuthorizationServerResponse::new(
+                AccessToken::new("token"),
+                Duration::from_secs(0),
+            ))
+        }
+    }
+
+    #[test]
+    fn missing_expiries_are_adjusted() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let mut with_default =
+            ManagedTokenGroupBuilder::single_token("default", vec![], NeverExpiringProvider);
+        with_default.with_default_expires_in(Duration::from_secs(60));
+        let mut clamped =
+            ManagedTokenGroupBuilder::single_token("clamped", vec![], NeverExpiringProvider);
+        clamped.with_min_expires_in(Duration::from_secs(10));
+        let groups = vec![with_default.build().unwrap(), clamped.build().unwrap()];
+        let tokens = create_tokens(&groups);
+        let rows = create_rows(groups, 0);
+        let listener = RecordingListener::default();
+
+        let updater =
+            TokenUpdater::new(&rows, &tokens, &is_running, &clock).with_listener(&listener);
+        updater.on_command(ManagerCommand::ScheduledRefresh(0, clock.now()));
+        updater.on_command(ManagerCommand::ScheduledRefresh(1, clock.now()));
+
+        assert_eq!(60_000, rows[0].lock().unwrap().expires_at);
+        assert_eq!(45_000, rows[0].lock().unwrap().refresh_at);
+        assert_eq!(10_000, rows[1].lock().unwrap().expires_at);
+        assert_eq!(7_500, rows[1].lock().unwrap().refresh_at);
+        let kinds: Vec<_> = listener
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|notification| (notification.token_id, notification.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("default", NotificationKind::ExpiryAdjusted),
+                ("clamped", NotificationKind::ExpiryAdjusted),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_tracked_refreshes_are_completed() {
+        let is_running = AtomicBool::new(true);
+        let clock = TestClock::new();
+        let (rows, tokens) = create_data();
+        let in_flight = InFlightRefreshes::new(1);
+
+        let updater = TokenUpdater::new(&rows, &tokens, &is_running, &clock)
+            .with_in_flight(&in_flight)
+            .without_retries();
+        assert!(in_flight.begin(0));
+        updater.on_tracked_command(None, ManagerCommand::ForceRefresh("token", clock.now()));
+        assert!(!in_flight.begin(0));
+
+        updater.on_tracked_command(Some(0), ManagerCommand::ScheduledRefresh(0, clock.now()));
+        assert!(in_flight.begin(0));
+    }
 }
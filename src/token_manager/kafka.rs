@@ -0,0 +1,137 @@
+//! Authenticating Kafka clients with managed `AccessToken`s.
+//!
+//! `OAuthBearerContext` is an `rdkafka` client context which hands the
+//! token of a `GivesFixedAccessToken` to librdkafka whenever it asks for a
+//! token for SASL/OAUTHBEARER. librdkafka asks again at 80% of the lifetime
+//! of the previous token.
+//!
+//! ```rust,no_run
+//! use rdkafka::config::ClientConfig;
+//! use rdkafka::consumer::BaseConsumer;
+//! use tokkit::token_manager::kafka::OAuthBearerContext;
+//! use tokkit::token_manager::FixedAccessTokenSourceSync;
+//! use tokkit::AccessToken;
+//!
+//! let source = FixedAccessTokenSourceSync::new_detached("kafka", AccessToken::new("token"));
+//!
+//! let consumer: BaseConsumer<_> = ClientConfig::new()
+//!     .set("bootstrap.servers", "localhost:9092")
+//!     .set("security.protocol", "SASL_SSL")
+//!     .set("sasl.mechanism", "OAUTHBEARER")
+//!     .create_with_context(OAuthBearerContext::new(source, "my-service"))
+//!     .unwrap();
+//! ```
+use std::error::Error;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rdkafka::client::{ClientContext, OAuthToken};
+use rdkafka::consumer::ConsumerContext;
+use rdkafka::producer::{DeliveryResult, ProducerContext};
+
+use super::GivesFixedAccessToken;
+
+/// The lifetime reported for tokens with an unknown expiry by default.
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(60);
+
+/// An `rdkafka` context authenticating with SASL/OAUTHBEARER using the
+/// `AccessToken`s of a `GivesFixedAccessToken`.
+///
+/// The context can be used for consumers and producers. Delivery reports
+/// of producers are ignored.
+pub struct OAuthBearerContext<S, T> {
+    token_source: S,
+    principal_name: String,
+    default_lifetime: Duration,
+    _token_id: PhantomData<fn() -> T>,
+}
+
+impl<S, T> OAuthBearerContext<S, T>
+where
+    S: GivesFixedAccessToken<T> + Send + Sync + 'static,
+    T: Eq + Ord + Clone + Debug,
+{
+    /// Creates a context passing the tokens of `token_source` on for the
+    /// principal `principal_name`, which librdkafka only uses for logging.
+    pub fn new<P: Into<String>>(token_source: S, principal_name: P) -> Self {
+        OAuthBearerContext {
+            token_source,
+            principal_name: principal_name.into(),
+            default_lifetime: DEFAULT_LIFETIME,
+            _token_id: PhantomData,
+        }
+    }
+
+    /// Sets the lifetime reported for tokens whose expiry is unknown, e.g.
+    /// tokens of a detached source. Defaults to `DEFAULT_LIFETIME`.
+    pub fn with_default_lifetime(mut self, default_lifetime: Duration) -> Self {
+        self.default_lifetime = default_lifetime;
+        self
+    }
+}
+
+impl<S, T> ClientContext for OAuthBearerContext<S, T>
+where
+    S: GivesFixedAccessToken<T> + Send + Sync + 'static,
+    T: Eq + Ord + Clone + Debug,
+{
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(
+        &self,
+        _oauthbearer_config: Option<&str>,
+    ) -> Result<OAuthToken, Box<dyn Error>> {
+        let token = self
+            .token_source
+            .get_access_token()
+            .map_err(|err| format!("No access token for Kafka: {}", err))?;
+        let expires_at = self.token_source.expires_at();
+        Ok(OAuthToken {
+            token: token.reveal().to_string(),
+            principal_name: self.principal_name.clone(),
+            lifetime_ms: lifetime_ms(expires_at, self.default_lifetime, SystemTime::now()),
+        })
+    }
+}
+
+impl<S, T> ConsumerContext for OAuthBearerContext<S, T>
+where
+    S: GivesFixedAccessToken<T> + Send + Sync + 'static,
+    T: Eq + Ord + Clone + Debug,
+{
+}
+
+impl<S, T> ProducerContext for OAuthBearerContext<S, T>
+where
+    S: GivesFixedAccessToken<T> + Send + Sync + 'static,
+    T: Eq + Ord + Clone + Debug,
+{
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, _delivery_result: &DeliveryResult<'_>, _delivery_opaque: ()) {}
+}
+
+/// The expiry as milliseconds since the epoch as librdkafka expects it.
+///
+/// A token without a known expiry expires `default_lifetime` after `now`.
+fn lifetime_ms(expires_at: Option<SystemTime>, default_lifetime: Duration, now: SystemTime) -> i64 {
+    let expires_at = expires_at.unwrap_or(now + default_lifetime);
+    expires_at
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lifetimes_are_milliseconds_since_the_epoch() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let expires_at = Some(now + Duration::from_millis(1_500));
+        assert_eq!(lifetime_ms(expires_at, DEFAULT_LIFETIME, now), 1_001_500);
+        assert_eq!(lifetime_ms(None, DEFAULT_LIFETIME, now), 1_060_000);
+    }
+}
@@ -3,23 +3,46 @@
 //! `AccessToken`s are managed by configuring a `ManagedToken`.
 //! They can later be queried by the identifier configured with
 //! the `ManagedToken`. The identifier can be any type `T` where
-//! `T: Eq + Ord + Send + Sync + Clone + Display + 'static`
+//! `T: Eq + Ord + Send + Sync + Clone + Debug + 'static`
 use std::collections::BTreeMap;
 use std::env;
-use std::fmt::Display;
+use std::fmt::Debug;
 use std::result::Result as StdResult;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
-use crate::{AccessToken, Scope};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::validation::{check_scope, ValidationReport};
+use crate::{AccessToken, Scope, ScopeList, TokenInfoService};
 
 mod error;
 mod internals;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod rotation;
+pub mod tenant;
+pub mod testing;
 pub mod token_provider;
+#[cfg(feature = "async")]
+pub mod watch;
 
 pub use self::error::*;
+use self::internals::command_queue::{self, CommandSender};
+pub use self::internals::schedule::{ScheduleFile, TokenSchedule};
+pub(crate) use self::internals::token_updater::is_refreshing_on_current_thread;
+#[cfg(test)]
+pub(crate) use self::internals::token_updater::RefreshingGuard;
+use self::internals::TokenSlots;
+pub use self::internals::{
+    NotificationKind, NotificationSeverity, ScopesDowngraded, TokenLifecycleListener,
+    TokenNotification,
+};
+#[cfg(feature = "async")]
+pub use self::watch::AccessTokenReceiver;
+#[cfg(feature = "async")]
+use self::watch::TokenWatchers;
+use self::rotation::RotationListeners;
 use self::token_provider::*;
 use super::{InitializationError, InitializationResult};
 
@@ -27,9 +50,11 @@ use super::{InitializationError, InitializationResult};
 pub struct ManagedTokenBuilder<T> {
     pub token_id: Option<T>,
     pub scopes: Vec<Scope>,
+    pub params: TokenRequestParams,
+    pub acquire: Acquire,
 }
 
-impl<T: Eq + Send + Clone + Display> ManagedTokenBuilder<T> {
+impl<T: Eq + Send + Clone + Debug> ManagedTokenBuilder<T> {
     /// Sets the token identifier to identify and query the managed token.
     ///
     /// Setting the identifier is mandatory.
@@ -52,6 +77,33 @@ impl<T: Eq + Send + Clone + Display> ManagedTokenBuilder<T> {
         self
     }
 
+    /// Sets the realm sent with every request for the `AccessToken`.
+    pub fn with_realm<R: Into<String>>(&mut self, realm: R) -> &mut Self {
+        self.params.realm = Some(realm.into());
+        self
+    }
+
+    /// Adds an audience the `AccessToken` is requested for.
+    pub fn with_audience<A: Into<String>>(&mut self, audience: A) -> &mut Self {
+        self.params.audiences.push(audience.into());
+        self
+    }
+
+    /// Adds a resource indicator as defined in
+    /// [RFC 8707](https://tools.ietf.org/html/rfc8707) the `AccessToken` is
+    /// requested for.
+    pub fn with_resource<R: Into<String>>(&mut self, resource: R) -> &mut Self {
+        self.params.resources.push(resource.into());
+        self
+    }
+
+    /// Sets when the `AccessToken` is fetched for the first time. The
+    /// default is `Acquire::Eager`.
+    pub fn with_acquire(&mut self, acquire: Acquire) -> &mut Self {
+        self.acquire = acquire;
+        self
+    }
+
     /// Adds `Scope`s from the environment. They are read from
     /// `TOKKIT_MANAGED_TOKEN_SCOPES` and must be separated by spaces.
     pub fn with_scopes_from_env(&mut self) -> StdResult<&mut Self, InitializationError> {
@@ -67,10 +119,12 @@ impl<T: Eq + Send + Clone + Display> ManagedTokenBuilder<T> {
     ) -> StdResult<&mut Self, InitializationError> {
         match env::var(env_name) {
             Ok(v) => {
-                let scopes = split_scopes(&v);
-                self.with_scopes(scopes)
+                let scopes: ScopeList = v.parse().map_err(|err: crate::InvalidScope| {
+                    InitializationError::env_var(env_name, err)
+                })?;
+                self.with_scopes(scopes.into_vec())
             }
-            Err(err) => return Err(InitializationError(err.to_string())),
+            Err(err) => return Err(InitializationError::env_var(env_name, err)),
         };
         Ok(self)
     }
@@ -80,24 +134,18 @@ impl<T: Eq + Send + Clone + Display> ManagedTokenBuilder<T> {
         let token_id = if let Some(token_id) = self.token_id {
             token_id
         } else {
-            return Err(InitializationError("Token name is mandatory".to_string()));
+            return Err(InitializationError::MissingField("Token name".to_string()));
         };
 
         Ok(ManagedToken {
             token_id,
-            scopes: self.scopes,
+            scopes: ScopeList::from(self.scopes).into_vec(),
+            params: self.params,
+            acquire: self.acquire,
         })
     }
 }
 
-fn split_scopes(input: &str) -> Vec<Scope> {
-    input
-        .split(' ')
-        .filter(|s| !s.is_empty())
-        .map(Scope::new)
-        .collect()
-}
-
 impl ManagedTokenBuilder<String> {
     /// Sets the `token_id` for this managed token from an environment variable.
     /// The `token_id` is read from `TOKKIT_MANAGED_TOKEN_ID`.
@@ -113,17 +161,19 @@ impl ManagedTokenBuilder<String> {
     ) -> StdResult<&mut Self, InitializationError> {
         match env::var(env_name) {
             Ok(v) => self.token_id = Some(v),
-            Err(err) => return Err(InitializationError(err.to_string())),
+            Err(err) => return Err(InitializationError::env_var(env_name, err)),
         };
         Ok(self)
     }
 }
 
-impl<T: Eq + Send + Clone + Display> Default for ManagedTokenBuilder<T> {
+impl<T: Eq + Send + Clone + Debug> Default for ManagedTokenBuilder<T> {
     fn default() -> Self {
         ManagedTokenBuilder {
             token_id: Default::default(),
             scopes: Default::default(),
+            params: Default::default(),
+            acquire: Default::default(),
         }
     }
 }
@@ -133,6 +183,40 @@ impl<T: Eq + Send + Clone + Display> Default for ManagedTokenBuilder<T> {
 pub struct ManagedToken<T> {
     pub token_id: T,
     pub scopes: Vec<Scope>,
+    /// Sent with every request for the `AccessToken` by providers
+    /// supporting them.
+    pub params: TokenRequestParams,
+    /// When the `AccessToken` is fetched for the first time.
+    pub acquire: Acquire,
+}
+
+/// The time a request for a lazily acquired token waits for the token by
+/// default.
+pub const DEFAULT_LAZY_ACQUISITION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// When a `ManagedToken` is fetched for the first time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Acquire {
+    /// The token is fetched when the `AccessTokenManager` is started. This
+    /// is the default.
+    #[default]
+    Eager,
+    /// The token is fetched when it is requested for the first time. The
+    /// request waits up to `timeout` for the token.
+    ///
+    /// This saves requests to the authorization server for tokens most
+    /// instances never use. Once fetched the token is refreshed like an
+    /// eagerly acquired one.
+    Lazy { timeout: Duration },
+}
+
+impl Acquire {
+    /// `Acquire::Lazy` with the `DEFAULT_LAZY_ACQUISITION_TIMEOUT`.
+    pub fn lazy() -> Acquire {
+        Acquire::Lazy {
+            timeout: DEFAULT_LAZY_ACQUISITION_TIMEOUT,
+        }
+    }
 }
 
 pub struct ManagedTokenGroupBuilder<T, S: AccessTokenProvider + 'static> {
@@ -140,9 +224,18 @@ pub struct ManagedTokenGroupBuilder<T, S: AccessTokenProvider + 'static> {
     managed_tokens: Vec<ManagedToken<T>>,
     refresh_threshold: f32,
     warning_threshold: f32,
+    escalation_window: Option<Duration>,
+    scope_introspection: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    verification: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    expected_token_type: Option<String>,
+    default_expires_in: Option<Duration>,
+    min_expires_in: Duration,
+    refresh_on_access_within: Option<Duration>,
+    schedule_file: Option<ScheduleFile>,
+    isolated: bool,
 }
 
-impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'static>
+impl<T: Eq + Send + Clone + Debug, S: AccessTokenProvider + Send + Sync + 'static>
     ManagedTokenGroupBuilder<T, S>
 {
     /// Sets the `AccessTokenProvider` for this group of `ManagedToken`s.
@@ -172,6 +265,131 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'sta
         self
     }
 
+    /// Sets the time before the expiry of a token within which its
+    /// notifications are escalated to `NotificationSeverity::Escalated`.
+    /// By default notifications are not escalated before the token expired.
+    pub fn with_escalation_window(&mut self, escalation_window: Duration) -> &mut Self {
+        self.escalation_window = Some(escalation_window);
+        self
+    }
+
+    /// Introspects refreshed tokens with `service` to find the granted
+    /// `Scope`s if the authorization server did not echo them.
+    ///
+    /// A `ScopesDowngraded` is delivered to the `TokenLifecycleListener`
+    /// if not all requested `Scope`s were granted.
+    pub fn with_scope_introspection<I>(&mut self, service: I) -> &mut Self
+    where
+        I: TokenInfoService + Send + Sync + 'static,
+    {
+        self.scope_introspection = Some(Arc::new(service));
+        self
+    }
+
+    /// Like `with_scope_introspection` but uses a `service` which is
+    /// shared, e.g. with the introspections of a resource server, as it is.
+    pub fn with_shared_scope_introspection(
+        &mut self,
+        service: Arc<dyn TokenInfoService + Send + Sync + 'static>,
+    ) -> &mut Self {
+        self.scope_introspection = Some(service);
+        self
+    }
+
+    /// Introspects every received token with `service` before it is
+    /// published.
+    ///
+    /// A token which is not active or lacks requested `Scope`s is treated
+    /// like a failed refresh.
+    pub fn verify_after_refresh<I>(&mut self, service: I) -> &mut Self
+    where
+        I: TokenInfoService + Send + Sync + 'static,
+    {
+        self.verification = Some(Arc::new(service));
+        self
+    }
+
+    /// Like `verify_after_refresh` but uses a `service` which is shared as
+    /// it is.
+    ///
+    /// This way the verifications can go through the same caching or
+    /// limiting `TokenInfoService`s as the introspections of a resource
+    /// server instead of asking the introspection service directly.
+    pub fn verify_after_refresh_shared(
+        &mut self,
+        service: Arc<dyn TokenInfoService + Send + Sync + 'static>,
+    ) -> &mut Self {
+        self.verification = Some(service);
+        self
+    }
+
+    /// Sets the `token_type` received tokens must have if the authorization
+    /// server sends one. It is compared case insensitively.
+    ///
+    /// The default is `Bearer`. Tokens of another type are treated like a
+    /// failed refresh.
+    pub fn with_expected_token_type<N: Into<String>>(&mut self, token_type: N) -> &mut Self {
+        self.expected_token_type = Some(token_type.into());
+        self
+    }
+
+    /// Accepts tokens of any `token_type`.
+    pub fn accept_any_token_type(&mut self) -> &mut Self {
+        self.expected_token_type = None;
+        self
+    }
+
+    /// Sets the lifetime assumed for tokens the authorization server sent
+    /// without an "expires in" or with an "expires in" of zero.
+    ///
+    /// By default such tokens are refreshed as if they expired after the
+    /// minimum "expires in".
+    pub fn with_default_expires_in(&mut self, default_expires_in: Duration) -> &mut Self {
+        self.default_expires_in = Some(default_expires_in);
+        self
+    }
+
+    /// Sets the shortest "expires in" accepted from the authorization
+    /// server. Shorter ones are raised to it so that tokens are not
+    /// refreshed in a tight loop. The default is one second.
+    ///
+    /// A `NotificationKind::ExpiryAdjusted` is delivered whenever the
+    /// "expires in" of a token was replaced.
+    pub fn with_min_expires_in(&mut self, min_expires_in: Duration) -> &mut Self {
+        self.min_expires_in = min_expires_in;
+        self
+    }
+
+    /// Refreshes the tokens only when they are requested within `window`
+    /// before their expiry instead of at the refresh threshold.
+    ///
+    /// The refresh is requested in the background and the current
+    /// `AccessToken` is returned. Tokens which are not requested are
+    /// refreshed once they expired so that rarely used tokens do not cause
+    /// requests to the authorization server.
+    pub fn refresh_on_access_within(&mut self, window: Duration) -> &mut Self {
+        self.refresh_on_access_within = Some(window);
+        self
+    }
+
+    /// Keeps the schedule of the tokens in `schedule_file` so that tokens
+    /// refreshed shortly before a restart are not fetched again right
+    /// after it.
+    pub fn with_schedule_file(&mut self, schedule_file: ScheduleFile) -> &mut Self {
+        self.schedule_file = Some(schedule_file);
+        self
+    }
+
+    /// Refreshes the tokens of this group on an updater thread of its own.
+    ///
+    /// By default all groups share one updater thread so that a provider
+    /// which hangs delays the refreshes of all other groups. The refreshes
+    /// of an isolated group can only delay each other.
+    pub fn isolated(&mut self, isolated: bool) -> &mut Self {
+        self.isolated = isolated;
+        self
+    }
+
     /// Adds a `ManagedToken` built from the given `ManagedTokenBuilder`.
     pub fn with_managed_token_from_builder(
         &mut self,
@@ -183,7 +401,12 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'sta
 
     /// Sets everything needed to manage the give token.
     pub fn single_token(token_id: T, scopes: Vec<Scope>, token_provider: S) -> Self {
-        let managed_token = ManagedToken { token_id, scopes };
+        let managed_token = ManagedToken {
+            token_id,
+            scopes: ScopeList::from(scopes).into_vec(),
+            params: TokenRequestParams::default(),
+            acquire: Acquire::Eager,
+        };
         let mut builder = Self::default();
         builder.with_managed_token(managed_token);
         builder.with_token_provider(token_provider);
@@ -209,6 +432,58 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'sta
         Ok(builder)
     }
 
+    /// Checks the configuration including the `AccessTokenProvider` and
+    /// returns a report of all checks instead of failing at the first
+    /// problem like `build`.
+    ///
+    /// The endpoints of the provider are only connected to if `live_probe`
+    /// is set.
+    pub fn validate(&self, live_probe: bool) -> ValidationReport {
+        let mut report = ValidationReport::new();
+
+        if self.managed_tokens.is_empty() {
+            report.failed(
+                "managed tokens are given",
+                "Managed Tokens must not be empty",
+            );
+        } else {
+            report.passed("managed tokens are given");
+        }
+        let mut seen = Vec::new();
+        for managed_token in &self.managed_tokens {
+            if seen.contains(&&managed_token.token_id) {
+                report.failed(
+                    format!("token id {:?} is unique", managed_token.token_id),
+                    "The token id is used more than once",
+                );
+            } else {
+                seen.push(&managed_token.token_id);
+            }
+            for scope in &managed_token.scopes {
+                check_scope(&mut report, scope);
+            }
+        }
+
+        report.check(
+            "refresh threshold is of (0;1]",
+            check_threshold(self.refresh_threshold),
+        );
+        report.check(
+            "warning threshold is of (0;1]",
+            check_threshold(self.warning_threshold),
+        );
+
+        match self.token_provider {
+            Some(ref token_provider) => {
+                report.passed("token provider is set");
+                report.merge("token provider", token_provider.validate(live_probe));
+            }
+            None => report.failed("token provider is set", "Token service is mandatory"),
+        }
+
+        report
+    }
+
     /// Build the `ManagedTokenGroup`.
     ///
     /// Fails if not all required fields are set properly.
@@ -216,25 +491,25 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'sta
         let token_provider = if let Some(token_provider) = self.token_provider {
             token_provider
         } else {
-            return Err(InitializationError(
-                "Token service is mandatory".to_string(),
+            return Err(InitializationError::MissingField(
+                "Token service".to_string(),
             ));
         };
 
         if self.managed_tokens.is_empty() {
-            return Err(InitializationError(
-                "Managed Tokens must not be empty".to_string(),
+            return Err(InitializationError::MissingField(
+                "A managed token".to_string(),
             ));
         }
 
-        if self.refresh_threshold <= 0.0 || self.refresh_threshold > 1.0 {
-            return Err(InitializationError(
+        if check_threshold(self.refresh_threshold).is_err() {
+            return Err(InitializationError::Other(
                 "Refresh threshold must be of (0;1]".to_string(),
             ));
         }
 
-        if self.warning_threshold <= 0.0 || self.warning_threshold > 1.0 {
-            return Err(InitializationError(
+        if check_threshold(self.warning_threshold).is_err() {
+            return Err(InitializationError::Other(
                 "Warning threshold must be of (0;1]".to_string(),
             ));
         }
@@ -244,11 +519,20 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + Send + Sync + 'sta
             managed_tokens: self.managed_tokens,
             refresh_threshold: self.refresh_threshold,
             warning_threshold: self.warning_threshold,
+            escalation_window: self.escalation_window,
+            scope_introspection: self.scope_introspection,
+            verification: self.verification,
+            expected_token_type: self.expected_token_type,
+            default_expires_in: self.default_expires_in,
+            min_expires_in: self.min_expires_in,
+            refresh_on_access_within: self.refresh_on_access_within,
+            schedule_file: self.schedule_file,
+            isolated: self.isolated,
         })
     }
 }
 
-impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + 'static> Default
+impl<T: Eq + Send + Clone + Debug, S: AccessTokenProvider + 'static> Default
     for ManagedTokenGroupBuilder<T, S>
 {
     fn default() -> Self {
@@ -257,10 +541,27 @@ impl<T: Eq + Send + Clone + Display, S: AccessTokenProvider + 'static> Default
             managed_tokens: Default::default(),
             refresh_threshold: 0.75,
             warning_threshold: 0.85,
+            escalation_window: None,
+            scope_introspection: None,
+            verification: None,
+            expected_token_type: Some("Bearer".to_string()),
+            default_expires_in: None,
+            min_expires_in: Duration::from_secs(1),
+            refresh_on_access_within: None,
+            schedule_file: None,
+            isolated: false,
         }
     }
 }
 
+fn check_threshold(threshold: f32) -> StdResult<(), String> {
+    if threshold <= 0.0 || threshold > 1.0 {
+        Err(format!("{} is out of range", threshold))
+    } else {
+        Ok(())
+    }
+}
+
 /// A group of `ManagedToken`s that are requested from the same authorization
 /// server
 pub struct ManagedTokenGroup<T> {
@@ -269,6 +570,26 @@ pub struct ManagedTokenGroup<T> {
     pub managed_tokens: Vec<ManagedToken<T>>,
     pub refresh_threshold: f32,
     pub warning_threshold: f32,
+    /// Notifications are escalated within this time before the expiry.
+    pub escalation_window: Option<Duration>,
+    /// Finds the granted `Scope`s if the response of the authorization
+    /// server does not contain them.
+    pub scope_introspection: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    /// Introspects received tokens before they are published.
+    pub verification: Option<Arc<dyn TokenInfoService + Send + Sync + 'static>>,
+    /// The `token_type` received tokens must have if there is one.
+    pub expected_token_type: Option<String>,
+    /// The lifetime of tokens received without an "expires in".
+    pub default_expires_in: Option<Duration>,
+    /// Shorter "expires in"s are raised to this one.
+    pub min_expires_in: Duration,
+    /// Tokens requested within this time before their expiry are
+    /// refreshed instead of at the refresh threshold.
+    pub refresh_on_access_within: Option<Duration>,
+    /// Keeps the schedule of the tokens across restarts.
+    pub schedule_file: Option<ScheduleFile>,
+    /// Refreshes the tokens on an updater thread of their own.
+    pub isolated: bool,
 }
 
 /// Keeps track of running client for global shutdown
@@ -293,21 +614,47 @@ impl Drop for IsRunningGuard {
 /// Can be queired for `AccessToken`s by their
 /// identifier configured with the respective
 /// `ManagedToken`.
-pub trait GivesAccessTokensById<T: Eq + Ord + Clone + Display> {
+pub trait GivesAccessTokensById<T: Eq + Ord + Clone + Debug> {
     /// Get an `AccessToken` by identifier.
     fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken>;
     /// Refresh the `AccessToken` for the given identifier.
     fn refresh(&self, name: &T);
 }
 
+/// A token source which can be shared between threads and components
+/// without knowing its type, e.g. to inject it with a dependency injection
+/// container.
+pub type SharedAccessTokenSource<T> = Arc<dyn GivesAccessTokensById<T> + Send + Sync>;
+
+impl<T, S> GivesAccessTokensById<T> for Arc<S>
+where
+    T: Eq + Ord + Clone + Debug,
+    S: GivesAccessTokensById<T> + ?Sized,
+{
+    fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
+        (**self).get_access_token(token_id)
+    }
+
+    fn refresh(&self, name: &T) {
+        (**self).refresh(name)
+    }
+}
+
 #[derive(Clone)]
 pub struct AccessTokenSource<T> {
-    tokens: Arc<BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>>,
-    sender: Sender<internals::ManagerCommand<T>>,
+    tokens: Arc<TokenSlots<T>>,
+    sender: CommandSender<T>,
     is_running: Arc<IsRunningGuard>,
+    paused: Arc<Vec<AtomicBool>>,
+    refresh_on_access_at: Arc<Vec<AtomicU64>>,
+    expires_at: Arc<Vec<AtomicU64>>,
+    rotation_listeners: Arc<RotationListeners<T>>,
+    acquire: Arc<Vec<Acquire>>,
+    #[cfg(feature = "async")]
+    watchers: Arc<TokenWatchers<T>>,
 }
 
-impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
+impl<T: Eq + Ord + Clone + Debug> AccessTokenSource<T> {
     /// Get a `SingleAccessTokenSource` for the given identifier.
     ///
     /// Fails if no `ManagedToken` with the given id exists.
@@ -317,7 +664,7 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
                 token_source: self.clone(),
                 token_id: token_id.clone(),
             }),
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
+            None => Err(TokenErrorKind::no_token(token_id).into()),
         }
     }
 
@@ -334,7 +681,7 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
                 token_source: self.synced(),
                 token_id: token_id.clone(),
             }),
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
+            None => Err(TokenErrorKind::no_token(token_id).into()),
         }
     }
 
@@ -342,11 +689,138 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
     pub fn synced(&self) -> AccessTokenSourceSync<T> {
         AccessTokenSourceSync {
             tokens: self.tokens.clone(),
-            sender: Arc::new(Mutex::new(self.sender.clone())),
+            sender: self.sender.clone(),
             is_running: self.is_running.clone(),
+            paused: self.paused.clone(),
+            refresh_on_access_at: self.refresh_on_access_at.clone(),
+            expires_at: self.expires_at.clone(),
+            rotation_listeners: self.rotation_listeners.clone(),
+            acquire: self.acquire.clone(),
+            #[cfg(feature = "async")]
+            watchers: self.watchers.clone(),
         }
     }
 
+    /// Get an `AccessTokenReceiver` notified whenever the `AccessToken`
+    /// for the given identifier changes.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    #[cfg(feature = "async")]
+    pub fn watch(&self, token_id: &T) -> TokenResult<AccessTokenReceiver> {
+        self.watchers
+            .subscribe(token_id)
+            .ok_or_else(|| TokenErrorKind::no_token(token_id).into())
+    }
+
+    /// Registers a callback invoked with the new `AccessToken` for the
+    /// given identifier right after each successful refresh, e.g. to
+    /// re-authenticate a long-lived connection.
+    ///
+    /// The callback runs on the thread refreshing the token and should
+    /// return quickly.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn on_rotation<F>(&self, token_id: &T, callback: F) -> TokenResult<()>
+    where
+        F: Fn(&AccessToken) + Send + Sync + 'static,
+    {
+        if self.rotation_listeners.register(token_id, callback) {
+            Ok(())
+        } else {
+            Err(TokenErrorKind::no_token(token_id).into())
+        }
+    }
+
+    /// Stops the scheduled refreshes of the token with the given identifier
+    /// until it is resumed, e.g. during a maintenance of the authorization
+    /// server. The current `AccessToken` is kept and explicit refreshes
+    /// are still executed.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn pause(&self, token_id: &T) -> TokenResult<()> {
+        set_paused(&self.tokens, &self.paused, token_id, true)
+    }
+
+    /// Resumes the scheduled refreshes of a paused token. A refresh that
+    /// became due while the token was paused is executed immediately.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn resume(&self, token_id: &T) -> TokenResult<()> {
+        set_paused(&self.tokens, &self.paused, token_id, false)
+    }
+
+    /// Get the `TokenStatus` of the token with the given identifier.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn token_status(&self, token_id: &T) -> TokenResult<TokenStatus> {
+        token_status(&self.tokens, &self.paused, &self.expires_at, token_id)
+    }
+
+    /// Get the identifiers of all managed tokens in their order.
+    pub fn token_ids(&self) -> Vec<T> {
+        self.tokens.keys().cloned().collect()
+    }
+
+    /// Iterates over the identifiers of all managed tokens in their order
+    /// together with their `TokenStatus`.
+    ///
+    /// Each `TokenStatus` is taken when the iterator reaches its token.
+    pub fn iter(&self) -> impl Iterator<Item = (T, TokenStatus)> + '_ {
+        token_statuses(&self.tokens, &self.paused, &self.expires_at)
+    }
+
+    /// Get all managed tokens ordered by their identifiers.
+    ///
+    /// All tokens are taken at the same point in time so no token is
+    /// updated while the snapshot is taken.
+    pub fn snapshot(&self) -> Vec<(T, TokenResult<AccessToken>)> {
+        snapshot(&self.tokens)
+    }
+
+    /// The number of commands for the updater which were dropped because
+    /// its queue was full, e.g. since an `AccessTokenProvider` is slow.
+    pub fn dropped_commands(&self) -> u64 {
+        self.sender.dropped_commands()
+    }
+
+    /// Refreshes the `AccessToken` for the given identifier and blocks until
+    /// the refresh completed or the `timeout` elapsed.
+    ///
+    /// Returns the new `AccessToken` or the error of the refresh even if
+    /// the current `AccessToken` is still valid and kept.
+    pub fn refresh_and_wait(&self, token_id: &T, timeout: Duration) -> TokenResult<AccessToken> {
+        refresh_and_wait(&self.tokens, token_id, timeout, |command| {
+            self.sender.send(command).is_ok()
+        })
+    }
+
+    /// Get an `AccessToken` by identifier without blocking while a lazily
+    /// acquired token is fetched for the first time.
+    #[cfg(feature = "async")]
+    pub async fn get_access_token_async(&self, token_id: &T) -> TokenResult<AccessToken> {
+        get_access_token_async(
+            &self.tokens,
+            &self.sender,
+            &self.acquire,
+            &self.refresh_on_access_at,
+            token_id,
+        )
+        .await
+    }
+
+    /// The async variant of `refresh_and_wait`.
+    #[cfg(feature = "async")]
+    pub async fn refresh_and_wait_async(
+        &self,
+        token_id: &T,
+        timeout: Duration,
+    ) -> TokenResult<AccessToken> {
+        refresh_and_wait_async(&self.tokens, token_id, timeout, |command| {
+            self.sender.send(command).is_ok()
+        })
+        .await
+    }
+
     /// Creates a new `AccessTokenSource` which is not attached to an
     /// `AccessTokenManager`.
     ///
@@ -355,6 +829,9 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
     /// `AccessToken`s do not need to be updated in the background(CLI etc).
     ///
     /// The `refresh` method will not do anything meaningful...
+    ///
+    /// Use a `testing::DetachedAccessTokenSource` to change the tokens
+    /// while testing.
     pub fn new_detached(tokens: &[(T, AccessToken)]) -> AccessTokenSource<T> {
         let mut tokens_map = BTreeMap::new();
 
@@ -363,9 +840,16 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
             tokens_map.insert(id.clone(), item);
         }
 
-        let (tx, _) = ::std::sync::mpsc::channel::<internals::ManagerCommand<T>>();
+        let tx = detached_command_queue(tokens_map.len());
 
         AccessTokenSource {
+            #[cfg(feature = "async")]
+            watchers: Arc::new(internals::create_watchers(&tokens_map)),
+            paused: Arc::new(internals::create_pause_flags(&tokens_map)),
+            refresh_on_access_at: Arc::new(internals::create_refresh_on_access_times(&tokens_map)),
+            expires_at: Arc::new(internals::create_expiry_times(&tokens_map)),
+            rotation_listeners: Arc::new(internals::create_rotation_listeners(&tokens_map)),
+            acquire: Arc::new(vec![Acquire::Eager; tokens_map.len()]),
             tokens: Arc::new(tokens_map),
             is_running: Default::default(),
             sender: tx,
@@ -373,25 +857,19 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSource<T> {
     }
 }
 
-impl<T: Eq + Ord + Clone + Display> GivesAccessTokensById<T> for AccessTokenSource<T> {
+impl<T: Eq + Ord + Clone + Debug> GivesAccessTokensById<T> for AccessTokenSource<T> {
     fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
-        match self.tokens.get(&token_id) {
-            Some((_, guard)) => match &*guard.lock().unwrap() {
-                Ok(token) => Ok(token.clone()),
-                Err(err) => Err(err.clone().into()),
-            },
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
-        }
+        get_access_token(
+            &self.tokens,
+            &self.sender,
+            &self.acquire,
+            &self.refresh_on_access_at,
+            token_id,
+        )
     }
 
     fn refresh(&self, name: &T) {
-        match self.sender.send(internals::ManagerCommand::ForceRefresh(
-            name.clone(),
-            internals::Clock::now(&internals::SystemClock),
-        )) {
-            Ok(_) => (),
-            Err(err) => warn!("Could send send refresh command for {}: {}", name, err),
-        }
+        request_refresh(&self.tokens, &self.sender, name)
     }
 }
 
@@ -400,12 +878,19 @@ impl<T: Eq + Ord + Clone + Display> GivesAccessTokensById<T> for AccessTokenSour
 /// Can be shared among threads. Use only, if really needed.
 #[derive(Clone)]
 pub struct AccessTokenSourceSync<T> {
-    tokens: Arc<BTreeMap<T, (usize, Mutex<StdResult<AccessToken, TokenErrorKind>>)>>,
-    sender: Arc<Mutex<Sender<internals::ManagerCommand<T>>>>,
+    tokens: Arc<TokenSlots<T>>,
+    sender: CommandSender<T>,
     is_running: Arc<IsRunningGuard>,
+    paused: Arc<Vec<AtomicBool>>,
+    refresh_on_access_at: Arc<Vec<AtomicU64>>,
+    expires_at: Arc<Vec<AtomicU64>>,
+    rotation_listeners: Arc<RotationListeners<T>>,
+    acquire: Arc<Vec<Acquire>>,
+    #[cfg(feature = "async")]
+    watchers: Arc<TokenWatchers<T>>,
 }
 
-impl<T: Eq + Ord + Clone + Display> AccessTokenSourceSync<T> {
+impl<T: Eq + Ord + Clone + Debug> AccessTokenSourceSync<T> {
     /// Get a `SingleAccessTokenSource` with `Sync `for the given identifier.
     ///
     /// Fails if no `ManagedToken` with the given id exists.
@@ -418,7 +903,7 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSourceSync<T> {
                 token_source: self.clone(),
                 token_id: token_id.clone(),
             }),
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
+            None => Err(TokenErrorKind::no_token(token_id).into()),
         }
     }
 
@@ -438,39 +923,414 @@ impl<T: Eq + Ord + Clone + Display> AccessTokenSourceSync<T> {
             tokens_map.insert(id.clone(), item);
         }
 
-        let (tx, _) = ::std::sync::mpsc::channel::<internals::ManagerCommand<T>>();
+        let tx = detached_command_queue(tokens_map.len());
 
         AccessTokenSourceSync {
+            #[cfg(feature = "async")]
+            watchers: Arc::new(internals::create_watchers(&tokens_map)),
+            paused: Arc::new(internals::create_pause_flags(&tokens_map)),
+            refresh_on_access_at: Arc::new(internals::create_refresh_on_access_times(&tokens_map)),
+            expires_at: Arc::new(internals::create_expiry_times(&tokens_map)),
+            rotation_listeners: Arc::new(internals::create_rotation_listeners(&tokens_map)),
+            acquire: Arc::new(vec![Acquire::Eager; tokens_map.len()]),
             tokens: Arc::new(tokens_map),
             is_running: Default::default(),
-            sender: Arc::new(Mutex::new(tx)),
+            sender: tx,
+        }
+    }
+
+    /// Get an `AccessTokenReceiver` notified whenever the `AccessToken`
+    /// for the given identifier changes.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    #[cfg(feature = "async")]
+    pub fn watch(&self, token_id: &T) -> TokenResult<AccessTokenReceiver> {
+        self.watchers
+            .subscribe(token_id)
+            .ok_or_else(|| TokenErrorKind::no_token(token_id).into())
+    }
+
+    /// Registers a callback invoked with the new `AccessToken` for the
+    /// given identifier right after each successful refresh, e.g. to
+    /// re-authenticate a long-lived connection.
+    ///
+    /// The callback runs on the thread refreshing the token and should
+    /// return quickly.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn on_rotation<F>(&self, token_id: &T, callback: F) -> TokenResult<()>
+    where
+        F: Fn(&AccessToken) + Send + Sync + 'static,
+    {
+        if self.rotation_listeners.register(token_id, callback) {
+            Ok(())
+        } else {
+            Err(TokenErrorKind::no_token(token_id).into())
         }
     }
+
+    /// Stops the scheduled refreshes of the token with the given identifier
+    /// until it is resumed, e.g. during a maintenance of the authorization
+    /// server. The current `AccessToken` is kept and explicit refreshes
+    /// are still executed.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn pause(&self, token_id: &T) -> TokenResult<()> {
+        set_paused(&self.tokens, &self.paused, token_id, true)
+    }
+
+    /// Resumes the scheduled refreshes of a paused token. A refresh that
+    /// became due while the token was paused is executed immediately.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn resume(&self, token_id: &T) -> TokenResult<()> {
+        set_paused(&self.tokens, &self.paused, token_id, false)
+    }
+
+    /// Get the `TokenStatus` of the token with the given identifier.
+    ///
+    /// Fails if no `ManagedToken` with the given id exists.
+    pub fn token_status(&self, token_id: &T) -> TokenResult<TokenStatus> {
+        token_status(&self.tokens, &self.paused, &self.expires_at, token_id)
+    }
+
+    /// Get the identifiers of all managed tokens in their order.
+    pub fn token_ids(&self) -> Vec<T> {
+        self.tokens.keys().cloned().collect()
+    }
+
+    /// Iterates over the identifiers of all managed tokens in their order
+    /// together with their `TokenStatus`.
+    ///
+    /// Each `TokenStatus` is taken when the iterator reaches its token.
+    pub fn iter(&self) -> impl Iterator<Item = (T, TokenStatus)> + '_ {
+        token_statuses(&self.tokens, &self.paused, &self.expires_at)
+    }
+
+    /// Get all managed tokens ordered by their identifiers.
+    ///
+    /// All tokens are taken at the same point in time so no token is
+    /// updated while the snapshot is taken.
+    pub fn snapshot(&self) -> Vec<(T, TokenResult<AccessToken>)> {
+        snapshot(&self.tokens)
+    }
+
+    /// The number of commands for the updater which were dropped because
+    /// its queue was full, e.g. since an `AccessTokenProvider` is slow.
+    pub fn dropped_commands(&self) -> u64 {
+        self.sender.dropped_commands()
+    }
+
+    /// Refreshes the `AccessToken` for the given identifier and blocks until
+    /// the refresh completed or the `timeout` elapsed.
+    ///
+    /// Returns the new `AccessToken` or the error of the refresh even if
+    /// the current `AccessToken` is still valid and kept.
+    pub fn refresh_and_wait(&self, token_id: &T, timeout: Duration) -> TokenResult<AccessToken> {
+        refresh_and_wait(&self.tokens, token_id, timeout, |command| {
+            self.sender.send(command).is_ok()
+        })
+    }
+
+    /// Get an `AccessToken` by identifier without blocking while a lazily
+    /// acquired token is fetched for the first time.
+    #[cfg(feature = "async")]
+    pub async fn get_access_token_async(&self, token_id: &T) -> TokenResult<AccessToken> {
+        get_access_token_async(
+            &self.tokens,
+            &self.sender,
+            &self.acquire,
+            &self.refresh_on_access_at,
+            token_id,
+        )
+        .await
+    }
+
+    /// The async variant of `refresh_and_wait`.
+    #[cfg(feature = "async")]
+    pub async fn refresh_and_wait_async(
+        &self,
+        token_id: &T,
+        timeout: Duration,
+    ) -> TokenResult<AccessToken> {
+        refresh_and_wait_async(&self.tokens, token_id, timeout, |command| {
+            self.sender.send(command).is_ok()
+        })
+        .await
+    }
 }
 
-impl<T: Eq + Ord + Clone + Display> GivesAccessTokensById<T> for AccessTokenSourceSync<T> {
+impl<T: Eq + Ord + Clone + Debug> GivesAccessTokensById<T> for AccessTokenSourceSync<T> {
     fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
-        match self.tokens.get(&token_id) {
-            Some((_, guard)) => match &*guard.lock().unwrap() {
-                Ok(token) => Ok(token.clone()),
-                Err(err) => Err(err.clone().into()),
-            },
-            None => Err(TokenErrorKind::NoToken(token_id.to_string()).into()),
-        }
+        get_access_token(
+            &self.tokens,
+            &self.sender,
+            &self.acquire,
+            &self.refresh_on_access_at,
+            token_id,
+        )
     }
 
     fn refresh(&self, name: &T) {
-        match self
-            .sender
-            .lock()
-            .unwrap()
-            .send(internals::ManagerCommand::ForceRefresh(
-                name.clone(),
-                internals::Clock::now(&internals::SystemClock),
-            )) {
-            Ok(_) => (),
-            Err(err) => warn!("Could send send refresh command for {}: {}", name, err),
+        request_refresh(&self.tokens, &self.sender, name)
+    }
+}
+
+/// The state of a managed token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenStatus {
+    /// `true` if an `AccessToken` can be retrieved.
+    pub available: bool,
+    /// `true` if the scheduled refreshes are paused.
+    pub paused: bool,
+    /// When the last received `AccessToken` expires. `None` until one was
+    /// received and for detached sources.
+    pub expires_at: Option<SystemTime>,
+}
+
+fn set_paused<T: Ord + Debug>(
+    tokens: &TokenSlots<T>,
+    paused: &[AtomicBool],
+    token_id: &T,
+    pause: bool,
+) -> TokenResult<()> {
+    match tokens.get(token_id) {
+        Some((idx, _)) => {
+            paused[*idx].store(pause, Ordering::Relaxed);
+            if pause {
+                info!("Paused the refreshes of token {:?}.", token_id);
+            } else {
+                info!("Resumed the refreshes of token {:?}.", token_id);
+            }
+            Ok(())
         }
+        None => Err(TokenErrorKind::no_token(token_id).into()),
+    }
+}
+
+/// Gets the `AccessToken` and requests a refresh in the background if the
+/// token is refreshed on access and expires soon.
+///
+/// Blocks while a lazily acquired token is fetched for the first time.
+fn get_access_token<T: Ord + Clone + Debug>(
+    tokens: &TokenSlots<T>,
+    sender: &CommandSender<T>,
+    acquire: &[Acquire],
+    refresh_on_access_at: &[AtomicU64],
+    token_id: &T,
+) -> TokenResult<AccessToken> {
+    if let Some(timeout) = pending_lazy_acquisition(tokens, acquire, token_id) {
+        return refresh_and_wait(tokens, token_id, timeout, |command| {
+            sender.send(command).is_ok()
+        });
+    }
+    let (idx, guard) = match tokens.get(token_id) {
+        Some((idx, guard)) => (*idx, guard),
+        None => return Err(TokenErrorKind::no_token(token_id).into()),
+    };
+    let token = match &*guard.lock().unwrap() {
+        Ok(token) => token.clone(),
+        Err(err) => return Err(err.clone().into()),
+    };
+    let now = internals::Clock::now(&internals::SystemClock);
+    if refresh_on_access_at[idx].load(Ordering::SeqCst) <= now {
+        request_refresh(tokens, sender, token_id);
+    }
+    Ok(token)
+}
+
+#[cfg(feature = "async")]
+async fn get_access_token_async<T: Ord + Clone + Debug>(
+    tokens: &TokenSlots<T>,
+    sender: &CommandSender<T>,
+    acquire: &[Acquire],
+    refresh_on_access_at: &[AtomicU64],
+    token_id: &T,
+) -> TokenResult<AccessToken> {
+    match pending_lazy_acquisition(tokens, acquire, token_id) {
+        Some(timeout) => {
+            refresh_and_wait_async(tokens, token_id, timeout, |command| {
+                sender.send(command).is_ok()
+            })
+            .await
+        }
+        None => get_access_token(tokens, sender, acquire, refresh_on_access_at, token_id),
+    }
+}
+
+/// The time to wait for the token if it is acquired lazily and was not
+/// fetched yet.
+fn pending_lazy_acquisition<T: Ord>(
+    tokens: &TokenSlots<T>,
+    acquire: &[Acquire],
+    token_id: &T,
+) -> Option<Duration> {
+    let (idx, guard) = tokens.get(token_id)?;
+    match (acquire[*idx], &*guard.lock().unwrap()) {
+        (Acquire::Lazy { timeout }, Err(TokenErrorKind::NotInitialized(_))) => Some(timeout),
+        _ => None,
+    }
+}
+
+/// Requests a refresh of the token unless one is already in flight.
+fn request_refresh<T: Ord + Clone + Debug>(
+    tokens: &TokenSlots<T>,
+    sender: &CommandSender<T>,
+    token_id: &T,
+) {
+    let idx = match tokens.get(token_id) {
+        Some((idx, _)) => *idx,
+        None => {
+            warn!("Can not refresh the unknown token {:?}", token_id);
+            return;
+        }
+    };
+    let command = internals::ManagerCommand::ForceRefresh(
+        token_id.clone(),
+        internals::Clock::now(&internals::SystemClock),
+    );
+    match sender.send_refresh(idx, command) {
+        Ok(true) => (),
+        Ok(false) => debug!("A refresh of token {:?} is already in flight", token_id),
+        Err(err) => warn!(
+            "Could send send refresh command for {:?}: {}",
+            token_id, err
+        ),
+    }
+}
+
+/// A queue for sources which are not attached to an `AccessTokenManager`.
+/// Sending to it fails since there is no receiver.
+fn detached_command_queue<T>(tokens: usize) -> CommandSender<T> {
+    let in_flight = Arc::new(command_queue::InFlightRefreshes::new(tokens));
+    command_queue::channel(1, in_flight).0
+}
+
+fn token_status<T: Ord + Debug>(
+    tokens: &TokenSlots<T>,
+    paused: &[AtomicBool],
+    expires_at: &[AtomicU64],
+    token_id: &T,
+) -> TokenResult<TokenStatus> {
+    match tokens.get(token_id) {
+        Some((idx, guard)) => Ok(status_at(*idx, guard, paused, expires_at)),
+        None => Err(TokenErrorKind::no_token(token_id).into()),
+    }
+}
+
+fn token_statuses<'a, T: Ord + Clone>(
+    tokens: &'a TokenSlots<T>,
+    paused: &'a [AtomicBool],
+    expires_at: &'a [AtomicU64],
+) -> impl Iterator<Item = (T, TokenStatus)> + 'a {
+    tokens.iter().map(move |(token_id, (idx, guard))| {
+        (token_id.clone(), status_at(*idx, guard, paused, expires_at))
+    })
+}
+
+fn status_at(
+    idx: usize,
+    guard: &Mutex<StdResult<AccessToken, TokenErrorKind>>,
+    paused: &[AtomicBool],
+    expires_at: &[AtomicU64],
+) -> TokenStatus {
+    TokenStatus {
+        available: guard.lock().unwrap().is_ok(),
+        paused: paused[idx].load(Ordering::Relaxed),
+        expires_at: match expires_at[idx].load(Ordering::SeqCst) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        },
+    }
+}
+
+fn snapshot<T: Ord + Clone>(tokens: &TokenSlots<T>) -> Vec<(T, TokenResult<AccessToken>)> {
+    // The locks are taken in the order of the map and held until all
+    // tokens are copied. The updater never holds more than one of them.
+    let guards: Vec<_> = tokens
+        .iter()
+        .map(|(token_id, (_, guard))| (token_id, guard.lock().unwrap()))
+        .collect();
+    guards
+        .iter()
+        .map(|(token_id, token)| {
+            let token = match **token {
+                Ok(ref token) => Ok(token.clone()),
+                Err(ref err) => Err(err.clone().into()),
+            };
+            ((*token_id).clone(), token)
+        })
+        .collect()
+}
+
+fn refresh_not_completed<T: Debug>(token_id: &T, reason: &str) -> TokenError {
+    TokenErrorKind::RefreshNotCompleted(format!(
+        "The refresh of token {:?} did not complete because {}.",
+        token_id, reason
+    ))
+    .into()
+}
+
+fn refresh_and_wait<T, F>(
+    tokens: &TokenSlots<T>,
+    token_id: &T,
+    timeout: Duration,
+    send: F,
+) -> TokenResult<AccessToken>
+where
+    T: Ord + Clone + Debug,
+    F: FnOnce(internals::ManagerCommand<T>) -> bool,
+{
+    if !tokens.contains_key(token_id) {
+        return Err(TokenErrorKind::no_token(token_id).into());
+    }
+    let (tx, rx) = mpsc::channel();
+    let command = internals::ManagerCommand::ForceRefreshAndAck(
+        token_id.clone(),
+        internals::Clock::now(&internals::SystemClock),
+        internals::RefreshAck::Blocking(tx),
+    );
+    if !send(command) {
+        return Err(refresh_not_completed(token_id, "the manager stopped"));
+    }
+    match rx.recv_timeout(timeout) {
+        Ok(outcome) => outcome.map_err(Into::into),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            Err(refresh_not_completed(token_id, "the timeout elapsed"))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(refresh_not_completed(token_id, "the manager stopped"))
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+async fn refresh_and_wait_async<T, F>(
+    tokens: &TokenSlots<T>,
+    token_id: &T,
+    timeout: Duration,
+    send: F,
+) -> TokenResult<AccessToken>
+where
+    T: Ord + Clone + Debug,
+    F: FnOnce(internals::ManagerCommand<T>) -> bool,
+{
+    if !tokens.contains_key(token_id) {
+        return Err(TokenErrorKind::no_token(token_id).into());
+    }
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let command = internals::ManagerCommand::ForceRefreshAndAck(
+        token_id.clone(),
+        internals::Clock::now(&internals::SystemClock),
+        internals::RefreshAck::Async(tx),
+    );
+    if !send(command) {
+        return Err(refresh_not_completed(token_id, "the manager stopped"));
+    }
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(outcome)) => outcome.map_err(Into::into),
+        Ok(Err(_)) => Err(refresh_not_completed(token_id, "the manager stopped")),
+        Err(_) => Err(refresh_not_completed(token_id, "the timeout elapsed")),
     }
 }
 
@@ -478,12 +1338,39 @@ impl<T: Eq + Ord + Clone + Display> GivesAccessTokensById<T> for AccessTokenSour
 ///
 /// This means the `token_id` for the `AccessToken` to be delivered
 /// has been previously selected.
-pub trait GivesFixedAccessToken<T: Eq + Ord + Clone + Display> {
+pub trait GivesFixedAccessToken<T: Eq + Ord + Clone + Debug> {
     /// Get the `AccessToken`.
     fn get_access_token(&self) -> TokenResult<AccessToken>;
 
     /// Refresh the `AccessToken`
     fn refresh(&self);
+
+    /// The time at which the current `AccessToken` expires if it is known.
+    fn expires_at(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// A source of a fixed `AccessToken` which can be shared like a
+/// `SharedAccessTokenSource`.
+pub type SharedFixedAccessTokenSource<T> = Arc<dyn GivesFixedAccessToken<T> + Send + Sync>;
+
+impl<T, S> GivesFixedAccessToken<T> for Arc<S>
+where
+    T: Eq + Ord + Clone + Debug,
+    S: GivesFixedAccessToken<T> + ?Sized,
+{
+    fn get_access_token(&self) -> TokenResult<AccessToken> {
+        (**self).get_access_token()
+    }
+
+    fn refresh(&self) {
+        (**self).refresh()
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        (**self).expires_at()
+    }
 }
 
 #[derive(Clone)]
@@ -492,7 +1379,7 @@ pub struct FixedAccessTokenSource<T> {
     token_id: T,
 }
 
-impl<T: Eq + Ord + Clone + Display> FixedAccessTokenSource<T> {
+impl<T: Eq + Ord + Clone + Debug> FixedAccessTokenSource<T> {
     /// Creates a new `FixedAccessTokenSource` which is not attached to an
     /// `AccessTokenManager`.
     ///
@@ -510,9 +1397,25 @@ impl<T: Eq + Ord + Clone + Display> FixedAccessTokenSource<T> {
             token_id,
         }
     }
+
+    /// Get an `AccessTokenReceiver` notified whenever the `AccessToken`
+    /// changes.
+    #[cfg(feature = "async")]
+    pub fn watch(&self) -> TokenResult<AccessTokenReceiver> {
+        self.token_source.watch(&self.token_id)
+    }
+
+    /// Registers a callback invoked with the new `AccessToken` right
+    /// after each successful refresh.
+    pub fn on_rotation<F>(&self, callback: F) -> TokenResult<()>
+    where
+        F: Fn(&AccessToken) + Send + Sync + 'static,
+    {
+        self.token_source.on_rotation(&self.token_id, callback)
+    }
 }
 
-impl<T: Eq + Ord + Clone + Display> GivesFixedAccessToken<T> for FixedAccessTokenSource<T> {
+impl<T: Eq + Ord + Clone + Debug> GivesFixedAccessToken<T> for FixedAccessTokenSource<T> {
     fn get_access_token(&self) -> TokenResult<AccessToken> {
         self.token_source.get_access_token(&self.token_id)
     }
@@ -520,6 +1423,13 @@ impl<T: Eq + Ord + Clone + Display> GivesFixedAccessToken<T> for FixedAccessToke
     fn refresh(&self) {
         self.token_source.refresh(&self.token_id)
     }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.token_source
+            .token_status(&self.token_id)
+            .ok()
+            .and_then(|status| status.expires_at)
+    }
 }
 
 /// A source for fixed access tokens which implements the `Sync` trait
@@ -529,7 +1439,7 @@ pub struct FixedAccessTokenSourceSync<T> {
     token_id: T,
 }
 
-impl<T: Eq + Ord + Clone + Display> FixedAccessTokenSourceSync<T> {
+impl<T: Eq + Ord + Clone + Debug> FixedAccessTokenSourceSync<T> {
     /// Creates a new `FixedAccessTokenSource` which is not attached to an
     /// `AccessTokenManager`.
     ///
@@ -547,9 +1457,25 @@ impl<T: Eq + Ord + Clone + Display> FixedAccessTokenSourceSync<T> {
             token_id,
         }
     }
+
+    /// Get an `AccessTokenReceiver` notified whenever the `AccessToken`
+    /// changes.
+    #[cfg(feature = "async")]
+    pub fn watch(&self) -> TokenResult<AccessTokenReceiver> {
+        self.token_source.watch(&self.token_id)
+    }
+
+    /// Registers a callback invoked with the new `AccessToken` right
+    /// after each successful refresh.
+    pub fn on_rotation<F>(&self, callback: F) -> TokenResult<()>
+    where
+        F: Fn(&AccessToken) + Send + Sync + 'static,
+    {
+        self.token_source.on_rotation(&self.token_id, callback)
+    }
 }
 
-impl<T: Eq + Ord + Clone + Display> GivesFixedAccessToken<T> for FixedAccessTokenSourceSync<T> {
+impl<T: Eq + Ord + Clone + Debug> GivesFixedAccessToken<T> for FixedAccessTokenSourceSync<T> {
     fn get_access_token(&self) -> TokenResult<AccessToken> {
         self.token_source.get_access_token(&self.token_id)
     }
@@ -557,6 +1483,13 @@ impl<T: Eq + Ord + Clone + Display> GivesFixedAccessToken<T> for FixedAccessToke
     fn refresh(&self) {
         self.token_source.refresh(&self.token_id)
     }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        self.token_source
+            .token_status(&self.token_id)
+            .ok()
+            .and_then(|status| status.expires_at)
+    }
 }
 
 /// The `TokenManager` refreshes `AccessTokens`s in the background.
@@ -567,8 +1500,24 @@ pub struct AccessTokenManager;
 
 impl AccessTokenManager {
     /// Starts the `AccessTokenManager` in the background.
-    pub fn start<T: Eq + Ord + Send + Sync + Clone + Display + 'static>(
+    pub fn start<T: Eq + Ord + Send + Sync + Clone + Debug + 'static>(
+        groups: Vec<ManagedTokenGroup<T>>,
+    ) -> InitializationResult<AccessTokenSource<T>> {
+        Self::start_internal(groups, None)
+    }
+
+    /// Starts the `AccessTokenManager` in the background and delivers all
+    /// notifications about the managed tokens to `listener`.
+    pub fn start_with_listener<T: Eq + Ord + Send + Sync + Clone + Debug + 'static>(
         groups: Vec<ManagedTokenGroup<T>>,
+        listener: Arc<dyn TokenLifecycleListener<T>>,
+    ) -> InitializationResult<AccessTokenSource<T>> {
+        Self::start_internal(groups, Some(listener))
+    }
+
+    fn start_internal<T: Eq + Ord + Send + Sync + Clone + Debug + 'static>(
+        groups: Vec<ManagedTokenGroup<T>>,
+        listener: Option<Arc<dyn TokenLifecycleListener<T>>>,
     ) -> InitializationResult<AccessTokenSource<T>> {
         {
             let mut seen = BTreeMap::default();
@@ -576,8 +1525,8 @@ impl AccessTokenManager {
                 for managed_token in &group.managed_tokens {
                     let token_id = &managed_token.token_id;
                     if seen.contains_key(token_id) {
-                        return Err(InitializationError(format!(
-                            "Token id '{}' is used more than once.",
+                        return Err(InitializationError::Other(format!(
+                            "Token id {:?} is used more than once.",
                             token_id
                         )));
                     } else {
@@ -586,19 +1535,28 @@ impl AccessTokenManager {
                 }
             }
         }
-        let (inner, sender) = internals::initialize(groups, internals::SystemClock);
+        let (inner, sender) = internals::initialize(groups, internals::SystemClock, listener);
         Ok(AccessTokenSource {
             tokens: inner.tokens,
             sender,
             is_running: Arc::new(IsRunningGuard {
                 is_running: inner.is_running,
             }),
+            paused: inner.paused,
+            refresh_on_access_at: inner.refresh_on_access_at,
+            expires_at: inner.expires_at,
+            rotation_listeners: inner.rotation_listeners,
+            acquire: inner.acquire,
+            #[cfg(feature = "async")]
+            watchers: inner.watchers,
         })
     }
 
     /// Starts the `AccessTokenManager` in the background and waits until all
     /// tokens have been initialized or a timeout elapsed..
-    pub fn start_and_wait_for_tokens<T: Eq + Ord + Send + Sync + Clone + Display + 'static>(
+    ///
+    /// Tokens acquired with `Acquire::Lazy` are not waited for.
+    pub fn start_and_wait_for_tokens<T: Eq + Ord + Send + Sync + Clone + Debug + 'static>(
         groups: Vec<ManagedTokenGroup<T>>,
         timeout_in: Duration,
     ) -> InitializationResult<AccessTokenSource<T>> {
@@ -608,8 +1566,8 @@ impl AccessTokenManager {
                 for managed_token in &group.managed_tokens {
                     let token_id = &managed_token.token_id;
                     if seen.contains_key(token_id) {
-                        return Err(InitializationError(format!(
-                            "Token id '{}' is used more than once.",
+                        return Err(InitializationError::Other(format!(
+                            "Token id {:?} is used more than once.",
                             token_id
                         )));
                     } else {
@@ -619,19 +1577,24 @@ impl AccessTokenManager {
             }
         }
 
-        let (inner, sender) = internals::initialize(groups, internals::SystemClock);
+        let (inner, sender) = internals::initialize(groups, internals::SystemClock, None);
 
         let start = Instant::now();
         loop {
             if start.elapsed() >= timeout_in {
-                return Err(InitializationError(
+                return Err(InitializationError::Other(
                     "Not all tokens were initialized within the \
                      given time."
                         .into(),
                 ));
             }
 
-            let all_initialized = inner.tokens.keys().all(|id| {
+            // Lazily acquired tokens are fetched when they are requested.
+            let mut eager_tokens = inner
+                .tokens
+                .iter()
+                .filter(|(_, (idx, _))| inner.acquire[*idx] == Acquire::Eager);
+            let all_initialized = eager_tokens.all(|(id, _)| {
                 if let Err(token_error) = inner.get_access_token(id) {
                     if let TokenErrorKind::NotInitialized(_) = *token_error.kind() {
                         false
@@ -656,6 +1619,263 @@ impl AccessTokenManager {
             is_running: Arc::new(IsRunningGuard {
                 is_running: inner.is_running,
             }),
+            paused: inner.paused,
+            refresh_on_access_at: inner.refresh_on_access_at,
+            expires_at: inner.expires_at,
+            rotation_listeners: inner.rotation_listeners,
+            acquire: inner.acquire,
+            #[cfg(feature = "async")]
+            watchers: inner.watchers,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::testing::{DetachedAccessTokenSource, ScriptedTokenProvider};
+    use super::*;
+
+    #[test]
+    fn refresh_and_wait_returns_the_outcome_of_the_refresh() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("first", Duration::from_secs(100));
+        provider.push_ok("second", Duration::from_secs(100));
+        provider.push_err(AccessTokenProviderError::Client("refused".to_string()));
+
+        let group = ManagedTokenGroupBuilder::single_token("token", vec![], provider)
+            .build()
+            .unwrap();
+        let source =
+            AccessTokenManager::start_and_wait_for_tokens(vec![group], Duration::from_secs(5))
+                .unwrap();
+
+        let token = source
+            .refresh_and_wait(&"token", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(token.reveal(), "second");
+
+        // The failed refresh is reported although the token is kept.
+        let err = source
+            .refresh_and_wait(&"token", Duration::from_secs(5))
+            .unwrap_err();
+        match *err.kind() {
+            TokenErrorKind::AccessTokenProvider(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(
+            source.get_access_token(&"token").unwrap().reveal(),
+            "second"
+        );
+
+        match *source
+            .refresh_and_wait(&"other", Duration::from_secs(5))
+            .unwrap_err()
+            .kind()
+        {
+            TokenErrorKind::NoToken(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refresh_and_wait_fails_for_detached_sources() {
+        let source = AccessTokenSource::new_detached(&[("token", AccessToken::new("token"))]);
+        match *source
+            .refresh_and_wait(&"token", Duration::from_secs(1))
+            .unwrap_err()
+            .kind()
+        {
+            TokenErrorKind::RefreshNotCompleted(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn managed_tokens_can_be_listed_with_their_status() {
+        let source = AccessTokenSourceSync::new_detached(&[
+            ("b", AccessToken::new("b")),
+            ("a", AccessToken::new("a")),
+        ]);
+        source.pause(&"b").unwrap();
+
+        assert_eq!(source.token_ids(), vec!["a", "b"]);
+        let statuses: Vec<_> = source
+            .iter()
+            .map(|(token_id, status)| (token_id, status.available, status.paused))
+            .collect();
+        assert_eq!(statuses, vec![("a", true, false), ("b", true, true)]);
+    }
+
+    /// Blocks until the sender of the channel is dropped.
+    struct HangingProvider(Mutex<mpsc::Receiver<()>>);
+
+    impl AccessTokenProvider for HangingProvider {
+        fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+            let _ = self.0.lock().unwrap().recv();
+            Err(AccessTokenProviderError::Other("Released.".to_string()))
+        }
+    }
+
+    #[test]
+    fn isolated_groups_are_not_delayed_by_hanging_providers() {
+        let (release, hang) = mpsc::channel();
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("isolated", Duration::from_secs(100));
+        let mut isolated = ManagedTokenGroupBuilder::single_token("isolated", vec![], provider);
+        isolated.isolated(true);
+
+        let groups = vec![
+            ManagedTokenGroupBuilder::single_token(
+                "hanging",
+                vec![],
+                HangingProvider(Mutex::new(hang)),
+            )
+            .build()
+            .unwrap(),
+            isolated.build().unwrap(),
+        ];
+        let source = AccessTokenManager::start(groups).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while source.get_access_token(&"isolated").is_err() && Instant::now() < deadline {
+            ::std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(
+            source.get_access_token(&"isolated").unwrap().reveal(),
+            "isolated"
+        );
+        assert!(source.get_access_token(&"hanging").is_err());
+        drop(release);
+    }
+
+    struct ActiveTokens;
+
+    impl TokenInfoService for ActiveTokens {
+        fn introspect(&self, _token: &AccessToken) -> crate::TokenInfoResult<crate::TokenInfo> {
+            Ok(crate::TokenInfo::new(true))
+        }
+    }
+
+    #[test]
+    fn shared_introspection_services_are_not_wrapped() {
+        let service: Arc<dyn TokenInfoService + Send + Sync> = Arc::new(ActiveTokens);
+        let mut builder =
+            ManagedTokenGroupBuilder::single_token("token", vec![], ScriptedTokenProvider::new());
+        builder
+            .verify_after_refresh_shared(service.clone())
+            .with_shared_scope_introspection(service.clone());
+        let group = builder.build().unwrap();
+
+        assert_eq!(Arc::strong_count(&service), 3);
+        let verification = group.verification.unwrap();
+        let token_info = verification.introspect(&AccessToken::new("a")).unwrap();
+        assert!(token_info.active);
+    }
+
+    #[test]
+    fn validate_checks_scopes_and_ids() {
+        let mut builder = ManagedTokenGroupBuilder::single_token(
+            "token",
+            vec![Scope::new("read"), Scope::new("read write")],
+            ScriptedTokenProvider::new(),
+        );
+        builder.with_managed_token(ManagedToken {
+            token_id: "token",
+            scopes: vec![],
+            params: TokenRequestParams::default(),
+            acquire: Acquire::Eager,
+        });
+        let report = builder.validate(false);
+        let failed: Vec<_> = report.failures().map(|check| check.name.clone()).collect();
+        assert_eq!(
+            failed,
+            vec![
+                "scope 'read write' is valid",
+                "token id \"token\" is unique"
+            ],
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn snapshot_contains_all_tokens_ordered_by_id() {
+        let first = ScriptedTokenProvider::new();
+        first.push_ok("first", Duration::from_secs(100));
+        let second = ScriptedTokenProvider::new();
+        second.push_err(AccessTokenProviderError::Client("refused".to_string()));
+
+        let groups = vec![
+            ManagedTokenGroupBuilder::single_token("b", vec![], first)
+                .build()
+                .unwrap(),
+            ManagedTokenGroupBuilder::single_token("a", vec![], second)
+                .build()
+                .unwrap(),
+        ];
+        let source = AccessTokenManager::start(groups).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while source
+            .token_status(&"b")
+            .map(|status| !status.available)
+            .unwrap()
+            && Instant::now() < deadline
+        {
+            ::std::thread::sleep(Duration::from_millis(5));
+        }
+
+        let snapshot = source.snapshot();
+        let ids: Vec<_> = snapshot.iter().map(|(token_id, _)| *token_id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(snapshot[0].1.is_err());
+        assert_eq!(snapshot[1].1.as_ref().unwrap().reveal(), "first");
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    enum TokenId {
+        Api,
+        Database,
+    }
+
+    #[test]
+    fn ids_only_need_to_implement_debug() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("api", Duration::from_secs(100));
+        let group = ManagedTokenGroupBuilder::single_token(TokenId::Api, vec![], provider)
+            .build()
+            .unwrap();
+        let source =
+            AccessTokenManager::start_and_wait_for_tokens(vec![group], Duration::from_secs(5))
+                .unwrap();
+
+        assert_eq!(
+            source.get_access_token(&TokenId::Api).unwrap().reveal(),
+            "api"
+        );
+        match *source
+            .get_access_token(&TokenId::Database)
+            .unwrap_err()
+            .kind()
+        {
+            TokenErrorKind::NoToken(ref err) => assert_eq!(err.token_id.as_str(), "Database"),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_sources_can_be_shared_as_trait_objects() {
+        let detached = DetachedAccessTokenSource::new();
+        detached.set_token("token", AccessToken::new("secret"));
+        let source: SharedAccessTokenSource<&str> = Arc::new(detached);
+        let shared = Arc::new(source.clone());
+        assert_eq!(
+            shared.get_access_token(&"token").unwrap().reveal(),
+            "secret"
+        );
+
+        let fixed: SharedFixedAccessTokenSource<&str> = Arc::new(
+            FixedAccessTokenSourceSync::new_detached("token", AccessToken::new("fixed")),
+        );
+        assert_eq!(fixed.get_access_token().unwrap().reveal(), "fixed");
+    }
+}
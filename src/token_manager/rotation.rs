@@ -0,0 +1,107 @@
+//! Callbacks invoked with each refreshed `AccessToken`.
+//!
+//! Protocols which authenticate a connection only once, like AMQP, Kafka
+//! with SASL/OAUTHBEARER or WebSockets, have to re-authenticate or rebuild
+//! their connections when the token was rotated. A callback registered with
+//! `AccessTokenSource::on_rotation` is invoked on the refreshing thread
+//! right after each successful refresh:
+//!
+//! ```rust,no_run
+//! # fn rebuild(source: tokkit::token_manager::AccessTokenSource<&'static str>) {
+//! source
+//!     .on_rotation(&"my_token", |token| {
+//!         // re-authenticate the connection with `token`
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! Callbacks block the refreshes of other tokens while running and should
+//! hand longer work over to another thread. A panicking callback is logged
+//! and does not affect the other callbacks.
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use super::internals::panic_message;
+use crate::AccessToken;
+
+type RotationCallback = Arc<dyn Fn(&AccessToken) + Send + Sync>;
+
+/// The rotation callbacks of each managed token.
+pub(crate) struct RotationListeners<T> {
+    callbacks: BTreeMap<T, Mutex<Vec<RotationCallback>>>,
+}
+
+impl<T: Ord + Debug> RotationListeners<T> {
+    /// Creates the listeners without callbacks for the given tokens.
+    pub fn new<I>(token_ids: I) -> RotationListeners<T>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let callbacks = token_ids
+            .into_iter()
+            .map(|token_id| (token_id, Mutex::new(Vec::new())))
+            .collect();
+        RotationListeners { callbacks }
+    }
+
+    /// Registers `callback` for the token. Returns `false` if the token is
+    /// not managed.
+    pub fn register<F>(&self, token_id: &T, callback: F) -> bool
+    where
+        F: Fn(&AccessToken) + Send + Sync + 'static,
+    {
+        match self.callbacks.get(token_id) {
+            Some(callbacks) => {
+                callbacks.lock().unwrap().push(Arc::new(callback));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Invokes the callbacks of the token with its new `AccessToken`.
+    pub fn rotated(&self, token_id: &T, token: &AccessToken) {
+        let callbacks = match self.callbacks.get(token_id) {
+            // Callbacks may register further callbacks.
+            Some(callbacks) => callbacks.lock().unwrap().clone(),
+            None => return,
+        };
+        for callback in callbacks {
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| callback(token))) {
+                error!(
+                    "A rotation callback of token {:?} panicked: {}",
+                    token_id,
+                    panic_message(&*panic)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn callbacks_are_invoked_even_if_others_panic() {
+        let listeners = RotationListeners::new(vec!["a", "b"]);
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        assert!(listeners.register(&"a", |_| panic!("boom")));
+        let calls1 = calls.clone();
+        assert!(listeners.register(&"a", move |token| {
+            assert_eq!(token.reveal(), "new");
+            calls1.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert!(!listeners.register(&"c", |_| {}));
+
+        listeners.rotated(&"a", &AccessToken::new("new"));
+        listeners.rotated(&"b", &AccessToken::new("other"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
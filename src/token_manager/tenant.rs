@@ -0,0 +1,278 @@
+//! Managing the tokens of many tenants.
+//!
+//! A `TenantTokenManager` starts an `AccessTokenManager` for a tenant the
+//! first time one of the tenant's tokens is requested. The
+//! `ManagedTokenGroup`s of the tenant are created by a
+//! `TenantConfigResolver`, e.g. from credentials stored per tenant.
+//! Tenants whose tokens were not requested for a while can be evicted
+//! which stops their `AccessTokenManager`.
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//!
+//! use tokkit::token_manager::tenant::TenantTokenManager;
+//! use tokkit::token_manager::token_provider::credentials::parsers::DefaultResourceOwnerCredentialsParser;
+//! use tokkit::token_manager::token_provider::credentials::SplitFileCredentialsProvider;
+//! use tokkit::token_manager::token_provider::ResourceOwnerPasswordCredentialsGrantProvider;
+//! use tokkit::token_manager::{ManagedTokenGroup, ManagedTokenGroupBuilder};
+//! use tokkit::{InitializationResult, Scope};
+//!
+//! let resolver = |tenant: &String| -> InitializationResult<Vec<ManagedTokenGroup<_>>> {
+//!     let credentials = SplitFileCredentialsProvider::with_default_client_parser(
+//!         format!("/secrets/{}/client.json", tenant),
+//!         format!("/secrets/{}/user.json", tenant),
+//!         DefaultResourceOwnerCredentialsParser,
+//!     );
+//!     let provider = ResourceOwnerPasswordCredentialsGrantProvider::new(
+//!         "https://auth.example.com/token",
+//!         credentials,
+//!         None,
+//!     )?;
+//!     let group = ManagedTokenGroupBuilder::single_token(
+//!         "orders",
+//!         vec![Scope::new("orders.read")],
+//!         provider,
+//!     )
+//!     .build()?;
+//!     Ok(vec![group])
+//! };
+//! let manager = TenantTokenManager::new(resolver).with_idle_timeout(Duration::from_secs(3600));
+//!
+//! let token = manager.get_access_token(&"tenant-a".to_string(), &"orders");
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{
+    AccessTokenManager, AccessTokenSourceSync, GivesAccessTokensById, ManagedTokenGroup,
+    TokenErrorKind, TokenResult,
+};
+use crate::{AccessToken, InitializationResult};
+
+/// The time a `TenantTokenManager` waits by default for the tokens of a
+/// tenant to be initialized.
+pub const DEFAULT_TENANT_INITIALIZATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The time after which a tenant whose tokens were not requested is
+/// evicted by default.
+pub const DEFAULT_TENANT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Creates the `ManagedTokenGroup`s for the tokens of a tenant.
+///
+/// It is implemented for closures taking the tenant id.
+pub trait TenantConfigResolver<K, T>: Send + Sync {
+    fn resolve(&self, tenant_id: &K) -> InitializationResult<Vec<ManagedTokenGroup<T>>>;
+}
+
+impl<K, T, F> TenantConfigResolver<K, T> for F
+where
+    F: Fn(&K) -> InitializationResult<Vec<ManagedTokenGroup<T>>> + Send + Sync,
+{
+    fn resolve(&self, tenant_id: &K) -> InitializationResult<Vec<ManagedTokenGroup<T>>> {
+        self(tenant_id)
+    }
+}
+
+struct Tenant<T> {
+    source: AccessTokenSourceSync<T>,
+    last_used: Instant,
+}
+
+/// Manages tokens keyed by `(tenant_id, token_id)` with an
+/// `AccessTokenManager` per tenant.
+///
+/// A tenant is set up on the first request for one of its tokens. If the
+/// `TenantConfigResolver` fails the request fails with
+/// `TokenErrorKind::Tenant` and the tenant is set up again with the next
+/// request.
+pub struct TenantTokenManager<K, T> {
+    resolver: Box<dyn TenantConfigResolver<K, T>>,
+    tenants: Mutex<BTreeMap<K, Tenant<T>>>,
+    initialization_timeout: Duration,
+    idle_timeout: Duration,
+}
+
+impl<K, T> TenantTokenManager<K, T>
+where
+    K: Ord + Clone + Debug,
+    T: Eq + Ord + Send + Sync + Clone + Debug + 'static,
+{
+    pub fn new<R>(resolver: R) -> Self
+    where
+        R: TenantConfigResolver<K, T> + 'static,
+    {
+        TenantTokenManager {
+            resolver: Box::new(resolver),
+            tenants: Mutex::new(BTreeMap::new()),
+            initialization_timeout: DEFAULT_TENANT_INITIALIZATION_TIMEOUT,
+            idle_timeout: DEFAULT_TENANT_IDLE_TIMEOUT,
+        }
+    }
+
+    /// Sets the time to wait for the tokens of a new tenant. The default
+    /// is `DEFAULT_TENANT_INITIALIZATION_TIMEOUT`.
+    pub fn with_initialization_timeout(mut self, initialization_timeout: Duration) -> Self {
+        self.initialization_timeout = initialization_timeout;
+        self
+    }
+
+    /// Sets the time after which a tenant whose tokens were not requested
+    /// is evicted. The default is `DEFAULT_TENANT_IDLE_TIMEOUT`.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Get the `AccessToken` `token_id` of the tenant `tenant_id`.
+    ///
+    /// Sets up the tenant if this is the first request for one of its
+    /// tokens.
+    pub fn get_access_token(&self, tenant_id: &K, token_id: &T) -> TokenResult<AccessToken> {
+        self.source(tenant_id)?.get_access_token(token_id)
+    }
+
+    /// Refresh the `AccessToken` `token_id` of the tenant `tenant_id`.
+    ///
+    /// Does nothing if the tenant is not set up.
+    pub fn refresh(&self, tenant_id: &K, token_id: &T) {
+        if let Some(tenant) = self.tenants.lock().unwrap().get(tenant_id) {
+            tenant.source.refresh(token_id);
+        }
+    }
+
+    /// Stops the `AccessTokenManager`s of the tenants whose tokens were not
+    /// requested within the idle timeout and returns their ids.
+    ///
+    /// Idle tenants are also evicted whenever a tenant is set up. Call this
+    /// periodically if tenants are rarely added.
+    pub fn evict_idle(&self) -> Vec<K> {
+        let idle_timeout = self.idle_timeout;
+        let mut tenants = self.tenants.lock().unwrap();
+        let idle: Vec<K> = tenants
+            .iter()
+            .filter(|(_, tenant)| tenant.last_used.elapsed() >= idle_timeout)
+            .map(|(tenant_id, _)| tenant_id.clone())
+            .collect();
+        for tenant_id in &idle {
+            tenants.remove(tenant_id);
+            info!("Evicted the idle tenant {:?}.", tenant_id);
+        }
+        idle
+    }
+
+    /// Stops the `AccessTokenManager` of the tenant. Returns `false` if the
+    /// tenant was not set up.
+    pub fn remove_tenant(&self, tenant_id: &K) -> bool {
+        self.tenants.lock().unwrap().remove(tenant_id).is_some()
+    }
+
+    /// The number of tenants currently set up.
+    pub fn len(&self) -> usize {
+        self.tenants.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn source(&self, tenant_id: &K) -> TokenResult<AccessTokenSourceSync<T>> {
+        if let Some(tenant) = self.tenants.lock().unwrap().get_mut(tenant_id) {
+            tenant.last_used = Instant::now();
+            return Ok(tenant.source.clone());
+        }
+
+        // Other tenants must not wait while this one is set up.
+        let source = self.start_tenant(tenant_id)?;
+        self.evict_idle();
+        let mut tenants = self.tenants.lock().unwrap();
+        // Another request may have set up the tenant in the meantime.
+        let tenant = tenants.entry(tenant_id.clone()).or_insert_with(|| Tenant {
+            source,
+            last_used: Instant::now(),
+        });
+        Ok(tenant.source.clone())
+    }
+
+    fn start_tenant(&self, tenant_id: &K) -> TokenResult<AccessTokenSourceSync<T>> {
+        let failed = |err: crate::InitializationError| {
+            TokenErrorKind::Tenant(format!(
+                "Could not set up the tokens of tenant {:?}: {}",
+                tenant_id, err
+            ))
+        };
+        let groups = self.resolver.resolve(tenant_id).map_err(failed)?;
+        let source =
+            AccessTokenManager::start_and_wait_for_tokens(groups, self.initialization_timeout)
+                .map_err(failed)?;
+        info!("Set up the tokens of tenant {:?}.", tenant_id);
+        Ok(source.synced())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::token_manager::testing::ScriptedTokenProvider;
+    use crate::token_manager::ManagedTokenGroupBuilder;
+    use crate::InitializationError;
+
+    fn counting_resolver(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Fn(&&'static str) -> InitializationResult<Vec<ManagedTokenGroup<&'static str>>> {
+        move |tenant: &&'static str| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            if *tenant == "unknown" {
+                return Err(InitializationError::Other("no such tenant".to_string()));
+            }
+            let provider = ScriptedTokenProvider::new();
+            provider.push_ok(format!("{}-token", tenant), Duration::from_secs(3600));
+            ManagedTokenGroupBuilder::single_token("token", vec![], provider)
+                .build()
+                .map(|group| vec![group])
+        }
+    }
+
+    #[test]
+    fn tenants_are_set_up_once_and_evicted_when_idle() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let manager = TenantTokenManager::new(counting_resolver(calls.clone()));
+
+        let token = manager.get_access_token(&"a", &"token").unwrap();
+        assert_eq!(token.reveal(), "a-token");
+        manager.get_access_token(&"a", &"token").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(manager.len(), 1);
+
+        let manager = manager.with_idle_timeout(Duration::from_secs(0));
+        assert_eq!(manager.evict_idle(), vec!["a"]);
+        assert!(manager.is_empty());
+        manager.get_access_token(&"a", &"token").unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn failing_tenants_are_not_kept() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let manager = TenantTokenManager::new(counting_resolver(calls.clone()));
+
+        for _ in 0..2 {
+            match *manager
+                .get_access_token(&"unknown", &"token")
+                .unwrap_err()
+                .kind()
+            {
+                TokenErrorKind::Tenant(ref msg) => {
+                    assert!(msg.contains("no such tenant"), "{}", msg)
+                }
+                ref other => panic!("unexpected error: {:?}", other),
+            }
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(manager.is_empty());
+    }
+}
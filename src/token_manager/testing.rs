@@ -0,0 +1,546 @@
+//! Deterministic simulation of the token manager.
+//!
+//! A `TokenManagerSimulation` drives the scheduler and the updater of a
+//! token manager without background threads and real sleeps. Time only
+//! passes when it is advanced and the `AccessTokenProvider` can be a
+//! `ScriptedTokenProvider` returning injected results. This allows testing
+//! refresh and warning thresholds and the resulting notifications.
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use tokkit::token_manager::testing::*;
+//! use tokkit::token_manager::*;
+//!
+//! let provider = ScriptedTokenProvider::new();
+//! provider.push_ok("first", Duration::from_secs(100));
+//! provider.push_ok("second", Duration::from_secs(100));
+//!
+//! let group = ManagedTokenGroupBuilder::single_token("token", vec![], provider.clone())
+//!     .build()
+//!     .unwrap();
+//! let simulation = TokenManagerSimulation::new(vec![group]);
+//!
+//! simulation.step();
+//! assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "first");
+//!
+//! // The default refresh threshold is 75% of the lifetime.
+//! simulation.advance(Duration::from_secs(75));
+//! assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "second");
+//! assert_eq!(provider.calls(), 2);
+//! ```
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use super::internals::command_queue::{self, CommandReceiver, CommandSender, InFlightRefreshes};
+use super::internals::request_scheduler::RefreshScheduler;
+use super::internals::token_updater::TokenUpdater;
+use super::internals::{self, Clock, ManagerCommand, TokenRow, TokenSlots};
+use super::token_provider::{
+    AccessTokenProvider, AccessTokenProviderError, AccessTokenProviderResult,
+    AuthorizationServerResponse,
+};
+use super::{
+    Acquire, GivesAccessTokensById, ManagedTokenGroup, TokenErrorKind, TokenRefreshError,
+    TokenResult,
+};
+use crate::{AccessToken, Scope};
+
+pub use super::internals::{NotificationKind, NotificationSeverity, TokenNotification};
+
+/// A clock which only moves when advanced. It starts at zero.
+#[derive(Clone, Default)]
+pub struct SimulatedClock {
+    now_ms: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    pub fn new() -> SimulatedClock {
+        SimulatedClock::default()
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, by: Duration) {
+        self.now_ms
+            .fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// The time passed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_millis(self.now_ms.load(Ordering::SeqCst))
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
+
+/// An `AccessTokenProvider` returning injected results in the order they
+/// were pushed.
+///
+/// Fails with `AccessTokenProviderError::Other` if no result is left.
+/// Clones share their results.
+#[derive(Clone, Default)]
+pub struct ScriptedTokenProvider {
+    results: Arc<Mutex<VecDeque<AccessTokenProviderResult>>>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl ScriptedTokenProvider {
+    pub fn new() -> ScriptedTokenProvider {
+        ScriptedTokenProvider::default()
+    }
+
+    /// Adds a result to be returned.
+    pub fn push(&self, result: AccessTokenProviderResult) {
+        self.results.lock().unwrap().push_back(result);
+    }
+
+    /// Adds an `AccessToken` expiring after `expires_in` to be returned.
+    pub fn push_ok<T: Into<String>>(&self, token: T, expires_in: Duration) {
+        self.push(Ok(AuthorizationServerResponse::new(
+            AccessToken::new(token),
+            expires_in,
+        )))
+    }
+
+    /// Adds an error to be returned.
+    pub fn push_err(&self, err: AccessTokenProviderError) {
+        self.push(Err(err))
+    }
+
+    /// The number of requests received.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl AccessTokenProvider for ScriptedTokenProvider {
+    fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        match self.results.lock().unwrap().pop_front() {
+            Some(result) => result,
+            None => Err(AccessTokenProviderError::Other(
+                "No result scripted.".to_string(),
+            )),
+        }
+    }
+}
+
+/// Runs `ManagedTokenGroup`s on a `SimulatedClock`.
+///
+/// Each `step` does a single round of the scheduler followed by all
+/// refreshes it requested. Errors of the providers are not retried.
+pub struct TokenManagerSimulation<T> {
+    clock: SimulatedClock,
+    rows: Vec<Mutex<TokenRow<T>>>,
+    tokens: TokenSlots<T>,
+    sender: CommandSender<T>,
+    receiver: CommandReceiver<T>,
+    in_flight: Arc<InFlightRefreshes>,
+    is_running: AtomicBool,
+    paused: Vec<AtomicBool>,
+    refresh_on_access_at: Vec<AtomicU64>,
+    acquire: Vec<Acquire>,
+    notifications: Mutex<Vec<TokenNotification<T>>>,
+}
+
+impl<T: Eq + Ord + Send + Clone + Debug> TokenManagerSimulation<T> {
+    pub fn new(groups: Vec<ManagedTokenGroup<T>>) -> TokenManagerSimulation<T> {
+        let clock = SimulatedClock::new();
+        let tokens = internals::create_tokens(&groups);
+        let acquire = internals::create_acquisitions(&groups, clock.now());
+        let rows = internals::create_rows(groups, clock.now());
+        let in_flight = Arc::new(InFlightRefreshes::new(rows.len()));
+        let (sender, receiver) = command_queue::channel(
+            rows.len() + internals::COMMAND_QUEUE_CAPACITY,
+            in_flight.clone(),
+        );
+        TokenManagerSimulation {
+            clock,
+            rows,
+            paused: internals::create_pause_flags(&tokens),
+            refresh_on_access_at: internals::create_refresh_on_access_times(&tokens),
+            acquire,
+            tokens,
+            sender,
+            receiver,
+            in_flight,
+            is_running: AtomicBool::new(true),
+            notifications: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn clock(&self) -> &SimulatedClock {
+        &self.clock
+    }
+
+    /// Does a round of the scheduler and executes the requested refreshes.
+    pub fn step(&self) {
+        RefreshScheduler::new(
+            &self.rows,
+            &self.sender,
+            internals::MAX_CYCLE_DUR_MS,
+            internals::MIN_NOTIFICATION_INTERVAL_MS,
+            &self.is_running,
+            &self.clock,
+        )
+        .with_notification_log(&self.notifications)
+        .with_pause_flags(&self.paused)
+        .do_a_scheduling_round();
+
+        let updater = TokenUpdater::new(&self.rows, &self.tokens, &self.is_running, &self.clock)
+            .with_in_flight(&self.in_flight)
+            .with_refresh_on_access_times(&self.refresh_on_access_at)
+            .without_retries();
+        while let Ok((tracked, command)) = self.receiver.try_recv_tracked() {
+            updater.on_tracked_command(tracked, command);
+        }
+    }
+
+    /// Advances the clock and does a `step`.
+    pub fn advance(&self, by: Duration) {
+        self.clock.advance(by);
+        self.step();
+    }
+
+    /// Advances the clock by `resolution` and does a `step` until
+    /// `duration` has passed.
+    pub fn run_for(&self, duration: Duration, resolution: Duration) {
+        let until = self.clock.elapsed() + duration;
+        while self.clock.elapsed() < until {
+            self.advance(resolution.min(until - self.clock.elapsed()));
+        }
+    }
+
+    /// Pauses the scheduled refreshes of a token. Returns `false` if the
+    /// token is not managed.
+    pub fn pause(&self, token_id: &T) -> bool {
+        self.set_paused(token_id, true)
+    }
+
+    /// Resumes the scheduled refreshes of a token. Returns `false` if the
+    /// token is not managed.
+    pub fn resume(&self, token_id: &T) -> bool {
+        self.set_paused(token_id, false)
+    }
+
+    fn set_paused(&self, token_id: &T, pause: bool) -> bool {
+        match self.tokens.get(token_id) {
+            Some((idx, _)) => {
+                self.paused[*idx].store(pause, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the notifications issued so far.
+    pub fn take_notifications(&self) -> Vec<TokenNotification<T>> {
+        std::mem::take(&mut *self.notifications.lock().unwrap())
+    }
+}
+
+impl<T: Eq + Ord + Send + Clone + Debug> GivesAccessTokensById<T> for TokenManagerSimulation<T> {
+    /// Requests a refresh executed with the next `step` if the token is
+    /// refreshed on access and expires soon.
+    ///
+    /// Lazily acquired tokens are not waited for. They are fetched with the
+    /// next `step` after they were requested for the first time.
+    fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
+        let (idx, guard) = match self.tokens.get(token_id) {
+            Some((idx, guard)) => (*idx, guard),
+            None => return Err(TokenErrorKind::no_token(token_id).into()),
+        };
+        let token = guard.lock().unwrap().clone();
+        let token = match (token, self.acquire[idx]) {
+            (Ok(token), _) => token,
+            (Err(err @ TokenErrorKind::NotInitialized(_)), Acquire::Lazy { .. }) => {
+                self.refresh(token_id);
+                return Err(err.into());
+            }
+            (Err(err), _) => return Err(err.into()),
+        };
+        if self.refresh_on_access_at[idx].load(Ordering::SeqCst) <= self.clock.now() {
+            self.refresh(token_id);
+        }
+        Ok(token)
+    }
+
+    /// Requests a refresh executed with the next `step` unless one is
+    /// already requested.
+    fn refresh(&self, token_id: &T) {
+        if let Some((idx, _)) = self.tokens.get(token_id) {
+            let command = ManagerCommand::ForceRefresh(token_id.clone(), self.clock.now());
+            self.sender.send_refresh(*idx, command).unwrap();
+        }
+    }
+}
+
+/// An `AccessTokenSource` which is not attached to an `AccessTokenManager`
+/// and whose tokens are set by the test.
+///
+/// Clones share their tokens so a test can keep a clone to simulate
+/// rotations and failures while the code under test holds another one.
+/// `refresh` does nothing.
+pub struct DetachedAccessTokenSource<T> {
+    tokens: Arc<RwLock<BTreeMap<T, StdResult<AccessToken, TokenErrorKind>>>>,
+}
+
+impl<T: Eq + Ord + Clone + Debug> DetachedAccessTokenSource<T> {
+    pub fn new() -> DetachedAccessTokenSource<T> {
+        DetachedAccessTokenSource::default()
+    }
+
+    /// Creates a source with the given tokens.
+    pub fn with_tokens(tokens: &[(T, AccessToken)]) -> DetachedAccessTokenSource<T> {
+        let source = DetachedAccessTokenSource::new();
+        for (token_id, token) in tokens {
+            source.set_token(token_id.clone(), token.clone());
+        }
+        source
+    }
+
+    /// Sets the `AccessToken` returned for `token_id` from now on.
+    pub fn set_token(&self, token_id: T, token: AccessToken) {
+        self.tokens.write().unwrap().insert(token_id, Ok(token));
+    }
+
+    /// Sets the error returned for `token_id` from now on.
+    pub fn set_error(&self, token_id: T, err: TokenErrorKind) {
+        self.tokens.write().unwrap().insert(token_id, Err(err));
+    }
+
+    /// Makes `token_id` fail like a managed token which could not be
+    /// refreshed before it expired.
+    pub fn expire(&self, token_id: T) {
+        let err = TokenErrorKind::AccessTokenProvider(TokenRefreshError::new(format!(
+            "The token {:?} expired",
+            token_id
+        )));
+        self.set_error(token_id, err);
+    }
+
+    /// Removes `token_id` so that it is no longer managed.
+    pub fn remove(&self, token_id: &T) {
+        self.tokens.write().unwrap().remove(token_id);
+    }
+}
+
+impl<T> Clone for DetachedAccessTokenSource<T> {
+    fn clone(&self) -> Self {
+        DetachedAccessTokenSource {
+            tokens: self.tokens.clone(),
+        }
+    }
+}
+
+impl<T: Ord> Default for DetachedAccessTokenSource<T> {
+    fn default() -> Self {
+        DetachedAccessTokenSource {
+            tokens: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+}
+
+impl<T: Eq + Ord + Clone + Debug> GivesAccessTokensById<T> for DetachedAccessTokenSource<T> {
+    fn get_access_token(&self, token_id: &T) -> TokenResult<AccessToken> {
+        match self.tokens.read().unwrap().get(token_id) {
+            Some(Ok(token)) => Ok(token.clone()),
+            Some(Err(err)) => Err(err.clone().into()),
+            None => Err(TokenErrorKind::no_token(token_id).into()),
+        }
+    }
+
+    fn refresh(&self, _token_id: &T) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token_manager::{ManagedTokenBuilder, ManagedTokenGroupBuilder};
+
+    #[test]
+    fn notifications_follow_the_thresholds() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("first", Duration::from_secs(100));
+        provider.push_err(AccessTokenProviderError::Server("down".to_string()));
+
+        let group = ManagedTokenGroupBuilder::single_token("token", vec![], provider.clone())
+            .build()
+            .unwrap();
+        let simulation = TokenManagerSimulation::new(vec![group]);
+
+        simulation.step();
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "first");
+
+        // The refresh at 75 s fails but the token is kept until it expires.
+        simulation.run_for(Duration::from_secs(80), Duration::from_secs(1));
+        assert_eq!(provider.calls(), 2);
+        assert!(simulation.get_access_token(&"token").is_ok());
+
+        // Notifications for a token are at least 10 s apart.
+        simulation.run_for(Duration::from_secs(30), Duration::from_secs(1));
+        let notifications = simulation.take_notifications();
+        assert_eq!(
+            notifications
+                .iter()
+                .map(|n| (n.kind, n.at.as_secs()))
+                .collect::<Vec<_>>(),
+            vec![
+                (NotificationKind::ExpiresSoon, 85),
+                (NotificationKind::ExpiresSoon, 95),
+                (NotificationKind::Expired, 100),
+                (NotificationKind::Expired, 110),
+            ]
+        );
+        assert!(simulation.take_notifications().is_empty());
+    }
+
+    #[test]
+    fn notifications_escalate_towards_the_expiry() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("first", Duration::from_secs(100));
+        provider.push_err(AccessTokenProviderError::Server("down".to_string()));
+
+        let mut builder = ManagedTokenGroupBuilder::single_token("token", vec![], provider);
+        builder.with_escalation_window(Duration::from_secs(12));
+        let simulation = TokenManagerSimulation::new(vec![builder.build().unwrap()]);
+
+        simulation.step();
+        simulation.run_for(Duration::from_secs(104), Duration::from_secs(1));
+
+        // A higher severity does not wait for the notification interval.
+        assert_eq!(
+            simulation
+                .take_notifications()
+                .iter()
+                .map(|n| (n.severity, n.at.as_secs()))
+                .collect::<Vec<_>>(),
+            vec![
+                (NotificationSeverity::Warning, 85),
+                (NotificationSeverity::Escalated, 88),
+                (NotificationSeverity::Escalated, 98),
+                (NotificationSeverity::Critical, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn paused_tokens_are_not_refreshed() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("first", Duration::from_secs(100));
+        provider.push_ok("second", Duration::from_secs(100));
+
+        let group = ManagedTokenGroupBuilder::single_token("token", vec![], provider.clone())
+            .build()
+            .unwrap();
+        let simulation = TokenManagerSimulation::new(vec![group]);
+
+        simulation.step();
+        assert!(simulation.pause(&"token"));
+        simulation.run_for(Duration::from_secs(90), Duration::from_secs(1));
+        assert_eq!(provider.calls(), 1);
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "first");
+
+        // The refresh that became due is executed with the next step.
+        assert!(simulation.resume(&"token"));
+        simulation.step();
+        assert_eq!(provider.calls(), 2);
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "second");
+
+        assert!(!simulation.pause(&"other"));
+    }
+
+    #[test]
+    fn tokens_refreshed_on_access_are_refreshed_when_requested_before_the_expiry() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("first", Duration::from_secs(100));
+        provider.push_ok("second", Duration::from_secs(100));
+        provider.push_ok("third", Duration::from_secs(100));
+
+        let mut builder = ManagedTokenGroupBuilder::single_token("token", vec![], provider.clone());
+        builder.refresh_on_access_within(Duration::from_secs(20));
+        let simulation = TokenManagerSimulation::new(vec![builder.build().unwrap()]);
+
+        simulation.step();
+        simulation.run_for(Duration::from_secs(79), Duration::from_secs(1));
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "first");
+        simulation.step();
+        assert_eq!(provider.calls(), 1);
+        assert!(simulation.take_notifications().is_empty());
+
+        // The current token is returned while the refresh is requested.
+        simulation.advance(Duration::from_secs(1));
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "first");
+        simulation.step();
+        assert_eq!(provider.calls(), 2);
+        assert_eq!(simulation.get_access_token(&"token").unwrap().reveal(), "second");
+
+        // Tokens which are not requested are refreshed once they expired.
+        simulation.run_for(Duration::from_secs(100), Duration::from_secs(1));
+        assert_eq!(provider.calls(), 3);
+    }
+
+    #[test]
+    fn lazy_tokens_are_fetched_when_requested() {
+        let provider = ScriptedTokenProvider::new();
+        provider.push_ok("lazy", Duration::from_secs(100));
+
+        let mut token = ManagedTokenBuilder::default();
+        token.with_identifier("token").with_acquire(Acquire::lazy());
+        let mut builder = ManagedTokenGroupBuilder::default();
+        builder
+            .with_token_provider(provider.clone())
+            .with_managed_token(token.build().unwrap());
+        let simulation = TokenManagerSimulation::new(vec![builder.build().unwrap()]);
+
+        simulation.run_for(Duration::from_secs(10), Duration::from_secs(1));
+        assert_eq!(provider.calls(), 0);
+
+        match *simulation.get_access_token(&"token").unwrap_err().kind() {
+            TokenErrorKind::NotInitialized(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        simulation.step();
+        assert_eq!(provider.calls(), 1);
+        assert_eq!(
+            simulation.get_access_token(&"token").unwrap().reveal(),
+            "lazy"
+        );
+    }
+
+    #[test]
+    fn detached_sources_share_their_tokens() {
+        let source = DetachedAccessTokenSource::with_tokens(&[("token", AccessToken::new("a"))]);
+        let held_by_code_under_test = source.clone();
+        let in_thread = source.clone();
+        std::thread::spawn(move || in_thread.set_token("token", AccessToken::new("b")))
+            .join()
+            .unwrap();
+        assert_eq!(
+            held_by_code_under_test
+                .get_access_token(&"token")
+                .unwrap()
+                .reveal(),
+            "b"
+        );
+
+        source.expire("token");
+        assert!(held_by_code_under_test.get_access_token(&"token").is_err());
+        source.remove(&"token");
+        match *held_by_code_under_test
+            .get_access_token(&"token")
+            .unwrap_err()
+            .kind()
+        {
+            TokenErrorKind::NoToken(_) => {}
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Using async `AccessTokenProvider`s with the thread based token manager.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+
+use super::*;
+
+/// The time an `AsyncProviderAdapter` waits for a token by default.
+pub const DEFAULT_ASYNC_PROVIDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Calls an authorization server for an `AccessToken` without blocking,
+/// e.g. via an async-only SDK.
+///
+/// Wrap it into an `AsyncProviderAdapter` to manage its tokens with the
+/// `AccessTokenManager`.
+pub trait AsyncAccessTokenProvider {
+    /// Issue a request to the authorization server for an `AccessToken`
+    /// with the given `Scope`s.
+    fn request_access_token<'a>(
+        &'a self,
+        scopes: &'a [Scope],
+    ) -> BoxFuture<'a, AccessTokenProviderResult>;
+
+    /// Issue a request to the authorization server for an `AccessToken`
+    /// with the given `Scope`s and `TokenRequestParams`.
+    ///
+    /// The default implementation ignores the `params`.
+    fn request_access_token_with_params<'a>(
+        &'a self,
+        scopes: &'a [Scope],
+        _params: &'a TokenRequestParams,
+    ) -> BoxFuture<'a, AccessTokenProviderResult> {
+        self.request_access_token(scopes)
+    }
+}
+
+/// Makes an `AsyncAccessTokenProvider` an `AccessTokenProvider`.
+///
+/// The futures of the wrapped provider are run to completion on a runtime
+/// dedicated to the adapter. A request which takes longer than the
+/// timeout fails with `AccessTokenProviderError::Connection` so that it
+/// is retried.
+///
+/// The adapter must not be called from within an async context since it
+/// blocks the calling thread. The `AccessTokenManager` calls it on its own
+/// thread.
+pub struct AsyncProviderAdapter<P> {
+    inner: P,
+    runtime: Mutex<tokio::runtime::Runtime>,
+    timeout: Duration,
+}
+
+impl<P> AsyncProviderAdapter<P>
+where
+    P: AsyncAccessTokenProvider + Send + Sync + 'static,
+{
+    /// Creates an adapter with the `DEFAULT_ASYNC_PROVIDER_TIMEOUT`.
+    ///
+    /// Fails if the runtime can not be created.
+    pub fn new(provider: P) -> InitializationResult<Self> {
+        let runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .map_err(|err| InitializationError::Io {
+                message: "Could not create the runtime".to_string(),
+                source: err,
+            })?;
+        Ok(AsyncProviderAdapter {
+            inner: provider,
+            runtime: Mutex::new(runtime),
+            timeout: DEFAULT_ASYNC_PROVIDER_TIMEOUT,
+        })
+    }
+
+    /// Sets the time to wait for the wrapped provider.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn block_on(&self, rsp: BoxFuture<'_, AccessTokenProviderResult>) -> AccessTokenProviderResult {
+        let timeout = self.timeout;
+        let mut runtime = self.runtime.lock().unwrap();
+        // The timer must be created within the runtime to use its driver.
+        match runtime.block_on(async move { tokio::time::timeout(timeout, rsp).await }) {
+            Ok(result) => result,
+            Err(_) => Err(AccessTokenProviderError::Connection(format!(
+                "The async provider did not respond within {:?}",
+                timeout
+            ))),
+        }
+    }
+}
+
+impl<P> AccessTokenProvider for AsyncProviderAdapter<P>
+where
+    P: AsyncAccessTokenProvider + Send + Sync + 'static,
+{
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.block_on(self.inner.request_access_token(scopes))
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        self.block_on(self.inner.request_access_token_with_params(scopes, params))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future::FutureExt;
+
+    struct DelayedProvider(Duration);
+
+    impl AsyncAccessTokenProvider for DelayedProvider {
+        fn request_access_token<'a>(
+            &'a self,
+            scopes: &'a [Scope],
+        ) -> BoxFuture<'a, AccessTokenProviderResult> {
+            async move {
+                tokio::time::delay_for(self.0).await;
+                Ok(AuthorizationServerResponse::new(
+                    AccessToken::new("token"),
+                    Duration::from_secs(60),
+                )
+                .with_scopes(scopes.to_vec()))
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn async_providers_are_run_to_completion() {
+        let provider =
+            AsyncProviderAdapter::new(DelayedProvider(Duration::from_millis(10))).unwrap();
+        let rsp = provider
+            .request_access_token(&[Scope::new("read")])
+            .unwrap();
+        assert_eq!(rsp.access_token.reveal(), "token");
+        assert_eq!(rsp.scopes, Some(vec![Scope::new("read")]));
+    }
+
+    #[test]
+    fn slow_providers_time_out() {
+        let provider = AsyncProviderAdapter::new(DelayedProvider(Duration::from_secs(10)))
+            .unwrap()
+            .with_timeout(Duration::from_millis(10));
+        match provider.request_access_token(&[]) {
+            Err(AccessTokenProviderError::Connection(_)) => {}
+            Err(other) => panic!("unexpected error: {}", other),
+            Ok(_) => panic!("the request did not time out"),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Access tokens for Azure managed identities
+//!
+//! Tokens are requested from the Azure Instance Metadata Service (IMDS).
+//!
+//! See [How to use managed identities](https://docs.microsoft.com/azure/active-directory/managed-identities-azure-resources/how-to-use-vm-token)
+use std::env::{self, VarError};
+use std::result::Result as StdResult;
+use std::str;
+use std::sync::Arc;
+
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use url::form_urlencoded;
+
+use super::{
+    parse_response, send_request, AccessTokenProvider, AccessTokenProviderError,
+    AccessTokenProviderResult,
+};
+use crate::request_decorator::RequestDecorators;
+use crate::{InitializationError, InitializationResult, RequestDecorator, Scope};
+
+/// The token endpoint of the Instance Metadata Service.
+pub const DEFAULT_IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+
+const IMDS_API_VERSION: &str = "2018-02-01";
+
+/// The suffix of v2 style scopes which denotes all permissions of
+/// a resource.
+const DEFAULT_SCOPE_SUFFIX: &str = "/.default";
+
+/// Provides tokens for the managed identity of an Azure VM or VM scale set.
+///
+/// Tokens are issued for a single resource. The resource is either
+/// configured with `with_resource` or derived from the requested `Scope`s
+/// where `https://management.azure.com/.default` maps to the resource
+/// `https://management.azure.com/`.
+///
+/// Responses with status codes 404, 410, 429 and 5xx are reported as
+/// `AccessTokenProviderError::Server` so that they are retried with backoff
+/// as recommended by Azure.
+pub struct AzureManagedIdentityProvider {
+    token_url: String,
+    resource: Option<String>,
+    identity: Option<(&'static str, String)>,
+    client: Client,
+    request_decorators: RequestDecorators,
+}
+
+impl AzureManagedIdentityProvider {
+    /// Creates a new instance requesting tokens for the system assigned
+    /// identity from `token_url`.
+    pub fn new<U: Into<String>>(token_url: U) -> Self {
+        AzureManagedIdentityProvider {
+            token_url: token_url.into(),
+            resource: None,
+            identity: None,
+            client: Client::new(),
+            request_decorators: Vec::new(),
+        }
+    }
+
+    /// Creates a new instance and gets the identity from environment
+    /// variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´AZURE_CLIENT_ID´': An optional client id of a user assigned
+    ///   identity
+    /// * '´TOKKIT_AZURE_RESOURCE´': An optional resource to request tokens
+    ///   for
+    pub fn from_env() -> InitializationResult<Self> {
+        let mut provider = AzureManagedIdentityProvider::default();
+
+        match env::var("AZURE_CLIENT_ID") {
+            Ok(client_id) => provider = provider.with_client_id(client_id),
+            Err(VarError::NotPresent) => {}
+            Err(err) => return Err(InitializationError::env_var("AZURE_CLIENT_ID", err)),
+        }
+
+        match env::var("TOKKIT_AZURE_RESOURCE") {
+            Ok(resource) => provider = provider.with_resource(resource),
+            Err(VarError::NotPresent) => {}
+            Err(err) => return Err(InitializationError::env_var("TOKKIT_AZURE_RESOURCE", err)),
+        }
+
+        Ok(provider)
+    }
+
+    /// Requests tokens for the given resource regardless of the requested
+    /// `Scope`s.
+    pub fn with_resource<T: Into<String>>(mut self, resource: T) -> Self {
+        self.resource = Some(resource.into());
+        self
+    }
+
+    /// Uses the user assigned identity with the given client id.
+    pub fn with_client_id<T: Into<String>>(mut self, client_id: T) -> Self {
+        self.identity = Some(("client_id", client_id.into()));
+        self
+    }
+
+    /// Uses the user assigned identity with the given object id.
+    pub fn with_object_id<T: Into<String>>(mut self, object_id: T) -> Self {
+        self.identity = Some(("object_id", object_id.into()));
+        self
+    }
+
+    /// Uses the user assigned identity with the given Azure resource id.
+    pub fn with_resource_id<T: Into<String>>(mut self, resource_id: T) -> Self {
+        self.identity = Some(("msi_res_id", resource_id.into()));
+        self
+    }
+
+    fn resource_for(&self, scopes: &[Scope]) -> StdResult<String, AccessTokenProviderError> {
+        if let Some(ref resource) = self.resource {
+            return Ok(resource.clone());
+        }
+
+        let mut resources: Vec<String> = scopes
+            .iter()
+            .map(|scope| scope_to_resource(scope.as_str()))
+            .collect();
+        resources.dedup();
+
+        match resources.len() {
+            1 => Ok(resources.remove(0)),
+            0 => Err(AccessTokenProviderError::Client(
+                "A resource or a scope is required to request a token from IMDS".to_string(),
+            )),
+            _ => Err(AccessTokenProviderError::Client(
+                "IMDS issues tokens for a single resource only".to_string(),
+            )),
+        }
+    }
+
+    fn request_url(&self, resource: &str) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("api-version", IMDS_API_VERSION);
+        query.append_pair("resource", resource);
+        if let Some((name, ref value)) = self.identity {
+            query.append_pair(name, value);
+        }
+        format!("{}?{}", self.token_url, query.finish())
+    }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// IMDS. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+}
+
+impl Default for AzureManagedIdentityProvider {
+    fn default() -> Self {
+        AzureManagedIdentityProvider::new(DEFAULT_IMDS_TOKEN_URL)
+    }
+}
+
+impl AccessTokenProvider for AzureManagedIdentityProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        let resource = self.resource_for(scopes)?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("metadata"),
+            HeaderValue::from_static("true"),
+        );
+
+        let rsp = send_request(
+            &self.client,
+            &self.request_decorators,
+            Method::GET,
+            &self.request_url(&resource),
+            headers,
+            None,
+        )?;
+
+        match rsp.status {
+            StatusCode::OK => parse_response(&rsp.body, None),
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::TOO_MANY_REQUESTS => {
+                Err(AccessTokenProviderError::Server(format!(
+                    "IMDS is not available yet({}): {}",
+                    rsp.status,
+                    String::from_utf8_lossy(&rsp.body)
+                )))
+            }
+            status if status.is_server_error() => Err(AccessTokenProviderError::Server(format!(
+                "IMDS returned an error({}): {}",
+                status,
+                String::from_utf8_lossy(&rsp.body)
+            ))),
+            status => Err(AccessTokenProviderError::Client(format!(
+                "IMDS rejected the request({}): {}",
+                status,
+                String::from_utf8_lossy(&rsp.body)
+            ))),
+        }
+    }
+}
+
+fn scope_to_resource(scope: &str) -> String {
+    match scope.strip_suffix(DEFAULT_SCOPE_SUFFIX) {
+        Some(resource) => format!("{}/", resource),
+        None => scope.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resource_is_derived_from_scopes() {
+        let provider = AzureManagedIdentityProvider::default();
+        assert_eq!(
+            provider
+                .resource_for(&[Scope::new("https://management.azure.com/.default")])
+                .unwrap(),
+            "https://management.azure.com/"
+        );
+        assert_eq!(
+            provider
+                .resource_for(&[Scope::new("https://vault.azure.net")])
+                .unwrap(),
+            "https://vault.azure.net"
+        );
+        assert!(provider.resource_for(&[]).is_err());
+        assert!(provider
+            .resource_for(&[
+                Scope::new("https://a/.default"),
+                Scope::new("https://b/.default")
+            ])
+            .is_err());
+
+        let provider = provider.with_resource("https://storage.azure.com/");
+        assert_eq!(
+            provider.resource_for(&[]).unwrap(),
+            "https://storage.azure.com/"
+        );
+    }
+
+    #[test]
+    fn request_url_contains_resource_and_identity() {
+        let provider = AzureManagedIdentityProvider::default().with_client_id("1234");
+        assert_eq!(
+            provider.request_url("https://management.azure.com/"),
+            "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01\
+             &resource=https%3A%2F%2Fmanagement.azure.com%2F&client_id=1234"
+        );
+    }
+
+    #[test]
+    fn parses_string_expires_in() {
+        let body = br#"{"access_token":"eyJ0eXAi","expires_in":"3599","token_type":"Bearer"}"#;
+        let rsp = parse_response(body, None).unwrap();
+        assert_eq!(rsp.expires_in.as_secs(), 3599);
+    }
+}
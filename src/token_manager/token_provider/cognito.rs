@@ -0,0 +1,139 @@
+//! Access tokens from AWS Cognito user pools
+use std::env::{self, VarError};
+use std::sync::Arc;
+
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Method;
+use url::form_urlencoded;
+
+use super::credentials::CredentialsProvider;
+use super::{
+    basic_auth_header, evaluate_response, send_request, AccessTokenProvider,
+    AccessTokenProviderResult, TokenRequestParams,
+};
+use crate::cognito;
+use crate::request_decorator::RequestDecorators;
+use crate::{InitializationError, InitializationResult, RequestDecorator, Scope, ScopeList};
+
+/// Provides tokens from an AWS Cognito user pool via the
+/// Client Credentials Grant.
+///
+/// Only the client credentials of the `CredentialsProvider` are used.
+///
+/// See [RFC6749 Sec. 4.4](https://tools.ietf.org/html/rfc6749#section-4.4)
+/// and the [Cognito token endpoint](https://docs.aws.amazon.com/cognito/latest/developerguide/token-endpoint.html)
+pub struct CognitoAccessTokenProvider {
+    token_endpoint_url: String,
+    client: Client,
+    credentials_provider: Box<dyn CredentialsProvider + Send + Sync + 'static>,
+    request_decorators: RequestDecorators,
+    extra_params: Vec<(String, String)>,
+}
+
+impl CognitoAccessTokenProvider {
+    /// Creates a new instance requesting tokens from
+    /// `https://<domain>.auth.<region>.amazoncognito.com/oauth2/token`.
+    ///
+    /// `domain` may also be a custom domain like `auth.example.com` in which
+    /// case `region` is ignored.
+    pub fn new<C>(domain: &str, region: &str, credentials_provider: C) -> InitializationResult<Self>
+    where
+        C: CredentialsProvider + Send + Sync + 'static,
+    {
+        let domain_url =
+            cognito::domain_url(domain, region).map_err(InitializationError::InvalidUrl)?;
+        Ok(CognitoAccessTokenProvider {
+            token_endpoint_url: format!("{}/oauth2/token", domain_url),
+            client: Client::new(),
+            request_decorators: Vec::new(),
+            extra_params: Vec::new(),
+            credentials_provider: Box::new(credentials_provider),
+        })
+    }
+
+    /// Creates a new instance from the given `CredentialsProvider`
+    /// and gets the remaining values from environment variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´TOKKIT_COGNITO_DOMAIN´': The prefix of the hosted domain or a
+    ///   custom domain
+    /// * '´TOKKIT_COGNITO_REGION´': The region of the user pool. Optional for
+    ///   custom domains. Falls back to '´AWS_REGION´'.
+    pub fn from_env_with_credentials_provider<C>(
+        credentials_provider: C,
+    ) -> InitializationResult<Self>
+    where
+        C: CredentialsProvider + Send + Sync + 'static,
+    {
+        let domain = env::var("TOKKIT_COGNITO_DOMAIN")
+            .map_err(|err| InitializationError::env_var("TOKKIT_COGNITO_DOMAIN", err))?;
+
+        let region = match env::var("TOKKIT_COGNITO_REGION").or_else(|_| env::var("AWS_REGION")) {
+            Ok(region) => region,
+            Err(VarError::NotPresent) => String::new(),
+            Err(err) => return Err(InitializationError::env_var("TOKKIT_COGNITO_REGION", err)),
+        };
+
+        CognitoAccessTokenProvider::new(&domain, &region, credentials_provider)
+    }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// token endpoint. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+
+    /// Adds a parameter sent in the form body of every token request.
+    pub fn with_extra_param<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_params.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl AccessTokenProvider for CognitoAccessTokenProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.request_access_token_with_params(scopes, &TokenRequestParams::default())
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        let credentials = self.credentials_provider.client_credentials()?;
+
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "client_credentials");
+        if !scopes.is_empty() {
+            form.append_pair("scope", &ScopeList::from(scopes).to_string());
+        }
+        params.append_to(&mut form);
+        form.extend_pairs(&self.extra_params);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(
+            AUTHORIZATION,
+            basic_auth_header(&credentials.client_id, &credentials.client_secret)?,
+        );
+
+        let rsp = send_request(
+            &self.client,
+            &self.request_decorators,
+            Method::POST,
+            &self.token_endpoint_url,
+            headers,
+            Some(form.finish().into_bytes()),
+        )?;
+        evaluate_response(rsp.status, &rsp.headers, &rsp.body)
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use futures::future::{BoxFuture, FutureExt};
+
+use super::*;
+
+/// Provides credentials without blocking the executor, e.g. from an async
+/// secret store.
+pub trait AsyncCredentialsProvider {
+    fn client_credentials<'a>(&'a self) -> BoxFuture<'a, CredentialsResult<ClientCredentials>>;
+    fn owner_credentials<'a>(
+        &'a self,
+    ) -> BoxFuture<'a, CredentialsResult<ResourceOwnerCredentials>>;
+
+    fn credentials<'a>(&'a self) -> BoxFuture<'a, CredentialsResult<RequestTokenCredentials>>
+    where
+        Self: Sync,
+    {
+        async move {
+            let client_credentials = self.client_credentials().await?;
+            let owner_credentials = self.owner_credentials().await?;
+            Ok(RequestTokenCredentials {
+                client_credentials,
+                owner_credentials,
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Makes a blocking `CredentialsProvider` an `AsyncCredentialsProvider`.
+///
+/// The wrapped provider is called on the blocking thread pool of the
+/// `tokio` runtime so that reading files does not block the executor.
+pub struct BlockingCredentialsProvider<P> {
+    inner: Arc<P>,
+}
+
+impl<P> BlockingCredentialsProvider<P>
+where
+    P: CredentialsProvider + Send + Sync + 'static,
+{
+    pub fn new(provider: P) -> Self {
+        BlockingCredentialsProvider {
+            inner: Arc::new(provider),
+        }
+    }
+
+    fn spawn_blocking<T, F>(&self, f: F) -> BoxFuture<'static, CredentialsResult<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&P) -> CredentialsResult<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&inner))
+            .map(|result| {
+                result.unwrap_or_else(|err| {
+                    Err(CredentialsError::Other(format!(
+                        "Reading the credentials failed: {}",
+                        err
+                    )))
+                })
+            })
+            .boxed()
+    }
+}
+
+impl<P> Clone for BlockingCredentialsProvider<P> {
+    fn clone(&self) -> Self {
+        BlockingCredentialsProvider {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<P> AsyncCredentialsProvider for BlockingCredentialsProvider<P>
+where
+    P: CredentialsProvider + Send + Sync + 'static,
+{
+    fn client_credentials<'a>(&'a self) -> BoxFuture<'a, CredentialsResult<ClientCredentials>> {
+        self.spawn_blocking(|provider| provider.client_credentials())
+    }
+
+    fn owner_credentials<'a>(
+        &'a self,
+    ) -> BoxFuture<'a, CredentialsResult<ResourceOwnerCredentials>> {
+        self.spawn_blocking(|provider| provider.owner_credentials())
+    }
+
+    fn credentials<'a>(&'a self) -> BoxFuture<'a, CredentialsResult<RequestTokenCredentials>> {
+        self.spawn_blocking(|provider| provider.credentials())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct StaticCredentials;
+
+    impl CredentialsProvider for StaticCredentials {
+        fn client_credentials(&self) -> CredentialsResult<ClientCredentials> {
+            Ok(ClientCredentials {
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+            })
+        }
+
+        fn owner_credentials(&self) -> CredentialsResult<ResourceOwnerCredentials> {
+            Err(CredentialsError::Io("not found".to_string()))
+        }
+    }
+
+    #[test]
+    fn blocking_providers_are_called_on_the_blocking_pool() {
+        let provider = BlockingCredentialsProvider::new(StaticCredentials);
+
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            let client_credentials = provider.client_credentials().await.unwrap();
+            assert_eq!(client_credentials.client_id, "client");
+            assert!(provider.credentials().await.is_err());
+        });
+    }
+}
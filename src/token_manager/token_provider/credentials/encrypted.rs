@@ -0,0 +1,249 @@
+use std::env::{self, VarError};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use super::parsers::*;
+use super::*;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Supplies the key to decrypt credentials files with.
+///
+/// The key is requested each time a file is read so that it may be
+/// rotated. Any `Fn() -> CredentialsResult<Vec<u8>>` is a `KeyProvider`
+/// which allows fetching the key from a KMS.
+pub trait KeyProvider {
+    /// Returns the 256 bit AES key.
+    fn key(&self) -> CredentialsResult<Vec<u8>>;
+}
+
+impl<F> KeyProvider for F
+where
+    F: Fn() -> CredentialsResult<Vec<u8>>,
+{
+    fn key(&self) -> CredentialsResult<Vec<u8>> {
+        self()
+    }
+}
+
+/// Reads a base64 encoded key from an environment variable.
+pub struct EnvKeyProvider {
+    env_name: String,
+}
+
+impl EnvKeyProvider {
+    /// Reads the key from the environment variable with the given name.
+    pub fn new<T: Into<String>>(env_name: T) -> Self {
+        EnvKeyProvider {
+            env_name: env_name.into(),
+        }
+    }
+}
+
+impl Default for EnvKeyProvider {
+    /// Reads the key from `TOKKIT_CREDENTIALS_KEY`.
+    fn default() -> Self {
+        EnvKeyProvider::new("TOKKIT_CREDENTIALS_KEY")
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn key(&self) -> CredentialsResult<Vec<u8>> {
+        match env::var(&self.env_name) {
+            Ok(key) => base64::decode(key.trim()).map_err(|err| {
+                CredentialsError::Other(format!("'{}' is not base64: {}", self.env_name, err))
+            }),
+            Err(VarError::NotPresent) => Err(CredentialsError::Other(format!(
+                "'{}' not found.",
+                self.env_name
+            ))),
+            Err(err) => Err(CredentialsError::Other(err.to_string())),
+        }
+    }
+}
+
+/// Reads the credentials for the resource owner and the client
+/// from two separate files encrypted with AES-256-GCM.
+///
+/// A file consists of the 12 byte nonce followed by the ciphertext and
+/// the 16 byte authentication tag. Files can be created with `encrypt`.
+/// The decrypted contents are parsed like the files of a
+/// `SplitFileCredentialsProvider`.
+pub struct EncryptedFileCredentialsProvider {
+    client_credentials_file_path: PathBuf,
+    owner_credentials_file_path: PathBuf,
+    client_credentials_parser: Box<dyn ClientCredentialsParser + Send + Sync + 'static>,
+    owner_credentials_parser: Box<dyn ResourceOwnerCredentialsParser + Send + Sync + 'static>,
+    key_provider: Box<dyn KeyProvider + Send + Sync + 'static>,
+}
+
+impl EncryptedFileCredentialsProvider {
+    /// Create a new instance with the given paths, parsers and
+    /// `KeyProvider`.
+    pub fn new<C, O, CP, UP, K>(
+        client_credentials_file_path: C,
+        owner_credentials_file_path: O,
+        client_credentials_parser: CP,
+        owner_credentials_parser: UP,
+        key_provider: K,
+    ) -> Self
+    where
+        C: Into<PathBuf>,
+        O: Into<PathBuf>,
+        CP: ClientCredentialsParser + Send + Sync + 'static,
+        UP: ResourceOwnerCredentialsParser + Send + Sync + 'static,
+        K: KeyProvider + Send + Sync + 'static,
+    {
+        EncryptedFileCredentialsProvider {
+            client_credentials_file_path: client_credentials_file_path.into(),
+            owner_credentials_file_path: owner_credentials_file_path.into(),
+            client_credentials_parser: Box::new(client_credentials_parser),
+            owner_credentials_parser: Box::new(owner_credentials_parser),
+            key_provider: Box::new(key_provider),
+        }
+    }
+
+    /// Creates a new instance for the given paths with default parsers.
+    pub fn with_default_parsers<C, O, K>(
+        client_credentials_file_path: C,
+        owner_credentials_file_path: O,
+        key_provider: K,
+    ) -> Self
+    where
+        C: Into<PathBuf>,
+        O: Into<PathBuf>,
+        K: KeyProvider + Send + Sync + 'static,
+    {
+        EncryptedFileCredentialsProvider::new(
+            client_credentials_file_path,
+            owner_credentials_file_path,
+            DefaultClientCredentialsParser,
+            DefaultResourceOwnerCredentialsParser,
+            key_provider,
+        )
+    }
+
+    fn decrypt_file(&self, path: &Path) -> CredentialsResult<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        decrypt(&self.key_provider.key()?, &contents)
+    }
+}
+
+impl CredentialsProvider for EncryptedFileCredentialsProvider {
+    fn client_credentials(&self) -> CredentialsResult<ClientCredentials> {
+        let contents = self.decrypt_file(&self.client_credentials_file_path)?;
+        self.client_credentials_parser.parse(&contents)
+    }
+
+    fn owner_credentials(&self) -> CredentialsResult<ResourceOwnerCredentials> {
+        let contents = self.decrypt_file(&self.owner_credentials_file_path)?;
+        self.owner_credentials_parser.parse(&contents)
+    }
+}
+
+/// Encrypts `plaintext` with the 256 bit `key` into the format read by an
+/// `EncryptedFileCredentialsProvider`.
+pub fn encrypt(key: &[u8], plaintext: &[u8]) -> CredentialsResult<Vec<u8>> {
+    check_key(key)?;
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce).map_err(|err| CredentialsError::Other(err.to_string()))?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        plaintext,
+        &mut tag,
+    )
+    .map_err(|err| CredentialsError::Other(format!("Encryption failed: {}", err)))?;
+
+    let mut encrypted = Vec::with_capacity(NONCE_LEN + ciphertext.len() + TAG_LEN);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    encrypted.extend_from_slice(&tag);
+    Ok(encrypted)
+}
+
+fn decrypt(key: &[u8], encrypted: &[u8]) -> CredentialsResult<Vec<u8>> {
+    check_key(key)?;
+    if encrypted.len() < NONCE_LEN + TAG_LEN {
+        return Err(CredentialsError::Parse(
+            "The encrypted credentials are too short.".to_string(),
+        ));
+    }
+    let (nonce, rest) = encrypted.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        &[],
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| {
+        CredentialsError::Other(
+            "Could not decrypt the credentials. The key is wrong or the file was modified."
+                .to_string(),
+        )
+    })
+}
+
+fn check_key(key: &[u8]) -> CredentialsResult<()> {
+    if key.len() == KEY_LEN {
+        Ok(())
+    } else {
+        Err(CredentialsError::Other(format!(
+            "Expected a key of {} bytes but got {} bytes.",
+            KEY_LEN,
+            key.len()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn credentials_are_decrypted_before_parsing() {
+        let key = vec![7; KEY_LEN];
+        let dir = env::temp_dir().join(format!("tokkit-encrypted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let client_path = dir.join("client.json.enc");
+        let owner_path = dir.join("user.json.enc");
+        let client = br#"{"client_id": "id", "client_secret": "secret"}"#;
+        let owner = br#"{"username": "user", "password": "pw"}"#;
+        fs::write(&client_path, encrypt(&key, client).unwrap()).unwrap();
+        fs::write(&owner_path, encrypt(&key, owner).unwrap()).unwrap();
+
+        let provider = EncryptedFileCredentialsProvider::with_default_parsers(
+            &client_path,
+            &owner_path,
+            move || Ok(key.clone()),
+        );
+        let credentials = provider.credentials().unwrap();
+        assert_eq!(credentials.client_credentials.client_secret, "secret");
+        assert_eq!(credentials.owner_credentials.password, "pw");
+
+        let provider = EncryptedFileCredentialsProvider::with_default_parsers(
+            &client_path,
+            &owner_path,
+            || Ok(vec![8; KEY_LEN]),
+        );
+        assert!(provider.client_credentials().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,9 +6,17 @@ use std::result::Result as StdResult;
 
 use crate::{InitializationError, InitializationResult};
 
+#[cfg(feature = "async")]
+mod async_credentials;
+#[cfg(feature = "encrypted-credentials")]
+mod encrypted;
 mod errors;
 pub mod parsers;
 
+#[cfg(feature = "async")]
+pub use self::async_credentials::*;
+#[cfg(feature = "encrypted-credentials")]
+pub use self::encrypted::*;
 pub use self::errors::*;
 
 use self::parsers::*;
@@ -203,7 +211,8 @@ impl SplitFileCredentialsProvider {
     where
         P: ResourceOwnerCredentialsParser + Send + Sync + 'static,
     {
-        let credentials_dir = credentials_dir_from_env().map_err(InitializationError)?;
+        let credentials_dir = credentials_dir_from_env()
+            .map_err(|err| InitializationError::env_var("TOKKIT_CREDENTIALS_DIR", err))?;
 
         let owner_file_name: PathBuf = match env::var("TOKKIT_CREDENTIALS_RESOURCE_OWNER_FILENAME")
         {
@@ -212,7 +221,12 @@ impl SplitFileCredentialsProvider {
                 warn!("No owner file name. Assuming 'user.json'");
                 "user.json".into()
             }
-            Err(err) => return Err(InitializationError(err.to_string())),
+            Err(err) => {
+                return Err(InitializationError::env_var(
+                    "TOKKIT_CREDENTIALS_RESOURCE_OWNER_FILENAME",
+                    err,
+                ))
+            }
         };
 
         let client_file_name: PathBuf = match env::var("TOKKIT_CREDENTIALS_CLIENT_FILENAME") {
@@ -221,7 +235,12 @@ impl SplitFileCredentialsProvider {
                 warn!("No client file name. Assuming 'client.json'");
                 "client.json".into()
             }
-            Err(err) => return Err(InitializationError(err.to_string())),
+            Err(err) => {
+                return Err(InitializationError::env_var(
+                    "TOKKIT_CREDENTIALS_CLIENT_FILENAME",
+                    err,
+                ))
+            }
         };
 
         let mut owner_credentials_file_path = credentials_dir.clone();
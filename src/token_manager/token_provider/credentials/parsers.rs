@@ -1,9 +1,6 @@
 //! Parser for credentials, mostl likely JSON
-use json;
-use json::*;
-use std::str;
-
 use super::*;
+use crate::claims;
 
 /// A parser for `ClientCredentials`
 pub trait ClientCredentialsParser {
@@ -97,36 +94,14 @@ fn parse_credentials(
     id_field_name: &str,
     secret_field_name: &str,
 ) -> CredentialsResult<(String, String)> {
-    let json_utf8 = str::from_utf8(bytes).map_err(|err| CredentialsError::Parse(err.to_string()))?;
-    let json = json::parse(json_utf8).map_err(|err| CredentialsError::Parse(err.to_string()))?;
-
-    if let JsonValue::Object(data) = json {
-        let id = match data.get(id_field_name) {
-            Some(&JsonValue::Short(user_id)) => user_id.to_string(),
-            Some(&JsonValue::String(ref user_id)) => user_id.clone(),
-            invalid => {
-                return Err(CredentialsError::Parse(format!(
-                    "Expected a string as the user id in field '{}' but found a {:?}",
-                    id_field_name, invalid
-                )))
-            }
-        };
-
-        let secret = match data.get(secret_field_name) {
-            Some(&JsonValue::Short(secret)) => secret.to_string(),
-            Some(&JsonValue::String(ref secret)) => secret.clone(),
-            invalid => {
-                return Err(CredentialsError::Parse(format!(
-                    "Expected a string as the secret in field '{}' but found a {:?}",
-                    secret_field_name, invalid
-                )))
-            }
-        };
-
-        Ok((id, secret))
-    } else {
-        Err(CredentialsError::Parse("Not a JSON object".to_string()))
-    }
+    let data = claims::object(bytes).map_err(CredentialsError::Parse)?;
+    let field = |field: &str| {
+        claims::required_string(&data, field)
+            .map(str::to_owned)
+            .map_err(CredentialsError::Parse)
+    };
+
+    Ok((field(id_field_name)?, field(secret_field_name)?))
 }
 
 #[cfg(test)]
@@ -0,0 +1,309 @@
+//! Short-lived database auth tokens for IAM database authentication
+//!
+//! Databases with IAM authentication accept a short-lived token instead of
+//! a password. Managed like any other `AccessToken` a connection pool
+//! always finds a fresh token to open new connections with.
+//!
+//! * Amazon RDS and Aurora accept a presigned request as token which
+//!   `RdsIamAuthTokenProvider` creates locally without asking any server.
+//!   See [IAM database authentication](https://docs.aws.amazon.com/AmazonRDS/latest/UserGuide/UsingWithRDS.IAMDBAuth.html)
+//! * Google Cloud SQL accepts an OAuth 2.0 access token with the scope
+//!   `CLOUD_SQL_LOGIN_SCOPE` which any provider of Google access tokens,
+//!   e.g. the `GceMetadataAccessTokenProvider`, can request.
+//!   See [IAM database authentication](https://cloud.google.com/sql/docs/postgres/iam-authentication)
+use std::env::{self, VarError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::{
+    AccessTokenProvider, AccessTokenProviderError, AccessTokenProviderResult,
+    AuthorizationServerResponse,
+};
+use crate::sha256::{hex, hmac_sha256, sha256};
+use crate::{AccessToken, InitializationError, InitializationResult, Scope};
+
+/// The scope of Google access tokens accepted by Cloud SQL.
+pub const CLOUD_SQL_LOGIN_SCOPE: &str = "https://www.googleapis.com/auth/sqlservice.login";
+
+/// The time an RDS auth token is accepted for, which is the maximum
+/// allowed by RDS.
+pub const RDS_AUTH_TOKEN_LIFETIME: Duration = Duration::from_secs(900);
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "rds-db";
+
+/// The characters AWS Signature Version 4 percent-encodes.
+const SIGV4_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// The AWS credentials RDS auth tokens are signed with.
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// The session token of temporary credentials.
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn new<A: Into<String>, S: Into<String>>(access_key_id: A, secret_access_key: S) -> Self {
+        AwsCredentials {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+        }
+    }
+
+    pub fn with_session_token<T: Into<String>>(mut self, session_token: T) -> Self {
+        self.session_token = Some(session_token.into());
+        self
+    }
+}
+
+/// Provides auth tokens for a database user of an Amazon RDS or Aurora
+/// instance.
+///
+/// The tokens are valid for `RDS_AUTH_TOKEN_LIFETIME`. The requested
+/// `Scope`s are ignored.
+pub struct RdsIamAuthTokenProvider {
+    host: String,
+    port: u16,
+    user: String,
+    region: String,
+    credentials: AwsCredentials,
+}
+
+impl RdsIamAuthTokenProvider {
+    /// Creates a new instance for `user` of the database at `host:port` in
+    /// `region`.
+    pub fn new<H, U, R>(host: H, port: u16, user: U, region: R, credentials: AwsCredentials) -> Self
+    where
+        H: Into<String>,
+        U: Into<String>,
+        R: Into<String>,
+    {
+        RdsIamAuthTokenProvider {
+            host: host.into(),
+            port,
+            user: user.into(),
+            region: region.into(),
+            credentials,
+        }
+    }
+
+    /// Creates a new instance configured from environment variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´TOKKIT_RDS_HOST´': The host of the database
+    /// * '´TOKKIT_RDS_PORT´': An optional port. The default is `5432`.
+    /// * '´TOKKIT_RDS_USER´': The database user
+    /// * '´AWS_REGION´' or '´AWS_DEFAULT_REGION´': The region of the
+    ///   database
+    /// * '´AWS_ACCESS_KEY_ID´', '´AWS_SECRET_ACCESS_KEY´' and an optional
+    ///   '´AWS_SESSION_TOKEN´': The credentials
+    pub fn from_env() -> InitializationResult<Self> {
+        let port = match optional_var("TOKKIT_RDS_PORT")? {
+            Some(port) => port
+                .parse()
+                .map_err(|err| InitializationError::env_var("TOKKIT_RDS_PORT", err))?,
+            None => 5432,
+        };
+        let region = match optional_var("AWS_REGION")? {
+            Some(region) => region,
+            None => required_var("AWS_DEFAULT_REGION")?,
+        };
+        let mut credentials = AwsCredentials::new(
+            required_var("AWS_ACCESS_KEY_ID")?,
+            required_var("AWS_SECRET_ACCESS_KEY")?,
+        );
+        if let Some(session_token) = optional_var("AWS_SESSION_TOKEN")? {
+            credentials = credentials.with_session_token(session_token);
+        }
+        Ok(RdsIamAuthTokenProvider::new(
+            required_var("TOKKIT_RDS_HOST")?,
+            port,
+            required_var("TOKKIT_RDS_USER")?,
+            region,
+            credentials,
+        ))
+    }
+
+    /// Creates the token as a request presigned at `now` with AWS
+    /// Signature Version 4.
+    fn auth_token(&self, now: SystemTime) -> Result<String, AccessTokenProviderError> {
+        let seconds = now
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| AccessTokenProviderError::Other(err.to_string()))?
+            .as_secs();
+        let timestamp = amz_date(seconds);
+        let date = &timestamp[..8];
+        let credential_scope = format!("{}/{}/{}/aws4_request", date, self.region, SERVICE);
+        let host = format!("{}:{}", self.host, self.port);
+        let expires = RDS_AUTH_TOKEN_LIFETIME.as_secs().to_string();
+
+        let mut query = vec![
+            ("Action", "connect".to_string()),
+            ("DBUser", self.user.clone()),
+            ("X-Amz-Algorithm", ALGORITHM.to_string()),
+            (
+                "X-Amz-Credential",
+                format!("{}/{}", self.credentials.access_key_id, credential_scope),
+            ),
+            ("X-Amz-Date", timestamp.clone()),
+            ("X-Amz-Expires", expires),
+        ];
+        if let Some(ref session_token) = self.credentials.session_token {
+            query.push(("X-Amz-Security-Token", session_token.clone()));
+        }
+        query.push(("X-Amz-SignedHeaders", "host".to_string()));
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = format!(
+            "GET\n/\n{}\nhost:{}\n\nhost\n{}",
+            query,
+            host,
+            hex(&sha256(b""))
+        );
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            timestamp,
+            credential_scope,
+            hex(&sha256(canonical_request.as_bytes()))
+        );
+        let signing_key = signing_key(
+            &self.credentials.secret_access_key,
+            date,
+            &self.region,
+            SERVICE,
+        );
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        Ok(format!("{}/?{}&X-Amz-Signature={}", host, query, signature))
+    }
+}
+
+impl AccessTokenProvider for RdsIamAuthTokenProvider {
+    fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+        let token = self.auth_token(SystemTime::now())?;
+        Ok(AuthorizationServerResponse::new(
+            AccessToken::new(token),
+            RDS_AUTH_TOKEN_LIFETIME,
+        ))
+    }
+}
+
+/// Derives the key requests are signed with on `date`.
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Formats seconds since the epoch like `20150830T123600Z`.
+fn amz_date(seconds: u64) -> String {
+    let days = seconds / 86_400;
+    let time = seconds % 86_400;
+    // Converts days since the epoch to a date of the proleptic Gregorian
+    // calendar as shown in http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, SIGV4_ENCODE_SET).to_string()
+}
+
+fn optional_var(name: &str) -> InitializationResult<Option<String>> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(err) => Err(InitializationError::env_var(name, err)),
+    }
+}
+
+fn required_var(name: &str) -> InitializationResult<String> {
+    optional_var(name)?.ok_or_else(|| InitializationError::env_var(name, "not set"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dates_are_formatted_in_utc() {
+        assert_eq!(amz_date(0), "19700101T000000Z");
+        assert_eq!(amz_date(951_782_400), "20000229T000000Z");
+        assert_eq!(amz_date(1_000_000_000), "20010909T014640Z");
+        assert_eq!(amz_date(1_440_938_160), "20150830T123600Z");
+    }
+
+    #[test]
+    fn signing_keys_match_the_aws_example() {
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn auth_tokens_are_presigned_connect_requests() {
+        let provider = RdsIamAuthTokenProvider::new(
+            "db.example.com",
+            5432,
+            "app user",
+            "eu-central-1",
+            AwsCredentials::new("AKIDEXAMPLE", "secret").with_session_token("a/b+c="),
+        );
+        let now = UNIX_EPOCH + Duration::from_secs(1_440_938_160);
+        let token = provider.auth_token(now).unwrap();
+
+        let prefix = "db.example.com:5432/?Action=connect&DBUser=app%20user\
+                      &X-Amz-Algorithm=AWS4-HMAC-SHA256\
+                      &X-Amz-Credential=AKIDEXAMPLE%2F20150830%2F\
+                      eu-central-1%2Frds-db%2Faws4_request\
+                      &X-Amz-Date=20150830T123600Z&X-Amz-Expires=900\
+                      &X-Amz-Security-Token=a%2Fb%2Bc%3D&X-Amz-SignedHeaders=host\
+                      &X-Amz-Signature=";
+        let signature = "5a78740b32a9bfd42a550f1f535b00f548f70f39621190433b5a24349e287e84";
+        assert_eq!(token, format!("{}{}", prefix, signature));
+        assert_eq!(provider.auth_token(now).unwrap(), token);
+        assert!(!token.contains("secret"));
+    }
+}
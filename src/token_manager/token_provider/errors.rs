@@ -1,8 +1,10 @@
 use std::error::Error;
 use std::fmt;
 use std::str;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum AccessTokenProviderError {
     /// An invalid request was sent which contains further information.
     /// No retry necessary.
@@ -23,6 +25,18 @@ pub enum AccessTokenProviderError {
     Credentials(super::credentials::CredentialsError),
     /// Something else happened. Most probably not worth a retry.
     Other(String),
+    /// The authorization server did not accept the credentials of the
+    /// client (`401`). No retry necessary.
+    Unauthorized(String),
+    /// The client is not allowed to request the token (`403`).
+    /// No retry necessary.
+    Forbidden(String),
+    /// The endpoint of the authorization server does not exist (`404`).
+    /// No retry necessary.
+    EndpointNotFound(String),
+    /// The authorization server refused the request because of too many
+    /// requests. Should be retried after `retry_after` if given.
+    RateLimited { retry_after: Option<Duration> },
 }
 
 /// An error in detail returned by the authorization server.
@@ -53,6 +67,27 @@ pub struct AuthorizationRequestError {
     pub error_uri: Option<String>,
 }
 
+impl AuthorizationRequestError {
+    /// Creates an error without a description and URI.
+    pub fn new(error: AuthorizationServerErrorCode) -> AuthorizationRequestError {
+        AuthorizationRequestError {
+            error,
+            error_description: None,
+            error_uri: None,
+        }
+    }
+
+    pub fn with_description<T: Into<String>>(mut self, description: T) -> Self {
+        self.error_description = Some(description.into());
+        self
+    }
+
+    pub fn with_uri<T: Into<String>>(mut self, uri: T) -> Self {
+        self.error_uri = Some(uri.into());
+        self
+    }
+}
+
 impl fmt::Display for AuthorizationRequestError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut error_msg = format!(
@@ -80,6 +115,8 @@ impl str::FromStr for AuthorizationServerErrorCode {
             "unauthorized_client" => Ok(AuthorizationServerErrorCode::UnauthorizedClient),
             "unsupported_grant_type" => Ok(AuthorizationServerErrorCode::UnsupportedGrantType),
             "invalid_scope" => Ok(AuthorizationServerErrorCode::InvalidScope),
+            "server_error" => Ok(AuthorizationServerErrorCode::ServerError),
+            "temporarily_unavailable" => Ok(AuthorizationServerErrorCode::TemporarilyUnavailable),
             x => Err(AccessTokenProviderError::Other(format!(
                 "'{}' is not a valid error kind.",
                 x
@@ -91,6 +128,7 @@ impl str::FromStr for AuthorizationServerErrorCode {
 /// The error code returned from the authorization server on
 /// `BadRequest`.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum AuthorizationServerErrorCode {
     /// The request is missing a required parameter, includes an
     /// unsupported parameter value (other than grant type),
@@ -124,6 +162,44 @@ pub enum AuthorizationServerErrorCode {
     /// The requested scope is invalid, unknown, malformed, or
     /// exceeds the scope granted by the resource owner.
     InvalidScope,
+    /// The authorization server encountered an unexpected
+    /// condition that prevented it from fulfilling the request.
+    ServerError,
+    /// The authorization server is currently unable to handle
+    /// the request due to a temporary overloading or maintenance
+    /// of the server.
+    TemporarilyUnavailable,
+}
+
+impl AuthorizationServerErrorCode {
+    /// Returns `true` if the request may succeed when sent again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            *self,
+            AuthorizationServerErrorCode::ServerError
+                | AuthorizationServerErrorCode::TemporarilyUnavailable
+        )
+    }
+}
+
+impl AccessTokenProviderError {
+    /// Returns `true` if the error usually goes away, e.g. a connection
+    /// error. These errors are retried when refreshing a managed token.
+    pub fn is_retry_suggested(&self) -> bool {
+        match *self {
+            AccessTokenProviderError::BadAuthorizationRequest(ref err) => err.error.is_transient(),
+            AccessTokenProviderError::Server(_)
+            | AccessTokenProviderError::Connection(_)
+            | AccessTokenProviderError::Credentials(_)
+            | AccessTokenProviderError::Other(_)
+            | AccessTokenProviderError::RateLimited { .. } => true,
+            AccessTokenProviderError::Client(_)
+            | AccessTokenProviderError::Parse(_)
+            | AccessTokenProviderError::Unauthorized(_)
+            | AccessTokenProviderError::Forbidden(_)
+            | AccessTokenProviderError::EndpointNotFound(_) => false,
+        }
+    }
 }
 
 impl fmt::Display for AccessTokenProviderError {
@@ -140,6 +216,25 @@ impl fmt::Display for AccessTokenProviderError {
                 write!(f, "Problem with credentials caused by {}", inner)
             }
             AccessTokenProviderError::Other(ref msg) => write!(f, "Other error {}", msg),
+            AccessTokenProviderError::Unauthorized(ref msg) => {
+                write!(f, "The client is not authorized: {}", msg)
+            }
+            AccessTokenProviderError::Forbidden(ref msg) => {
+                write!(f, "The request is forbidden: {}", msg)
+            }
+            AccessTokenProviderError::EndpointNotFound(ref msg) => {
+                write!(f, "The token endpoint was not found: {}", msg)
+            }
+            AccessTokenProviderError::RateLimited {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Rate limited by the authorization server for {:.3} seconds",
+                retry_after.as_secs_f64()
+            ),
+            AccessTokenProviderError::RateLimited { retry_after: None } => {
+                write!(f, "Rate limited by the authorization server")
+            }
         }
     }
 }
@@ -158,10 +253,16 @@ impl Error for AccessTokenProviderError {
             }
             AccessTokenProviderError::Credentials(_) => "problem with the credentials",
             AccessTokenProviderError::Other(_) => "something unexpected happened",
+            AccessTokenProviderError::Unauthorized(_) => "the client credentials were refused",
+            AccessTokenProviderError::Forbidden(_) => "the client may not request the token",
+            AccessTokenProviderError::EndpointNotFound(_) => "the token endpoint does not exist",
+            AccessTokenProviderError::RateLimited { .. } => {
+                "the authorization server received too many requests"
+            }
         }
     }
 
-    fn cause(&self) -> Option<&dyn Error> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
             AccessTokenProviderError::Credentials(ref inner) => Some(inner),
             _ => None,
@@ -171,10 +272,7 @@ impl Error for AccessTokenProviderError {
 
 impl From<AccessTokenProviderError> for crate::token_manager::error::TokenError {
     fn from(what: AccessTokenProviderError) -> crate::token_manager::error::TokenError {
-        crate::token_manager::error::TokenErrorKind::AccessTokenProvider(format!(
-            "Error from Tokenprovider: {}",
-            what
-        )).into()
+        crate::token_manager::error::TokenErrorKind::from(what).into()
     }
 }
 
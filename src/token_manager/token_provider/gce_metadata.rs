@@ -0,0 +1,224 @@
+//! Access tokens from the metadata server of GCE and GKE
+//!
+//! See [Authenticating workloads](https://cloud.google.com/compute/docs/access/create-enable-service-accounts-for-instances#applications)
+use std::env::{self, VarError};
+use std::sync::Arc;
+
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Method;
+use url::form_urlencoded;
+
+use super::{
+    evaluate_response, send_request, AccessTokenProvider, AccessTokenProviderError,
+    AccessTokenProviderResult,
+};
+use crate::request_decorator::RequestDecorators;
+use crate::{InitializationError, InitializationResult, RequestDecorator, Scope};
+
+/// The metadata server as reachable from within GCE and GKE.
+pub const DEFAULT_METADATA_URL: &str = "http://metadata.google.internal";
+
+const METADATA_FLAVOR_HEADER: &str = "metadata-flavor";
+const METADATA_FLAVOR: &str = "Google";
+
+/// Provides tokens for the service account attached to a GCE instance or
+/// a GKE workload.
+///
+/// The scopes of the tokens are determined by the instance configuration.
+/// Requested `Scope`s are only sent to the metadata server if enabled with
+/// `with_scopes_forwarded`.
+pub struct GceMetadataAccessTokenProvider {
+    metadata_url: String,
+    service_account: String,
+    forward_scopes: bool,
+    client: Client,
+    request_decorators: RequestDecorators,
+}
+
+impl GceMetadataAccessTokenProvider {
+    /// Creates a new instance requesting tokens for the default service
+    /// account from the metadata server at `metadata_url`.
+    pub fn new<U: Into<String>>(metadata_url: U) -> Self {
+        let mut metadata_url = metadata_url.into();
+        if metadata_url.ends_with('/') {
+            metadata_url.pop();
+        }
+        GceMetadataAccessTokenProvider {
+            metadata_url,
+            service_account: "default".to_string(),
+            forward_scopes: false,
+            client: Client::new(),
+            request_decorators: Vec::new(),
+        }
+    }
+
+    /// Creates a new instance and gets the metadata server from
+    /// environment variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´GCE_METADATA_HOST´': An optional `host:port` of the metadata
+    ///   server. The default is `metadata.google.internal`.
+    pub fn from_env() -> InitializationResult<Self> {
+        match env::var("GCE_METADATA_HOST") {
+            Ok(host) => Ok(GceMetadataAccessTokenProvider::new(format!(
+                "http://{}",
+                host
+            ))),
+            Err(VarError::NotPresent) => Ok(GceMetadataAccessTokenProvider::default()),
+            Err(err) => Err(InitializationError::env_var("GCE_METADATA_HOST", err)),
+        }
+    }
+
+    /// Requests tokens for the given service account email instead of the
+    /// default service account.
+    pub fn with_service_account<T: Into<String>>(mut self, service_account: T) -> Self {
+        self.service_account = service_account.into();
+        self
+    }
+
+    /// Sends the requested `Scope`s as the `scopes` parameter if enabled.
+    /// The default is `false`.
+    pub fn with_scopes_forwarded(mut self, enabled: bool) -> Self {
+        self.forward_scopes = enabled;
+        self
+    }
+
+    fn token_url(&self, scopes: &[Scope]) -> String {
+        let mut url = format!(
+            "{}/computeMetadata/v1/instance/service-accounts/{}/token",
+            self.metadata_url, self.service_account
+        );
+        if self.forward_scopes && !scopes.is_empty() {
+            let scopes: Vec<&str> = scopes.iter().map(|s| s.as_str()).collect();
+            url.push('?');
+            url.push_str(
+                &form_urlencoded::Serializer::new(String::new())
+                    .append_pair("scopes", &scopes.join(","))
+                    .finish(),
+            );
+        }
+        url
+    }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// metadata server. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+}
+
+impl Default for GceMetadataAccessTokenProvider {
+    fn default() -> Self {
+        GceMetadataAccessTokenProvider::new(DEFAULT_METADATA_URL)
+    }
+}
+
+impl AccessTokenProvider for GceMetadataAccessTokenProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(METADATA_FLAVOR_HEADER),
+            HeaderValue::from_static(METADATA_FLAVOR),
+        );
+
+        let rsp = send_request(
+            &self.client,
+            &self.request_decorators,
+            Method::GET,
+            &self.token_url(scopes),
+            headers,
+            None,
+        )?;
+
+        // Anything not answering with the flavor header is not a metadata
+        // server even if it happens to be reachable under its name.
+        if rsp
+            .headers
+            .get(METADATA_FLAVOR_HEADER)
+            .map(|v| v.as_bytes())
+            != Some(METADATA_FLAVOR.as_bytes())
+        {
+            return Err(AccessTokenProviderError::Connection(
+                "The response did not come from a metadata server".to_string(),
+            ));
+        }
+
+        evaluate_response(rsp.status, &rsp.headers, &rsp.body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_url_contains_service_account_and_scopes() {
+        let provider = GceMetadataAccessTokenProvider::default();
+        assert_eq!(
+            provider.token_url(&[Scope::new("a")]),
+            "http://metadata.google.internal/computeMetadata/v1/instance/\
+             service-accounts/default/token"
+        );
+
+        let provider = GceMetadataAccessTokenProvider::new("http://localhost:8080/")
+            .with_service_account("robot@project.iam.gserviceaccount.com")
+            .with_scopes_forwarded(true);
+        assert_eq!(
+            provider.token_url(&[Scope::new("https://a/x"), Scope::new("b")]),
+            "http://localhost:8080/computeMetadata/v1/instance/service-accounts/\
+             robot@project.iam.gserviceaccount.com/token?scopes=https%3A%2F%2Fa%2Fx%2Cb"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn requests_token_from_metadata_server() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let socket_path =
+            std::env::temp_dir().join(format!("tokkit-gce-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                request.push(line.trim().to_lowercase());
+            }
+            let body = r#"{"access_token":"ya29.token","expires_in":3599,"token_type":"Bearer"}"#;
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nMetadata-Flavor: Google\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request
+        });
+
+        let provider =
+            GceMetadataAccessTokenProvider::new(format!("unix://{}:", socket_path.display()));
+        let rsp = provider.request_access_token(&[]).unwrap();
+        let _ = std::fs::remove_file(&socket_path);
+
+        assert_eq!(rsp.access_token.reveal(), "ya29.token");
+        assert_eq!(rsp.expires_in.as_secs(), 3599);
+        let request = server.join().unwrap();
+        assert!(request.contains(&"metadata-flavor: google".to_string()));
+    }
+}
@@ -0,0 +1,286 @@
+//! Access tokens for Google service accounts
+//!
+//! A JWT assertion signed with the service account's private key is
+//! exchanged for an access token at Google's token endpoint.
+//!
+//! See [Using OAuth 2.0 for Server to Server Applications](https://developers.google.com/identity/protocols/oauth2/service-account)
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::result::Result as StdResult;
+use std::str;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::{self, JsonValue};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::Method;
+use url::form_urlencoded;
+
+use super::{
+    evaluate_response, send_request, AccessTokenProvider, AccessTokenProviderError,
+    AccessTokenProviderResult,
+};
+use crate::request_decorator::RequestDecorators;
+use crate::{InitializationError, InitializationResult, RequestDecorator, Scope, ScopeList};
+
+/// The token endpoint used if the key does not contain a `token_uri`.
+pub const DEFAULT_GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Google does not accept assertions valid for more than an hour.
+const ASSERTION_LIFETIME_SECS: u64 = 3600;
+
+/// Provides tokens for a Google service account via the JWT Bearer Grant.
+///
+/// See [RFC7523](https://tools.ietf.org/html/rfc7523)
+pub struct GoogleServiceAccountProvider {
+    client_email: String,
+    private_key_id: Option<String>,
+    private_key: PKey<Private>,
+    token_uri: String,
+    subject: Option<String>,
+    client: Client,
+    request_decorators: RequestDecorators,
+    extra_params: Vec<(String, String)>,
+}
+
+impl GoogleServiceAccountProvider {
+    /// Creates a new instance from the contents of a service account
+    /// JSON key as downloaded from the Google Cloud console.
+    pub fn from_key_json(key: &[u8]) -> InitializationResult<Self> {
+        let key =
+            str::from_utf8(key).map_err(|err| InitializationError::Credentials(err.to_string()))?;
+        let key = json::parse(key).map_err(|err| {
+            InitializationError::Credentials(format!("Invalid service account key: {}", err))
+        })?;
+
+        if let Some(key_type) = key["type"].as_str() {
+            if key_type != "service_account" {
+                return Err(InitializationError::Credentials(format!(
+                    "Expected a key of type 'service_account' but found '{}'",
+                    key_type
+                )));
+            }
+        }
+
+        let client_email = key["client_email"]
+            .as_str()
+            .ok_or_else(|| {
+                InitializationError::Credentials("The key has no 'client_email'.".to_string())
+            })?
+            .to_string();
+
+        let private_key = key["private_key"].as_str().ok_or_else(|| {
+            InitializationError::Credentials("The key has no 'private_key'.".to_string())
+        })?;
+        let private_key = PKey::private_key_from_pem(private_key.as_bytes()).map_err(|err| {
+            InitializationError::Credentials(format!("Invalid private key: {}", err))
+        })?;
+
+        Ok(GoogleServiceAccountProvider {
+            client_email,
+            private_key_id: key["private_key_id"].as_str().map(ToOwned::to_owned),
+            private_key,
+            token_uri: key["token_uri"]
+                .as_str()
+                .unwrap_or(DEFAULT_GOOGLE_TOKEN_URI)
+                .to_string(),
+            subject: None,
+            client: Client::new(),
+            request_decorators: Vec::new(),
+            extra_params: Vec::new(),
+        })
+    }
+
+    /// Creates a new instance from a service account JSON key file.
+    pub fn from_key_file<P: AsRef<Path>>(path: P) -> InitializationResult<Self> {
+        let path = path.as_ref();
+        let mut contents = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut contents))
+            .map_err(|err| InitializationError::Io {
+                message: format!("Could not read '{}'", path.display()),
+                source: err,
+            })?;
+        GoogleServiceAccountProvider::from_key_json(&contents)
+    }
+
+    /// Creates a new instance from the key file referenced by environment
+    /// variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´GOOGLE_APPLICATION_CREDENTIALS´': Path to the service account JSON
+    ///   key file
+    pub fn from_env() -> InitializationResult<Self> {
+        match env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            Ok(path) => GoogleServiceAccountProvider::from_key_file(path),
+            Err(err) => Err(InitializationError::env_var(
+                "GOOGLE_APPLICATION_CREDENTIALS",
+                err,
+            )),
+        }
+    }
+
+    /// Requests tokens on behalf of the given user.
+    ///
+    /// This requires domain-wide delegation for the service account.
+    pub fn with_subject<T: Into<String>>(mut self, subject: T) -> Self {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Overrides the token endpoint taken from the key.
+    pub fn with_token_uri<T: Into<String>>(mut self, token_uri: T) -> Self {
+        self.token_uri = token_uri.into();
+        self
+    }
+
+    fn assertion(&self, scopes: &[Scope]) -> StdResult<String, AccessTokenProviderError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| AccessTokenProviderError::Other(err.to_string()))?
+            .as_secs();
+
+        let mut header = json::object! { "alg" => "RS256", "typ" => "JWT" };
+        if let Some(ref private_key_id) = self.private_key_id {
+            header["kid"] = private_key_id.as_str().into();
+        }
+
+        let mut claims = json::object! {
+            "iss" => self.client_email.as_str(),
+            "scope" => ScopeList::from(scopes).to_string(),
+            "aud" => self.token_uri.as_str(),
+            "iat" => now,
+            "exp" => now + ASSERTION_LIFETIME_SECS
+        };
+        if let Some(ref subject) = self.subject {
+            claims["sub"] = subject.as_str().into();
+        }
+
+        let signing_input = format!("{}.{}", encode_segment(&header), encode_segment(&claims));
+
+        let signature = Signer::new(MessageDigest::sha256(), &self.private_key)
+            .and_then(|mut signer| {
+                signer.update(signing_input.as_bytes())?;
+                signer.sign_to_vec()
+            })
+            .map_err(|err| {
+                AccessTokenProviderError::Other(format!("Could not sign the assertion: {}", err))
+            })?;
+
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(&signature, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// token endpoint. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+
+    /// Adds a parameter sent in the form body of every token request.
+    pub fn with_extra_param<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_params.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl AccessTokenProvider for GoogleServiceAccountProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        let assertion = self.assertion(scopes)?;
+
+        let form_encoded = form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", JWT_BEARER_GRANT_TYPE)
+            .append_pair("assertion", &assertion)
+            .extend_pairs(&self.extra_params)
+            .finish();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+
+        let rsp = send_request(
+            &self.client,
+            &self.request_decorators,
+            Method::POST,
+            &self.token_uri,
+            headers,
+            Some(form_encoded.into_bytes()),
+        )?;
+        evaluate_response(rsp.status, &rsp.headers, &rsp.body)
+    }
+}
+
+fn encode_segment(value: &JsonValue) -> String {
+    base64::encode_config(value.dump(), base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod test {
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    use super::*;
+
+    fn key_json(private_key: &PKey<Private>) -> String {
+        let pem = private_key.private_key_to_pem_pkcs8().unwrap();
+        json::object! {
+            "type" => "service_account",
+            "private_key_id" => "key-1",
+            "private_key" => String::from_utf8(pem).unwrap(),
+            "client_email" => "robot@project.iam.gserviceaccount.com",
+            "token_uri" => "https://oauth2.example.com/token"
+        }
+        .dump()
+    }
+
+    #[test]
+    fn assertion_is_signed_and_contains_the_claims() {
+        let private_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let provider =
+            GoogleServiceAccountProvider::from_key_json(key_json(&private_key).as_bytes())
+                .unwrap()
+                .with_subject("user@example.com");
+
+        let assertion = provider
+            .assertion(&[Scope::new("a"), Scope::new("b")])
+            .unwrap();
+
+        let segments: Vec<&str> = assertion.split('.').collect();
+        let claims = base64::decode_config(segments[1], base64::URL_SAFE_NO_PAD).unwrap();
+        let claims = json::parse(str::from_utf8(&claims).unwrap()).unwrap();
+        assert_eq!(claims["iss"], "robot@project.iam.gserviceaccount.com");
+        assert_eq!(claims["aud"], "https://oauth2.example.com/token");
+        assert_eq!(claims["scope"], "a b");
+        assert_eq!(claims["sub"], "user@example.com");
+
+        let signature = base64::decode_config(segments[2], base64::URL_SAFE_NO_PAD).unwrap();
+        let signing_input = format!("{}.{}", segments[0], segments[1]);
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &private_key).unwrap();
+        verifier.update(signing_input.as_bytes()).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+    }
+
+    #[test]
+    fn rejects_other_key_types() {
+        let key = br#"{"type":"authorized_user","client_email":"a","private_key":"b"}"#;
+        assert!(GoogleServiceAccountProvider::from_key_json(key).is_err());
+    }
+}
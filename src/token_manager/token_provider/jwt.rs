@@ -0,0 +1,78 @@
+//! Inspection of JSON Web Tokens issued to this application.
+//!
+//! Signatures are not verified since the tokens are only used
+//! to determine their lifetime.
+use std::result::Result as StdResult;
+use std::time::Duration;
+
+use json::JsonValue;
+
+use super::AccessTokenProviderError;
+
+/// Returns the decoded claims of a compact serialized JWT.
+pub(crate) fn claims(token: &str) -> StdResult<JsonValue, AccessTokenProviderError> {
+    let mut parts = token.split('.');
+    let payload = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(payload), Some(_), None) => payload,
+        _ => {
+            return Err(AccessTokenProviderError::Parse(
+                "A JWT must consist of 3 parts".to_string(),
+            ))
+        }
+    };
+
+    let decoded = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| AccessTokenProviderError::Parse(format!("Invalid JWT payload: {}", err)))?;
+    crate::claims::object(&decoded)
+        .map_err(|err| AccessTokenProviderError::Parse(format!("Invalid JWT claims: {}", err)))
+}
+
+/// Returns the time left until the JWT expires as given by its `exp` claim.
+pub(crate) fn expires_in(token: &str) -> StdResult<Duration, AccessTokenProviderError> {
+    let claims = claims(token)?;
+    let exp = claims["exp"].as_u64().ok_or_else(|| {
+        AccessTokenProviderError::Parse("The JWT has no valid 'exp' claim".to_string())
+    })?;
+
+    let now = crate::claims::unix_time().map_err(AccessTokenProviderError::Other)?;
+
+    if exp > now {
+        Ok(Duration::from_secs(exp - now))
+    } else {
+        Err(AccessTokenProviderError::Other(format!(
+            "The JWT expired at {} which is not after now({})",
+            exp, now
+        )))
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn encode_unsigned(claims: &JsonValue) -> String {
+    format!(
+        "{}.{}.c2ln",
+        base64::encode_config(r#"{"alg":"none"}"#, base64::URL_SAFE_NO_PAD),
+        base64::encode_config(claims.dump(), base64::URL_SAFE_NO_PAD)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expires_in_is_taken_from_exp_claim() {
+        let now = crate::claims::unix_time().unwrap();
+        let token = encode_unsigned(&json::object! { "sub" => "me", "exp" => now + 600 });
+
+        let expires_in = expires_in(&token).unwrap();
+        assert!(expires_in <= Duration::from_secs(600));
+        assert!(expires_in > Duration::from_secs(590));
+    }
+
+    #[test]
+    fn rejects_malformed_and_expired_tokens() {
+        assert!(expires_in("abc").is_err());
+        assert!(expires_in(&encode_unsigned(&json::object! { "sub" => "me" })).is_err());
+        assert!(expires_in(&encode_unsigned(&json::object! { "exp" => 1 })).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Access tokens from Kubernetes service account token volumes
+//!
+//! The kubelet rotates projected service account tokens before they expire.
+//! Since the lifetime of a token is taken from its `exp` claim the
+//! `AccessTokenManager` re-reads the file according to its refresh
+//! thresholds and always hands out the current token.
+//!
+//! See [Service Account Token Volume Projection](https://kubernetes.io/docs/tasks/configure-pod-container/configure-service-account/#service-account-token-volume-projection)
+use std::env::{self, VarError};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::AuthorizationServerResponse;
+use super::{jwt, AccessTokenProvider, AccessTokenProviderError, AccessTokenProviderResult};
+use crate::{AccessToken, InitializationError, InitializationResult, Scope};
+
+/// The path of the token mounted by default into every pod.
+pub const DEFAULT_SERVICE_ACCOUNT_TOKEN_PATH: &str =
+    "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// Provides the bound service account token of a pod read from a
+/// (projected) token volume.
+///
+/// The requested `Scope`s are ignored since the audience of the token is
+/// part of the volume projection.
+pub struct KubernetesServiceAccountTokenProvider {
+    token_path: PathBuf,
+    default_expires_in: Duration,
+}
+
+impl KubernetesServiceAccountTokenProvider {
+    /// Creates a new instance reading the token from `token_path`.
+    pub fn new<P: Into<PathBuf>>(token_path: P) -> Self {
+        KubernetesServiceAccountTokenProvider {
+            token_path: token_path.into(),
+            default_expires_in: Duration::from_secs(300),
+        }
+    }
+
+    /// Creates a new instance and gets the path of the token from
+    /// environment variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´TOKKIT_K8S_TOKEN_PATH´': An optional path of the token file. The
+    ///   default is `DEFAULT_SERVICE_ACCOUNT_TOKEN_PATH`.
+    pub fn from_env() -> InitializationResult<Self> {
+        match env::var("TOKKIT_K8S_TOKEN_PATH") {
+            Ok(path) => Ok(KubernetesServiceAccountTokenProvider::new(path)),
+            Err(VarError::NotPresent) => Ok(KubernetesServiceAccountTokenProvider::default()),
+            Err(err) => Err(InitializationError::env_var("TOKKIT_K8S_TOKEN_PATH", err)),
+        }
+    }
+
+    /// Sets the lifetime assumed for tokens without an `exp` claim like
+    /// legacy secret based tokens. The file is re-read accordingly.
+    /// The default is 5 minutes.
+    pub fn with_default_expires_in(mut self, default_expires_in: Duration) -> Self {
+        self.default_expires_in = default_expires_in;
+        self
+    }
+}
+
+impl Default for KubernetesServiceAccountTokenProvider {
+    fn default() -> Self {
+        KubernetesServiceAccountTokenProvider::new(DEFAULT_SERVICE_ACCOUNT_TOKEN_PATH)
+    }
+}
+
+impl AccessTokenProvider for KubernetesServiceAccountTokenProvider {
+    fn request_access_token(&self, _scopes: &[Scope]) -> AccessTokenProviderResult {
+        let token = fs::read_to_string(&self.token_path).map_err(|err| {
+            AccessTokenProviderError::Other(format!(
+                "Could not read the token from '{}': {}",
+                self.token_path.display(),
+                err
+            ))
+        })?;
+        let token = token.trim();
+
+        let expires_in = if jwt::claims(token)?["exp"].is_null() {
+            self.default_expires_in
+        } else {
+            jwt::expires_in(token)?
+        };
+
+        Ok(AuthorizationServerResponse {
+            access_token: AccessToken::new(token),
+            expires_in,
+            refresh_token: None,
+            scopes: None,
+            token_type: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn reads_token_and_expiry_from_file() {
+        let path = env::temp_dir().join(format!("tokkit-k8s-{}.token", std::process::id()));
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+
+        let token = jwt::encode_unsigned(
+            &json::object! { "sub" => "system:serviceaccount:a:b", "exp" => exp },
+        );
+        fs::write(&path, format!("{}\n", token)).unwrap();
+        let rsp = KubernetesServiceAccountTokenProvider::new(&path)
+            .request_access_token(&[])
+            .unwrap();
+        assert_eq!(rsp.access_token.reveal(), token);
+        assert!(rsp.expires_in > Duration::from_secs(3590));
+
+        let legacy = jwt::encode_unsigned(&json::object! { "sub" => "system:serviceaccount:a:b" });
+        fs::write(&path, &legacy).unwrap();
+        let rsp = KubernetesServiceAccountTokenProvider::new(&path)
+            .with_default_expires_in(Duration::from_secs(60))
+            .request_access_token(&[])
+            .unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(rsp.expires_in, Duration::from_secs(60));
+    }
+}
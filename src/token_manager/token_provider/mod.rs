@@ -1,23 +1,56 @@
 //! Interaction with the authorization server
 use std::env::{self, VarError};
-use std::io::Read;
 use std::result::Result as StdResult;
 use std::str;
+use std::sync::Arc;
 use std::time::Duration;
 
-use json;
-use json::*;
 use reqwest::header::*;
-use reqwest::{Error as RError, StatusCode};
-use reqwest::blocking::{Client, Response};
+use reqwest::{Method, StatusCode, Url};
+use reqwest::blocking::Client;
 use url::form_urlencoded;
 
-use self::credentials::{CredentialsProvider, RequestTokenCredentials};
+use crate::claims;
+use crate::client::{read_body_limited, DEFAULT_MAX_RESPONSE_SIZE};
+use crate::request_decorator::{decorate, RequestDecorators};
+use crate::core::{is_rate_limited, parse_retry_after};
+use crate::unix_socket;
+use crate::validation::{check_endpoint, ValidationReport};
+use crate::tls;
+use crate::{RedirectPolicy, RequestDecorator, TlsPolicy};
+
+use self::credentials::{CredentialsError, CredentialsProvider, RequestTokenCredentials};
 pub use self::errors::*;
 use super::*;
 
+#[cfg(feature = "async")]
+mod async_provider;
+pub mod azure;
+pub mod cognito;
 pub mod credentials;
+pub mod database;
 mod errors;
+pub mod gce_metadata;
+#[cfg(feature = "gcp")]
+pub mod google;
+mod jwt;
+pub mod kubernetes;
+mod pool;
+#[cfg(feature = "spiffe")]
+pub mod spiffe;
+
+#[cfg(feature = "async")]
+pub use self::async_provider::*;
+pub use self::azure::AzureManagedIdentityProvider;
+pub use self::cognito::CognitoAccessTokenProvider;
+pub use self::database::RdsIamAuthTokenProvider;
+pub use self::gce_metadata::GceMetadataAccessTokenProvider;
+#[cfg(feature = "gcp")]
+pub use self::google::GoogleServiceAccountProvider;
+pub use self::kubernetes::KubernetesServiceAccountTokenProvider;
+pub use self::pool::ProviderPool;
+#[cfg(feature = "spiffe")]
+pub use self::spiffe::SpiffeAccessTokenProvider;
 
 pub type AccessTokenProviderResult =
     StdResult<AuthorizationServerResponse, AccessTokenProviderError>;
@@ -25,8 +58,79 @@ pub type AccessTokenProviderResult =
 /// The response an `AccessTokenProvider` received from an authorization server.
 pub struct AuthorizationServerResponse {
     pub access_token: AccessToken,
+    /// Zero if the authorization server sent no `expires_in` and there was
+    /// no default. The `ManagedTokenGroup` then applies its own default.
     pub expires_in: Duration,
     pub refresh_token: Option<String>,
+    /// The `Scope`s granted as echoed in the `scope` field of the response.
+    ///
+    /// `None` if the authorization server did not echo them.
+    pub scopes: Option<Vec<Scope>>,
+    /// The `token_type` of the response, e.g. `Bearer`.
+    ///
+    /// `None` if the authorization server did not send it.
+    pub token_type: Option<String>,
+}
+
+impl AuthorizationServerResponse {
+    /// Creates a response without a refresh token, `Scope`s or token type.
+    pub fn new(access_token: AccessToken, expires_in: Duration) -> AuthorizationServerResponse {
+        AuthorizationServerResponse {
+            access_token,
+            expires_in,
+            refresh_token: None,
+            scopes: None,
+            token_type: None,
+        }
+    }
+
+    pub fn with_refresh_token<T: Into<String>>(mut self, refresh_token: T) -> Self {
+        self.refresh_token = Some(refresh_token.into());
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<Scope>) -> Self {
+        self.scopes = Some(scopes);
+        self
+    }
+
+    pub fn with_token_type<T: Into<String>>(mut self, token_type: T) -> Self {
+        self.token_type = Some(token_type.into());
+        self
+    }
+}
+
+/// Parameters of a token request besides the `Scope`s declared per
+/// `ManagedToken`.
+///
+/// Providers sending a form to the authorization server append them to the
+/// form body. A resource is a resource indicator as defined in
+/// [RFC 8707](https://tools.ietf.org/html/rfc8707).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenRequestParams {
+    pub realm: Option<String>,
+    pub audiences: Vec<String>,
+    pub resources: Vec<String>,
+}
+
+impl TokenRequestParams {
+    pub fn is_empty(&self) -> bool {
+        self.realm.is_none() && self.audiences.is_empty() && self.resources.is_empty()
+    }
+
+    /// Appends the parameters to a form body. Each audience and resource
+    /// is sent as a separate parameter.
+    pub fn append_to(&self, form: &mut form_urlencoded::Serializer<String>) {
+        if let Some(ref realm) = self.realm {
+            form.append_pair("realm", realm);
+        }
+        for audience in &self.audiences {
+            form.append_pair("audience", audience);
+        }
+        for resource in &self.resources {
+            form.append_pair("resource", resource);
+        }
+    }
 }
 
 /// Calls an authorization server for an `AccessToken` and the
@@ -38,6 +142,45 @@ pub trait AccessTokenProvider {
     /// Issue a request to the authorization server for an `AccessToken`
     /// with the given `Scope`s.
     fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult;
+
+    /// Issue a request to the authorization server for an `AccessToken`
+    /// with the given `Scope`s and `TokenRequestParams`.
+    ///
+    /// The default implementation ignores the `params`.
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        _params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        self.request_access_token(scopes)
+    }
+
+    /// Checks the configuration of the provider without requesting an
+    /// `AccessToken`. Endpoints are only connected to if `live_probe`
+    /// is set.
+    ///
+    /// The default implementation does no checks.
+    fn validate(&self, _live_probe: bool) -> ValidationReport {
+        ValidationReport::new()
+    }
+}
+
+impl<P: AccessTokenProvider + ?Sized> AccessTokenProvider for Arc<P> {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        (**self).request_access_token(scopes)
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
+        (**self).request_access_token_with_params(scopes, params)
+    }
+
+    fn validate(&self, live_probe: bool) -> ValidationReport {
+        (**self).validate(live_probe)
+    }
 }
 
 /// Provides tokens via Resource Owner Password Credentials Grant
@@ -47,9 +190,16 @@ pub struct ResourceOwnerPasswordCredentialsGrantProvider {
     full_endpoint_url: String,
     client: Client,
     credentials_provider: Box<dyn CredentialsProvider + Send + Sync + 'static>,
+    request_decorators: RequestDecorators,
+    extra_params: Vec<(String, String)>,
+    response_parser: Arc<dyn TokenResponseParser>,
 }
 
 impl ResourceOwnerPasswordCredentialsGrantProvider {
+    /// Creates a new instance requesting tokens from `endpoint_url`.
+    ///
+    /// An endpoint reachable via a Unix domain socket can be given as
+    /// `unix:///var/run/token.sock:/oauth2/token`.
     pub fn new<U, C>(
         endpoint_url: U,
         credentials_provider: C,
@@ -59,16 +209,36 @@ impl ResourceOwnerPasswordCredentialsGrantProvider {
         U: Into<String>,
         C: CredentialsProvider + Send + Sync + 'static,
     {
-        let client = Client::new();
-        let mut full_endpoint_url = endpoint_url.into();
-        if let Some(realm) = realm {
-            full_endpoint_url.push_str("?realm=");
-            full_endpoint_url.push_str(realm);
-        }
+        ResourceOwnerPasswordCredentialsGrantProvider::with_client(
+            endpoint_url,
+            credentials_provider,
+            realm,
+            RedirectPolicy::default().blocking_client()?,
+        )
+    }
+
+    /// Creates a new instance like `new` which sends its requests with
+    /// `client`.
+    ///
+    /// Clones of a `Client` share their connections so that providers
+    /// requesting tokens from the same authorization server can reuse them.
+    pub fn with_client<U, C>(
+        endpoint_url: U,
+        credentials_provider: C,
+        realm: Option<&str>,
+        client: Client,
+    ) -> InitializationResult<Self>
+    where
+        U: Into<String>,
+        C: CredentialsProvider + Send + Sync + 'static,
+    {
         Ok(ResourceOwnerPasswordCredentialsGrantProvider {
-            full_endpoint_url,
+            full_endpoint_url: full_endpoint_url(endpoint_url.into(), realm),
             client,
             credentials_provider: Box::new(credentials_provider),
+            request_decorators: Vec::new(),
+            extra_params: Vec::new(),
+            response_parser: Arc::new(JsonTokenResponseParser),
         })
     }
 
@@ -86,20 +256,18 @@ impl ResourceOwnerPasswordCredentialsGrantProvider {
     where
         C: CredentialsProvider + Send + Sync + 'static,
     {
-        let endpoint_url: String = match env::var("TOKKIT_AUTHORIZATION_SERVER_URL") {
-            Ok(url) => url,
-            Err(VarError::NotPresent) => {
-                return Err(InitializationError(
-                    "'TOKKIT_AUTHORIZATION_SERVER_URL' not found.".to_string(),
-                ))
-            }
-            Err(err) => return Err(InitializationError(err.to_string())),
-        };
+        let endpoint_url: String = env::var("TOKKIT_AUTHORIZATION_SERVER_URL")
+            .map_err(|err| InitializationError::env_var("TOKKIT_AUTHORIZATION_SERVER_URL", err))?;
 
         let realm: Option<String> = match env::var("TOKKIT_AUTHORIZATION_SERVER_REALM") {
             Ok(realm) => Some(realm),
             Err(VarError::NotPresent) => None,
-            Err(err) => return Err(InitializationError(err.to_string())),
+            Err(err) => {
+                return Err(InitializationError::env_var(
+                    "TOKKIT_AUTHORIZATION_SERVER_REALM",
+                    err,
+                ))
+            }
         };
 
         ResourceOwnerPasswordCredentialsGrantProvider::new(
@@ -108,48 +276,149 @@ impl ResourceOwnerPasswordCredentialsGrantProvider {
             realm.as_ref().map(|x| &**x),
         )
     }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// authorization server. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+
+    /// Sends the token requests with a new client connecting according to
+    /// `tls_policy`. The client given to `with_client` is replaced.
+    ///
+    /// Fails if the HTTP client can not be created.
+    pub fn with_tls_policy(mut self, tls_policy: TlsPolicy) -> InitializationResult<Self> {
+        self.client = tls::blocking_client(&RedirectPolicy::default(), &tls_policy)?;
+        Ok(self)
+    }
+
+    /// Adds a parameter sent in the form body of every token request,
+    /// e.g. a provider specific parameter like Keycloak's
+    /// `client_session_state`.
+    pub fn with_extra_param<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.extra_params.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the `TokenResponseParser` for the bodies of successful token
+    /// responses. The default is the `JsonTokenResponseParser`.
+    pub fn with_response_parser<P>(mut self, parser: P) -> Self
+    where
+        P: TokenResponseParser + 'static,
+    {
+        self.response_parser = Arc::new(parser);
+        self
+    }
 }
 
 impl AccessTokenProvider for ResourceOwnerPasswordCredentialsGrantProvider {
     fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        self.request_access_token_with_params(scopes, &TokenRequestParams::default())
+    }
+
+    fn request_access_token_with_params(
+        &self,
+        scopes: &[Scope],
+        params: &TokenRequestParams,
+    ) -> AccessTokenProviderResult {
         let credentials = self.credentials_provider.credentials()?;
-        match execute_access_token_request(
+        let rsp = execute_access_token_request(
             &self.client,
+            &self.request_decorators,
             &self.full_endpoint_url,
             scopes,
+            params,
+            &self.extra_params,
             credentials,
-        ) {
-            Ok(mut rsp) => evaluate_response(&mut rsp),
-            Err(err) => Err(AccessTokenProviderError::Connection(err.to_string())),
-        }
+        )?;
+        evaluate_response_with(rsp.status, &rsp.headers, &rsp.body, &*self.response_parser)
+    }
+
+    /// Checks the endpoint and that the credentials can be read.
+    fn validate(&self, live_probe: bool) -> ValidationReport {
+        let mut report = ValidationReport::new();
+        check_endpoint(
+            &mut report,
+            "authorization server",
+            &self.full_endpoint_url,
+            live_probe,
+        );
+        report.check(
+            "credentials can be read",
+            self.credentials_provider.credentials().map(|_| ()),
+        );
+        report
     }
 }
 
-fn evaluate_response(rsp: &mut Response) -> AccessTokenProviderResult {
-    let status = rsp.status();
-    let mut body = Vec::new();
-    rsp.read_to_end(&mut body)?;
+/// Appends the `realm` to the `endpoint_url` as a query parameter.
+fn full_endpoint_url(mut endpoint_url: String, realm: Option<&str>) -> String {
+    if let Some(realm) = realm {
+        endpoint_url.push_str("?realm=");
+        endpoint_url.push_str(realm);
+    }
+    endpoint_url
+}
+
+fn evaluate_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> AccessTokenProviderResult {
+    evaluate_response_with(status, headers, body, &JsonTokenResponseParser)
+}
+
+/// Evaluates a response like `evaluate_response` but parses the body of a
+/// successful response with `parser`. Error responses are always JSON.
+fn evaluate_response_with(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    parser: &dyn TokenResponseParser,
+) -> AccessTokenProviderResult {
     match status {
-        StatusCode::OK => parse_response(&body, None),
+        StatusCode::OK => parser.parse(body, None),
+        _ if is_rate_limited(status, headers) => Err(AccessTokenProviderError::RateLimited {
+            retry_after: parse_retry_after(headers),
+        }),
         StatusCode::BAD_REQUEST => Err(AccessTokenProviderError::BadAuthorizationRequest(
-            parse_error(&body)?,
+            parse_error(body)?,
         )),
+        StatusCode::UNAUTHORIZED => Err(AccessTokenProviderError::Unauthorized(format!(
+            "The authorization server refused the client credentials({}): {}",
+            status,
+            String::from_utf8_lossy(body)
+        ))),
+        StatusCode::FORBIDDEN => Err(AccessTokenProviderError::Forbidden(format!(
+            "The authorization server refused to issue the token({}): {}",
+            status,
+            String::from_utf8_lossy(body)
+        ))),
+        StatusCode::NOT_FOUND => Err(AccessTokenProviderError::EndpointNotFound(format!(
+            "The authorization server has no such endpoint({}): {}",
+            status,
+            String::from_utf8_lossy(body)
+        ))),
         _ if status.is_client_error() => {
-            let body = str::from_utf8(&body)?;
+            let body = str::from_utf8(body)?;
             Err(AccessTokenProviderError::Server(format!(
                 "The request sent to the authorization server was faulty({}): {}",
                 status, body
             )))
         }
         _ if status.is_server_error() => {
-            let body = str::from_utf8(&body)?;
+            let body = str::from_utf8(body)?;
             Err(AccessTokenProviderError::Server(format!(
                 "The authorization server returned an error({}): {}",
                 status, body
             )))
         }
         _ => {
-            let body = str::from_utf8(&body)?;
+            let body = str::from_utf8(body)?;
             Err(AccessTokenProviderError::Client(format!(
                 "Received unexpected status code({}) from authorization server: {}",
                 status, body
@@ -160,155 +429,237 @@ fn evaluate_response(rsp: &mut Response) -> AccessTokenProviderResult {
 
 fn execute_access_token_request(
     client: &Client,
+    decorators: &[Arc<dyn RequestDecorator + Send + Sync + 'static>],
     full_url: &str,
     scopes: &[Scope],
+    params: &TokenRequestParams,
+    extra_params: &[(String, String)],
     credentials: RequestTokenCredentials,
-) -> StdResult<Response, RError> {
-    let request_builder = client
-        .post(full_url)
-        .header(
-            CONTENT_TYPE,
-            HeaderValue::from_static("application/x-www-form-urlencoded"),
-        ).basic_auth(
-            credentials.client_credentials.client_id,
-            Some(credentials.client_credentials.client_secret),
-        );
+) -> StdResult<RawResponse, AccessTokenProviderError> {
+    let form_encoded = password_grant_form(scopes, params, extra_params, &credentials);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    );
+    headers.insert(
+        AUTHORIZATION,
+        basic_auth_header(
+            &credentials.client_credentials.client_id,
+            &credentials.client_credentials.client_secret,
+        )?,
+    );
+
+    send_request(
+        client,
+        decorators,
+        Method::POST,
+        full_url,
+        headers,
+        Some(form_encoded.into_bytes()),
+    )
+}
+
+fn basic_auth_header(
+    client_id: &str,
+    client_secret: &str,
+) -> StdResult<HeaderValue, AccessTokenProviderError> {
+    let encoded = base64::encode(format!("{}:{}", client_id, client_secret));
+    HeaderValue::from_str(&format!("Basic {}", encoded)).map_err(|err| {
+        AccessTokenProviderError::Credentials(CredentialsError::Other(err.to_string()))
+    })
+}
 
-    let mut scope_vec = Vec::new();
+/// A response received from an authorization server with the body
+/// already read.
+struct RawResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
 
-    for scope in scopes {
-        scope_vec.push(scope.0.clone());
+/// Sends a request to `url` which may also be an endpoint reachable via a
+/// Unix domain socket. The `decorators` are applied right before it is sent.
+///
+/// The response body is limited to `DEFAULT_MAX_RESPONSE_SIZE` bytes.
+fn send_request(
+    client: &Client,
+    decorators: &[Arc<dyn RequestDecorator + Send + Sync + 'static>],
+    method: Method,
+    url: &str,
+    mut headers: HeaderMap,
+    body: Option<Vec<u8>>,
+) -> StdResult<RawResponse, AccessTokenProviderError> {
+    let url: Url = url
+        .parse()
+        .map_err(|err| AccessTokenProviderError::Other(format!("Invalid URL: {}", err)))?;
+    decorate(decorators, &method, &url, &mut headers, body.as_deref());
+
+    if url.scheme() == unix_socket::UNIX_SCHEME {
+        return match unix_socket::send(
+            method,
+            &url,
+            &headers,
+            body.as_ref().map(|b| &b[..]),
+            DEFAULT_MAX_RESPONSE_SIZE,
+            unix_socket::SOCKET_TIMEOUT,
+        ) {
+            Ok(rsp) => Ok(RawResponse {
+                status: rsp.status,
+                headers: rsp.headers,
+                body: rsp.body,
+            }),
+            Err(err) => Err(AccessTokenProviderError::Connection(err.to_string())),
+        };
     }
 
-    let form_encoded = form_urlencoded::Serializer::new(String::new())
-        .append_pair("grant_type", "password")
+    let mut request_builder = client.request(method, url).headers(headers);
+    if let Some(body) = body {
+        request_builder = request_builder.body(body);
+    }
+
+    let rsp = request_builder
+        .send()
+        .map_err(|err| AccessTokenProviderError::Connection(err.to_string()))?;
+    let status = rsp.status();
+    let headers = rsp.headers().clone();
+    let body = read_body_limited(rsp, DEFAULT_MAX_RESPONSE_SIZE)
+        .map_err(|err| AccessTokenProviderError::Connection(err.to_string()))?;
+
+    Ok(RawResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn password_grant_form(
+    scopes: &[Scope],
+    params: &TokenRequestParams,
+    extra_params: &[(String, String)],
+    credentials: &RequestTokenCredentials,
+) -> String {
+    let mut form = form_urlencoded::Serializer::new(String::new());
+    form.append_pair("grant_type", "password")
         .append_pair("username", &credentials.owner_credentials.username)
         .append_pair("password", &credentials.owner_credentials.password)
-        .append_pair("scope", &scope_vec.join(" "))
-        .finish();
-
-    let rsp = request_builder.body(form_encoded).send()?;
+        .append_pair("scope", &ScopeList::from(scopes).to_string());
+    params.append_to(&mut form);
+    form.extend_pairs(extra_params);
+    form.finish()
+}
 
-    Ok(rsp)
+/// Parses the body of a successful response of an authorization server.
+///
+/// `default_expires_in` is used if the body contains no `expires_in`. If
+/// there is no default either the `expires_in` is zero.
+pub trait TokenResponseParser: Send + Sync {
+    fn parse(&self, body: &[u8], default_expires_in: Option<Duration>)
+        -> AccessTokenProviderResult;
 }
 
-fn parse_response(bytes: &[u8], default_expires_in: Option<Duration>) -> AccessTokenProviderResult {
-    let json_utf8 =
-        str::from_utf8(bytes).map_err(|err| AccessTokenProviderError::Parse(err.to_string()))?;
-    let json =
-        json::parse(json_utf8).map_err(|err| AccessTokenProviderError::Parse(err.to_string()))?;
-
-    if let JsonValue::Object(data) = json {
-        let access_token = match data.get("access_token") {
-            Some(&JsonValue::Short(user_id)) => user_id.to_string(),
-            Some(&JsonValue::String(ref user_id)) => user_id.clone(),
-            _ => {
-                return Err(AccessTokenProviderError::Parse(
-                    "Expected a string as the access token but found something else".to_string(),
-                ))
-            }
-        };
+/// Parses a JSON object as defined in
+/// [RFC 6749 Sec. 5.1](https://tools.ietf.org/html/rfc6749#section-5.1).
+///
+/// This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonTokenResponseParser;
+
+impl TokenResponseParser for JsonTokenResponseParser {
+    fn parse(
+        &self,
+        body: &[u8],
+        default_expires_in: Option<Duration>,
+    ) -> AccessTokenProviderResult {
+        parse_response(body, default_expires_in)
+    }
+}
 
-        let expires_in: Duration = match data.get("expires_in") {
-            Some(&JsonValue::Number(expires_in)) => {
-                if let Some(expires_in) = expires_in.as_fixed_point_u64(0) {
-                    Duration::from_secs(expires_in)
-                } else {
-                    return Err(AccessTokenProviderError::Parse(
-                        "'expires in must fit into an u64'".to_string(),
-                    ));
+/// Parses an `application/x-www-form-urlencoded` body as sent by legacy
+/// authorization servers implementing early drafts of OAuth 2.0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormTokenResponseParser;
+
+impl TokenResponseParser for FormTokenResponseParser {
+    fn parse(
+        &self,
+        body: &[u8],
+        default_expires_in: Option<Duration>,
+    ) -> AccessTokenProviderResult {
+        let mut access_token = None;
+        let mut expires_in = None;
+        let mut refresh_token = None;
+        let mut scopes = None;
+        let mut token_type = None;
+        for (key, value) in form_urlencoded::parse(body) {
+            match &*key {
+                "access_token" => access_token = Some(value.into_owned()),
+                "expires_in" => {
+                    let seconds = claims::parse_seconds(&value, "expires_in")
+                        .map_err(AccessTokenProviderError::Parse)?;
+                    expires_in = Some(Duration::from_secs(seconds))
                 }
+                "refresh_token" => refresh_token = Some(value.into_owned()),
+                "scope" => scopes = Some(parse_scopes(&value)),
+                "token_type" => token_type = Some(value.into_owned()),
+                _ => {}
             }
-            None => {
-                if let Some(default_expires_in) = default_expires_in {
-                    default_expires_in
-                } else {
-                    return Err(AccessTokenProviderError::Parse(
-                        "No field 'expires_in' found and no default".to_string(),
-                    ));
-                }
-            }
-            invalid => {
-                return Err(AccessTokenProviderError::Parse(format!(
-                    "Expected a number as 'expires_in' but found a {:?}",
-                    invalid
-                )))
-            }
-        };
+        }
 
-        let refresh_token = match data.get("refresh_token") {
-            Some(&JsonValue::Short(refresh_token)) => Some(refresh_token.to_string()),
-            Some(&JsonValue::String(ref refresh_token)) => Some(refresh_token.clone()),
-            None => None,
-            _ => {
-                return Err(AccessTokenProviderError::Parse(
-                    "Expected a string as the refresh token but found something else".to_string(),
-                ))
-            }
-        };
+        let access_token = access_token.ok_or_else(|| {
+            AccessTokenProviderError::Parse("No field 'access_token' found".to_string())
+        })?;
+        let expires_in = expires_in
+            .or(default_expires_in)
+            .unwrap_or_else(|| Duration::from_secs(0));
 
         Ok(AuthorizationServerResponse {
             access_token: AccessToken::new(access_token),
             expires_in,
             refresh_token,
+            scopes,
+            token_type,
         })
-    } else {
-        Err(AccessTokenProviderError::Parse(
-            "Token service response is not a JSON object".to_string(),
-        ))
     }
 }
 
-fn parse_error(bytes: &[u8]) -> StdResult<AuthorizationRequestError, AccessTokenProviderError> {
-    let json_utf8 =
-        str::from_utf8(bytes).map_err(|err| AccessTokenProviderError::Parse(err.to_string()))?;
-    let json =
-        json::parse(json_utf8).map_err(|err| AccessTokenProviderError::Parse(err.to_string()))?;
-
-    if let JsonValue::Object(data) = json {
-        let error = match data.get("error") {
-            Some(&JsonValue::Short(kind)) => kind.parse()?,
-            Some(&JsonValue::String(ref kind)) => kind.parse()?,
-            _ => {
-                return Err(AccessTokenProviderError::Parse(
-                    "Expected a string as the error but found something else".to_string(),
-                ))
-            }
-        };
-
-        let error_description = match data.get("error_description") {
-            Some(&JsonValue::Short(error_description)) => Some(error_description.to_string()),
-            Some(&JsonValue::String(ref error_description)) => Some(error_description.clone()),
-            None => None,
-            _ => {
-                return Err(AccessTokenProviderError::Parse(
-                    "Expected a string as the error_description but found something else"
-                        .to_string(),
-                ))
-            }
-        };
+fn parse_response(bytes: &[u8], default_expires_in: Option<Duration>) -> AccessTokenProviderResult {
+    let data = claims::object(bytes).map_err(AccessTokenProviderError::Parse)?;
+    let field = |field: &str| claims::string(&data, field).map_err(AccessTokenProviderError::Parse);
+
+    let access_token =
+        claims::required_string(&data, "access_token").map_err(AccessTokenProviderError::Parse)?;
+    let expires_in = claims::seconds(&data, "expires_in")
+        .map_err(AccessTokenProviderError::Parse)?
+        .map(Duration::from_secs)
+        .or(default_expires_in)
+        .unwrap_or_else(|| Duration::from_secs(0));
+
+    Ok(AuthorizationServerResponse {
+        access_token: AccessToken::new(access_token),
+        expires_in,
+        refresh_token: field("refresh_token")?.map(str::to_owned),
+        scopes: field("scope")?.map(parse_scopes),
+        token_type: field("token_type")?.map(str::to_owned),
+    })
+}
 
-        let error_uri = match data.get("error_uri") {
-            Some(&JsonValue::Short(error_uri)) => Some(error_uri.to_string()),
-            Some(&JsonValue::String(ref error_uri)) => Some(error_uri.clone()),
-            None => None,
-            _ => {
-                return Err(AccessTokenProviderError::Parse(
-                    "Expected a string as the error_uri but found something else".to_string(),
-                ))
-            }
-        };
+fn parse_scopes(scope: &str) -> Vec<Scope> {
+    scope.split_whitespace().map(Scope::new).collect()
+}
 
-        Ok(AuthorizationRequestError {
-            error,
-            error_description,
-            error_uri,
-        })
-    } else {
-        Err(AccessTokenProviderError::Parse(
-            "The response is not a JSON object".to_string(),
-        ))
-    }
+fn parse_error(bytes: &[u8]) -> StdResult<AuthorizationRequestError, AccessTokenProviderError> {
+    let data = claims::object(bytes).map_err(AccessTokenProviderError::Parse)?;
+    let field = |field: &str| claims::string(&data, field).map_err(AccessTokenProviderError::Parse);
+
+    Ok(AuthorizationRequestError {
+        error: claims::required_string(&data, "error")
+            .map_err(AccessTokenProviderError::Parse)?
+            .parse()?,
+        error_description: field("error_description")?.map(str::to_owned),
+        error_uri: field("error_uri")?.map(str::to_owned),
+    })
 }
 
 /// Provides access tokens from an environment variable
@@ -333,9 +684,7 @@ impl EnvAccessTokenProvider {
         let env_var_name = env_var_name.into();
 
         if env_var_name.is_empty() {
-            Err(InitializationError(
-                "'env_var_name' may not be empty".into(),
-            ))
+            Err(InitializationError::MissingField("'env_var_name'".into()))
         } else {
             Ok(EnvAccessTokenProvider {
                 env_var_name,
@@ -361,8 +710,123 @@ impl AccessTokenProvider for EnvAccessTokenProvider {
             access_token,
             expires_in: self.expires_in,
             refresh_token: None,
+            scopes: None,
+            token_type: None,
         };
 
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::credentials::{ClientCredentials, ResourceOwnerCredentials};
+    use super::*;
+
+    #[test]
+    fn password_grant_form_contains_params_and_extra_params() {
+        let credentials = RequestTokenCredentials {
+            client_credentials: ClientCredentials {
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+            },
+            owner_credentials: ResourceOwnerCredentials {
+                username: "user".to_string(),
+                password: "pw".to_string(),
+            },
+        };
+        let params = TokenRequestParams {
+            audiences: vec!["api".to_string()],
+            ..Default::default()
+        };
+        let extra_params = vec![("client_session_state".to_string(), "s 1".to_string())];
+
+        assert_eq!(
+            password_grant_form(
+                &[Scope::new("write"), Scope::new("read")],
+                &params,
+                &extra_params,
+                &credentials
+            ),
+            "grant_type=password&username=user&password=pw&scope=read+write\
+             &audience=api&client_session_state=s+1"
+        );
+    }
+
+    #[test]
+    fn rate_limited_responses_carry_the_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+        match evaluate_response(StatusCode::TOO_MANY_REQUESTS, &headers, b"slow down") {
+            Err(AccessTokenProviderError::RateLimited { retry_after }) => {
+                assert_eq!(retry_after, Some(Duration::from_secs(30)))
+            }
+            other => panic!("unexpected result: {:?}", other.map(|rsp| rsp.expires_in)),
+        }
+    }
+
+    #[test]
+    fn refusals_are_typed() {
+        let headers = HeaderMap::new();
+        match evaluate_response(StatusCode::UNAUTHORIZED, &headers, b"bad secret") {
+            Err(AccessTokenProviderError::Unauthorized(msg)) => assert!(msg.contains("bad secret")),
+            other => panic!("unexpected result: {:?}", other.map(|rsp| rsp.expires_in)),
+        }
+        match evaluate_response(StatusCode::FORBIDDEN, &headers, b"") {
+            Err(AccessTokenProviderError::Forbidden(_)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|rsp| rsp.expires_in)),
+        }
+        match evaluate_response(StatusCode::NOT_FOUND, &headers, b"") {
+            Err(AccessTokenProviderError::EndpointNotFound(_)) => {}
+            other => panic!("unexpected result: {:?}", other.map(|rsp| rsp.expires_in)),
+        }
+    }
+
+    #[test]
+    fn token_type_and_scope_are_parsed() {
+        let rsp = parse_response(
+            br#"{"access_token":"a","expires_in":60,"token_type":"Bearer","scope":"read write"}"#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(rsp.token_type.as_deref(), Some("Bearer"));
+        assert_eq!(
+            rsp.scopes,
+            Some(vec![Scope::new("read"), Scope::new("write")])
+        );
+    }
+
+    #[test]
+    fn form_encoded_responses_are_parsed() {
+        let rsp = FormTokenResponseParser
+            .parse(
+                b"access_token=a%2Bb&expires_in=60&token_type=bearer&scope=read+write",
+                None,
+            )
+            .unwrap();
+        assert_eq!(rsp.access_token.0, "a+b");
+        assert_eq!(rsp.expires_in, Duration::from_secs(60));
+        assert_eq!(rsp.token_type.as_deref(), Some("bearer"));
+        assert_eq!(
+            rsp.scopes,
+            Some(vec![Scope::new("read"), Scope::new("write")])
+        );
+        assert!(FormTokenResponseParser
+            .parse(b"expires_in=60", None)
+            .is_err());
+    }
+
+    #[test]
+    fn transient_error_codes_are_parsed() {
+        let err = parse_error(br#"{"error":"temporarily_unavailable"}"#).unwrap();
+        match err.error {
+            AuthorizationServerErrorCode::TemporarilyUnavailable => {}
+            ref other => panic!("unexpected error code: {:?}", other),
+        }
+        assert!(err.error.is_transient());
+        let err = parse_error(br#"{"error":"server_error"}"#).unwrap();
+        assert!(err.error.is_transient());
+        let err = parse_error(br#"{"error":"invalid_scope"}"#).unwrap();
+        assert!(!err.error.is_transient());
+    }
+}
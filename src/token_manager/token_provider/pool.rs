@@ -0,0 +1,148 @@
+//! Sharing providers and their connections between `ManagedTokenGroup`s.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::blocking::Client;
+
+use super::credentials::CredentialsProvider;
+use super::{full_endpoint_url, ResourceOwnerPasswordCredentialsGrantProvider};
+use crate::InitializationResult;
+
+/// Caches `ResourceOwnerPasswordCredentialsGrantProvider`s by their
+/// endpoint so that groups requesting tokens from the same authorization
+/// server share a provider. All providers of a pool share one `Client`
+/// and thereby its connections.
+///
+/// ```rust,no_run
+/// use tokkit::token_manager::token_provider::credentials::SplitFileCredentialsProvider;
+/// use tokkit::token_manager::token_provider::ProviderPool;
+/// use tokkit::token_manager::ManagedTokenGroupBuilder;
+///
+/// let pool = ProviderPool::new();
+/// let provider = pool
+///     .get_or_create("https://auth.example.com/token", None, || {
+///         SplitFileCredentialsProvider::with_default_parsers_from_env()
+///     })
+///     .unwrap();
+///
+/// let group = ManagedTokenGroupBuilder::single_token("orders", vec![], provider.clone())
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ProviderPool {
+    client: Client,
+    providers: Mutex<HashMap<String, Arc<ResourceOwnerPasswordCredentialsGrantProvider>>>,
+}
+
+impl ProviderPool {
+    pub fn new() -> ProviderPool {
+        ProviderPool::with_client(Client::new())
+    }
+
+    /// Creates a pool whose providers send their requests with `client`.
+    pub fn with_client(client: Client) -> ProviderPool {
+        ProviderPool {
+            client,
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The `Client` shared by the providers of this pool.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns the provider for the endpoint and the realm and creates it
+    /// if there is none yet.
+    ///
+    /// `credentials_provider` is only called to create a provider. A cached
+    /// provider keeps the credentials it was created with.
+    pub fn get_or_create<F, C>(
+        &self,
+        endpoint_url: &str,
+        realm: Option<&str>,
+        credentials_provider: F,
+    ) -> InitializationResult<Arc<ResourceOwnerPasswordCredentialsGrantProvider>>
+    where
+        F: FnOnce() -> InitializationResult<C>,
+        C: CredentialsProvider + Send + Sync + 'static,
+    {
+        let key = full_endpoint_url(endpoint_url.to_string(), realm);
+        let mut providers = self.providers.lock().unwrap();
+        if let Some(provider) = providers.get(&key) {
+            return Ok(provider.clone());
+        }
+
+        let provider = Arc::new(ResourceOwnerPasswordCredentialsGrantProvider::with_client(
+            endpoint_url,
+            credentials_provider()?,
+            realm,
+            self.client.clone(),
+        )?);
+        providers.insert(key, provider.clone());
+        Ok(provider)
+    }
+
+    /// The number of cached providers.
+    pub fn len(&self) -> usize {
+        self.providers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ProviderPool {
+    fn default() -> ProviderPool {
+        ProviderPool::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token_manager::token_provider::credentials::*;
+
+    struct StaticCredentials;
+
+    impl CredentialsProvider for StaticCredentials {
+        fn client_credentials(&self) -> CredentialsResult<ClientCredentials> {
+            Ok(ClientCredentials {
+                client_id: "client".to_string(),
+                client_secret: "secret".to_string(),
+            })
+        }
+
+        fn owner_credentials(&self) -> CredentialsResult<ResourceOwnerCredentials> {
+            Ok(ResourceOwnerCredentials {
+                username: "user".to_string(),
+                password: "password".to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn providers_are_cached_by_endpoint_and_realm() {
+        let pool = ProviderPool::new();
+        let endpoint = "http://127.0.0.1:1/token";
+
+        let first = pool
+            .get_or_create(endpoint, None, || Ok(StaticCredentials))
+            .unwrap();
+        let second = pool
+            .get_or_create(
+                endpoint,
+                None,
+                || -> InitializationResult<StaticCredentials> {
+                    panic!("the cached provider must be used")
+                },
+            )
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        pool.get_or_create(endpoint, Some("/services"), || Ok(StaticCredentials))
+            .unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+//! Access tokens from the [SPIFFE](https://spiffe.io) Workload API
+//!
+//! JWT-SVIDs are fetched via an HTTP bridge to the Workload API
+//! (e.g. exposed by the SPIRE agent's sidecar) which may also be reachable
+//! via a Unix domain socket. The gRPC interface of the Workload API
+//! is not supported.
+//!
+//! Since the lifetime of a JWT-SVID is taken from its `exp` claim the
+//! `AccessTokenManager` rotates the SVIDs before they expire.
+use std::env::{self, VarError};
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+use json::{self, JsonValue};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use reqwest::{Method, StatusCode};
+
+use super::{
+    jwt, send_request, AccessTokenProvider, AccessTokenProviderError, AccessTokenProviderResult,
+    AuthorizationServerResponse,
+};
+use crate::request_decorator::RequestDecorators;
+use crate::{AccessToken, InitializationError, InitializationResult, RequestDecorator, Scope};
+
+/// The header the Workload API requires on every request to prevent
+/// SSRF attacks.
+pub const WORKLOAD_API_SECURITY_HEADER: &str = "workload.spiffe.io";
+
+/// Provides JWT-SVIDs as `AccessToken`s from the SPIFFE Workload API.
+///
+/// The audiences requested are the configured audiences or, if none were
+/// configured, the `Scope`s requested from the provider.
+pub struct SpiffeAccessTokenProvider {
+    endpoint_url: String,
+    audiences: Vec<String>,
+    spiffe_id: Option<String>,
+    client: Client,
+    request_decorators: RequestDecorators,
+}
+
+impl SpiffeAccessTokenProvider {
+    /// Creates a new instance requesting JWT-SVIDs from `endpoint_url`.
+    ///
+    /// An endpoint reachable via a Unix domain socket can be given as
+    /// `unix:///run/spire/bridge.sock:/v1/jwtsvid`.
+    pub fn new<U: Into<String>>(endpoint_url: U) -> Self {
+        SpiffeAccessTokenProvider {
+            endpoint_url: endpoint_url.into(),
+            audiences: Vec::new(),
+            spiffe_id: None,
+            client: Client::new(),
+            request_decorators: Vec::new(),
+        }
+    }
+
+    /// Adds an audience to request the JWT-SVIDs for.
+    pub fn with_audience<T: Into<String>>(mut self, audience: T) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Requests the JWT-SVID for the given SPIFFE ID in case
+    /// the workload has more than one identity.
+    pub fn with_spiffe_id<T: Into<String>>(mut self, spiffe_id: T) -> Self {
+        self.spiffe_id = Some(spiffe_id.into());
+        self
+    }
+
+    /// Creates a new instance from environment variables.
+    ///
+    /// Environment variables:
+    ///
+    /// * '´TOKKIT_SPIFFE_WORKLOAD_API_URL´': URL of the Workload API HTTP bridge
+    /// * '´TOKKIT_SPIFFE_AUDIENCES´': An optional comma separated list of
+    ///   audiences
+    /// * '´TOKKIT_SPIFFE_ID´': An optional SPIFFE ID to request the JWT-SVIDs
+    ///   for
+    pub fn from_env() -> InitializationResult<Self> {
+        let endpoint_url = env::var("TOKKIT_SPIFFE_WORKLOAD_API_URL")
+            .map_err(|err| InitializationError::env_var("TOKKIT_SPIFFE_WORKLOAD_API_URL", err))?;
+
+        let mut provider = SpiffeAccessTokenProvider::new(endpoint_url);
+
+        match env::var("TOKKIT_SPIFFE_AUDIENCES") {
+            Ok(audiences) => {
+                for audience in audiences
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|a| !a.is_empty())
+                {
+                    provider = provider.with_audience(audience);
+                }
+            }
+            Err(VarError::NotPresent) => {}
+            Err(err) => return Err(InitializationError::env_var("TOKKIT_SPIFFE_AUDIENCES", err)),
+        }
+
+        match env::var("TOKKIT_SPIFFE_ID") {
+            Ok(spiffe_id) => provider = provider.with_spiffe_id(spiffe_id),
+            Err(VarError::NotPresent) => {}
+            Err(err) => return Err(InitializationError::env_var("TOKKIT_SPIFFE_ID", err)),
+        }
+
+        Ok(provider)
+    }
+
+    fn audiences_for(&self, scopes: &[Scope]) -> Vec<String> {
+        if self.audiences.is_empty() {
+            scopes.iter().map(|s| s.0.to_string()).collect()
+        } else {
+            self.audiences.clone()
+        }
+    }
+
+    /// Adds a `RequestDecorator` applied to every request to the
+    /// Workload API. Decorators are applied in the order they were added.
+    pub fn with_request_decorator<D>(mut self, decorator: D) -> Self
+    where
+        D: RequestDecorator + Send + Sync + 'static,
+    {
+        self.request_decorators.push(Arc::new(decorator));
+        self
+    }
+}
+
+impl AccessTokenProvider for SpiffeAccessTokenProvider {
+    fn request_access_token(&self, scopes: &[Scope]) -> AccessTokenProviderResult {
+        let audiences = self.audiences_for(scopes);
+        if audiences.is_empty() {
+            return Err(AccessTokenProviderError::Client(
+                "At least one audience is required to fetch a JWT-SVID".to_string(),
+            ));
+        }
+
+        let mut request = json::object! { "audience" => audiences };
+        if let Some(ref spiffe_id) = self.spiffe_id {
+            request["spiffe_id"] = spiffe_id.as_str().into();
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(WORKLOAD_API_SECURITY_HEADER),
+            HeaderValue::from_static("true"),
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        let rsp = send_request(
+            &self.client,
+            &self.request_decorators,
+            Method::POST,
+            &self.endpoint_url,
+            headers,
+            Some(request.dump().into_bytes()),
+        )?;
+
+        match rsp.status {
+            StatusCode::OK => parse_svid_response(&rsp.body, self.spiffe_id.as_deref()),
+            status if status.is_server_error() => Err(AccessTokenProviderError::Server(format!(
+                "The Workload API returned an error({}): {}",
+                status,
+                String::from_utf8_lossy(&rsp.body)
+            ))),
+            status => Err(AccessTokenProviderError::Client(format!(
+                "The Workload API rejected the request({}): {}",
+                status,
+                String::from_utf8_lossy(&rsp.body)
+            ))),
+        }
+    }
+}
+
+fn parse_svid_response(bytes: &[u8], spiffe_id: Option<&str>) -> AccessTokenProviderResult {
+    let json_utf8 = str::from_utf8(bytes)?;
+    let json =
+        json::parse(json_utf8).map_err(|err| AccessTokenProviderError::Parse(err.to_string()))?;
+
+    let svid = json["svids"]
+        .members()
+        .find(|svid| spiffe_id.is_none() || svid["spiffe_id"].as_str() == spiffe_id)
+        .ok_or_else(|| {
+            AccessTokenProviderError::Parse(
+                "The Workload API returned no matching JWT-SVID".to_string(),
+            )
+        })?;
+
+    let token = match svid["svid"] {
+        JsonValue::Short(ref token) => token.to_string(),
+        JsonValue::String(ref token) => token.clone(),
+        _ => {
+            return Err(AccessTokenProviderError::Parse(
+                "Expected a string as the JWT-SVID but found something else".to_string(),
+            ))
+        }
+    };
+
+    let expires_in: Duration = jwt::expires_in(&token)?;
+
+    Ok(AuthorizationServerResponse {
+        access_token: AccessToken::new(token),
+        expires_in,
+        refresh_token: None,
+        scopes: None,
+        token_type: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    fn svid(sub: &str) -> String {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        jwt::encode_unsigned(&json::object! { "sub" => sub, "exp" => exp })
+    }
+
+    #[test]
+    fn parses_the_first_svid() {
+        let token = svid("spiffe://example.org/a");
+        let body = json::object! {
+            "svids" => json::array![
+                json::object! { "spiffe_id" => "spiffe://example.org/a", "svid" => token.as_str() }
+            ]
+        }
+        .dump();
+
+        let rsp = parse_svid_response(body.as_bytes(), None).unwrap();
+        assert_eq!(rsp.access_token.reveal(), token);
+        assert!(rsp.expires_in <= Duration::from_secs(300));
+    }
+
+    #[test]
+    fn selects_the_svid_for_the_spiffe_id() {
+        let token_a = svid("spiffe://example.org/a");
+        let token_b = svid("spiffe://example.org/b");
+        let body = json::object! {
+            "svids" => json::array![
+                json::object! { "spiffe_id" => "spiffe://example.org/a", "svid" => token_a.as_str() },
+                json::object! { "spiffe_id" => "spiffe://example.org/b", "svid" => token_b.as_str() }
+            ]
+        }
+        .dump();
+
+        let rsp = parse_svid_response(body.as_bytes(), Some("spiffe://example.org/b")).unwrap();
+        assert_eq!(rsp.access_token.reveal(), token_b);
+        assert!(parse_svid_response(body.as_bytes(), Some("spiffe://example.org/c")).is_err());
+    }
+}
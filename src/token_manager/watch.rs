@@ -0,0 +1,126 @@
+//! Watching managed `AccessToken`s for rotations.
+//!
+//! Instead of polling `get_access_token` a consumer can get an
+//! `AccessTokenReceiver` for a token id and wait for the next rotation,
+//! e.g. to rebuild a connection that was authenticated with the old
+//! `AccessToken`:
+//!
+//! ```rust,no_run
+//! # async fn rebuild(source: tokkit::token_manager::AccessTokenSource<&'static str>) {
+//! let mut receiver = source.watch(&"my_token").unwrap();
+//! while let Some(token) = receiver.recv().await {
+//!     match token {
+//!         Ok(token) => { /* reconnect with the new token */ }
+//!         Err(err) => { /* the token could not be refreshed */ }
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! A receiver gets the current state immediately on its first `recv` and
+//! then only when the `AccessToken` or the error changed. `recv` returns
+//! `None` once the `AccessTokenManager` stopped.
+use std::collections::BTreeMap;
+use std::result::Result as StdResult;
+
+use tokio::sync::watch;
+
+use super::TokenErrorKind;
+use crate::AccessToken;
+
+/// Receives the state of a managed `AccessToken` whenever it changes.
+pub type AccessTokenReceiver = watch::Receiver<StdResult<AccessToken, TokenErrorKind>>;
+
+/// A watch channel for each managed token.
+pub(crate) struct TokenWatchers<T> {
+    channels: BTreeMap<
+        T,
+        (
+            watch::Sender<StdResult<AccessToken, TokenErrorKind>>,
+            AccessTokenReceiver,
+        ),
+    >,
+}
+
+impl<T: Ord> TokenWatchers<T> {
+    /// Creates the channels starting with the given states.
+    pub fn new<I>(initial: I) -> TokenWatchers<T>
+    where
+        I: IntoIterator<Item = (T, StdResult<AccessToken, TokenErrorKind>)>,
+    {
+        let channels = initial
+            .into_iter()
+            .map(|(token_id, state)| (token_id, watch::channel(state)))
+            .collect();
+        TokenWatchers { channels }
+    }
+
+    /// Returns a new receiver for the token or `None` if it is not managed.
+    pub fn subscribe(&self, token_id: &T) -> Option<AccessTokenReceiver> {
+        self.channels
+            .get(token_id)
+            .map(|(_, receiver)| receiver.clone())
+    }
+
+    /// Publishes the state of the token unless it did not change.
+    pub fn publish(&self, token_id: &T, state: &StdResult<AccessToken, TokenErrorKind>) {
+        if let Some((sender, receiver)) = self.channels.get(token_id) {
+            if is_same_state(&receiver.borrow(), state) {
+                return;
+            }
+            // Fails only if there are no receivers left which is fine.
+            let _ = sender.broadcast(state.clone());
+        }
+    }
+}
+
+fn is_same_state(
+    a: &StdResult<AccessToken, TokenErrorKind>,
+    b: &StdResult<AccessToken, TokenErrorKind>,
+) -> bool {
+    match (a, b) {
+        (Ok(a), Ok(b)) => a.reveal() == b.reveal(),
+        // The message of a pending token changes with the time waited.
+        (Err(TokenErrorKind::NotInitialized(a)), Err(TokenErrorKind::NotInitialized(b))) => {
+            a.token_id == b.token_id
+        }
+        (Err(a), Err(b)) => a.to_string() == b.to_string(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn only_changes_are_published() {
+        let watchers = TokenWatchers::new(vec![(
+            "token",
+            Err(TokenErrorKind::not_initialized("token", Instant::now())),
+        )]);
+        let mut receiver = watchers.subscribe(&"token").unwrap();
+
+        futures::executor::block_on(async move {
+            assert!(receiver.recv().await.unwrap().is_err());
+
+            watchers.publish(&"token", &Ok(AccessToken::new("first")));
+            watchers.publish(&"token", &Ok(AccessToken::new("first")));
+            watchers.publish(&"token", &Ok(AccessToken::new("second")));
+            let token = receiver.recv().await.unwrap().unwrap();
+            assert_eq!(token.reveal(), "second");
+
+            watchers.publish(&"token", &Ok(AccessToken::new("second")));
+            drop(watchers);
+            assert!(receiver.recv().await.is_none());
+        });
+    }
+
+    #[test]
+    fn unknown_tokens_can_not_be_watched() {
+        let watchers = TokenWatchers::<&str>::new(Vec::new());
+        assert!(watchers.subscribe(&"token").is_none());
+    }
+}
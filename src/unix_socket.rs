@@ -0,0 +1,620 @@
+//! A minimal HTTP/1.1 transport for endpoints reachable over a Unix domain
+//! socket.
+//!
+//! Such endpoints are configured with the `unix` scheme where the path of
+//! the socket and the path of the HTTP request are separated by a colon:
+//!
+//! * `unix:///var/run/introspect.sock` requests `/` on the socket
+//!   `/var/run/introspect.sock`
+//! * `unix:///var/run/introspect.sock:/oauth2/tokeninfo` requests
+//!   `/oauth2/tokeninfo` on the socket `/var/run/introspect.sock`
+//!
+//! This is mostly useful for sidecars like Envoy or SPIRE agents.
+//! Each request uses a new connection which the server is asked to close
+//! after the response. Response bodies are delimited as described in
+//! [RFC 7230, section 3.3.3](https://tools.ietf.org/html/rfc7230#section-3.3.3):
+//! by chunked transfer coding, by `Content-Length` or by the server
+//! closing the connection.
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+use percent_encoding::percent_decode_str;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode, Url};
+
+use crate::{TokenInfoError, TokenInfoErrorKind};
+
+/// The scheme that identifies an endpoint reachable via a Unix domain socket
+pub(crate) const UNIX_SCHEME: &str = "unix";
+
+/// The timeout for reading from and writing to the socket if a request
+/// has no deadline
+pub(crate) const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum number of header lines accepted in a response
+const MAX_HEADER_LINES: usize = 100;
+
+/// Separates the path of the socket from the path of the HTTP request.
+const PATH_SEPARATOR: &str = ":/";
+
+/// Returns true if the endpoint has to be reached via a Unix domain socket.
+pub(crate) fn is_unix_endpoint(endpoint: &str) -> bool {
+    endpoint.starts_with("unix://")
+}
+
+/// Makes sure the socket path is terminated so that anything appended to an
+/// endpoint ends up in the path of the HTTP request.
+pub(crate) fn normalize_endpoint(endpoint: &str) -> String {
+    let mut endpoint = endpoint.to_string();
+    if !endpoint["unix://".len()..].contains(PATH_SEPARATOR) {
+        if endpoint.ends_with('/') {
+            endpoint.pop();
+        }
+        endpoint.push(':');
+    }
+    endpoint
+}
+
+/// A response received via a Unix domain socket
+pub(crate) struct UnixSocketResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) enum UnixSocketError {
+    /// The URL could not be used to address a socket
+    InvalidUrl(String),
+    /// Something went wrong with the connection
+    Io(io::Error),
+    /// The response was not a valid HTTP response
+    InvalidResponse(String),
+    /// The body exceeded the given maximum size
+    ResponseTooLarge(usize),
+}
+
+impl fmt::Display for UnixSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnixSocketError::InvalidUrl(ref msg) => write!(f, "Invalid socket URL: {}", msg),
+            UnixSocketError::Io(ref err) => write!(f, "Socket error: {}", err),
+            UnixSocketError::InvalidResponse(ref msg) => {
+                write!(f, "Invalid HTTP response from socket: {}", msg)
+            }
+            UnixSocketError::ResponseTooLarge(max) => write!(
+                f,
+                "The response exceeded the maximum size of {} bytes",
+                max
+            ),
+        }
+    }
+}
+
+impl From<UnixSocketError> for TokenInfoError {
+    fn from(err: UnixSocketError) -> Self {
+        match err {
+            UnixSocketError::InvalidUrl(msg) => TokenInfoErrorKind::UrlError(msg).into(),
+            UnixSocketError::ResponseTooLarge(max) => {
+                TokenInfoErrorKind::ResponseTooLarge(max).into()
+            }
+            err => TokenInfoErrorKind::Connection(err.to_string()).into(),
+        }
+    }
+}
+
+impl From<io::Error> for UnixSocketError {
+    fn from(err: io::Error) -> Self {
+        UnixSocketError::Io(err)
+    }
+}
+
+/// Splits a `unix` URL into the path of the socket and the request target
+/// (path and query) of the HTTP request.
+pub(crate) fn split_url(url: &Url) -> Result<(String, String), UnixSocketError> {
+    if url.scheme() != UNIX_SCHEME {
+        return Err(UnixSocketError::InvalidUrl(format!(
+            "Expected scheme '{}' but found '{}'",
+            UNIX_SCHEME,
+            url.scheme()
+        )));
+    }
+
+    let path = url.path();
+    let (socket_path, request_path) = match path.find(PATH_SEPARATOR) {
+        Some(idx) => (&path[..idx], &path[idx + 1..]),
+        None => (path.trim_end_matches(':'), "/"),
+    };
+
+    if socket_path.is_empty() {
+        return Err(UnixSocketError::InvalidUrl("No socket path".to_string()));
+    }
+
+    let socket_path = percent_decode_str(socket_path)
+        .decode_utf8()
+        .map_err(|err| UnixSocketError::InvalidUrl(err.to_string()))?
+        .into_owned();
+
+    let mut target = request_path.to_string();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+
+    Ok((socket_path, target))
+}
+
+/// Sends a request to the socket addressed by `url` and reads the complete
+/// response.
+///
+/// Each read from and write to the socket may take up to `timeout`.
+#[cfg(unix)]
+pub(crate) fn send(
+    method: Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Option<&[u8]>,
+    max_response_size: usize,
+    timeout: Duration,
+) -> Result<UnixSocketResponse, UnixSocketError> {
+    use std::os::unix::net::UnixStream;
+
+    let (socket_path, target) = split_url(url)?;
+
+    let mut stream = UnixStream::connect(&socket_path)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    write_request(&mut stream, &method, &target, headers, body)?;
+    read_response(BufReader::new(stream), &method, max_response_size)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn send(
+    _method: Method,
+    _url: &Url,
+    _headers: &HeaderMap,
+    _body: Option<&[u8]>,
+    _max_response_size: usize,
+    _timeout: Duration,
+) -> Result<UnixSocketResponse, UnixSocketError> {
+    Err(UnixSocketError::InvalidUrl(
+        "Unix domain sockets are not supported on this platform".to_string(),
+    ))
+}
+
+fn write_request<W: Write>(
+    writer: &mut W,
+    method: &Method,
+    target: &str,
+    headers: &HeaderMap,
+    body: Option<&[u8]>,
+) -> io::Result<()> {
+    let mut request = Vec::new();
+    write!(request, "{} {} HTTP/1.1\r\n", method, target)?;
+    write!(request, "Host: localhost\r\nConnection: close\r\n")?;
+    for (name, value) in headers {
+        request.extend_from_slice(name.as_str().as_bytes());
+        request.extend_from_slice(b": ");
+        request.extend_from_slice(value.as_bytes());
+        request.extend_from_slice(b"\r\n");
+    }
+    let body = body.unwrap_or(&[]);
+    if !body.is_empty() || *method == Method::POST {
+        write!(request, "Content-Length: {}\r\n", body.len())?;
+    }
+    request.extend_from_slice(b"\r\n");
+    request.extend_from_slice(body);
+    writer.write_all(&request)?;
+    writer.flush()
+}
+
+fn read_response<R: BufRead>(
+    mut reader: R,
+    method: &Method,
+    max_response_size: usize,
+) -> Result<UnixSocketResponse, UnixSocketError> {
+    let (status, headers) = loop {
+        let status = parse_status_line(&read_line(&mut reader)?)?;
+        let headers = read_headers(&mut reader)?;
+        // Interim responses like `100 Continue` precede the final response.
+        if !status.is_informational() {
+            break (status, headers);
+        }
+    };
+
+    let body = if *method == Method::HEAD
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+    {
+        Vec::new()
+    } else {
+        read_body(&mut reader, &headers, max_response_size)?
+    };
+
+    Ok(UnixSocketResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<HeaderMap, UnixSocketError> {
+    let mut headers = HeaderMap::new();
+    for _ in 0..=MAX_HEADER_LINES {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let idx = line
+            .find(':')
+            .ok_or_else(|| UnixSocketError::InvalidResponse(format!("Invalid header: {}", line)))?;
+        let name = HeaderName::from_bytes(line[..idx].trim().as_bytes())
+            .map_err(|err| UnixSocketError::InvalidResponse(err.to_string()))?;
+        let value = HeaderValue::from_str(line[idx + 1..].trim())
+            .map_err(|err| UnixSocketError::InvalidResponse(err.to_string()))?;
+        headers.append(name, value);
+    }
+    Err(UnixSocketError::InvalidResponse(format!(
+        "More than {} header lines",
+        MAX_HEADER_LINES
+    )))
+}
+
+fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &HeaderMap,
+    max_response_size: usize,
+) -> Result<Vec<u8>, UnixSocketError> {
+    let is_chunked = headers
+        .get_all("transfer-encoding")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .last()
+        .map(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+        .unwrap_or(false);
+
+    let content_length = match headers.get("content-length") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .ok_or_else(|| {
+                    UnixSocketError::InvalidResponse("Invalid Content-Length".to_string())
+                })?,
+        ),
+        None => None,
+    };
+
+    if is_chunked {
+        read_chunked_body(reader, max_response_size)
+    } else if let Some(content_length) = content_length {
+        if content_length > max_response_size as u64 {
+            return Err(UnixSocketError::ResponseTooLarge(max_response_size));
+        }
+        read_limited(reader, Some(content_length as usize), max_response_size)
+    } else if is_keep_alive(headers) {
+        // Without a length the body ends when the connection is closed,
+        // which a server keeping the connection alive never does.
+        Err(UnixSocketError::InvalidResponse(
+            "Response without length on a persistent connection".to_string(),
+        ))
+    } else {
+        read_limited(reader, None, max_response_size)
+    }
+}
+
+fn is_keep_alive(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("keep-alive"))
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    max_response_size: usize,
+) -> Result<Vec<u8>, UnixSocketError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let size_str = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| {
+            UnixSocketError::InvalidResponse(format!("Invalid chunk size: {}", size_str))
+        })?;
+        if size == 0 {
+            // Skip trailers
+            while !read_line(reader)?.is_empty() {}
+            return Ok(body);
+        }
+        if size > max_response_size - body.len() {
+            return Err(UnixSocketError::ResponseTooLarge(max_response_size));
+        }
+        body.extend(read_limited(reader, Some(size), max_response_size)?);
+        if !read_line(reader)?.is_empty() {
+            return Err(UnixSocketError::InvalidResponse(
+                "Chunk longer than its size".to_string(),
+            ));
+        }
+    }
+}
+
+fn read_limited<R: Read>(
+    reader: &mut R,
+    exact: Option<usize>,
+    max_response_size: usize,
+) -> Result<Vec<u8>, UnixSocketError> {
+    let mut buf = Vec::new();
+    match exact {
+        Some(n) => {
+            buf.resize(n, 0);
+            reader.read_exact(&mut buf).map_err(|err| {
+                if err.kind() == io::ErrorKind::UnexpectedEof {
+                    UnixSocketError::InvalidResponse("Truncated response body".to_string())
+                } else {
+                    UnixSocketError::Io(err)
+                }
+            })?;
+        }
+        None => {
+            reader
+                .take(max_response_size as u64 + 1)
+                .read_to_end(&mut buf)?;
+            if buf.len() > max_response_size {
+                return Err(UnixSocketError::ResponseTooLarge(max_response_size));
+            }
+        }
+    }
+    Ok(buf)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, UnixSocketError> {
+    let mut line = Vec::new();
+    reader.take(8 * 1024).read_until(b'\n', &mut line)?;
+    if !line.ends_with(b"\n") {
+        return Err(UnixSocketError::InvalidResponse(
+            "Unexpected end of response".to_string(),
+        ));
+    }
+    let line = String::from_utf8(line)
+        .map_err(|err| UnixSocketError::InvalidResponse(err.to_string()))?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn parse_status_line(line: &str) -> Result<StatusCode, UnixSocketError> {
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => {
+            StatusCode::from_bytes(code.as_bytes())
+                .map_err(|err| UnixSocketError::InvalidResponse(err.to_string()))
+        }
+        _ => Err(UnixSocketError::InvalidResponse(format!(
+            "Invalid status line: {}",
+            line
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_socket_path_and_request_path() {
+        let url: Url = "unix:///var/run/introspect.sock:/oauth2/tokeninfo?access_token=abc"
+            .parse()
+            .unwrap();
+        let (socket_path, target) = split_url(&url).unwrap();
+        assert_eq!("/var/run/introspect.sock", socket_path);
+        assert_eq!("/oauth2/tokeninfo?access_token=abc", target);
+    }
+
+    #[test]
+    fn normalized_endpoint_without_request_path_requests_root() {
+        let endpoint = normalize_endpoint("unix:///var/run/introspect.sock");
+        assert_eq!("unix:///var/run/introspect.sock:", endpoint);
+
+        let url: Url = format!("{}/abc", endpoint).parse().unwrap();
+        let (socket_path, target) = split_url(&url).unwrap();
+        assert_eq!("/var/run/introspect.sock", socket_path);
+        assert_eq!("/abc", target);
+    }
+
+    #[test]
+    fn reads_response_with_content_length() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: b\r\n\r\nhello";
+        let rsp = read_response(&raw[..], &Method::GET, 100).unwrap();
+        assert_eq!(StatusCode::OK, rsp.status);
+        assert_eq!("b", rsp.headers.get("x-a").unwrap());
+        assert_eq!(b"hello".to_vec(), rsp.body);
+    }
+
+    #[test]
+    fn reads_chunked_response() {
+        let raw = b"HTTP/1.1 401 Unauthorized\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n";
+        let rsp = read_response(&raw[..], &Method::GET, 100).unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, rsp.status);
+        assert_eq!(b"hello".to_vec(), rsp.body);
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhello\r\n0\r\n\r\n";
+        assert!(read_response(&raw[..], &Method::GET, 100).is_err());
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n";
+        assert!(read_response(&raw[..], &Method::GET, 100).is_err());
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n";
+        match read_response(&raw[..], &Method::GET, 100) {
+            Err(UnixSocketError::ResponseTooLarge(100)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn reads_body_until_the_connection_is_closed() {
+        let raw = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello";
+        let rsp = read_response(&raw[..], &Method::GET, 100).unwrap();
+        assert_eq!(b"hello".to_vec(), rsp.body);
+
+        let raw = b"HTTP/1.1 200 OK\r\nConnection: keep-alive\r\n\r\nhello";
+        assert!(read_response(&raw[..], &Method::GET, 100).is_err());
+    }
+
+    #[test]
+    fn rejects_too_many_header_lines() {
+        let response = |lines: usize| {
+            let mut raw = b"HTTP/1.1 200 OK\r\n".to_vec();
+            for i in 0..lines {
+                raw.extend_from_slice(format!("X-Header-{}: {}\r\n", i, i).as_bytes());
+            }
+            raw.extend_from_slice(b"Content-Length: 0\r\n\r\n");
+            raw
+        };
+        let raw = response(MAX_HEADER_LINES - 1);
+        let rsp = read_response(&raw[..], &Method::GET, 100).unwrap();
+        assert_eq!(MAX_HEADER_LINES, rsp.headers.len());
+
+        let raw = response(MAX_HEADER_LINES);
+        match read_response(&raw[..], &Method::GET, 100) {
+            Err(UnixSocketError::InvalidResponse(_)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello";
+        match read_response(&raw[..], &Method::GET, 100) {
+            Err(UnixSocketError::InvalidResponse(_)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn skips_interim_responses_and_bodies_of_bodiless_responses() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\
+                    Connection: keep-alive\r\n\r\n";
+        let rsp = read_response(&raw[..], &Method::POST, 100).unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, rsp.status);
+        assert!(rsp.body.is_empty());
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+        let rsp = read_response(&raw[..], &Method::HEAD, 100).unwrap();
+        assert!(rsp.body.is_empty());
+    }
+
+    #[test]
+    fn response_size_is_limited() {
+        let raw = b"HTTP/1.1 200 OK\r\n\r\nhello";
+        match read_response(&raw[..], &Method::GET, 4) {
+            Err(UnixSocketError::ResponseTooLarge(4)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn writes_request() {
+        let url: Url = "unix:///tmp/x.sock:/token?realm=a".parse().unwrap();
+        let (_, target) = split_url(&url).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", HeaderValue::from_static("*/*"));
+        let mut out = Vec::new();
+        write_request(&mut out, &Method::POST, &target, &headers, Some(b"a=b")).unwrap();
+        assert_eq!(
+            "POST /token?realm=a HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             accept: */*\r\nContent-Length: 3\r\n\r\na=b",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sends_request_over_socket() {
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let socket_path = std::env::temp_dir().join(format!("tokkit-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let request_line = read_line(&mut reader).unwrap();
+            while !read_line(&mut reader).unwrap().is_empty() {}
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+            request_line
+        });
+
+        let url: Url = format!("unix://{}:/tokeninfo?access_token=abc", socket_path.display())
+            .parse()
+            .unwrap();
+        let rsp = send(Method::GET, &url, &HeaderMap::new(), None, 100, SOCKET_TIMEOUT).unwrap();
+        let _ = std::fs::remove_file(&socket_path);
+
+        assert_eq!(StatusCode::OK, rsp.status);
+        assert_eq!(b"ok".to_vec(), rsp.body);
+        assert_eq!(
+            "GET /tokeninfo?access_token=abc HTTP/1.1",
+            server.join().unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reading_from_a_silent_server_times_out() {
+        use std::os::unix::net::UnixListener;
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Instant;
+
+        let socket_path =
+            std::env::temp_dir().join(format!("tokkit-silent-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            let _ = done_rx.recv();
+        });
+
+        let url: Url = format!("unix://{}:/tokeninfo", socket_path.display())
+            .parse()
+            .unwrap();
+        let start = Instant::now();
+        let result = send(
+            Method::GET,
+            &url,
+            &HeaderMap::new(),
+            None,
+            100,
+            Duration::from_millis(50),
+        );
+        let _ = std::fs::remove_file(&socket_path);
+        drop(done_tx);
+        server.join().unwrap();
+
+        match result {
+            Err(UnixSocketError::Io(_)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+        assert!(start.elapsed() < SOCKET_TIMEOUT);
+    }
+}
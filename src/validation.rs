@@ -0,0 +1,256 @@
+//! Validating configurations before they are used.
+//!
+//! The builders of clients and managed token groups can check their
+//! configuration with `validate` and return a `ValidationReport` instead of
+//! failing at the first request:
+//!
+//! ```rust
+//! use tokkit::client::TokenInfoServiceClientBuilder;
+//!
+//! let builder = TokenInfoServiceClientBuilder::plan_b("http://localhost:9000".to_string());
+//! let report = builder.validate(false);
+//! assert!(report.is_valid(), "{}", report);
+//! ```
+//!
+//! With `live_probe` enabled the endpoints are also connected to.
+use std::fmt;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::unix_socket;
+use crate::Scope;
+
+/// The time a live probe waits for a connection.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    Failed(String),
+    /// The check was not done, e.g. a live probe which was not requested.
+    Skipped(String),
+}
+
+/// A check done while validating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub outcome: CheckOutcome,
+}
+
+/// All checks done while validating a configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub checks: Vec<Check>,
+}
+
+impl ValidationReport {
+    pub fn new() -> ValidationReport {
+        ValidationReport::default()
+    }
+
+    /// Returns `true` if no check failed.
+    pub fn is_valid(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// The checks which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+
+    pub fn passed<N: Into<String>>(&mut self, name: N) {
+        self.add(name, CheckOutcome::Passed)
+    }
+
+    pub fn failed<N: Into<String>, R: Into<String>>(&mut self, name: N, reason: R) {
+        self.add(name, CheckOutcome::Failed(reason.into()))
+    }
+
+    pub fn skipped<N: Into<String>, R: Into<String>>(&mut self, name: N, reason: R) {
+        self.add(name, CheckOutcome::Skipped(reason.into()))
+    }
+
+    /// Adds a check which passed if `result` is `Ok`.
+    pub fn check<N: Into<String>, E: fmt::Display>(&mut self, name: N, result: Result<(), E>) {
+        match result {
+            Ok(()) => self.passed(name),
+            Err(err) => self.failed(name, err.to_string()),
+        }
+    }
+
+    /// Adds all checks of `other` with their names prefixed.
+    pub fn merge(&mut self, prefix: &str, other: ValidationReport) {
+        for check in other.checks {
+            self.add(format!("{}: {}", prefix, check.name), check.outcome);
+        }
+    }
+
+    fn add<N: Into<String>>(&mut self, name: N, outcome: CheckOutcome) {
+        self.checks.push(Check {
+            name: name.into(),
+            outcome,
+        })
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failures = self.failures().count();
+        write!(f, "{} checks, {} failed", self.checks.len(), failures)?;
+        for check in &self.checks {
+            match check.outcome {
+                CheckOutcome::Passed => write!(f, "\n  ok      {}", check.name)?,
+                CheckOutcome::Failed(ref reason) => {
+                    write!(f, "\n  FAILED  {}: {}", check.name, reason)?
+                }
+                CheckOutcome::Skipped(ref reason) => {
+                    write!(f, "\n  skipped {}: {}", check.name, reason)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `endpoint` is a valid URL whose host resolves and connects
+/// to it if `live_probe` is set.
+pub(crate) fn check_endpoint(
+    report: &mut ValidationReport,
+    name: &str,
+    endpoint: &str,
+    live_probe: bool,
+) {
+    let url = if unix_socket::is_unix_endpoint(endpoint) {
+        unix_socket::normalize_endpoint(endpoint).parse::<Url>()
+    } else {
+        endpoint.parse::<Url>()
+    };
+    let url = match url {
+        Ok(url) => {
+            report.passed(format!("{} is a valid URL", name));
+            url
+        }
+        Err(err) => {
+            report.failed(format!("{} is a valid URL", name), err.to_string());
+            return;
+        }
+    };
+
+    if url.scheme() == unix_socket::UNIX_SCHEME {
+        check_unix_socket(report, name, &url, live_probe);
+        return;
+    }
+
+    let addrs = match url.socket_addrs(|| None) {
+        Ok(addrs) if !addrs.is_empty() => {
+            report.passed(format!("{} resolves", name));
+            addrs
+        }
+        Ok(_) => {
+            report.failed(format!("{} resolves", name), "No addresses found");
+            return;
+        }
+        Err(err) => {
+            report.failed(format!("{} resolves", name), err.to_string());
+            return;
+        }
+    };
+
+    let probe = format!("{} accepts connections", name);
+    if live_probe {
+        report.check(probe, connect(&addrs));
+    } else {
+        report.skipped(probe, "No live probe requested");
+    }
+}
+
+fn connect(addrs: &[SocketAddr]) -> Result<(), String> {
+    let mut last_err = String::new();
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(err) => last_err = format!("{}: {}", addr, err),
+        }
+    }
+    Err(last_err)
+}
+
+fn check_unix_socket(report: &mut ValidationReport, name: &str, url: &Url, live_probe: bool) {
+    let socket_path = match unix_socket::split_url(url) {
+        Ok((socket_path, _)) => socket_path,
+        Err(err) => {
+            report.failed(format!("{} addresses a socket", name), err.to_string());
+            return;
+        }
+    };
+    report.check(
+        format!("{} addresses a socket", name),
+        if std::path::Path::new(&socket_path).exists() {
+            Ok(())
+        } else {
+            Err(format!("'{}' does not exist", socket_path))
+        },
+    );
+
+    let probe = format!("{} accepts connections", name);
+    if !live_probe {
+        report.skipped(probe, "No live probe requested");
+        return;
+    }
+    #[cfg(unix)]
+    report.check(
+        probe,
+        std::os::unix::net::UnixStream::connect(&socket_path).map(|_| ()),
+    );
+    #[cfg(not(unix))]
+    report.skipped(probe, "Unix domain sockets are not supported");
+}
+
+/// Checks that the scope is a `scope-token` as defined in
+/// [RFC 6749 Sec. 3.3](https://tools.ietf.org/html/rfc6749#section-3.3).
+pub(crate) fn check_scope(report: &mut ValidationReport, scope: &Scope) {
+    report.check(
+        format!("scope '{}' is valid", scope.as_str().escape_debug()),
+        Scope::validate(scope.as_str()),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalid_urls_fail() {
+        let mut report = ValidationReport::new();
+        check_endpoint(&mut report, "endpoint", "not a url", false);
+        assert!(!report.is_valid());
+        assert_eq!(report.checks.len(), 1);
+    }
+
+    #[test]
+    fn probes_are_skipped_unless_requested() {
+        let mut report = ValidationReport::new();
+        check_endpoint(&mut report, "endpoint", "http://127.0.0.1:1/info", false);
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(
+            report.checks[2].outcome,
+            CheckOutcome::Skipped("No live probe requested".to_string())
+        );
+    }
+
+    #[test]
+    fn scopes_are_scope_tokens() {
+        let mut report = ValidationReport::new();
+        check_scope(&mut report, &Scope::new("read:orders"));
+        assert!(report.is_valid());
+        check_scope(&mut report, &Scope::new("read orders"));
+        check_scope(&mut report, &Scope::new("\"quoted\""));
+        assert_eq!(report.failures().count(), 2);
+    }
+}